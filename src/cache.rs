@@ -0,0 +1,58 @@
+use crate::sql::Token;
+use crate::Row;
+
+struct Entry {
+    key: Vec<Token>,
+    tables: Vec<String>,
+    rows: Vec<Row>,
+}
+
+/// Recently used SELECT results, keyed by the query's tokens so spacing
+/// differences share an entry. Most recently used entries come first.
+pub(crate) struct QueryCache {
+    capacity: usize,
+    entries: Vec<Entry>,
+}
+
+impl QueryCache {
+    pub fn new(capacity: usize) -> Self {
+        Self { capacity, entries: Vec::new() }
+    }
+
+    pub fn get(&mut self, key: &[Token]) -> Option<Vec<Row>> {
+        let pos = self.entries.iter().position(|e| e.key == key)?;
+        let entry = self.entries.remove(pos);
+        let rows = entry.rows.clone();
+        self.entries.insert(0, entry);
+
+        Some(rows)
+    }
+
+    pub fn put(&mut self, key: Vec<Token>, tables: Vec<String>, rows: Vec<Row>) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        self.entries.retain(|e| e.key != key);
+        self.entries.insert(0, Entry { key, tables, rows });
+        self.entries.truncate(self.capacity);
+    }
+
+    /// Drop every result that read `table`
+    pub fn invalidate(&mut self, table: &str) {
+        self.entries.retain(|e| !e.tables.iter().any(|t| t == table));
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+impl std::fmt::Debug for QueryCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("QueryCache")
+            .field("capacity", &self.capacity)
+            .field("entries", &self.entries.len())
+            .finish()
+    }
+}
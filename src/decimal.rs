@@ -0,0 +1,142 @@
+use std::cmp::Ordering;
+use std::fmt;
+
+/// An exact decimal: `mantissa / 10^scale`, for DECIMAL arithmetic and
+/// comparisons. DECIMAL values are stored as text and parsed as needed.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Decimal {
+    mantissa: i128,
+    scale: u32,
+}
+
+impl Decimal {
+    /// `[-+]digits[.digits]`, with at least one digit; `None` for anything
+    /// else or more digits than fit
+    pub fn parse(s: &str) -> Option<Self> {
+        let s = s.trim();
+        let (negative, digits) = match s.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, s.strip_prefix('+').unwrap_or(s)),
+        };
+        let (whole, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+        let all_digits = |part: &str| part.bytes().all(|b| b.is_ascii_digit());
+        if whole.len() + fraction.len() == 0 || !all_digits(whole) || !all_digits(fraction) {
+            return None;
+        }
+
+        let mut mantissa: i128 = 0;
+        for b in whole.bytes().chain(fraction.bytes()) {
+            mantissa = mantissa.checked_mul(10)?.checked_add(i128::from(b - b'0'))?;
+        }
+        let scale = u32::try_from(fraction.len()).ok()?;
+        Some(Self { mantissa: if negative { -mantissa } else { mantissa }, scale })
+    }
+
+    pub fn from_int(n: i64) -> Self {
+        Self { mantissa: i128::from(n), scale: 0 }
+    }
+
+    pub fn to_f64(self) -> f64 {
+        self.mantissa as f64 / 10f64.powi(self.scale as i32)
+    }
+
+    /// The mantissa at `scale` fraction digits, which must not be below ours
+    fn rescaled(self, scale: u32) -> Option<i128> {
+        10i128.checked_pow(scale - self.scale).and_then(|factor| self.mantissa.checked_mul(factor))
+    }
+
+    /// Both mantissas at the larger of the two scales
+    fn aligned(self, other: Self) -> Option<(i128, i128, u32)> {
+        let scale = self.scale.max(other.scale);
+        Some((self.rescaled(scale)?, other.rescaled(scale)?, scale))
+    }
+
+    pub fn compare(self, other: Self) -> Option<Ordering> {
+        match self.aligned(other) {
+            Some((a, b, _)) => Some(a.cmp(&b)),
+            // too many digits to line up exactly
+            None => self.to_f64().partial_cmp(&other.to_f64()),
+        }
+    }
+
+    pub fn checked_add(self, other: Self) -> Option<Self> {
+        let (a, b, scale) = self.aligned(other)?;
+        Some(Self { mantissa: a.checked_add(b)?, scale })
+    }
+
+    pub fn checked_sub(self, other: Self) -> Option<Self> {
+        let (a, b, scale) = self.aligned(other)?;
+        Some(Self { mantissa: a.checked_sub(b)?, scale })
+    }
+
+    pub fn checked_mul(self, other: Self) -> Option<Self> {
+        Some(Self { mantissa: self.mantissa.checked_mul(other.mantissa)?, scale: self.scale.checked_add(other.scale)? })
+    }
+
+    pub fn checked_abs(self) -> Option<Self> {
+        Some(Self { mantissa: self.mantissa.checked_abs()?, scale: self.scale })
+    }
+
+    /// Rounded to `digits` fraction digits, or to tens, hundreds and so on
+    /// when negative; unchanged if it has no more digits than that
+    pub fn rounded(self, digits: i64, rounding: Rounding) -> Option<Self> {
+        if digits >= i64::from(self.scale) {
+            return Some(self);
+        }
+        let factor = 10i128.checked_pow(u32::try_from(i64::from(self.scale) - digits).ok()?);
+        // rounding away more digits than there are leaves zero, or one unit up or down
+        let (quotient, remainder) = match factor {
+            Some(factor) => (self.mantissa / factor, self.mantissa % factor),
+            None => (0, self.mantissa),
+        };
+        let half_or_more = factor.is_some_and(|factor| remainder.unsigned_abs() * 2 >= factor.unsigned_abs());
+        let quotient = match rounding {
+            Rounding::HalfAway if half_or_more => quotient + remainder.signum(),
+            Rounding::Floor if remainder < 0 => quotient - 1,
+            Rounding::Ceil if remainder > 0 => quotient + 1,
+            _ => quotient,
+        };
+
+        match u32::try_from(digits) {
+            Ok(scale) => Some(Self { mantissa: quotient, scale }),
+            Err(_) if quotient == 0 => Some(Self { mantissa: 0, scale: 0 }),
+            Err(_) => {
+                let unit = 10i128.checked_pow(u32::try_from(-digits).ok()?)?;
+                Some(Self { mantissa: quotient.checked_mul(unit)?, scale: 0 })
+            }
+        }
+    }
+
+    /// The value as an INT, if it has no fraction digits and fits
+    pub fn to_i64(self) -> Option<i64> {
+        if self.scale != 0 {
+            return None;
+        }
+        i64::try_from(self.mantissa).ok()
+    }
+}
+
+/// How [`Decimal::rounded`] treats the digits it drops
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Rounding {
+    /// To the nearest, halves away from zero
+    HalfAway,
+    Floor,
+    Ceil,
+}
+
+/// Plain decimal notation keeping the scale: `12.30`, `-0.5`, `7`
+impl fmt::Display for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let digits = self.mantissa.unsigned_abs().to_string();
+        let scale = self.scale as usize;
+        let digits = format!("{:0>width$}", digits, width = scale + 1);
+        let (whole, fraction) = digits.split_at(digits.len() - scale);
+        let sign = if self.mantissa < 0 { "-" } else { "" };
+        if fraction.is_empty() {
+            write!(f, "{}{}", sign, whole)
+        } else {
+            write!(f, "{}{}.{}", sign, whole, fraction)
+        }
+    }
+}
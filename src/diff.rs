@@ -0,0 +1,157 @@
+use crate::index::IndexKey;
+use crate::schema::TableDef;
+use crate::{LocalDB, LocalDBError, LocalDBValue, Result, Row};
+use std::collections::{BTreeMap, HashMap};
+
+/// What changed from one database to another, as reported by [`LocalDB::diff`]
+#[derive(Debug, Clone, Default)]
+pub struct DbDiff {
+    /// Tables only in the second database, in name order
+    pub tables_added: Vec<String>,
+    /// Tables only in the first database, in name order
+    pub tables_removed: Vec<String>,
+    /// Tables in both whose rows differ; unchanged tables are left out
+    pub tables: BTreeMap<String, TableDiff>,
+}
+
+impl DbDiff {
+    /// Whether the two databases hold the same tables and rows
+    pub fn is_empty(&self) -> bool {
+        self.tables_added.is_empty() && self.tables_removed.is_empty() && self.tables.is_empty()
+    }
+}
+
+/// Row changes of a table both databases have.
+///
+/// Rows are matched by primary key when both schemas declare the same one.
+/// A keyless table, or a row without a key value, is compared as a whole:
+/// a row is added or removed unless an identical row is on the other side,
+/// and is never reported as changed.
+#[derive(Debug, Clone, Default)]
+pub struct TableDiff {
+    /// Rows only in the second database, in its order
+    pub added: Vec<Row>,
+    /// Rows only in the first database, in its order
+    pub removed: Vec<Row>,
+    /// Rows with the same key and different values, in the first database's order
+    pub changed: Vec<RowChange>,
+}
+
+impl TableDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// A keyed row as it is in each database
+#[derive(Debug, Clone)]
+pub struct RowChange {
+    pub key: LocalDBValue,
+    pub before: Row,
+    pub after: Row,
+}
+
+impl LocalDB {
+    /// What changed from `a` to `b`: tables added and removed, and for each
+    /// table in both, the rows added, removed and changed. Fails with
+    /// `CorruptData` if a keyed table holds two rows with the same key.
+    pub fn diff(a: &LocalDB, b: &LocalDB) -> Result<DbDiff> {
+        let mut diff = DbDiff::default();
+        for (table, _) in b.tables.iter().filter(|(table, _)| !a.tables.contains_key(table)) {
+            diff.tables_added.push(table.clone());
+        }
+
+        for (table, before) in a.tables.iter() {
+            let Some(after) = b.tables.get(table) else {
+                diff.tables_removed.push(table.clone());
+                continue;
+            };
+
+            let key = a.primary_key(table).filter(|key| b.primary_key(table) == Some(*key));
+            let changes = table_diff(table, key, before, after)?;
+            if !changes.is_empty() {
+                diff.tables.insert(table.clone(), changes);
+            }
+        }
+
+        Ok(diff)
+    }
+
+    /// Declared primary key column of `table`
+    fn primary_key(&self, table: &str) -> Option<&str> {
+        self.schema.get(table).and_then(TableDef::primary_key).map(|c| c.name.as_str())
+    }
+}
+
+/// Row changes from `before` to `after`, matching rows by `key` if given
+fn table_diff(table: &str, key: Option<&str>, before: &[Row], after: &[Row]) -> Result<TableDiff> {
+    let key_of = |row: &Row| {
+        key.and_then(|key| row.get(key)).filter(|v| !matches!(v, LocalDBValue::NULL)).cloned().map(IndexKey)
+    };
+    let by_key = |rows: &[Row]| -> Result<BTreeMap<IndexKey, usize>> {
+        let mut keyed = BTreeMap::new();
+        for (pos, row) in rows.iter().enumerate() {
+            if let Some(k) = key_of(row) {
+                if let Some(old) = keyed.insert(k, pos) {
+                    let key = rows[old].get(key.unwrap_or_default()).cloned().unwrap_or(LocalDBValue::NULL);
+                    return Err(LocalDBError::CorruptData(format!("Table {} has two rows with key {:?}", table, key)));
+                }
+            }
+        }
+        Ok(keyed)
+    };
+    let (before_keys, after_keys) = (by_key(before)?, by_key(after)?);
+
+    let mut diff = TableDiff::default();
+    for row in before {
+        let Some(k) = key_of(row) else { continue };
+        match after_keys.get(&k) {
+            Some(&other) if *row != after[other] => {
+                diff.changed.push(RowChange { key: k.0, before: row.clone(), after: after[other].clone() })
+            }
+            _ => {}
+        }
+    }
+
+    // keyed rows are added or removed on their key alone, the rest as whole rows
+    let keyless = |row: &Row| key_of(row).is_none();
+    let unknown_keys = |rows: &[Row], known: &BTreeMap<IndexKey, usize>| -> Vec<usize> {
+        let unknown = |row: &Row| key_of(row).is_some_and(|k| !known.contains_key(&k));
+        rows.iter().enumerate().filter(|(_, row)| unknown(row)).map(|(pos, _)| pos).collect()
+    };
+    let added = unmatched(after, before, keyless).into_iter().chain(unknown_keys(after, &before_keys));
+    let removed = unmatched(before, after, keyless).into_iter().chain(unknown_keys(before, &after_keys));
+    diff.added = in_order(added, after);
+    diff.removed = in_order(removed, before);
+    Ok(diff)
+}
+
+/// Positions of the `rows` passing `include` that have no identical row
+/// among the `others` passing it, each row of `others` matching at most one
+fn unmatched(rows: &[Row], others: &[Row], include: impl Fn(&Row) -> bool) -> Vec<usize> {
+    let mut available: HashMap<String, usize> = HashMap::new();
+    for row in others.iter().filter(|row| include(row)) {
+        *available.entry(fingerprint(row)).or_default() += 1;
+    }
+
+    let mut positions = Vec::new();
+    for (pos, row) in rows.iter().enumerate().filter(|(_, row)| include(row)) {
+        match available.get_mut(&fingerprint(row)) {
+            Some(count) if *count > 0 => *count -= 1,
+            _ => positions.push(pos),
+        }
+    }
+    positions
+}
+
+/// A string equal for two rows exactly when they are `==`
+fn fingerprint(row: &Row) -> String {
+    format!("{:?}", row.iter().collect::<BTreeMap<_, _>>())
+}
+
+/// The `rows` at `positions`, in table order
+fn in_order(positions: impl Iterator<Item = usize>, rows: &[Row]) -> Vec<Row> {
+    let mut positions: Vec<usize> = positions.collect();
+    positions.sort_unstable();
+    positions.into_iter().map(|pos| rows[pos].clone()).collect()
+}
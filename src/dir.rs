@@ -0,0 +1,171 @@
+use crate::{parse_file, FileData, FileDataRef, LocalDB, LocalDBError, Operation, Result, SaveOptions, Tables};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// How a database is laid out on disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) enum Layout {
+    /// One JSON file holding every table
+    #[default]
+    File,
+    /// A directory with `__meta__.json` for the format and schema, and one
+    /// `<table>.json` array of rows per table
+    Dir,
+}
+
+/// File of a database directory that holds `__meta__` and `__schema__`
+const META_FILE: &str = "__meta__.json";
+
+/// The file of database directory `dir` that every save rewrites
+pub(crate) fn meta_file(dir: &str) -> PathBuf {
+    Path::new(dir).join(META_FILE)
+}
+
+impl LocalDB {
+    /// Create a new database stored as a directory, one JSON file per table,
+    /// so a save only rewrites the tables that changed since the last one.
+    /// The directory is created if needed and must not already hold a
+    /// database.
+    pub fn create_dir(path: &str) -> Result<Self> {
+        Self::create_dir_with(path, SaveOptions::default())
+    }
+
+    /// `create_dir`, saving with `options`
+    pub fn create_dir_with(path: &str, options: SaveOptions) -> Result<Self> {
+        let meta = Path::new(path).join(META_FILE);
+        if meta.exists() {
+            let source = std::io::Error::new(std::io::ErrorKind::AlreadyExists, "Database directory already exists");
+            return Err(LocalDBError::io(path)(source));
+        }
+
+        let mut db = Self::from_data(FileData::default());
+        db.path = path.to_string();
+        db.layout = Layout::Dir;
+        db.save_options = options;
+        db.save()?;
+
+        Ok(db)
+    }
+
+    /// Open a database directory written by `create_dir`
+    pub fn open_dir(path: &str) -> Result<Self> {
+        Self::open_dir_with(path, SaveOptions::default())
+    }
+
+    /// `open_dir`, saving with `options` from now on
+    pub fn open_dir_with(path: &str, options: SaveOptions) -> Result<Self> {
+        let meta = Path::new(path).join(META_FILE);
+        if !meta.exists() {
+            let source = std::io::Error::new(std::io::ErrorKind::NotFound, "Database directory not found");
+            return Err(LocalDBError::io(path)(source));
+        }
+
+        let content = fs::read_to_string(&meta).map_err(LocalDBError::io(path))?;
+        let data: FileData = parse_file(&content, path)?;
+
+        let mut raw = BTreeMap::new();
+        for entry in fs::read_dir(path).map_err(LocalDBError::io(path))? {
+            let file = entry.map_err(LocalDBError::io(path))?.path();
+            let table = match (file.file_stem().and_then(|s| s.to_str()), file.extension()) {
+                (Some(table), Some(ext)) if ext == "json" && file.file_name() != Some(META_FILE.as_ref()) => table.to_string(),
+                _ => continue,
+            };
+            let content = fs::read_to_string(&file).map_err(io_at(&file))?;
+            let rows = serde_json::value::RawValue::from_string(content)
+                .map_err(LocalDBError::corrupt(&file.display().to_string()))?;
+            raw.insert(table, rows);
+        }
+        let names: Vec<String> = raw.keys().cloned().collect();
+        let names: Vec<&str> = names.iter().map(String::as_str).collect();
+
+        let data = FileData { tables: Tables::partial(raw, &names), ..data };
+        let mut db = Self::from_data(data);
+        db.path = path.to_string();
+        db.layout = Layout::Dir;
+        db.save_options = options;
+        db.stamp.set(db.file_stamp());
+
+        Ok(db)
+    }
+
+    /// Write the schema, and every table changed since the last save, under
+    /// the directory `dir`; tables dropped since then lose their file. Every
+    /// file is rendered and checked against `max_file_bytes` before any is
+    /// written.
+    pub(crate) fn save_dir(&self, dir: &str) -> Result<()> {
+        let started = self.metrics.start();
+        let dirty = self.tables.take_dirty();
+        let result = self.render_dir(dir, &dirty).and_then(|files| {
+            fs::create_dir_all(dir).map_err(LocalDBError::io(dir))?;
+            for (file, json) in &files {
+                match json {
+                    Some(json) => fs::write(file, json).map_err(io_at(file))?,
+                    None => match fs::remove_file(file) {
+                        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(io_at(file)(e)),
+                        _ => {}
+                    },
+                }
+            }
+            Ok(())
+        });
+        if result.is_err() {
+            // still to be written by the next save
+            self.tables.mark_dirty(dirty.iter().cloned());
+            return result;
+        }
+
+        self.stamp.set(self.file_stamp());
+        let rows = started.map_or(0, |_| dirty.iter().filter_map(|table| self.tables.get(table)).map(Vec::len).sum());
+        self.metrics.record(Operation::Save, started, rows);
+        Ok(())
+    }
+
+    /// The files `save_dir` writes, each with its JSON, or `None` to remove it
+    fn render_dir(&self, dir: &str, dirty: &BTreeSet<String>) -> Result<Vec<(PathBuf, Option<Vec<u8>>)>> {
+        let render = |value: serde_json::Result<serde_json::Value>, file: &Path| {
+            let json = value
+                .map_err(std::io::Error::from)
+                .and_then(|value| self.format_json(&value))
+                .map_err(io_at(file))?;
+            match self.limits.max_file_bytes {
+                Some(max) if json.len() as u64 > max => Err(LocalDBError::LimitExceeded(format!(
+                    "saving would write {} bytes to {}, over the {} byte limit",
+                    json.len(),
+                    file.display(),
+                    max
+                ))),
+                _ => Ok(json),
+            }
+        };
+
+        let meta_file = Path::new(dir).join(META_FILE);
+        let meta = FileDataRef { meta: self.file_meta(), schema: &self.schema, tables: &Tables::default() };
+        let mut files = vec![(meta_file.clone(), Some(render(serde_json::to_value(meta), &meta_file)?))];
+        for table in dirty {
+            let file = table_file(dir, table)?;
+            let json = match self.tables.get(table) {
+                Some(rows) => Some(render(serde_json::to_value(rows), &file)?),
+                None => None,
+            };
+            files.push((file, json));
+        }
+        Ok(files)
+    }
+}
+
+/// `LocalDBError::io` for a file of the directory
+fn io_at(file: &Path) -> impl FnOnce(std::io::Error) -> LocalDBError {
+    let path = file.display().to_string();
+    move |source| LocalDBError::IoError { path, source }
+}
+
+/// File holding `table` in the directory `dir`; the name must be usable as
+/// a file name as it is
+fn table_file(dir: &str, table: &str) -> Result<PathBuf> {
+    let usable = !table.starts_with('.') && table.chars().all(|c| c.is_alphanumeric() || matches!(c, '_' | '-' | '.'));
+    if !usable {
+        return Err(LocalDBError::SqlError(format!("Table name {} can't be saved as a file", table)));
+    }
+    Ok(Path::new(dir).join(format!("{}.json", table)))
+}
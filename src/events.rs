@@ -0,0 +1,80 @@
+use crate::Row;
+use std::fmt;
+
+/// What kind of change a [`ChangeEvent`] reports
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+    Insert,
+    Update,
+    Delete,
+}
+
+/// Rows of one table changed by a single statement or call.
+///
+/// Inserted and updated rows are reported as they are now; deleted rows as
+/// they were before removal.
+#[derive(Debug, Clone)]
+pub struct ChangeEvent {
+    pub kind: ChangeKind,
+    pub table: String,
+    pub rows: Vec<Row>,
+}
+
+type Listener = Box<dyn FnMut(ChangeEvent) + Send>;
+
+/// Registered `on_change` callbacks plus the events waiting for the next save
+#[derive(Default)]
+pub(crate) struct Listeners {
+    listeners: Vec<Listener>,
+    pending: Vec<ChangeEvent>,
+}
+
+impl Listeners {
+    pub fn add(&mut self, listener: Listener) {
+        self.listeners.push(listener);
+    }
+
+    /// Whether anyone is listening; callers skip building events otherwise
+    pub fn active(&self) -> bool {
+        !self.listeners.is_empty()
+    }
+
+    pub fn push(&mut self, event: ChangeEvent) {
+        if self.active() && !event.rows.is_empty() {
+            self.pending.push(event);
+        }
+    }
+
+    /// Drop pending events, e.g. when the change could not be saved
+    pub fn discard(&mut self) {
+        self.pending.clear();
+    }
+
+    /// Number of events waiting for the next save
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Drop the events queued after the first `len`, for undoing changes
+    pub fn truncate(&mut self, len: usize) {
+        self.pending.truncate(len);
+    }
+
+    /// Hand every pending event to every listener, in registration order
+    pub fn fire(&mut self) {
+        for event in std::mem::take(&mut self.pending) {
+            for listener in &mut self.listeners {
+                listener(event.clone());
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Listeners {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Listeners")
+            .field("listeners", &self.listeners.len())
+            .field("pending", &self.pending)
+            .finish()
+    }
+}
@@ -0,0 +1,129 @@
+use crate::numeric;
+use crate::sql::Expr;
+use crate::{LocalDBError, LocalDBValue, Result};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+/// A scalar function registered with `register_function`
+pub type UserFunction = Arc<dyn Fn(&[LocalDBValue]) -> Result<LocalDBValue> + Send + Sync>;
+
+/// Names the parser reserves for its own functions
+const BUILT_IN: &[&str] = &[
+    "coalesce", "nullif", "length", "upper", "lower", "trim", "ltrim", "rtrim", "substr", "substring", "count", "sum", "avg",
+    "min", "max", "abs", "round", "ceil", "ceiling", "floor", "greatest", "least", "now",
+];
+
+/// Registered functions by lowercased name
+#[derive(Clone, Default)]
+pub(crate) struct Functions(HashMap<String, UserFunction>);
+
+impl Functions {
+    pub fn insert(&mut self, name: &str, func: UserFunction) -> Result<()> {
+        let valid = name.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid {
+            return Err(LocalDBError::SqlError(format!("Invalid function name: {}", name)));
+        }
+        let name = name.to_ascii_lowercase();
+        if BUILT_IN.contains(&name.as_str()) {
+            return Err(LocalDBError::SqlError(format!("Cannot replace built-in function {}", name.to_ascii_uppercase())));
+        }
+
+        self.0.insert(name, func);
+        Ok(())
+    }
+
+    /// Resolve a call to its built-in numeric or registered function, so
+    /// that `failure` keeps the first error it returns. Other expressions
+    /// are left alone.
+    pub fn bind(&self, expr: &mut Expr, failure: &Failure) -> Result<()> {
+        if let Expr::Call { name, args, func } = expr {
+            let lower = name.to_ascii_lowercase();
+            let found = match numeric::built_in(&lower) {
+                Some((_, min, max)) if args.len() < min || args.len() > max => {
+                    let count = match max {
+                        usize::MAX => format!("at least {}", min),
+                        _ if min == max => min.to_string(),
+                        _ => format!("{} or {}", min, max),
+                    };
+                    let plural = if max == 1 || (max == usize::MAX && min == 1) { "" } else { "s" };
+                    return Err(LocalDBError::SqlError(format!(
+                        "{} takes {} argument{}, got {}",
+                        name.to_ascii_uppercase(),
+                        count,
+                        plural,
+                        args.len()
+                    )));
+                }
+                Some((found, _, _)) => found,
+                None => self
+                    .0
+                    .get(&lower)
+                    .cloned()
+                    .ok_or_else(|| LocalDBError::SqlError(format!("Unknown function {}", name)))?,
+            };
+            *func = Some(BoundCall { func: found, failure: failure.clone() });
+        }
+        Ok(())
+    }
+}
+
+/// Fail on any user function call, for clauses that run after a write
+pub(crate) fn reject_calls(clause: &str) -> impl Fn(&mut Expr) -> Result<()> + '_ {
+    move |expr| match expr {
+        Expr::Call { name, .. } => Err(LocalDBError::SqlError(format!("Function {} cannot be used in {}", name, clause))),
+        _ => Ok(()),
+    }
+}
+
+impl fmt::Debug for Functions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut names: Vec<&String> = self.0.keys().collect();
+        names.sort();
+        f.debug_tuple("Functions").field(&names).finish()
+    }
+}
+
+/// The first error a statement's function calls returned, if any
+#[derive(Clone, Default)]
+pub(crate) struct Failure(Arc<Mutex<Option<LocalDBError>>>);
+
+impl Failure {
+    /// `result`, unless a call failed while it was computed
+    pub fn check<T>(&self, result: Result<T>) -> Result<T> {
+        match self.0.lock().map(|mut slot| slot.take()) {
+            Ok(Some(e)) => Err(e),
+            _ => result,
+        }
+    }
+}
+
+/// A call resolved before its statement runs
+#[derive(Clone)]
+pub(crate) struct BoundCall {
+    func: UserFunction,
+    failure: Failure,
+}
+
+impl BoundCall {
+    /// The function's result; an error is kept for the statement to return,
+    /// and the call reads as NULL meanwhile
+    pub fn call(&self, args: &[LocalDBValue]) -> LocalDBValue {
+        match (self.func)(args) {
+            Ok(value) => value,
+            Err(e) => {
+                if let Ok(mut slot) = self.failure.0.lock() {
+                    slot.get_or_insert(e);
+                }
+                LocalDBValue::NULL
+            }
+        }
+    }
+}
+
+impl fmt::Debug for BoundCall {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("BoundCall")
+    }
+}
@@ -0,0 +1,61 @@
+use crate::{check_arrays, check_table_name, LocalDB, LocalDBError, Row};
+use std::fmt;
+
+/// A problem [`LocalDB::validate_import`] found with one row
+#[derive(Debug)]
+pub struct ImportError {
+    /// Position of the row in the imported rows
+    pub row: usize,
+    /// Columns involved; empty for a problem with the row as a whole
+    pub columns: Vec<String>,
+    /// The error inserting the row would fail with
+    pub error: LocalDBError,
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "row {}: {}", self.row, self.error)
+    }
+}
+
+impl LocalDB {
+    /// Check `rows` for import into `table` without inserting them, and
+    /// report every problem rather than just the first: malformed ARRAYs,
+    /// values not of their column's declared type, as inserts reject them,
+    /// `NOT NULL`, `PRIMARY KEY` and `UNIQUE` violations against the table
+    /// and the rows before it, and the `max_rows` limit. Column `DEFAULT`s
+    /// are applied first, as an insert would. A rejected row doesn't count
+    /// against the ones after it; no problems means `insert_many` would
+    /// accept the rows as they stand.
+    pub fn validate_import(&self, table: &str, rows: &[Row]) -> Vec<ImportError> {
+        let mut problems = Vec::new();
+        if let Err(error) = check_table_name(table) {
+            problems.push(ImportError { row: 0, columns: Vec::new(), error });
+            return problems;
+        }
+
+        let mut working: Vec<Row> = self.tables.get(table).cloned().unwrap_or_default();
+        for (n, row) in rows.iter().enumerate() {
+            let row = self.with_defaults(table, row.clone());
+
+            let before = problems.len();
+            if let Err(error) = check_arrays(&row) {
+                problems.push(ImportError { row: n, columns: Vec::new(), error });
+            }
+            // a working copy, not the stored rows, so every row is scanned
+            for (columns, error) in self.constraint_violations(table, &working, &row, Some(usize::MAX)) {
+                problems.push(ImportError { row: n, columns, error });
+            }
+            if let Some(max) = self.limits.max_rows.filter(|max| working.len() >= *max) {
+                let error = LocalDBError::LimitExceeded(format!("table {} is limited to {} rows", table, max));
+                problems.push(ImportError { row: n, columns: Vec::new(), error });
+            }
+
+            if problems.len() == before {
+                working.push(row);
+            }
+        }
+
+        problems
+    }
+}
@@ -0,0 +1,230 @@
+use crate::sql::{compare_values, like_prefix, text_words, CmpOp, Comparison, Predicate};
+use crate::{Collation, LocalDBValue, Row};
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::ops::Bound;
+
+/// Index key ordering: values of one type family compare naturally,
+/// different families are grouped by variant rank.
+#[derive(Debug, Clone)]
+pub(crate) struct IndexKey(pub LocalDBValue);
+
+impl IndexKey {
+    fn rank(&self) -> u8 {
+        match self.0 {
+            LocalDBValue::INT(_) | LocalDBValue::REAL(_) | LocalDBValue::TIMESTAMP(_) | LocalDBValue::DECIMAL(_) => 0,
+            LocalDBValue::TEXT(_) | LocalDBValue::UUID(_) => 1,
+            LocalDBValue::BOOL(_) => 2,
+            LocalDBValue::JSON(_) => 3,
+            LocalDBValue::ARRAY(_) => 4,
+            LocalDBValue::NULL => 5,
+        }
+    }
+
+    /// `cmp`, with text ordered by `collation`
+    pub fn collated_cmp(&self, other: &Self, collation: Collation) -> Ordering {
+        let comparison = Comparison { collation, ..Comparison::STRICT };
+        self.rank()
+            .cmp(&other.rank())
+            .then_with(|| compare_values(&self.0, &other.0, comparison).unwrap_or(Ordering::Equal))
+    }
+}
+
+impl Ord for IndexKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.collated_cmp(other, Collation::Binary)
+    }
+}
+
+impl PartialOrd for IndexKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for IndexKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for IndexKey {}
+
+/// Sorted (BTree) index over one column, mapping values to row positions.
+///
+/// Indexes live in memory only; they are built by `CREATE INDEX` and kept
+/// up to date as rows are inserted or modified. A partial index holds only
+/// the rows matching its filter, and serves only predicates implying it.
+///
+/// A full-text index instead maps each word of the column's TEXT values,
+/// split by `text_words`, to the rows holding it, and serves only `MATCH`.
+/// Unlike other indexes its definition is saved with the file, and it is
+/// built again from the rows on open.
+#[derive(Debug, Clone)]
+pub(crate) struct Index {
+    pub name: String,
+    pub table: String,
+    pub column: String,
+    pub filter: Option<Predicate>,
+    pub fulltext: bool,
+    entries: BTreeMap<IndexKey, Vec<usize>>,
+}
+
+impl Index {
+    pub fn build(name: String, table: String, column: String, filter: Option<Predicate>, rows: &[Row]) -> Self {
+        let mut index = Self { name, table, column, filter, fulltext: false, entries: BTreeMap::new() };
+        index.rebuild(rows);
+        index
+    }
+
+    pub fn build_fulltext(name: String, table: String, column: String, rows: &[Row]) -> Self {
+        let mut index = Self { name, table, column, filter: None, fulltext: true, entries: BTreeMap::new() };
+        index.rebuild(rows);
+        index
+    }
+
+    pub fn rebuild(&mut self, rows: &[Row]) {
+        self.entries.clear();
+        for (pos, row) in rows.iter().enumerate() {
+            self.insert(row, pos);
+        }
+    }
+
+    pub fn insert(&mut self, row: &Row, pos: usize) {
+        if self.filter.as_ref().is_some_and(|filter| !filter.matches(row, Comparison::STRICT)) {
+            return;
+        }
+        if self.fulltext {
+            if let Some(LocalDBValue::TEXT(text)) = row.get(&self.column) {
+                for word in text_words(text) {
+                    self.entries.entry(IndexKey(LocalDBValue::TEXT(word))).or_default().push(pos);
+                }
+            }
+        } else if let Some(value) = row.get(&self.column) {
+            self.entries.entry(IndexKey(value.clone())).or_default().push(pos);
+        }
+    }
+
+    /// Whether `lookup` can answer `predicate`
+    pub fn can_serve(&self, predicate: &Predicate) -> bool {
+        if self.filter.as_ref().is_some_and(|filter| !predicate.implies(filter)) {
+            return false;
+        }
+        self.serves(predicate)
+    }
+
+    /// `can_serve`, leaving aside the index filter; for `AND` either side
+    /// will do, as candidates are checked against the whole predicate
+    fn serves(&self, predicate: &Predicate) -> bool {
+        match predicate {
+            Predicate::And(left, right) => self.serves(left) || self.serves(right),
+            Predicate::Match { column, .. } => self.fulltext && *column == self.column,
+            _ if self.fulltext => false,
+            Predicate::Compare { column, op, .. } => *column == self.column && *op != CmpOp::Ne,
+            Predicate::Between { column, .. } => *column == self.column,
+            Predicate::Like { column, pattern, case_insensitive: false, negated: false } => {
+                *column == self.column && !like_prefix(pattern).is_empty()
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether every one of the table's `rows` rows is in the index, so the
+    /// index order covers the whole table
+    pub fn covers(&self, rows: usize) -> bool {
+        !self.fulltext && self.filter.is_none() && self.entries.values().map(Vec::len).sum::<usize>() == rows
+    }
+
+    /// Row positions in the order of their values, descending if asked;
+    /// rows with equal values stay in insertion order either way
+    pub fn ordered(&self, descending: bool) -> Box<dyn Iterator<Item = usize> + '_> {
+        if descending {
+            Box::new(self.entries.values().rev().flat_map(|positions| positions.iter().copied()))
+        } else {
+            Box::new(self.entries.values().flat_map(|positions| positions.iter().copied()))
+        }
+    }
+
+    /// Candidate row positions (in insertion order) for a predicate on the
+    /// indexed column, or `None` when the index can't serve the predicate.
+    /// Candidates must still be checked against the predicate.
+    pub fn lookup(&self, predicate: &Predicate) -> Option<Vec<usize>> {
+        use Bound::*;
+
+        let (lower, upper) = match predicate {
+            Predicate::And(left, right) => return self.lookup(left).or_else(|| self.lookup(right)),
+            Predicate::Match { column, query } if self.fulltext && *column == self.column => {
+                return Some(self.word_lookup(query));
+            }
+            _ if self.fulltext => return None,
+            Predicate::Compare { column, op, value } if *column == self.column => {
+                let key = IndexKey(value.clone());
+                match op {
+                    CmpOp::Eq => (Included(key.clone()), Included(key)),
+                    CmpOp::Lt => (Unbounded, Excluded(key)),
+                    CmpOp::Le => (Unbounded, Included(key)),
+                    CmpOp::Gt => (Excluded(key), Unbounded),
+                    CmpOp::Ge => (Included(key), Unbounded),
+                    CmpOp::Ne => return None,
+                }
+            }
+            Predicate::Between { column, low, high } if *column == self.column => {
+                let (low, high) = (IndexKey(low.clone()), IndexKey(high.clone()));
+                if low > high {
+                    return Some(vec![]);
+                }
+                (Included(low), Included(high))
+            }
+            Predicate::Like { column, pattern, case_insensitive: false, negated: false } if *column == self.column => {
+                return self.prefix_lookup(like_prefix(pattern));
+            }
+            _ => return None,
+        };
+
+        let mut positions: Vec<usize> = self
+            .entries
+            .range((lower, upper))
+            .flat_map(|(_, positions)| positions.iter().copied())
+            .collect();
+        positions.sort_unstable();
+
+        Some(positions)
+    }
+
+    /// Positions of the rows holding every word of `query`, for `MATCH`
+    fn word_lookup(&self, query: &str) -> Vec<usize> {
+        let mut words = text_words(query).into_iter();
+        let Some(first) = words.next() else {
+            return Vec::new();
+        };
+
+        let positions = |word: String| self.entries.get(&IndexKey(LocalDBValue::TEXT(word))).cloned().unwrap_or_default();
+        let mut found = positions(first);
+        for word in words {
+            let mut also = positions(word);
+            also.sort_unstable();
+            found.retain(|pos| also.binary_search(pos).is_ok());
+        }
+        found.sort_unstable();
+        found
+    }
+
+    /// Positions of the text values starting with `prefix`, for an anchored
+    /// `LIKE`: text sorts by code point, so they form one run of keys
+    fn prefix_lookup(&self, prefix: &str) -> Option<Vec<usize>> {
+        if prefix.is_empty() {
+            return None;
+        }
+
+        let start = IndexKey(LocalDBValue::TEXT(prefix.to_string()));
+        let mut positions: Vec<usize> = self
+            .entries
+            .range(start..)
+            .take_while(|(key, _)| matches!(&key.0, LocalDBValue::TEXT(s) | LocalDBValue::UUID(s) if s.starts_with(prefix)))
+            .flat_map(|(_, positions)| positions.iter().copied())
+            .collect();
+        positions.sort_unstable();
+
+        Some(positions)
+    }
+}
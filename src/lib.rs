@@ -1,17 +1,113 @@
 use serde::{Serialize, Deserialize};
-use std::collections::HashMap;
+use std::cell::{Cell, RefCell};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fs;
 use std::io::{Write, Read};
-use uuid::Uuid;
+use std::time::{Duration, Instant};
 use thiserror::Error;
 
+mod cache;
+mod decimal;
+mod diff;
+mod dir;
+mod events;
+mod functions;
+mod import;
+mod index;
+mod metrics;
+mod numeric;
+mod query;
+mod repair;
+mod schema;
+mod sql;
+#[cfg(feature = "rusqlite")]
+mod sqlite;
+mod tables;
+mod transaction;
+
+pub use diff::{DbDiff, RowChange, TableDiff};
+pub use events::{ChangeEvent, ChangeKind};
+pub use functions::UserFunction;
+pub use import::ImportError;
+pub use metrics::{OpMetrics, Operation};
+pub use query::{Filter, QueryResult, SelectBuilder};
+pub use repair::{RemovedRow, RepairReport};
+pub use schema::{ColType, ColumnDef, ColumnDefault, SchemaCheck, TableBuilder, TableDef};
+pub use transaction::Transaction;
+
+use cache::QueryCache;
+use dir::Layout;
+use events::Listeners;
+use functions::{Failure, Functions};
+use index::{Index, IndexKey};
+use metrics::Metrics;
+use sql::{Comment, Comparison, Expr, InsertSource, Parser, Predicate, Query, Select};
+use tables::Tables;
+
 #[derive(Debug, Error)]
 pub enum LocalDBError {
-    #[error("IO error: {0}")]
-    IoError(String),
+    #[error("IO error on {path}: {source}")]
+    IoError {
+        /// File the operation failed on, or `<reader>`/`<writer>` for streams
+        path: String,
+        #[source]
+        source: std::io::Error,
+    },
 
+    /// A statement that parsed but could not be run
     #[error("SQL error: {0}")]
     SqlError(String),
+
+    /// SQL that could not be tokenized or parsed
+    #[error("Parse error: {0}")]
+    Parse(String),
+
+    /// A declared column that doesn't exist, as `table.column`
+    #[error("Column not found: {0}")]
+    ColumnNotFound(String),
+
+    /// A value that can't be stored as the column's type
+    #[error("Type mismatch: {0}")]
+    TypeMismatch(String),
+
+    /// A row breaking a NOT NULL, PRIMARY KEY or UNIQUE constraint
+    #[error("{0}")]
+    ConstraintViolation(String),
+
+    #[error("Database is read-only")]
+    ReadOnly,
+
+    #[error("Limit exceeded: {0}")]
+    LimitExceeded(String),
+
+    /// A query that would examine more rows than `set_scan_limit` allows
+    #[error("Query scanned more than {0} rows")]
+    ScanLimitExceeded(usize),
+
+    #[error("Schema mismatch: {0}")]
+    SchemaMismatch(String),
+
+    #[error("Table not found: {0}")]
+    TableNotFound(String),
+
+    #[error("File format {found} is newer than this version of localdb supports ({supported})")]
+    UnsupportedFormat { found: u32, supported: u32 },
+
+    /// A database file that doesn't parse, or data given to `from_value`
+    /// or `load_value` that isn't tables of rows
+    #[error("Corrupt data: {0}")]
+    CorruptData(String),
+}
+
+impl LocalDBError {
+    fn io(path: &str) -> impl FnOnce(std::io::Error) -> LocalDBError + '_ {
+        move |source| LocalDBError::IoError { path: path.to_string(), source }
+    }
+
+    fn corrupt(path: &str) -> impl FnOnce(serde_json::Error) -> LocalDBError + '_ {
+        move |e| LocalDBError::CorruptData(format!("{}: {}", path, e))
+    }
 }
 
 pub type Result<T> = std::result::Result<T, LocalDBError>;
@@ -19,181 +115,3597 @@ pub type Result<T> = std::result::Result<T, LocalDBError>;
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum LocalDBValue {
     INT(i64),
+    REAL(f64),
     TEXT(String),
     UUID(String),
+    BOOL(bool),
+    /// Arbitrary nested data, stored inline; read with `->` and `->>`
+    JSON(serde_json::Value),
+    /// Milliseconds since the Unix epoch; compares with INT as its millis
+    TIMESTAMP(i64),
+    /// An exact decimal such as a money amount, kept as its text (`12.30`);
+    /// compares exactly with DECIMAL and INT. See [`LocalDBValue::decimal`]
+    DECIMAL(String),
+    /// A list of non-NULL scalars of one type, e.g. tags; see [`LocalDBValue::array`]
+    ARRAY(Vec<LocalDBValue>),
+    NULL,
+}
+
+impl LocalDBValue {
+    /// The current time as a TIMESTAMP
+    pub fn now() -> Self {
+        let millis = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as i64);
+        LocalDBValue::TIMESTAMP(millis)
+    }
+
+    /// A DECIMAL of `[-]digits[.digits]` text such as `"12.30"`, which keeps
+    /// its digits after the point
+    pub fn decimal(s: &str) -> Result<Self> {
+        decimal::Decimal::parse(s)
+            .map(|d| LocalDBValue::DECIMAL(d.to_string()))
+            .ok_or_else(|| LocalDBError::TypeMismatch(format!("Invalid DECIMAL: {}", s)))
+    }
+
+    /// An ARRAY of `items`, which must all be non-NULL, non-array values of
+    /// the same type; INT and REAL don't mix
+    pub fn array(items: Vec<LocalDBValue>) -> Result<Self> {
+        check_array(&items)?;
+        Ok(LocalDBValue::ARRAY(items))
+    }
+}
+
+impl From<bool> for LocalDBValue {
+    fn from(v: bool) -> Self {
+        LocalDBValue::BOOL(v)
+    }
+}
+
+impl From<i64> for LocalDBValue {
+    fn from(v: i64) -> Self {
+        LocalDBValue::INT(v)
+    }
+}
+
+impl From<f64> for LocalDBValue {
+    fn from(v: f64) -> Self {
+        LocalDBValue::REAL(v)
+    }
+}
+
+impl From<serde_json::Value> for LocalDBValue {
+    fn from(v: serde_json::Value) -> Self {
+        LocalDBValue::JSON(v)
+    }
+}
+
+impl From<&str> for LocalDBValue {
+    fn from(v: &str) -> Self {
+        LocalDBValue::TEXT(v.to_string())
+    }
+}
+
+impl From<String> for LocalDBValue {
+    fn from(v: String) -> Self {
+        LocalDBValue::TEXT(v)
+    }
+}
+
+impl PartialEq for LocalDBValue {
+    /// The same type holding the same value; unlike `compare_values`, NULL
+    /// equals NULL, INT 1 differs from REAL 1.0 and TEXT from UUID, and
+    /// DECIMALs are equal by value, so `1.10` equals `1.1`
+    fn eq(&self, other: &Self) -> bool {
+        use LocalDBValue::*;
+
+        match (self, other) {
+            (INT(x), INT(y)) => x == y,
+            (REAL(x), REAL(y)) => x == y,
+            (TEXT(x), TEXT(y)) | (UUID(x), UUID(y)) => x == y,
+            (BOOL(x), BOOL(y)) => x == y,
+            (JSON(x), JSON(y)) => x == y,
+            (TIMESTAMP(x), TIMESTAMP(y)) => x == y,
+            (DECIMAL(_), DECIMAL(_)) => sql::compare_values(self, other, Comparison::STRICT) == Some(Ordering::Equal),
+            (ARRAY(x), ARRAY(y)) => x == y,
+            (NULL, NULL) => true,
+            _ => false,
+        }
+    }
+}
+
+impl PartialOrd for LocalDBValue {
+    /// Values of one type in the order ORDER BY gives them, comparing TEXT
+    /// by code point and ARRAYs element by element. Values of different
+    /// types, REAL NaN and JSON values that differ are unordered, so this
+    /// agrees with `==`.
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        use LocalDBValue::*;
+
+        match (self, other) {
+            (ARRAY(x), ARRAY(y)) => {
+                for (a, b) in x.iter().zip(y) {
+                    match a.partial_cmp(b)? {
+                        Ordering::Equal => continue,
+                        ord => return Some(ord),
+                    }
+                }
+                Some(x.len().cmp(&y.len()))
+            }
+            (JSON(x), JSON(y)) => (x == y).then_some(Ordering::Equal),
+            (NULL, NULL) => Some(Ordering::Equal),
+            _ if std::mem::discriminant(self) == std::mem::discriminant(other) => sql::compare_values(self, other, Comparison::STRICT),
+            _ => None,
+        }
+    }
+}
+
+/// How WHERE comparisons treat values of different types.
+///
+/// TEXT and UUID both hold strings and always compare by content, and INT and
+/// REAL always compare numerically. Beyond that, `Strict` never matches
+/// values of different types, while `Coerce` parses a TEXT/UUID operand as a
+/// number or BOOL (`'true'`/`'false'`) when the other side has that type.
+/// Comparisons that still don't line up, or involve NULL, never match, for
+/// any operator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Coercion {
+    #[default]
+    Strict,
+    Coerce,
+}
+
+/// How TEXT and UUID values sort in ORDER BY and compare in WHERE; chosen
+/// for the database with `set_collation`, or for one sort key with `ORDER BY
+/// name COLLATE NOCASE`. Keys, UNIQUE checks, GROUP BY and DISTINCT always
+/// compare exactly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Collation {
+    /// By code point, so `'B'` sorts before `'a'` (`COLLATE BINARY`)
+    #[default]
+    Binary,
+    /// Ignoring letter case (`COLLATE NOCASE`)
+    NoCase,
+    /// Ignoring case and accents, with accented Latin letters folded to ASCII:
+    /// `'Émile'` sorts between `'Eli'` and `'Emma'` (`COLLATE FOLD`)
+    Fold,
+}
+
+/// What `insert_many_with` does with a row holding PRIMARY KEY or UNIQUE
+/// values that a row of the table already has
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OnConflict {
+    /// Fail, inserting nothing, as `insert_many` does
+    #[default]
+    Error,
+    /// Keep the existing row and drop the new one
+    Skip,
+    /// Put the new row in place of the existing one
+    Overwrite,
+}
+
+/// What `insert_many_with` did with the rows it was given
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct InsertCounts {
+    pub inserted: usize,
+    pub skipped: usize,
+    pub overwritten: usize,
+}
+
+/// How the database is formatted when written out
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SaveOptions {
+    /// Spaces per indentation level for pretty output
+    pub indent: usize,
+    /// Write compact JSON with sorted keys and no whitespace, suitable for
+    /// hashing; `indent` is ignored
+    pub canonical: bool,
+}
+
+impl SaveOptions {
+    /// Pretty-printed JSON indented by `indent` spaces
+    pub fn pretty(indent: usize) -> Self {
+        Self { indent, canonical: false }
+    }
+
+    /// Canonical single-line JSON
+    pub fn canonical() -> Self {
+        Self { indent: 0, canonical: true }
+    }
+}
+
+impl Default for SaveOptions {
+    fn default() -> Self {
+        Self::pretty(2)
+    }
+}
+
+/// Optional size guards, all off by default
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Limits {
+    /// Most rows any one table may hold; inserts beyond it fail
+    pub max_rows: Option<usize>,
+    /// Largest the saved file may get; a change that would make it bigger
+    /// fails and is undone
+    pub max_file_bytes: Option<u64>,
+}
+
+/// A single table row, keyed by column name
+pub type Row = HashMap<String, LocalDBValue>;
+
+/// A table in a joined query: its qualifier, columns and rows
+type JoinSource<'a> = (&'a str, Vec<String>, &'a [Row]);
+
+/// What the NATURAL JOINs of a query join on, from `natural_columns`
+#[derive(Default)]
+struct NaturalColumns<'a> {
+    /// For each JOIN, the columns it joins on, each with the name of its
+    /// copy in the rows joined before it; empty unless NATURAL
+    keys: Vec<Vec<(String, String)>>,
+    /// Columns merged by a NATURAL JOIN, which `*` shows once, unqualified,
+    /// in place of the copies of the tables listed
+    merged: Vec<(String, Vec<&'a str>)>,
+}
+
+impl NaturalColumns<'_> {
+    /// Whether `*` over the joined rows shows `column`
+    fn shown(&self, column: &str) -> bool {
+        match column.split_once('.') {
+            Some((qualifier, name)) => !self.merged.iter().any(|(c, tables)| c == name && tables.contains(&qualifier)),
+            None => self.merged.iter().any(|(c, _)| c == column),
+        }
+    }
+}
+
+/// File keys that hold metadata rather than a table
+const RESERVED_KEYS: [&str; 2] = ["__meta__", "__schema__"];
+
+/// Version of the on-disk layout, bumped on incompatible changes
+const FORMAT_VERSION: u32 = 1;
+
+/// The reserved `__meta__` key: which format and crate version wrote the file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct FileMeta {
+    format: u32,
+    localdb: String,
+    /// Full-text indexes, rebuilt from the rows on open
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    fulltext: Vec<FullTextDef>,
+}
+
+impl Default for FileMeta {
+    fn default() -> Self {
+        Self { format: FORMAT_VERSION, localdb: env!("CARGO_PKG_VERSION").to_string(), fulltext: Vec::new() }
+    }
+}
+
+/// A `CREATE FULLTEXT INDEX`, as saved in `__meta__`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct FullTextDef {
+    name: String,
+    table: String,
+    column: String,
+}
+
+/// Modification time and size of the backing file, to notice other writers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct FileStamp {
+    modified: std::time::SystemTime,
+    len: u64,
+}
+
+/// On-disk layout: one key per table, plus the reserved `__meta__` key and
+/// the `__schema__` key holding table definitions (omitted when no table
+/// has one). Files without `__meta__` predate it and are format 1.
+#[derive(Debug, Default, Deserialize)]
+struct FileData {
+    #[serde(rename = "__meta__", default)]
+    meta: Option<FileMeta>,
+    #[serde(rename = "__schema__", default)]
+    schema: BTreeMap<String, TableDef>,
+    #[serde(flatten)]
+    tables: Tables,
+}
+
+#[derive(Serialize)]
+struct FileDataRef<'a> {
+    #[serde(rename = "__meta__")]
+    meta: FileMeta,
+    #[serde(rename = "__schema__", skip_serializing_if = "BTreeMap::is_empty")]
+    schema: &'a BTreeMap<String, TableDef>,
+    #[serde(flatten)]
+    tables: &'a Tables,
+}
+
+/// `FileDataRef` with keys sorted at every level, as the file is written,
+/// serialized a table row at a time; rows are HashMaps, and each goes
+/// through `serde_json::Value` to sort its keys
+struct SortedFile<'a> {
+    meta: serde_json::Value,
+    schema: Option<serde_json::Value>,
+    tables: &'a Tables,
+}
+
+impl Serialize for SortedFile<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap;
+
+        let mut reserved = [("__meta__", Some(&self.meta)), ("__schema__", self.schema.as_ref())]
+            .into_iter()
+            .filter_map(|(key, value)| Some((key, value?)))
+            .peekable();
+        let mut map = serializer.serialize_map(None)?;
+        for (name, table) in self.tables.sorted() {
+            while let Some((key, value)) = reserved.next_if(|(key, _)| *key < name) {
+                map.serialize_entry(key, value)?;
+            }
+            map.serialize_entry(name, &table)?;
+        }
+        for (key, value) in reserved {
+            map.serialize_entry(key, value)?;
+        }
+        map.end()
+    }
+}
+
+/// Only the format of a file's `__meta__`, read before anything else
+#[derive(Deserialize)]
+struct FileHeader {
+    #[serde(rename = "__meta__", default)]
+    meta: Option<FormatOnly>,
+}
+
+#[derive(Deserialize)]
+struct FormatOnly {
+    format: u32,
+}
+
+/// Parse the database file `content` read from `path`. Its format is
+/// checked first, so a file written in a newer format fails with
+/// `UnsupportedFormat` even if it holds values this build can't read;
+/// anything else that doesn't parse fails with `CorruptData`, never
+/// opening as an empty database a save would then overwrite.
+fn parse_file<T: serde::de::DeserializeOwned>(content: &str, path: &str) -> Result<T> {
+    let header: FileHeader = serde_json::from_str(content).map_err(LocalDBError::corrupt(path))?;
+    if let Some(found) = header.meta.map(|meta| meta.format).filter(|&found| found > FORMAT_VERSION) {
+        return Err(LocalDBError::UnsupportedFormat { found, supported: FORMAT_VERSION });
+    }
+    serde_json::from_str(content).map_err(LocalDBError::corrupt(path))
+}
+
+/// Table recording which `migrate` versions have been applied
+const MIGRATIONS_TABLE: &str = "schema_migrations";
+
+/// One schema change for [`LocalDB::migrate`]
+#[derive(Debug, Clone, Copy)]
+pub struct Migration<'a> {
+    pub version: i64,
+    /// Statements to run, as accepted by `exec`
+    pub up_sql: &'a str,
+}
+
+#[derive(Clone)]
+struct Checkpoint {
+    tables: Tables,
+    schema: BTreeMap<String, TableDef>,
+    indexes: Vec<Index>,
+    /// Change events already pending when the checkpoint was taken
+    events: usize,
 }
 
 #[derive(Debug)]
 pub struct LocalDB {
+    /// Backing file, empty for databases loaded from a reader
     pub path: String,
+    /// A single file at `path`, or a directory from `create_dir`
+    layout: Layout,
+    /// The backing file as last read or written by this handle
+    stamp: Cell<Option<FileStamp>>,
+    tables: Tables,
+    schema: BTreeMap<String, TableDef>,
+    indexes: Vec<Index>,
+    attached: BTreeMap<String, LocalDB>,
+    coercion: Coercion,
+    collation: Collation,
+    save_options: SaveOptions,
+    read_only: bool,
+    listeners: Listeners,
+    /// Opt-in SELECT result cache; `query` takes `&self`, hence the RefCell
+    cache: RefCell<Option<QueryCache>>,
+    limits: Limits,
+    metrics: Metrics,
+    /// Queries on a missing table fail instead of returning no rows
+    strict_tables: bool,
+    scans: ScanStats,
+    functions: Functions,
+    debounce: Debounce,
+}
+
+/// `set_flush_debounce` state: saves due within `wait` of the last write
+/// are put off, leaving `pending` set until a write goes through
+#[derive(Debug, Default)]
+struct Debounce {
+    wait: Option<Duration>,
+    last_write: Cell<Option<Instant>>,
+    pending: Cell<bool>,
+}
+
+impl Debounce {
+    /// Whether to put off a save due now, noting that one is owed if so
+    fn defer(&self) -> bool {
+        let defer = self.wait.zip(self.last_write.get()).is_some_and(|(wait, at)| at.elapsed() < wait);
+        if defer {
+            self.pending.set(true);
+        }
+        defer
+    }
+
+    fn wrote(&self) {
+        self.last_write.set(Some(Instant::now()));
+        self.pending.set(false);
+    }
+}
+
+impl Drop for LocalDB {
+    /// Write any change a debounced save put off; errors are lost here, so
+    /// call `save` first to see them
+    fn drop(&mut self) {
+        if self.debounce.pending.get() {
+            let _ = self.save();
+        }
+    }
+}
+
+/// What reads have done since the last reset, for `explain_analyze`
+#[derive(Debug, Default)]
+struct ScanStats {
+    /// Rows examined, whether from a full scan or as index candidates
+    rows: Cell<usize>,
+    /// Index used by each lookup that had one
+    indexes: RefCell<Vec<String>>,
+    /// Most rows one query may examine, from `set_scan_limit`
+    limit: Option<usize>,
+    /// Rows the running query may still examine; `None` outside queries
+    budget: Cell<Option<usize>>,
+}
+
+impl ScanStats {
+    /// Record `rows` examined, failing once the running query's budget is spent
+    fn count(&self, rows: usize) -> Result<()> {
+        self.rows.set(self.rows.get() + rows);
+        match self.budget.get() {
+            Some(left) if rows > left => Err(LocalDBError::ScanLimitExceeded(self.limit.unwrap_or(0))),
+            Some(left) => {
+                self.budget.set(Some(left - rows));
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn reset(&self) {
+        self.rows.set(0);
+        self.indexes.borrow_mut().clear();
+    }
+}
+
+/// What a statement changed: how many rows, and its `RETURNING` rows if it
+/// asked for them
+#[derive(Default)]
+struct Outcome {
+    affected: usize,
+    returning: Option<Vec<Row>>,
 }
 
 impl LocalDB {
     /// Create a new DB file
     pub fn create(path: &str) -> Result<Self> {
-        fs::write(path, "{}")
-            .map_err(|e| LocalDBError::IoError(e.to_string()))?;
+        Self::create_with(path, SaveOptions::default())
+    }
+
+    /// Create a new DB file that is saved with `options`
+    pub fn create_with(path: &str, options: SaveOptions) -> Result<Self> {
+        let mut db = Self::from_data(FileData::default());
+        db.path = path.to_string();
+        db.save_options = options;
 
-        Ok(Self {
-            path: path.to_string(),
-        })
+        fs::write(path, db.render_json().map_err(LocalDBError::io(path))?)
+            .map_err(LocalDBError::io(path))?;
+        db.stamp.set(db.file_stamp());
+
+        Ok(db)
     }
 
     /// Open existing DB
     pub fn open(path: &str) -> Result<Self> {
+        Self::open_with(path, SaveOptions::default())
+    }
+
+    /// Open existing DB, saving it with `options` from now on
+    pub fn open_with(path: &str, options: SaveOptions) -> Result<Self> {
         if !std::path::Path::new(path).exists() {
-            return Err(LocalDBError::IoError("Database file not found".into()));
+            let source = std::io::Error::new(std::io::ErrorKind::NotFound, "Database file not found");
+            return Err(LocalDBError::io(path)(source));
         }
 
-        Ok(Self {
-            path: path.to_string(),
-        })
+        let file = fs::File::open(path)
+            .map_err(LocalDBError::io(path))?;
+
+        let data = Self::read_data(file, path)?;
+        let mut db = Self::from_data(data);
+        db.path = path.to_string();
+        db.save_options = options;
+        db.stamp.set(db.file_stamp());
+
+        Ok(db)
     }
 
-    /// Add SQL lines into a single block
-    pub fn add_lines(&self, lines: [&str; 2]) -> String {
-        let mut out = String::new();
-        for l in lines {
-            out.push_str(l);
-            out.push('\n');
+    /// Open existing DB, parsing only the rows of `tables` up front. Every
+    /// other table is kept as raw JSON and parsed the first time something
+    /// reads or writes it, so unused tables cost little memory or time.
+    ///
+    /// Tables parsed on demand have no PRIMARY KEY index until the DB is
+    /// opened again, so key lookups on them scan.
+    pub fn open_tables(path: &str, tables: &[&str]) -> Result<Self> {
+        if !std::path::Path::new(path).exists() {
+            let source = std::io::Error::new(std::io::ErrorKind::NotFound, "Database file not found");
+            return Err(LocalDBError::io(path)(source));
         }
-        out
+
+        let content = fs::read_to_string(path).map_err(LocalDBError::io(path))?;
+        let mut raw: BTreeMap<String, Box<serde_json::value::RawValue>> = parse_file(&content, path)?;
+        let meta = raw.remove("__meta__").map(|value| serde_json::from_str(value.get())).transpose();
+        let schema = raw.remove("__schema__").map(|value| serde_json::from_str(value.get())).transpose();
+
+        let data = FileData {
+            meta: meta.map_err(LocalDBError::corrupt(path))?,
+            schema: schema.map_err(LocalDBError::corrupt(path))?.unwrap_or_default(),
+            tables: Tables::partial(raw, tables),
+        };
+        let mut db = Self::from_data(data);
+        db.path = path.to_string();
+        db.stamp.set(db.file_stamp());
+
+        Ok(db)
     }
 
-    /// Execute SQL statements
-    pub fn exec(&mut self, sql: String) -> Result<()> {
-        let statements = sql.split(";");
+    /// Open existing DB for reading only: writes fail with `ReadOnly` and
+    /// the file is never written
+    pub fn open_readonly(path: &str) -> Result<Self> {
+        let mut db = Self::open(path)?;
+        db.read_only = true;
 
-        for raw in statements {
-            let stmt = raw.trim();
-            if stmt.is_empty() {
-                continue;
-            }
+        Ok(db)
+    }
 
-            if stmt.starts_with("CREATE TABLE") {
-                self.handle_create_table(stmt)?;
-            } else if stmt.starts_with("INSERT INTO") || stmt.starts_with("INSET INTO") {
-                self.handle_insert(stmt)?;
-            } else {
-                return Err(LocalDBError::SqlError(format!("Unsupported SQL: {}", stmt)));
+    /// Load a DB from any reader; the result is not bound to a file
+    pub fn from_reader(reader: impl Read) -> Result<Self> {
+        let data = Self::read_data(reader, "<reader>")?;
+
+        Ok(Self::from_data(data))
+    }
+
+    /// An in-memory database holding the tables of `value`, shaped as for
+    /// `load_value`
+    pub fn from_value(value: serde_json::Value) -> Result<Self> {
+        let mut db = Self::from_data(FileData::default());
+        db.load_value(value)?;
+        Ok(db)
+    }
+
+    fn read_data(mut reader: impl Read, path: &str) -> Result<FileData> {
+        let mut content = String::new();
+        reader.read_to_string(&mut content).map_err(LocalDBError::io(path))?;
+
+        parse_file(&content, path)
+    }
+
+    fn from_data(data: FileData) -> Self {
+        let mut db = Self {
+            path: String::new(),
+            layout: Layout::File,
+            stamp: Cell::new(None),
+            tables: data.tables,
+            schema: data.schema,
+            indexes: Vec::new(),
+            attached: BTreeMap::new(),
+            coercion: Coercion::default(),
+            collation: Collation::default(),
+            save_options: SaveOptions::default(),
+            read_only: false,
+            listeners: Listeners::default(),
+            cache: RefCell::new(None),
+            limits: Limits::default(),
+            metrics: Metrics::default(),
+            strict_tables: false,
+            scans: ScanStats::default(),
+            functions: Functions::default(),
+            debounce: Debounce::default(),
+        };
+
+        let defs: Vec<TableDef> = db.schema.values().cloned().collect();
+        for def in &defs {
+            if !db.tables.is_unread(&def.name) {
+                db.index_primary_key(def);
             }
         }
+        for def in data.meta.map(|meta| meta.fulltext).unwrap_or_default() {
+            let rows = db.tables.get(&def.table).map(Vec::as_slice).unwrap_or_default();
+            db.indexes.push(Index::build_fulltext(def.name, def.table, def.column, rows));
+        }
 
-        Ok(())
+        db
     }
 
-    /// SELECT * FROM table;
-    pub fn query(&self, sql: &str) -> Result<Vec<HashMap<String, LocalDBValue>>> {
-        let sql = sql.trim();
+    /// The `__meta__` entry to save, listing the full-text indexes
+    fn file_meta(&self) -> FileMeta {
+        let fulltext = self
+            .indexes
+            .iter()
+            .filter(|index| index.fulltext)
+            .map(|index| FullTextDef { name: index.name.clone(), table: index.table.clone(), column: index.column.clone() })
+            .collect();
+        FileMeta { fulltext, ..FileMeta::default() }
+    }
 
-        if !sql.starts_with("SELECT") {
-            return Err(LocalDBError::SqlError("Only SELECT is supported".into()));
+    /// Attach another DB file under `alias`, so queries can read `alias.table`.
+    ///
+    /// The file is read once, at attach time, and attached databases are
+    /// read-only. No lock is held on the attached file: writes made to it by
+    /// other handles are not seen until it is attached again.
+    pub fn attach(&mut self, alias: &str, path: &str) -> Result<()> {
+        if self.attached.contains_key(alias) {
+            return Err(LocalDBError::SqlError(format!("Alias already attached: {}", alias)));
         }
 
-        let table = Self::extract_table_name_from_select(sql)?;
+        let db = Self::open(path)?;
+        self.attached.insert(alias.to_string(), db);
+        self.clear_cache();
 
-        let content = fs::read_to_string(&self.path)
-            .map_err(|e| LocalDBError::IoError(e.to_string()))?;
+        Ok(())
+    }
 
-        let data: HashMap<String, Vec<HashMap<String, LocalDBValue>>> =
-            serde_json::from_str(&content).unwrap_or_default();
+    /// Choose how WHERE compares values of different types (default: strict)
+    pub fn set_coercion(&mut self, coercion: Coercion) {
+        self.coercion = coercion;
+        self.clear_cache();
+    }
 
-        Ok(data.get(&table).cloned().unwrap_or_default())
+    /// Choose how text sorts and compares (default: binary); `COLLATE` on an
+    /// ORDER BY key overrides it for that key
+    pub fn set_collation(&mut self, collation: Collation) {
+        self.collation = collation;
+        self.clear_cache();
     }
 
-    // ========================= INTERNAL HANDLERS =============================
+    /// How WHERE compares values, per the coercion and collation settings
+    fn comparison(&self) -> Comparison {
+        Comparison { coercion: self.coercion, collation: self.collation }
+    }
 
-    fn handle_create_table(&self, sql: &str) -> Result<()> {
-        let name = Self::extract_table_name_from_create(sql)?;
+    /// Make queries on a table that doesn't exist fail with `TableNotFound`
+    /// rather than return no rows (default: off, returning no rows)
+    pub fn set_strict_tables(&mut self, strict: bool) {
+        self.strict_tables = strict;
+        self.clear_cache();
+    }
 
-        let mut data: HashMap<String, Vec<HashMap<String, LocalDBValue>>> =
-            Self::load_json(&self.path)?;
+    /// Detach a database previously attached under `alias`
+    pub fn detach(&mut self, alias: &str) -> Result<()> {
+        self.attached
+            .remove(alias)
+            .ok_or_else(|| LocalDBError::SqlError(format!("No database attached as {}", alias)))?;
+        self.clear_cache();
 
-        if !data.contains_key(&name) {
-            data.insert(name, vec![]);
-        }
+        Ok(())
+    }
 
-        Self::save_json(&self.path, &data)?;
+    /// Change how the DB is formatted on save
+    pub fn set_save_options(&mut self, options: SaveOptions) {
+        self.save_options = options;
+    }
 
-        Ok(())
+    /// Write the in-memory state to any writer, formatted per the save options.
+    ///
+    /// Tables and columns are written in sorted order so the output is
+    /// byte-for-byte stable across runs. The same as `export_json_to`.
+    pub fn write_to(&self, writer: impl Write) -> Result<()> {
+        self.export_json_to(writer)
     }
 
-    fn handle_insert(&self, sql: &str) -> Result<()> {
-        let sql_fixed = sql.replace("INSET", "INSERT");
+    /// Write the whole database to `writer` as one JSON document, exactly
+    /// as `save` would write its file, for backups and transfers of large
+    /// databases: the JSON is written as it's produced, a row at a time, so
+    /// unlike `save` it is never held in memory whole. A table left unread
+    /// by `open_tables` is still parsed whole while it's written. A database
+    /// opened with `open_dir` is written in the single-file layout.
+    pub fn export_json_to(&self, writer: impl Write) -> Result<()> {
+        self.write_json(writer).map_err(LocalDBError::io("<writer>"))
+    }
+
+    fn write_json(&self, mut writer: impl Write) -> std::io::Result<()> {
+        let schema = (!self.schema.is_empty()).then(|| serde_json::to_value(&self.schema)).transpose()?;
+        let file = SortedFile { meta: serde_json::to_value(self.file_meta())?, schema, tables: &self.tables };
+        if self.save_options.canonical {
+            serde_json::to_writer(&mut writer, &file)?;
+        } else {
+            let indent = vec![b' '; self.save_options.indent];
+            let mut ser = serde_json::Serializer::with_formatter(&mut writer, serde_json::ser::PrettyFormatter::with_indent(&indent));
+            file.serialize(&mut ser)?;
+        }
+        writer.flush()
+    }
+
+    /// The file's contents, as `write_json` streams them, for `save` to
+    /// check against `max_file_bytes` before writing
+    fn render_json(&self) -> std::io::Result<Vec<u8>> {
+        let mut json = Vec::new();
+        self.write_json(&mut json)?;
+        Ok(json)
+    }
+
+    /// `value` as JSON text, compact or indented per the save options
+    fn format_json(&self, value: &serde_json::Value) -> std::io::Result<Vec<u8>> {
+        let json = if self.save_options.canonical {
+            serde_json::to_vec(value)
+        } else {
+            let indent = vec![b' '; self.save_options.indent];
+            let mut buf = Vec::new();
+            let mut ser = serde_json::Serializer::with_formatter(
+                &mut buf,
+                serde_json::ser::PrettyFormatter::with_indent(&indent),
+            );
+            value.serialize(&mut ser).map(|_| buf)
+        }?;
 
-        let (table, (uuid, name)) = Self::extract_insert_data(&sql_fixed)?;
+        Ok(json)
+    }
 
-        let mut data: HashMap<String, Vec<HashMap<String, LocalDBValue>>> =
-            Self::load_json(&self.path)?;
+    /// Bytes the rows of `table` take up serialized as they are saved, to see
+    /// which tables a file's size goes to. In a pretty-printed file each of
+    /// the table's lines is also indented one level deeper than counted.
+    pub fn table_size(&self, table: &str) -> Result<usize> {
+        let rows = self.table_rows(table)?;
+        let json = serde_json::to_value(rows)
+            .map_err(std::io::Error::from)
+            .and_then(|value| self.format_json(&value))
+            .map_err(|e| LocalDBError::SqlError(format!("Cannot serialize table {}: {}", table, e)))?;
+
+        Ok(json.len())
+    }
 
-        if !data.contains_key(&table) {
-            data.insert(table.clone(), vec![]);
+    /// Write a copy of the database to `dest_path` and return a handle to it.
+    ///
+    /// The original is flushed first and stays untouched. The copy keeps the
+    /// indexes, comparison, save and table options and registered functions,
+    /// but not attachments or `on_change` listeners, and is writable even if the original isn't.
+    pub fn snapshot(&self, dest_path: &str) -> Result<LocalDB> {
+        if !self.read_only {
+            self.save()?;
         }
 
-        let mut row = HashMap::new();
-        row.insert("id".into(), LocalDBValue::UUID(uuid));
-        row.insert("name".into(), LocalDBValue::TEXT(name));
+        let copy = LocalDB {
+            path: dest_path.to_string(),
+            layout: self.layout,
+            stamp: Cell::new(None),
+            tables: self.tables.clone(),
+            schema: self.schema.clone(),
+            indexes: self.indexes.clone(),
+            attached: BTreeMap::new(),
+            coercion: self.coercion,
+            collation: self.collation,
+            save_options: self.save_options,
+            read_only: false,
+            listeners: Listeners::default(),
+            cache: RefCell::new(None),
+            limits: self.limits,
+            metrics: Metrics::default(),
+            strict_tables: self.strict_tables,
+            scans: ScanStats::default(),
+            functions: self.functions.clone(),
+            debounce: Debounce { wait: self.debounce.wait, ..Debounce::default() },
+        };
+        copy.tables.mark_all_dirty();
+        copy.save()?;
+
+        Ok(copy)
+    }
+
+    /// The backing file, `None` for a DB loaded from a reader
+    pub fn path(&self) -> Option<&str> {
+        (!self.path.is_empty()).then_some(self.path.as_str())
+    }
+
+    /// Move the backing file to `new_path` and keep saving there, as for a
+    /// "save as". Across filesystems the file is copied and the original
+    /// removed; a `create_dir` directory can only be moved within one. A DB
+    /// without a file is simply saved to `new_path`.
+    pub fn move_to(&mut self, new_path: &str) -> Result<()> {
+        self.check_writable()?;
 
-        data.get_mut(&table).unwrap().push(row);
+        let Some(old_path) = self.path().map(str::to_string) else {
+            self.path = new_path.to_string();
+            return self.save();
+        };
 
-        Self::save_json(&self.path, &data)?;
+        match fs::rename(&old_path, new_path) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::CrossesDevices && self.layout == Layout::File => {
+                fs::copy(&old_path, new_path).map_err(LocalDBError::io(new_path))?;
+                fs::remove_file(&old_path).map_err(LocalDBError::io(&old_path))?;
+            }
+            Err(e) => return Err(LocalDBError::io(&old_path)(e)),
+        }
 
+        self.path = new_path.to_string();
+        self.stamp.set(self.file_stamp());
         Ok(())
     }
 
-    // ========================= JSON HELPERS =============================
+    /// Re-read the backing file if something else changed it since this
+    /// handle last read or wrote it, going by its modification time and
+    /// size; returns whether it did. A process reading a database another
+    /// one writes can poll this to pick up the writes.
+    ///
+    /// This only detects staleness: there is no locking, so a file caught
+    /// mid-write may read as empty, and the next poll will see the change
+    /// again. Unsaved changes of this handle are lost on reload. Indexes
+    /// are kept and rebuilt; the query cache is dropped; no change events
+    /// are sent. A DB without a file never reloads.
+    pub fn reload_if_changed(&mut self) -> Result<bool> {
+        let Some(path) = self.path().map(str::to_string) else {
+            return Ok(false);
+        };
+        let stamp = self.file_stamp();
+        if stamp.is_none() || stamp == self.stamp.get() {
+            return Ok(false);
+        }
+
+        let mut fresh = match self.layout {
+            Layout::File => Self::open(&path)?,
+            Layout::Dir => Self::open_dir(&path)?,
+        };
+        self.tables = std::mem::take(&mut fresh.tables);
+        self.schema = std::mem::take(&mut fresh.schema);
 
-    fn load_json(path: &str) -> Result<HashMap<String, Vec<HashMap<String, LocalDBValue>>>> {
-        let content = fs::read_to_string(path)
-            .map_err(|e| LocalDBError::IoError(e.to_string()))?;
+        // the fresh PRIMARY KEY indexes, then this handle's own indexes
+        let mut indexes = std::mem::take(&mut fresh.indexes);
+        for mut index in std::mem::take(&mut self.indexes) {
+            if !indexes.iter().any(|i| i.name == index.name) {
+                index.rebuild(self.tables.get(&index.table).map(Vec::as_slice).unwrap_or_default());
+                indexes.push(index);
+            }
+        }
+        self.indexes = indexes;
+        self.clear_cache();
+        self.stamp.set(stamp);
 
-        let json = serde_json::from_str(&content).unwrap_or_default();
-        Ok(json)
+        Ok(true)
     }
 
-    fn save_json(
-        path: &str,
-        data: &HashMap<String, Vec<HashMap<String, LocalDBValue>>>
-    ) -> Result<()> {
-        let json = serde_json::to_string_pretty(&data)
-            .map_err(|e| LocalDBError::IoError(e.to_string()))?;
+    /// Current stamp of the backing file, `None` if it can't be read
+    fn file_stamp(&self) -> Option<FileStamp> {
+        let path = self.path()?;
+        let file = match self.layout {
+            Layout::File => std::path::PathBuf::from(path),
+            Layout::Dir => dir::meta_file(path),
+        };
+        let meta = fs::metadata(file).ok()?;
+        Some(FileStamp { modified: meta.modified().ok()?, len: meta.len() })
+    }
+
+    /// Flush the in-memory state to the backing file, if any
+    pub fn save(&self) -> Result<()> {
+        self.check_writable()?;
+
+        let Some(path) = self.path() else {
+            return Ok(());
+        };
+        if self.layout == Layout::Dir {
+            self.save_dir(path)?;
+            self.debounce.wrote();
+            return Ok(());
+        }
+        let started = self.metrics.start();
+
+        let json = self.render_json().map_err(LocalDBError::io(path))?;
+        if let Some(max) = self.limits.max_file_bytes {
+            if json.len() as u64 > max {
+                return Err(LocalDBError::LimitExceeded(format!(
+                    "saving would write {} bytes, over the {} byte limit",
+                    json.len(),
+                    max
+                )));
+            }
+        }
 
-        fs::write(path, json)
-            .map_err(|e| LocalDBError::IoError(e.to_string()))?;
+        let mut file = fs::File::create(path)
+            .map_err(LocalDBError::io(path))?;
+
+        file.write_all(&json).map_err(LocalDBError::io(path))?;
+        self.tables.take_dirty();
+        self.debounce.wrote();
+        self.stamp.set(self.file_stamp());
+        // counting rows parses tables `open_tables` left unread, so only when measured
+        let rows = started.map_or(0, |_| self.tables.values().map(Vec::len).sum());
+        self.metrics.record(Operation::Save, started, rows);
 
         Ok(())
     }
 
-    // ========================= PARSE HELPERS =============================
+    /// Report how long each successful `exec`, `query` and save takes, and
+    /// how many rows it touched, replacing any earlier sink. Without one no
+    /// timing is done.
+    pub fn set_metrics(&mut self, sink: impl Fn(OpMetrics) + Send + 'static) {
+        self.metrics.set(Box::new(sink));
+    }
+
+    /// Coalesce bursts of writes into fewer file writes: a change made
+    /// within `wait` of the last write to the file stays in memory, and
+    /// goes out with the first change made after that, an explicit `save`,
+    /// or when the database is dropped. `Duration::ZERO` turns it off, so
+    /// every change is written at once, as by default.
+    ///
+    /// Changes put off this way are lost if the process dies before they
+    /// are written, so up to `wait` of changes, or more if writes then
+    /// stop, until `save` or drop. A put-off change is not checked against
+    /// `max_file_bytes` until it is written, and change events are
+    /// delivered before it is.
+    pub fn set_flush_debounce(&mut self, wait: Duration) {
+        self.debounce.wait = (!wait.is_zero()).then_some(wait);
+    }
+
+    /// Guard against runaway growth; see [`Limits`]
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    /// Make a query fail with `ScanLimitExceeded` rather than examine more
+    /// than `rows` rows, counted as `explain_analyze` counts them, across
+    /// the query's subqueries and joins; `0` turns the limit off. Applies to
+    /// SELECTs through `query`, `query_result`, `find_by` and `select`, and
+    /// to tables of attached databases they read.
+    pub fn set_scan_limit(&mut self, rows: usize) {
+        self.scans.limit = (rows > 0).then_some(rows);
+    }
 
-    fn extract_table_name_from_create(sql: &str) -> Result<String> {
-        let parts: Vec<&str> = sql.split_whitespace().collect();
-        Ok(parts[2].to_string())
+    /// Run a read under this database's scan limit, if it has one
+    fn scan_limited<T>(&self, run: impl FnOnce() -> Result<T>) -> Result<T> {
+        let dbs = || std::iter::once(self).chain(self.attached.values());
+        dbs().for_each(|db| db.scans.budget.set(self.scans.limit));
+        let result = run();
+        dbs().for_each(|db| db.scans.budget.set(None));
+        result
     }
 
-    fn extract_table_name_from_select(sql: &str) -> Result<String> {
-        let parts: Vec<&str> = sql.split_whitespace().collect();
+    /// Checkpoint to undo the next change with if it outgrows `max_file_bytes`
+    fn size_checkpoint(&self) -> Option<Checkpoint> {
+        self.limits.max_file_bytes.map(|_| self.checkpoint())
+    }
 
-        if parts.len() < 4 {
-            return Err(LocalDBError::SqlError("Invalid SELECT syntax".into()));
+    /// `save_and_notify`, undoing the change back to `checkpoint` if the
+    /// file would grow past its size limit
+    fn save_or_undo(&mut self, checkpoint: Option<Checkpoint>) -> Result<()> {
+        match (self.save_and_notify(), checkpoint) {
+            (Err(e @ LocalDBError::LimitExceeded(_)), Some(checkpoint)) => {
+                self.restore(checkpoint);
+                Err(e)
+            }
+            (result, _) => result,
         }
+    }
+
+    /// Make `func` callable from SQL as `name(arg, ...)`, in SELECT (its
+    /// projection, WHERE, JOIN ON, GROUP BY, HAVING and ORDER BY, and
+    /// also under INSERT ... SELECT) and in the WHERE and SET of UPDATE and DELETE.
+    /// Names are case-insensitive; registering a name again replaces the
+    /// function, and built-in names such as `UPPER` can't be taken.
+    ///
+    /// The function gets the evaluated arguments, as many as the call
+    /// passed: it does its own arity and type checks. The first error it
+    /// returns fails the statement with that same error, before any write.
+    /// Calls are also rejected in RETURNING and index filters, which run
+    /// apart from the statement's checks. A call to a name that isn't
+    /// registered fails with "Unknown function". The query cache keys on the
+    /// SQL text, so it should stay off for functions whose results change.
+    pub fn register_function(
+        &mut self,
+        name: &str,
+        func: impl Fn(&[LocalDBValue]) -> Result<LocalDBValue> + Send + Sync + 'static,
+    ) -> Result<()> {
+        self.functions.insert(name, std::sync::Arc::new(func))?;
+        self.clear_cache();
+        Ok(())
+    }
+
+    /// Resolve the user function calls `walk` visits, returning where their
+    /// errors go
+    fn bind_calls(&self, walk: impl FnOnce(&mut dyn FnMut(&mut Expr) -> Result<()>) -> Result<()>) -> Result<Failure> {
+        let failure = Failure::default();
+        walk(&mut |expr| self.functions.bind(expr, &failure))?;
+        Ok(failure)
+    }
 
-        Ok(parts[3].replace(";", ""))
+    /// Call `listener` for every insert, update and delete, once the change
+    /// has been saved.
+    ///
+    /// Listeners run synchronously on the thread that made the change, in
+    /// registration order, before the mutating call returns. They must be
+    /// `Send` so the database stays `Send`; they are never called
+    /// concurrently, since every change needs `&mut LocalDB`.
+    pub fn on_change(&mut self, listener: impl FnMut(ChangeEvent) + Send + 'static) {
+        self.listeners.add(Box::new(listener));
     }
 
-    fn extract_insert_data(sql: &str) -> Result<(String, (String, String))> {
-        let parts: Vec<&str> = sql.split_whitespace().collect();
-        let table = parts[2].to_string();
+    /// Save, then deliver the pending change events if that succeeded; a
+    /// debounced save is put off, delivering them right away
+    fn save_and_notify(&mut self) -> Result<()> {
+        if self.path().is_some() && self.debounce.defer() {
+            self.listeners.fire();
+            return Ok(());
+        }
+        if let Err(e) = self.save() {
+            self.listeners.discard();
+            return Err(e);
+        }
 
-        let start = sql.find("(").unwrap() + 1;
-        let end = sql.find(")").unwrap();
-        let params = &sql[start..end];
+        self.listeners.fire();
+        Ok(())
+    }
 
-        let list: Vec<&str> = params.split(",").collect();
+    /// Queue an insert event for the rows of `table` from position `from` on
+    fn record_inserts(&mut self, table: &str, from: usize) {
+        if !self.listeners.active() {
+            return;
+        }
 
-        let uuid_raw = list[0].trim().replace("'", "");
-        let name_raw = list[1].trim().replace("'", "");
+        let rows = self.tables.get(table).map_or_else(Vec::new, |rows| rows[from..].to_vec());
+        self.listeners.push(ChangeEvent { kind: ChangeKind::Insert, table: table.to_string(), rows });
+    }
 
-        Ok((table, (uuid_raw, name_raw)))
+    /// Add SQL lines into a single block
+    pub fn add_lines(&self, lines: [&str; 2]) -> String {
+        let mut out = String::new();
+        for l in lines {
+            out.push_str(l);
+            out.push('\n');
+        }
+        out
     }
+
+    /// Execute SQL statements
+    pub fn exec(&mut self, sql: String) -> Result<()> {
+        self.exec_returning(sql).map(drop)
+    }
+
+    /// Execute exactly one statement, returning how many rows it inserted,
+    /// updated or deleted. Input holding more than one (or none) is
+    /// rejected before anything runs, so SQL from users can't smuggle in a
+    /// second statement after a `;`; an empty statement, such as a
+    /// trailing `;`, doesn't count.
+    pub fn exec_one(&mut self, sql: &str) -> Result<usize> {
+        self.check_writable()?;
+        let stmts: Vec<&str> = statements(sql).collect();
+        let [stmt] = stmts[..] else {
+            return Err(LocalDBError::SqlError(format!("exec_one takes one statement, got {}", stmts.len())));
+        };
+
+        let started = self.metrics.start();
+        let checkpoint = self.size_checkpoint();
+        let outcome = self.run_statement(stmt)?;
+        self.save_or_undo(checkpoint)?;
+        self.metrics.record(Operation::Exec, started, outcome.affected);
+        Ok(outcome.affected)
+    }
+
+    /// Execute SQL statements like `exec`, returning the rows produced by
+    /// their `RETURNING` clauses, in statement order.
+    ///
+    /// `INSERT`, `UPDATE` and `DELETE` accept `RETURNING *` or a projection
+    /// list; they return the inserted rows, the rows as updated and the
+    /// deleted rows respectively.
+    pub fn exec_returning(&mut self, sql: String) -> Result<Vec<Row>> {
+        self.check_writable()?;
+        let started = self.metrics.start();
+
+        let (mut returned, mut affected) = (Vec::new(), 0);
+        for stmt in statements(&sql) {
+            let checkpoint = self.size_checkpoint();
+            let outcome = self.run_statement(stmt)?;
+            self.save_or_undo(checkpoint)?;
+            affected += outcome.affected;
+            returned.extend(outcome.returning.into_iter().flatten());
+        }
+
+        self.metrics.record(Operation::Exec, started, affected);
+        Ok(returned)
+    }
+
+    /// Check that `exec` would accept the statements, constraint checks
+    /// against the current data included, without changing anything: they
+    /// run in turn as a transaction would and are then undone, so nothing is
+    /// saved and no change events are delivered. The size limit on saving
+    /// is not checked, as no file is written.
+    pub fn exec_dry_run(&mut self, sql: &str) -> Result<()> {
+        self.check_writable()?;
+
+        let (checkpoint, dirty) = (self.checkpoint(), self.tables.take_dirty());
+        let result = statements(sql).try_for_each(|stmt| self.run_statement(stmt).map(drop));
+        self.restore(checkpoint);
+        // the undone changes don't need saving
+        self.tables.take_dirty();
+        self.tables.mark_dirty(dirty);
+        result
+    }
+
+    /// Apply every migration whose version hasn't been applied yet, in
+    /// version order, returning how many ran.
+    ///
+    /// Applied versions are recorded in the `schema_migrations` table. Each
+    /// migration is all-or-nothing: if one of its statements fails, the
+    /// database is left as the previous migration left it.
+    pub fn migrate(&mut self, migrations: &[Migration]) -> Result<usize> {
+        self.check_writable()?;
+
+        let mut pending: Vec<&Migration> = migrations.iter().collect();
+        pending.sort_by_key(|m| m.version);
+        if let Some(pair) = pending.windows(2).find(|pair| pair[0].version == pair[1].version) {
+            return Err(LocalDBError::SqlError(format!("Duplicate migration version: {}", pair[0].version)));
+        }
+
+        let mut def = TableDef::new(MIGRATIONS_TABLE);
+        def.columns.push(ColumnDef { primary_key: true, ..ColumnDef::new("version", ColType::Int) });
+        self.define_table(def)?;
+
+        let applied: Vec<i64> = self
+            .table_rows(MIGRATIONS_TABLE)?
+            .iter()
+            .filter_map(|row| match row.get("version") {
+                Some(LocalDBValue::INT(v)) => Some(*v),
+                _ => None,
+            })
+            .collect();
+        pending.retain(|m| !applied.contains(&m.version));
+
+        for migration in &pending {
+            let checkpoint = self.checkpoint();
+
+            let result = statements(migration.up_sql).try_for_each(|stmt| self.run_statement(stmt).map(drop)).and_then(|_| {
+                let row = Row::from([("version".to_string(), LocalDBValue::INT(migration.version))]);
+                self.insert_row(MIGRATIONS_TABLE, row)
+            });
+
+            if let Err(e) = result {
+                self.restore(checkpoint);
+                return Err(e);
+            }
+
+            self.save_or_undo(Some(checkpoint))?;
+        }
+
+        Ok(pending.len())
+    }
+
+    /// Start a transaction: its changes stay in memory until
+    /// [`Transaction::commit`] saves them all at once, and are undone if it
+    /// is rolled back or dropped.
+    pub fn transaction(&mut self) -> Result<Transaction<'_>> {
+        self.check_writable()?;
+        Ok(Transaction::new(self))
+    }
+
+    /// Run `f` in a transaction: committed if it returns `Ok`, with its value
+    /// returned, and rolled back if it returns `Err`.
+    ///
+    /// If `f` panics, the transaction is rolled back before the panic carries
+    /// on, so no half-made change stays in memory or reaches the file.
+    pub fn with_transaction<T>(&mut self, f: impl FnOnce(&mut Transaction<'_>) -> Result<T>) -> Result<T> {
+        let mut tx = self.transaction()?;
+
+        match std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| f(&mut tx))) {
+            Ok(Ok(value)) => tx.commit().map(|_| value),
+            Ok(Err(e)) => {
+                tx.rollback();
+                Err(e)
+            }
+            Err(panic) => {
+                tx.rollback();
+                std::panic::resume_unwind(panic)
+            }
+        }
+    }
+
+    fn run_statement(&mut self, stmt: &str) -> Result<Outcome> {
+        if stmt.starts_with("CREATE TABLE") {
+            self.handle_create_table(stmt).map(|_| Outcome::default())
+        } else if stmt.starts_with("CREATE INDEX") || stmt.starts_with("CREATE FULLTEXT INDEX") {
+            self.handle_create_index(stmt).map(|_| Outcome::default())
+        } else if stmt.starts_with("COMMENT ON") {
+            self.handle_comment(stmt).map(|_| Outcome::default())
+        } else if stmt.starts_with("INSERT INTO") || stmt.starts_with("INSET INTO") {
+            self.handle_insert(stmt)
+        } else if stmt.starts_with("DELETE FROM") {
+            self.handle_delete(stmt)
+        } else if stmt.starts_with("UPDATE") {
+            self.handle_update(stmt)
+        } else {
+            Err(LocalDBError::Parse(format!("Unsupported SQL: {}", stmt)))
+        }
+    }
+
+    /// Copy of the in-memory state, for undoing a failed multi-statement change
+    fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            tables: self.tables.clone(),
+            schema: self.schema.clone(),
+            indexes: self.indexes.clone(),
+            events: self.listeners.pending(),
+        }
+    }
+
+    fn restore(&mut self, checkpoint: Checkpoint) {
+        // tables changed since the checkpoint still differ from the last save
+        let dirty = self.tables.take_dirty();
+        self.tables = checkpoint.tables;
+        self.tables.mark_dirty(dirty);
+        self.schema = checkpoint.schema;
+        self.indexes = checkpoint.indexes;
+        self.listeners.truncate(checkpoint.events);
+        self.clear_cache();
+    }
+
+    /// SELECT * | columns FROM table [WHERE column op value] [ORDER BY expr [DESC], ...] [LIMIT n];
+    ///
+    /// Supported operators: `=`, `!=`, `<`, `<=`, `>`, `>=`, `BETWEEN`, `LIKE`
+    /// and the case-insensitive `ILIKE`. Range and equality predicates on an
+    /// indexed column are answered from the index instead of a full scan.
+    ///
+    /// Projections may compute values with `+`, `-`, `*` and `/` over columns
+    /// and literals, optionally named with `AS`. `COUNT`, `SUM`, `AVG`, `MIN`
+    /// and `MAX` fold all matching rows into one result row.
+    /// `COALESCE(expr, ...)` picks its first non-NULL argument, in projections
+    /// and as the left side of a WHERE comparison.
+    ///
+    /// WHERE accepts `column [NOT] IN (values)`, `column [NOT] IN (SELECT ...)`
+    /// and `column op (SELECT ...)`; subqueries run once, before the outer
+    /// query, and must select a single column.
+    ///
+    /// `SELECT EXISTS(SELECT ...)` returns a single row with a BOOL `exists`
+    /// column and stops at the first matching row. `SELECT COUNT(*)` returns
+    /// a single `COUNT(*)` row; without WHERE it does not scan the table.
+    ///
+    /// Without ORDER BY, rows come back in insertion order, whether or not
+    /// an index was used; joined rows follow the FROM table's order, then
+    /// each joined table's, and GROUP BY groups the order of their first
+    /// row. Rows are maps with no column order; `query_result` has one.
+    pub fn query(&self, sql: &str) -> Result<Vec<Row>> {
+        let started = self.metrics.start();
+        let rows = self.cached_query(sql)?;
+        self.metrics.record(Operation::Query, started, rows.len());
+
+        Ok(rows)
+    }
+
+    /// Like `query`, but rows come back newest first: the reverse of the
+    /// order `query` gives without ORDER BY, with LIMIT and OFFSET counted
+    /// from the newest row. For a table without a stored `ROWID` column this
+    /// is `ORDER BY ROWID DESC` without the sort. The query must not have
+    /// its own ORDER BY.
+    pub fn query_rev(&self, sql: &str) -> Result<Vec<Row>> {
+        let started = self.metrics.start();
+        let mut query = parse_select_query(sql)?;
+        let (limit, offset) = match &mut query {
+            Query::Select(select) if !select.order_by.is_empty() => {
+                return Err(LocalDBError::SqlError("query_rev doesn't take ORDER BY".into()));
+            }
+            Query::Select(select) => (select.limit.take(), select.offset.take()),
+            _ => (None, None),
+        };
+
+        let mut rows = self.run_query(&query)?;
+        rows.reverse();
+        let rows: Vec<Row> = rows.into_iter().skip(offset.unwrap_or(0)).take(limit.unwrap_or(usize::MAX)).collect();
+        self.metrics.record(Operation::Query, started, rows.len());
+
+        Ok(rows)
+    }
+
+    /// Like `query`, but every row has every column of `defaults`: a column
+    /// missing from a row is filled in with its default, so rows of
+    /// schemaless tables come back uniform. Present values, NULL included,
+    /// are kept.
+    pub fn query_with_defaults(&self, sql: &str, defaults: &HashMap<String, LocalDBValue>) -> Result<Vec<Row>> {
+        let mut rows = self.query(sql)?;
+        for row in &mut rows {
+            for (column, value) in defaults {
+                row.entry(column.clone()).or_insert_with(|| value.clone());
+            }
+        }
+        Ok(rows)
+    }
+
+    /// Like `query`, but each row is a JSON object of its columns, ready to
+    /// serialize: INT and TIMESTAMP (milliseconds) become integers, REAL a
+    /// number, TEXT and UUID strings, DECIMAL a string keeping its digits,
+    /// BOOL a boolean, ARRAY an array and NULL null; JSON values are nested
+    /// as they are.
+    pub fn query_json(&self, sql: &str) -> Result<Vec<serde_json::Value>> {
+        let rows = self.query(sql)?;
+        Ok(rows.iter().map(|row| row.iter().map(|(column, value)| (column.clone(), plain_json(value))).collect()).collect())
+    }
+
+    /// Every row of `table` with only the `columns` values, in insertion
+    /// order; a row lacking one of them simply doesn't have it.
+    ///
+    /// Other columns are dropped as early as possible. For a table not yet
+    /// read, as with `open_tables`, only the wanted values are parsed from
+    /// the file, so large JSON or TEXT values in other columns are never
+    /// built, and the table stays unread. Tables already in memory are
+    /// copied without the other columns.
+    pub fn query_columns(&self, table: &str, columns: &[&str]) -> Result<Vec<Row>> {
+        let started = self.metrics.start();
+        if let Some(def) = self.schema.get(table) {
+            if let Some(missing) = columns.iter().find(|c| def.column(c).is_none()) {
+                return Err(LocalDBError::ColumnNotFound(format!("{}.{}", table, missing)));
+            }
+        }
+
+        let rows = self.tables.project(table, columns).ok_or_else(|| LocalDBError::TableNotFound(table.to_string()))?;
+        self.metrics.record(Operation::Query, started, rows.len());
+        Ok(rows)
+    }
+
+    /// The rows of `table` with a `ROWID` above `since`, each with its
+    /// `ROWID`, in insertion order: for a sync client that keeps the highest
+    /// `ROWID` it has seen and asks only for rows added after it. `0` gives
+    /// every row. A table with a stored `ROWID` column, such as a TIMESTAMP
+    /// or a sequence number the application assigns, is filtered on that
+    /// column's values instead.
+    ///
+    /// Only sound while rows are only ever appended: updates change rows in
+    /// place and keep their `ROWID`, so an updated row isn't returned again,
+    /// and a delete renumbers the rows after it, so rows added later can
+    /// get `ROWID`s a client has already seen. Stored `ROWID`s are never
+    /// renumbered, but an update is still only seen if it raises the value.
+    pub fn changes_since(&self, table: &str, since: i64) -> Result<Vec<Row>> {
+        let started = self.metrics.start();
+        let rows = self.tables.get(table).ok_or_else(|| LocalDBError::TableNotFound(table.to_string()))?;
+
+        let changed: Vec<Row> = if self.virtual_rowid(table) {
+            let start = usize::try_from(since).unwrap_or(0).min(rows.len());
+            rows[start..].iter().enumerate().map(|(i, row)| with_rowid(row, start + i)).collect()
+        } else {
+            let since = LocalDBValue::INT(since);
+            rows.iter()
+                .filter(|row| {
+                    let id = row.get(sql::ROWID);
+                    id.and_then(|id| sql::compare_values(id, &since, Comparison::STRICT)) == Some(Ordering::Greater)
+                })
+                .cloned()
+                .collect()
+        };
+        self.metrics.record(Operation::Query, started, changed.len());
+        Ok(changed)
+    }
+
+    /// Answer `sql` from the query cache if enabled, filling it on a miss
+    fn cached_query(&self, sql: &str) -> Result<Vec<Row>> {
+        if self.cache.borrow().is_none() {
+            return self.run_query(&parse_select_query(sql)?);
+        }
+
+        let tokens = sql::tokenize(sql)?;
+        // a cached answer would keep the time it was computed at
+        if sql::calls_now(&tokens) {
+            return self.run_query(&parse_select_query(sql)?);
+        }
+        if let Some(rows) = self.cache.borrow_mut().as_mut().and_then(|cache| cache.get(&tokens)) {
+            return Ok(rows);
+        }
+
+        let query = parse_select_query(sql)?;
+        let rows = self.run_query(&query)?;
+        if let Some(cache) = self.cache.borrow_mut().as_mut() {
+            cache.put(tokens, query.select().tables(), rows.clone());
+        }
+
+        Ok(rows)
+    }
+
+    /// Cache the results of up to `capacity` distinct `query` calls, most
+    /// recently used first; `0` turns the cache off.
+    ///
+    /// Queries are keyed by their tokens, so whitespace doesn't matter but
+    /// letter case does. Any write to a table drops every cached result that
+    /// read it, so cached results are never stale.
+    pub fn enable_query_cache(&mut self, capacity: usize) {
+        *self.cache.get_mut() = (capacity > 0).then(|| QueryCache::new(capacity));
+    }
+
+    /// Drop cached results that read `table`, after it changed
+    fn invalidate_cache(&mut self, table: &str) {
+        if let Some(cache) = self.cache.get_mut() {
+            cache.invalidate(table);
+        }
+    }
+
+    fn clear_cache(&mut self) {
+        if let Some(cache) = self.cache.get_mut() {
+            cache.clear();
+        }
+    }
+
+    /// Like `query`, but also returns the result's column names in order:
+    /// projection order for explicit columns, otherwise the table's declared
+    /// columns followed by any other columns present, sorted.
+    pub fn query_result(&self, sql: &str) -> Result<QueryResult> {
+        let started = self.metrics.start();
+        let query = parse_select_query(sql)?;
+        let rows = self.run_query(&query)?;
+
+        let columns = match &query {
+            Query::Select(Select { columns: Some(items), .. }) if !items.iter().any(|c| matches!(c.expr, Expr::All)) => {
+                items.iter().map(|c| c.name.clone()).collect()
+            }
+            Query::Select(select @ Select { columns: Some(items), .. }) => {
+                let star = self.star_columns(select, &rows)?;
+                let mut columns: Vec<String> = Vec::new();
+                for name in items.iter().flat_map(|c| if matches!(c.expr, Expr::All) { star.clone() } else { vec![c.name.clone()] }) {
+                    if !columns.contains(&name) {
+                        columns.push(name);
+                    }
+                }
+                columns
+            }
+            Query::Select(select) => self.star_columns(select, &rows)?,
+            Query::Exists(_) => vec!["exists".to_string()],
+            Query::Count(_) => vec!["COUNT(*)".to_string()],
+        };
+
+        self.metrics.record(Operation::Query, started, rows.len());
+        Ok(QueryResult { columns, rows })
+    }
+
+    /// Like `query_result`, but the columns are exactly `shape`, in that
+    /// order: every row gets each of them, NULL where the query gave it no
+    /// value, and loses any other column. For callers that need a fixed
+    /// layout whatever the rows hold; rename columns with `AS` in the SQL.
+    pub fn query_shaped(&self, sql: &str, shape: &[&str]) -> Result<QueryResult> {
+        if let Some(column) = shape.iter().enumerate().find_map(|(i, c)| shape[..i].contains(c).then_some(c)) {
+            return Err(LocalDBError::SqlError(format!("Column {} is in the shape twice", column)));
+        }
+
+        let rows = self
+            .query(sql)?
+            .into_iter()
+            .map(|mut row| {
+                shape.iter().map(|c| (c.to_string(), row.remove(*c).unwrap_or(LocalDBValue::NULL))).collect()
+            })
+            .collect();
+        Ok(QueryResult { columns: shape.iter().map(|c| c.to_string()).collect(), rows })
+    }
+
+    /// `query_result(sql)` rendered with `QueryResult::to_html`, for
+    /// looking at results in a browser
+    pub fn query_html(&self, sql: &str) -> Result<String> {
+        Ok(self.query_result(sql)?.to_html())
+    }
+
+    /// Run a SELECT and write its rows to `writer` as CSV with
+    /// `QueryResult::write_csv`, line by line rather than as one string
+    pub fn query_csv_to(&self, sql: &str, writer: impl Write) -> Result<()> {
+        self.query_result(sql)?.write_csv(writer).map_err(LocalDBError::io("<writer>"))
+    }
+
+    /// Columns `*` stands for in `select`, whose result is `rows`
+    fn star_columns(&self, select: &Select, rows: &[Row]) -> Result<Vec<String>> {
+        if !select.joins.is_empty() {
+            let sources = self.join_sources(select)?;
+            let natural = natural_columns(select, &sources)?;
+            let qualified = sources
+                .into_iter()
+                .flat_map(|(qualifier, columns, _)| columns.into_iter().map(move |c| format!("{}.{}", qualifier, c)))
+                .filter(|c| natural.shown(c));
+            return Ok(natural.merged.iter().map(|(c, _)| c.clone()).chain(qualified).collect());
+        }
+
+        let (db, table) = self.resolve_table(&select.table)?;
+        let mut columns = db.column_order(table, rows);
+        if db.virtual_rowid(table) {
+            columns.retain(|c| c != sql::ROWID);
+        }
+        Ok(columns)
+    }
+
+    /// Declared columns of `table`, then the other columns found in `rows`, sorted
+    fn column_order(&self, table: &str, rows: &[Row]) -> Vec<String> {
+        let mut columns: Vec<String> = self
+            .schema
+            .get(table)
+            .map(|def| def.columns.iter().map(|c| c.name.clone()).collect())
+            .unwrap_or_default();
+
+        let mut extra: Vec<&String> = rows
+            .iter()
+            .flat_map(|row| row.keys())
+            .filter(|c| !columns.contains(c))
+            .collect();
+        extra.sort();
+        extra.dedup();
+
+        columns.extend(extra.into_iter().cloned());
+        columns
+    }
+
+    fn run_query(&self, query: &Query) -> Result<Vec<Row>> {
+        let mut query = query.clone();
+        let failure = self.bind_calls(|bind| query.select_mut().walk_exprs_mut(bind))?;
+        failure.check(self.scan_limited(|| self.execute_query(&query)))
+    }
+
+    fn execute_query(&self, query: &Query) -> Result<Vec<Row>> {
+        match query {
+            Query::Select(select) => self.run_select(select),
+            Query::Exists(select) if !select.joins.is_empty() => {
+                let exists = !self.run_select(select)?.is_empty();
+                Ok(vec![Row::from([("exists".to_string(), LocalDBValue::BOOL(exists))])])
+            }
+            Query::Exists(select) => {
+                let (db, table) = self.resolve_table(&select.table)?;
+                let filter = self.bind_subqueries(select.filter.as_ref())?;
+                let exists = db.any_row(table, filter.as_ref())?;
+
+                Ok(vec![Row::from([("exists".to_string(), LocalDBValue::BOOL(exists))])])
+            }
+            Query::Count(select) => {
+                let (db, table) = self.resolve_table(&select.table)?;
+                let count = match &self.bind_subqueries(select.filter.as_ref())? {
+                    Some(filter) => db.filter_rows(table, Some(filter))?.len(),
+                    None => db.tables.get(table).map_or(0, Vec::len),
+                };
+
+                Ok(vec![Row::from([("COUNT(*)".to_string(), LocalDBValue::INT(count as i64))])])
+            }
+        }
+    }
+
+    /// Declared definition of `table`, including comments, if it has one
+    pub fn schema(&self, table: &str) -> Option<&TableDef> {
+        self.schema.get(table)
+    }
+
+    /// Check that every `expected` table is declared with matching columns,
+    /// types and constraints; extra stored columns are allowed. Tables not
+    /// listed are not checked.
+    pub fn assert_schema(&self, expected: &[TableDef]) -> Result<()> {
+        self.assert_schema_with(expected, SchemaCheck::AllowExtra)
+    }
+
+    /// `assert_schema` with a choice of how to treat extra columns. Every
+    /// difference found is listed in the error.
+    pub fn assert_schema_with(&self, expected: &[TableDef], check: SchemaCheck) -> Result<()> {
+        let problems: Vec<String> = expected
+            .iter()
+            .flat_map(|def| match self.schema.get(&def.name) {
+                Some(stored) => def.drift(stored, check),
+                None => vec![format!("missing table {}", def.name)],
+            })
+            .collect();
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(LocalDBError::SchemaMismatch(problems.join("; ")))
+        }
+    }
+
+    /// Number of rows in `table`, without scanning it
+    pub fn count(&self, table: &str) -> Result<usize> {
+        self.tables
+            .get(table)
+            .map(Vec::len)
+            .ok_or_else(|| LocalDBError::TableNotFound(table.to_string()))
+    }
+
+    /// Every column name found in any row of `table`, sorted; unlike
+    /// `schema`, this works for tables never declared with `CREATE TABLE`
+    pub fn columns(&self, table: &str) -> Result<Vec<String>> {
+        let mut columns: Vec<String> = self.table_rows(table)?.iter().flat_map(|row| row.keys().cloned()).collect();
+        columns.sort();
+        columns.dedup();
+
+        Ok(columns)
+    }
+
+    /// Describe how a SELECT would run, without executing it
+    pub fn explain(&self, sql: &str) -> Result<String> {
+        let (select, kind) = match Parser::new(sql.trim())?.parse_query()? {
+            Query::Select(select) => (select, "SELECT"),
+            Query::Exists(select) => (select, "EXISTS"),
+            Query::Count(select) => (select, "COUNT"),
+        };
+
+        let (db, table) = self.resolve_table(&select.table)?;
+        let mut plan = vec![format!("{} {}", kind, select.table)];
+
+        let indexed = select.filter.as_ref().filter(|_| select.joins.is_empty()).and_then(|f| db.choose_index(table, f));
+        let ordered = (kind == "SELECT" && select.joins.is_empty())
+            .then(|| db.order_index(table, &select, select.filter.as_ref()))
+            .flatten();
+        let access = match (indexed, ordered) {
+            (_, Some(index)) => format!("index {} ({}) in ORDER BY order, stopping after LIMIT", index.name, index.column),
+            (Some(index), _) => match &index.filter {
+                Some(filter) => format!("index {} ({}) WHERE {}", index.name, index.column, filter),
+                None => format!("index {} ({})", index.name, index.column),
+            },
+            (None, _) if kind == "COUNT" && select.filter.is_none() => "row count".to_string(),
+            (None, _) => "full scan".to_string(),
+        };
+        plan.push(format!("access: {}", access));
+
+        for join in &select.joins {
+            plan.push(format!("join: {} (nested loop)", join));
+        }
+
+        if let Some(filter) = &select.filter {
+            plan.push(format!("filter: {}", filter));
+        }
+
+        if kind == "EXISTS" {
+            plan.push("stop: first match".to_string());
+        } else if kind == "SELECT" {
+            let columns = match &select.columns {
+                Some(columns) => columns.iter().map(|c| c.to_string()).collect::<Vec<_>>().join(", "),
+                None => "*".to_string(),
+            };
+            plan.push(format!("columns: {}", columns));
+
+            if !select.distinct_on.is_empty() {
+                let keys: Vec<String> = select.distinct_on.iter().map(|e| e.to_string()).collect();
+                plan.push(format!("distinct on: {}", keys.join(", ")));
+            }
+
+            if let Some(limit) = select.limit {
+                plan.push(format!("limit: {}", limit));
+            }
+            if let Some(offset) = select.offset {
+                plan.push(format!("offset: {}", offset));
+            }
+        }
+
+        Ok(plan.join("\n"))
+    }
+
+    /// Run a SELECT and report how it actually went: the `explain` plan,
+    /// then the rows scanned and returned, the indexes used and the time
+    /// taken. The query cache is bypassed.
+    pub fn explain_analyze(&self, sql: &str) -> Result<String> {
+        let mut plan = self.explain(sql)?;
+        let query = parse_select_query(sql)?;
+
+        let dbs = || std::iter::once(self).chain(self.attached.values());
+        dbs().for_each(|db| db.scans.reset());
+        let started = std::time::Instant::now();
+        let rows = self.run_query(&query)?;
+        let elapsed = started.elapsed();
+
+        let scanned: usize = dbs().map(|db| db.scans.rows.get()).sum();
+        let mut indexes: Vec<String> = dbs().flat_map(|db| db.scans.indexes.take()).collect();
+        indexes.dedup();
+        let indexes = if indexes.is_empty() { "none".to_string() } else { indexes.join(", ") };
+
+        plan.push_str(&format!(
+            "\nrows scanned: {}\nrows returned: {}\nindexes used: {}\nelapsed: {:?}",
+            scanned,
+            rows.len(),
+            indexes,
+            elapsed
+        ));
+        Ok(plan)
+    }
+
+    /// Start defining `table` programmatically, an alternative to `CREATE TABLE`
+    pub fn create_table(&mut self, table: &str) -> TableBuilder<'_> {
+        TableBuilder::new(self, table)
+    }
+
+    /// Start a fluent SELECT on `table`, an alternative to SQL strings
+    pub fn select(&self, table: &str) -> SelectBuilder<'_> {
+        SelectBuilder::new(self, table)
+    }
+
+    fn run_select(&self, select: &Select) -> Result<Vec<Row>> {
+        let joined: Vec<Row>;
+        let mut ordered = false;
+        let mut numbered = false;
+        let rows = if select.joins.is_empty() {
+            let (db, table) = self.resolve_table(&select.table)?;
+            let filter = self.bind_subqueries(select.filter.as_ref())?;
+            numbered = select.reads_rowid() && db.virtual_rowid(table);
+            if numbered {
+                let rows = db.tables.get(table).map(Vec::as_slice).unwrap_or_default();
+                let positions = db.first_positions(table, filter.as_ref(), select.stop_after().unwrap_or(usize::MAX))?;
+                joined = positions.into_iter().map(|pos| with_rowid(&rows[pos], pos)).collect();
+                joined.iter().collect()
+            } else {
+                match db.order_index(table, select, filter.as_ref()) {
+                    Some(index) => {
+                        ordered = true;
+                        db.top_rows(table, index, select, filter.as_ref())?
+                    }
+                    None => db.first_rows(table, filter.as_ref(), select.stop_after().unwrap_or(usize::MAX))?,
+                }
+            }
+        } else {
+            joined = self.joined_rows(select)?;
+            let filter = self.bind_subqueries(select.filter.as_ref())?;
+            joined.iter().filter(|row| filter.as_ref().is_none_or(|f| f.matches(row, self.comparison()))).collect()
+        };
+
+        if select.is_aggregate() {
+            if !select.distinct_on.is_empty() {
+                return Err(LocalDBError::SqlError("DISTINCT ON cannot be used with GROUP BY or aggregates".into()));
+            }
+            return self.run_grouped(select, rows);
+        }
+
+        // the first row, in result order, of each DISTINCT ON group
+        let mut seen = BTreeSet::new();
+        let mut first_of_group = |row: &Row| {
+            select.distinct_on.is_empty() || seen.insert(select.distinct_on.iter().map(|e| IndexKey(e.eval(row))).collect::<Vec<_>>())
+        };
+
+        // joined rows also carry unqualified names; * shows only qualified
+        // ones, and those NATURAL JOINs merged
+        let natural = match select.joins.iter().any(|join| join.on.is_none()) {
+            true => natural_columns(select, &self.join_sources(select)?)?,
+            false => NaturalColumns::default(),
+        };
+        let shown = |column: &str| if select.joins.is_empty() { !numbered || column != sql::ROWID } else { natural.shown(column) };
+        let project = |row: &Row| -> Row {
+            match &select.columns {
+                Some(columns) => sql::project(columns, row, shown),
+                None if select.joins.is_empty() && !numbered => row.clone(),
+                None => row.iter().filter(|(k, _)| shown(k)).map(|(k, v)| (k.clone(), v.clone())).collect(),
+            }
+        };
+
+        if select.order_by.is_empty() || ordered {
+            return Ok(select.page(rows.into_iter().filter(|row| first_of_group(row))).map(project).collect());
+        }
+
+        if let Some(item) = select.order_by.iter().find(|item| item.expr.has_aggregate()) {
+            return Err(LocalDBError::SqlError(format!("Cannot ORDER BY {} in a query without aggregates", item.expr)));
+        }
+
+        let mut keyed: Vec<(Vec<IndexKey>, &Row, Row)> = rows
+            .into_iter()
+            .map(|row| {
+                let projected = project(row);
+                (select.order_by.iter().map(|item| item.key(row, &projected)).collect(), row, projected)
+            })
+            .collect();
+        // rows arrive in insertion order, even from an index; the stable sort keeps ties that way
+        keyed.sort_by(|(a, _, _), (b, _, _)| sql::compare_keys(&select.order_by, a, b, self.collation));
+
+        let distinct = keyed.into_iter().filter(|(_, row, _)| first_of_group(row));
+        Ok(select.page(distinct).map(|(_, _, projected)| projected).collect())
+    }
+
+    /// Index whose order answers the single-column `ORDER BY ... LIMIT` of
+    /// `select` on `table`, so only the top rows need reading instead of
+    /// sorting every match. Not used when an index can serve the WHERE
+    /// instead, or when the index doesn't hold every row.
+    fn order_index(&self, table: &str, select: &Select, filter: Option<&Predicate>) -> Option<&Index> {
+        let [item] = select.order_by.as_slice() else {
+            return None;
+        };
+        let Expr::Column(column) = &item.expr else {
+            return None;
+        };
+        // an output column of that name would be sorted on instead
+        let renamed = select
+            .columns
+            .iter()
+            .flatten()
+            .any(|c| c.name == *column && !matches!(&c.expr, Expr::Column(name) if name == column));
+        if select.limit.is_none()
+            || item.collation.unwrap_or(self.collation) != Collation::Binary
+            || select.is_aggregate()
+            || !select.distinct_on.is_empty()
+            || renamed
+            || filter.is_some_and(|f| self.choose_index(table, f).is_some())
+        {
+            return None;
+        }
+
+        let rows = self.tables.get(table).map_or(0, Vec::len);
+        self.indexes.iter().find(|index| index.table == table && index.column == *column && index.covers(rows))
+    }
+
+    /// The first `OFFSET + LIMIT` rows of `table` matching `filter`, read in
+    /// `index` order
+    fn top_rows(&self, table: &str, index: &Index, select: &Select, filter: Option<&Predicate>) -> Result<Vec<&Row>> {
+        let rows = self.tables.get(table).map(Vec::as_slice).unwrap_or_default();
+        let wanted = select.offset.unwrap_or(0).saturating_add(select.limit.unwrap_or(usize::MAX));
+
+        self.scans.indexes.borrow_mut().push(index.name.clone());
+        let mut top = Vec::new();
+        for pos in index.ordered(select.order_by[0].descending) {
+            if top.len() >= wanted {
+                break;
+            }
+            self.scans.count(1)?;
+            if filter.is_none_or(|f| f.matches(&rows[pos], self.comparison())) {
+                top.push(&rows[pos]);
+            }
+        }
+
+        Ok(top)
+    }
+
+    /// The FROM table then each JOINed table
+    fn join_sources<'a>(&'a self, select: &'a Select) -> Result<Vec<JoinSource<'a>>> {
+        let mut names = vec![(sql::qualifier(&select.table, select.alias.as_deref()), select.table.as_str())];
+        names.extend(select.joins.iter().map(|join| (join.qualifier(), join.table.as_str())));
+
+        let mut sources: Vec<JoinSource> = Vec::new();
+        for (qualifier, name) in names {
+            if sources.iter().any(|(q, _, _)| *q == qualifier) {
+                return Err(LocalDBError::SqlError(format!("Table name {} is used twice; give one an alias", qualifier)));
+            }
+            let (db, table) = self.resolve_table(name)?;
+            let rows = db.tables.get(table).map(Vec::as_slice).unwrap_or_default();
+            sources.push((qualifier, db.column_order(table, rows), rows));
+        }
+        Ok(sources)
+    }
+
+    /// Rows of the FROM table joined with each JOINed table in turn, by
+    /// nested loop. Columns are named `qualifier.column`; a column name only
+    /// one of the tables has can be used unqualified too, as can a column a
+    /// NATURAL JOIN merged. LEFT JOIN keeps an unmatched row once, with the
+    /// joined table's columns NULL.
+    fn joined_rows(&self, select: &Select) -> Result<Vec<Row>> {
+        let sources = self.join_sources(select)?;
+        let natural = natural_columns(select, &sources)?;
+
+        let mut uses: HashMap<&str, usize> = HashMap::new();
+        for column in sources.iter().flat_map(|(_, columns, _)| columns) {
+            *uses.entry(column).or_default() += 1;
+        }
+        let qualify = |(qualifier, columns, _): &JoinSource, row: Option<&Row>, into: &mut Row| {
+            for column in columns {
+                let value = row.and_then(|r| r.get(column)).cloned().unwrap_or(LocalDBValue::NULL);
+                if uses[column.as_str()] == 1 {
+                    into.insert(column.clone(), value.clone());
+                }
+                into.insert(format!("{}.{}", qualifier, column), value);
+            }
+        };
+
+        let (first, rest) = sources.split_first().expect("FROM table is always a source");
+        self.scans.count(first.2.len())?;
+        let mut joined: Vec<Row> = first
+            .2
+            .iter()
+            .map(|row| {
+                let mut out = Row::new();
+                qualify(first, Some(row), &mut out);
+                out
+            })
+            .collect();
+
+        for ((join, source), keys) in select.joins.iter().zip(rest).zip(&natural.keys) {
+            // a NATURAL JOIN without shared columns joins every pair of rows
+            let natural_on = keys.iter().map(|(column, left)| Predicate::CompareColumns {
+                column: left.clone(),
+                op: sql::CmpOp::Eq,
+                other: format!("{}.{}", source.0, column),
+            });
+            let on = join.on.clone().or_else(|| natural_on.reduce(|a, b| Predicate::And(Box::new(a), Box::new(b))));
+            // the merged value is the left side's, equal to the right's when matched
+            let merge = |row: &mut Row| {
+                for (column, left) in keys {
+                    let value = row.get(left).cloned().unwrap_or(LocalDBValue::NULL);
+                    row.insert(column.clone(), value);
+                }
+            };
+
+            self.scans.count(joined.len() * source.2.len())?;
+            let mut next = Vec::new();
+            for left in &joined {
+                let matched = next.len();
+                for right in source.2 {
+                    let mut row = left.clone();
+                    qualify(source, Some(right), &mut row);
+                    if on.as_ref().is_none_or(|on| on.matches(&row, self.comparison())) {
+                        merge(&mut row);
+                        next.push(row);
+                    }
+                }
+                if join.left && next.len() == matched {
+                    let mut row = left.clone();
+                    qualify(source, None, &mut row);
+                    merge(&mut row);
+                    next.push(row);
+                }
+            }
+            joined = next;
+        }
+
+        Ok(joined)
+    }
+
+    /// Aggregate query: fold the matching `rows` into one result row per
+    /// GROUP BY group, in order of each group's first row. Without GROUP BY
+    /// every row is one group, so there is always exactly one result row.
+    fn run_grouped(&self, select: &Select, rows: Vec<&Row>) -> Result<Vec<Row>> {
+        let items = match &select.columns {
+            Some(items) if !items.iter().any(|c| matches!(c.expr, Expr::All)) => items,
+            _ => return Err(LocalDBError::SqlError("SELECT * cannot be used with GROUP BY or HAVING".into())),
+        };
+
+        let grouped: Vec<String> = select.group_by.iter().map(|e| e.to_string()).collect();
+        for item in items.iter().filter(|item| !grouped.contains(&item.expr.to_string())) {
+            if let Some(column) = item.expr.bare_columns().into_iter().find(|c| !grouped.contains(&sql::quote_ident(c))) {
+                return Err(LocalDBError::SqlError(format!(
+                    "Column {} must be inside an aggregate function or listed in GROUP BY",
+                    column
+                )));
+            }
+        }
+
+        let mut groups: Vec<Vec<&Row>> = Vec::new();
+        if select.group_by.is_empty() {
+            groups.push(rows);
+        } else {
+            let mut positions: BTreeMap<Vec<IndexKey>, usize> = BTreeMap::new();
+            for row in rows {
+                let key = select.group_by.iter().map(|e| IndexKey(e.eval(row))).collect();
+                let pos = *positions.entry(key).or_insert_with(|| {
+                    groups.push(Vec::new());
+                    groups.len() - 1
+                });
+                groups[pos].push(row);
+            }
+        }
+
+        let mut keyed: Vec<(Vec<IndexKey>, Row)> = groups
+            .iter()
+            .filter(|group| select.having.as_ref().is_none_or(|having| having.matches_group(group, self.comparison())))
+            .map(|group| {
+                let row: Row = items.iter().map(|c| (c.name.clone(), c.expr.eval_aggregate(group))).collect();
+                (select.order_by.iter().map(|item| item.group_key(group, &row)).collect(), row)
+            })
+            .collect();
+        keyed.sort_by(|(a, _), (b, _)| sql::compare_keys(&select.order_by, a, b, self.collation));
+
+        Ok(select.page(keyed).map(|(_, row)| row).collect())
+    }
+
+    /// Run the subqueries in `filter`, replacing them with their results
+    fn bind_subqueries(&self, filter: Option<&Predicate>) -> Result<Option<Predicate>> {
+        filter.map(|filter| self.bind_predicate(filter)).transpose()
+    }
+
+    /// `filter` with each of its subqueries replaced by its results
+    fn bind_predicate(&self, filter: &Predicate) -> Result<Predicate> {
+        let bound = match filter {
+            Predicate::And(left, right) => {
+                Predicate::And(Box::new(self.bind_predicate(left)?), Box::new(self.bind_predicate(right)?))
+            }
+            Predicate::Or(left, right) => {
+                Predicate::Or(Box::new(self.bind_predicate(left)?), Box::new(self.bind_predicate(right)?))
+            }
+            Predicate::Not(inner) => Predicate::Not(Box::new(self.bind_predicate(inner)?)),
+            Predicate::InSelect { column, select, negated } => Predicate::In {
+                column: column.clone(),
+                values: self.subquery_values(select)?,
+                negated: *negated,
+            },
+            Predicate::CompareSelect { column, op, select } => {
+                let mut values = self.subquery_values(select)?;
+                if values.len() > 1 {
+                    return Err(LocalDBError::SqlError("Scalar subquery returned more than one row".into()));
+                }
+                let value = values.pop().unwrap_or(LocalDBValue::NULL);
+                Predicate::Compare { column: column.clone(), op: *op, value }
+            }
+            Predicate::QuantifiedSelect { column, op, all, select } => Predicate::Quantified {
+                column: column.clone(),
+                op: *op,
+                all: *all,
+                values: self.subquery_values(select)?,
+            },
+            other => other.clone(),
+        };
+
+        Ok(bound)
+    }
+
+    /// Values of the single column `select` projects, one per row
+    fn subquery_values(&self, select: &Select) -> Result<Vec<LocalDBValue>> {
+        let column = match select.columns.as_deref() {
+            Some([item]) => &item.name,
+            _ => return Err(LocalDBError::SqlError("A subquery must select exactly one column".into())),
+        };
+
+        Ok(self
+            .run_select(select)?
+            .into_iter()
+            .filter_map(|mut row| row.remove(column))
+            .collect())
+    }
+
+    /// Split an `alias.table` reference into the attached DB and table name
+    fn resolve_table<'a>(&'a self, name: &'a str) -> Result<(&'a LocalDB, &'a str)> {
+        match name.split_once('.') {
+            Some((alias, table)) => {
+                let db = self.attached.get(alias).ok_or_else(|| {
+                    LocalDBError::SqlError(format!("No database attached as {}", alias))
+                })?;
+                self.check_table_exists(db, table, name)?;
+                Ok((db, table))
+            }
+            None => {
+                self.check_table_exists(self, name, name)?;
+                Ok((self, name))
+            }
+        }
+    }
+
+    /// With `strict_tables`, reject reading a `table` of `db` that doesn't exist
+    fn check_table_exists(&self, db: &LocalDB, table: &str, name: &str) -> Result<()> {
+        if self.strict_tables && !db.tables.contains_key(table) && !db.schema.contains_key(table) {
+            return Err(LocalDBError::TableNotFound(name.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Whether any row of `table` matches `filter`, stopping at the first hit
+    fn any_row(&self, table: &str, filter: Option<&Predicate>) -> Result<bool> {
+        let rows = self.tables.get(table).map(Vec::as_slice).unwrap_or_default();
+        if filter.is_some_and(|f| f.columns().contains(&sql::ROWID)) && self.virtual_rowid(table) {
+            return Ok(!self.matching_positions(table, filter)?.is_empty());
+        }
+
+        let hit = match filter {
+            Some(filter) => rows.iter().position(|row| filter.matches(row, self.comparison())),
+            None => (!rows.is_empty()).then_some(0),
+        };
+        self.scans.count(hit.map_or(rows.len(), |pos| pos + 1))?;
+
+        Ok(hit.is_some())
+    }
+
+    /// Whether `ROWID` in queries on `table` is the virtual row number,
+    /// rather than a stored column of that name
+    fn virtual_rowid(&self, table: &str) -> bool {
+        let rows = self.tables.get(table).map(Vec::as_slice).unwrap_or_default();
+        !rows.iter().any(|row| row.contains_key(sql::ROWID))
+    }
+
+    /// Index that can answer `filter` on `table`, if any
+    fn choose_index(&self, table: &str, filter: &Predicate) -> Option<&Index> {
+        // indexes order values strictly and by code point, so coercive or
+        // collated comparisons must scan
+        if self.coercion != Coercion::Strict || self.collation != Collation::Binary {
+            return None;
+        }
+
+        self.indexes
+            .iter()
+            .find(|index| index.table == table && index.can_serve(filter))
+    }
+
+    /// Rows of `table` matching `filter`, in insertion order
+    fn filter_rows(&self, table: &str, filter: Option<&Predicate>) -> Result<Vec<&Row>> {
+        self.first_rows(table, filter, usize::MAX)
+    }
+
+    /// `filter_rows`, stopping at the first `limit` matches
+    fn first_rows(&self, table: &str, filter: Option<&Predicate>, limit: usize) -> Result<Vec<&Row>> {
+        let rows = self.tables.get(table).map(Vec::as_slice).unwrap_or_default();
+
+        Ok(self
+            .first_positions(table, filter, limit)?
+            .into_iter()
+            .map(|pos| &rows[pos])
+            .collect())
+    }
+
+    /// Positions of the rows of `table` matching `filter`, in insertion order
+    fn matching_positions(&self, table: &str, filter: Option<&Predicate>) -> Result<Vec<usize>> {
+        self.first_positions(table, filter, usize::MAX)
+    }
+
+    /// `matching_positions`, stopping at the first `limit` matches; only
+    /// the rows examined until then count as scanned
+    fn first_positions(&self, table: &str, filter: Option<&Predicate>, limit: usize) -> Result<Vec<usize>> {
+        let rows = self.tables.get(table).map(Vec::as_slice).unwrap_or_default();
+
+        let Some(filter) = filter else {
+            let pick = rows.len().min(limit);
+            self.scans.count(pick)?;
+            return Ok((0..pick).collect());
+        };
+        if filter.columns().contains(&sql::ROWID) && self.virtual_rowid(table) {
+            return self.scan(0..rows.len(), limit, |pos| filter.matches(&with_rowid(&rows[pos], pos), self.comparison()));
+        }
+
+        let index = self.choose_index(table, filter);
+        let candidates = index.and_then(|index| index.lookup(filter));
+
+        match candidates {
+            Some(positions) => {
+                self.scans.indexes.borrow_mut().extend(index.map(|index| index.name.clone()));
+                self.scan(positions.into_iter(), limit, |pos| filter.matches(&rows[pos], self.comparison()))
+            }
+            None => self.scan(0..rows.len(), limit, |pos| filter.matches(&rows[pos], self.comparison())),
+        }
+    }
+
+    /// The `candidates` passing `matches`, up to `limit` of them. Without a
+    /// limit every candidate is counted as scanned up front, so a scan
+    /// limit fails before the work is done.
+    fn scan(&self, candidates: impl ExactSizeIterator<Item = usize>, limit: usize, matches: impl Fn(usize) -> bool) -> Result<Vec<usize>> {
+        if limit == usize::MAX {
+            self.scans.count(candidates.len())?;
+            return Ok(candidates.filter(|&pos| matches(pos)).collect());
+        }
+
+        let (mut found, mut examined) = (Vec::new(), 0);
+        for pos in candidates {
+            if found.len() == limit {
+                break;
+            }
+            examined += 1;
+            if matches(pos) {
+                found.push(pos);
+            }
+        }
+        self.scans.count(examined)?;
+        Ok(found)
+    }
+
+    /// Rows of `table` for which `predicate` returns true, in insertion
+    /// order: filtering in Rust for conditions SQL can't express. A missing
+    /// table is treated like it is by `query`.
+    pub fn find<F: Fn(&Row) -> bool>(&self, table: &str, predicate: F) -> Result<Vec<Row>> {
+        let started = self.metrics.start();
+        let (db, table) = self.resolve_table(table)?;
+        let rows = db.tables.get(table).map(Vec::as_slice).unwrap_or_default();
+
+        let found: Vec<Row> = rows.iter().filter(|row| predicate(row)).cloned().collect();
+        self.metrics.record(Operation::Query, started, found.len());
+
+        Ok(found)
+    }
+
+    /// Rows of `table` whose `column` equals `value`, in insertion order,
+    /// compared as `WHERE column = value` would and using an index on
+    /// `column` when there is one
+    pub fn find_by(&self, table: &str, column: &str, value: impl Into<LocalDBValue>) -> Result<Vec<Row>> {
+        let started = self.metrics.start();
+        let (db, table) = self.resolve_table(table)?;
+        let filter = Predicate::Compare { column: column.to_string(), op: sql::CmpOp::Eq, value: value.into() };
+
+        let found: Vec<Row> = self.scan_limited(|| db.filter_rows(table, Some(&filter)))?.into_iter().cloned().collect();
+        self.metrics.record(Operation::Query, started, found.len());
+
+        Ok(found)
+    }
+
+    /// Stream every table with its rows, cloning one row at a time.
+    ///
+    /// Tables are yielded in name order and rows in insertion order.
+    pub fn iter_tables(&self) -> impl Iterator<Item = (String, impl Iterator<Item = Row> + '_)> + '_ {
+        self.tables
+            .iter()
+            .map(|(name, rows)| (name.clone(), rows.iter().cloned()))
+    }
+
+    /// Append `rows` to `table` with a single save, returning how many were
+    /// inserted.
+    ///
+    /// Rows are checked against the table's constraints like `INSERT`; if any
+    /// is rejected, none are inserted.
+    pub fn insert_many(&mut self, table: &str, rows: impl IntoIterator<Item = Row>) -> Result<usize> {
+        self.check_writable()?;
+
+        let checkpoint = self.size_checkpoint();
+        let inserted = self.insert_rows(table, rows)?;
+        self.save_or_undo(checkpoint)?;
+
+        Ok(inserted)
+    }
+
+    /// Append `rows` to `table`, creating it if needed, with one save and
+    /// none of the checks `insert_many` makes: no types, ARRAYs, `NOT
+    /// NULL`, `PRIMARY KEY` or `UNIQUE`, and no column `DEFAULT`s filled in.
+    /// Indexes are rebuilt once at the end rather than row by row. Only the
+    /// `max_rows` limit is checked, for the table as a whole.
+    ///
+    /// For seeding large, known-good datasets. Bad rows go in as they are
+    /// and can break later inserts, key lookups and constraint checks; run
+    /// `verify` afterwards to find them, or `repair` to remove them.
+    pub fn bulk_load_unchecked(&mut self, table: &str, rows: impl IntoIterator<Item = Row>) -> Result<usize> {
+        self.check_writable()?;
+        check_table_name(table)?;
+
+        let rows: Vec<Row> = rows.into_iter().collect();
+        let (before, loaded) = (self.tables.get(table).map_or(0, Vec::len), rows.len());
+        if let Some(max) = self.limits.max_rows.filter(|max| before + loaded > *max) {
+            return Err(LocalDBError::LimitExceeded(format!("table {} is limited to {} rows", table, max)));
+        }
+
+        let checkpoint = self.size_checkpoint();
+        self.tables.entry(table.to_string()).or_default().extend(rows);
+        self.rebuild_indexes(table);
+        self.invalidate_cache(table);
+        self.record_inserts(table, before);
+        self.save_or_undo(checkpoint)?;
+
+        Ok(loaded)
+    }
+
+    /// `insert_many`, with rows whose PRIMARY KEY or UNIQUE values are
+    /// already taken, by an existing row or an earlier one of `rows`, handled
+    /// by `on_conflict`. `Overwrite` replaces the row in place, failing if
+    /// the new row conflicts with more than one. Other constraints are
+    /// checked as usual; if any row is rejected, nothing changes.
+    ///
+    /// Listeners get an insert event for the inserted rows and an update
+    /// event for the overwritten ones.
+    pub fn insert_many_with(
+        &mut self,
+        table: &str,
+        rows: impl IntoIterator<Item = Row>,
+        on_conflict: OnConflict,
+    ) -> Result<InsertCounts> {
+        if on_conflict == OnConflict::Error {
+            let inserted = self.insert_many(table, rows)?;
+            return Ok(InsertCounts { inserted, ..InsertCounts::default() });
+        }
+        self.check_writable()?;
+
+        let checkpoint = self.checkpoint();
+        match self.insert_resolving(table, rows, on_conflict).and_then(|counts| self.save_and_notify().map(|_| counts)) {
+            Ok(counts) => Ok(counts),
+            Err(e) => {
+                self.restore(checkpoint);
+                Err(e)
+            }
+        }
+    }
+
+    /// `insert_rows` for `insert_many_with`, leaving the rows inserted so
+    /// far in place on an error
+    fn insert_resolving(&mut self, table: &str, rows: impl IntoIterator<Item = Row>, on_conflict: OnConflict) -> Result<InsertCounts> {
+        check_table_name(table)?;
+        let before = self.tables.get(table).map_or(0, Vec::len);
+        let (mut counts, mut overwritten) = (InsertCounts::default(), Vec::new());
+
+        for row in rows {
+            let row = self.with_defaults(table, row);
+            let stored = self.tables.get(table).map(Vec::as_slice).unwrap_or_default();
+            // the usual, indexed checks first; only a row breaking one is compared with every row
+            let conflicts = match self.constraint_violations(table, stored, &row, None).is_empty() {
+                true => Vec::new(),
+                false => self.conflicting_positions(table, &row),
+            };
+
+            match (conflicts.as_slice(), on_conflict) {
+                ([], _) => {
+                    self.insert_row(table, row)?;
+                    counts.inserted += 1;
+                }
+                (_, OnConflict::Skip) => counts.skipped += 1,
+                (&[pos], _) => {
+                    check_arrays(&row)?;
+                    self.check_constraints(table, stored, &row, Some(pos))?;
+                    if self.listeners.active() {
+                        overwritten.push(row.clone());
+                    }
+                    self.tables.get_mut(table).expect("conflicting row is stored")[pos] = row;
+                    self.rebuild_indexes(table);
+                    counts.overwritten += 1;
+                }
+                (_, _) => {
+                    return Err(LocalDBError::ConstraintViolation(format!(
+                        "Row conflicts with {} rows of {}; it can only overwrite one",
+                        conflicts.len(),
+                        table
+                    )))
+                }
+            }
+        }
+
+        self.invalidate_cache(table);
+        self.record_inserts(table, before);
+        if !overwritten.is_empty() {
+            self.listeners.push(ChangeEvent { kind: ChangeKind::Update, table: table.to_string(), rows: overwritten });
+        }
+        Ok(counts)
+    }
+
+    /// Swap every row of `table` for `rows` with a single save, keeping its
+    /// schema and indexes, and return the new row count; e.g. for a full
+    /// refresh from an upstream source. Rows are checked like `INSERT`, and
+    /// if any is rejected or the save fails the old rows stay.
+    ///
+    /// Listeners get a delete event for the old rows, if any, then an
+    /// insert event for the new ones.
+    pub fn replace_table(&mut self, table: &str, rows: Vec<Row>) -> Result<usize> {
+        self.check_writable()?;
+        self.table_rows(table)?;
+
+        let checkpoint = self.checkpoint();
+        let old = self.tables.insert(table.to_string(), Vec::new()).unwrap_or_default();
+        self.rebuild_indexes(table);
+        self.invalidate_cache(table);
+        if !old.is_empty() {
+            self.listeners.push(ChangeEvent { kind: ChangeKind::Delete, table: table.to_string(), rows: old });
+        }
+
+        match self.insert_rows(table, rows).and_then(|count| self.save_and_notify().map(|_| count)) {
+            Ok(count) => Ok(count),
+            Err(e) => {
+                self.restore(checkpoint);
+                Err(e)
+            }
+        }
+    }
+
+    /// Create `table` if it doesn't exist and append `rows` to it with a
+    /// single save, e.g. for fixtures. If any row is rejected, nothing
+    /// changes, and a table created for the rows is removed again.
+    pub fn seed(&mut self, table: &str, rows: Vec<Row>) -> Result<()> {
+        self.check_writable()?;
+        check_table_name(table)?;
+
+        let checkpoint = self.size_checkpoint();
+        let created = !self.tables.contains_key(table);
+        self.tables.entry(table.to_string()).or_default();
+        self.invalidate_cache(table);
+
+        if let Err(e) = self.insert_rows(table, rows) {
+            if created {
+                self.tables.remove(table);
+            }
+            return Err(e);
+        }
+        self.save_or_undo(checkpoint)
+    }
+
+    /// Insert a serializable struct (or map) as one row of `table`.
+    ///
+    /// Fields become columns: strings are TEXT, or UUID in a column declared
+    /// UUID; integers are INT, or REAL in a column declared REAL; other
+    /// numbers are REAL; `None` is NULL; lists of one scalar type are ARRAY.
+    /// Any field of a column declared JSON is stored as JSON; other nested
+    /// values are rejected.
+    pub fn insert_typed<T: Serialize>(&mut self, table: &str, value: &T) -> Result<()> {
+        let row = self.typed_row(table, value)?;
+        self.insert_many(table, [row]).map(|_| ())
+    }
+
+    fn typed_row<T: Serialize>(&self, table: &str, value: &T) -> Result<Row> {
+        use serde_json::Value;
+
+        match serde_json::to_value(value) {
+            Ok(Value::Object(fields)) => self.typed_fields(table, fields),
+            Ok(other) => Err(LocalDBError::TypeMismatch(format!("insert_typed expects a struct or map, got {}", other))),
+            Err(e) => Err(LocalDBError::SqlError(format!("Cannot serialize row for {}: {}", table, e))),
+        }
+    }
+
+    fn typed_fields(&self, table: &str, fields: serde_json::Map<String, serde_json::Value>) -> Result<Row> {
+        let declared = |column: &str| self.schema.get(table).and_then(|def| def.column(column)).map(|c| c.col_type);
+
+        fields
+            .into_iter()
+            .map(|(column, field)| {
+                let value = typed_value(table, &column, field, declared(&column))?;
+                Ok((column, value))
+            })
+            .collect()
+    }
+
+    /// Append the tables of `value` with a single save, returning how many
+    /// rows were loaded. `value` is a JSON object of table names to arrays
+    /// of row objects, such as `{"users": [{"name": "kk", "age": 3}]}`.
+    ///
+    /// Fields become values as for `insert_typed`; missing tables are
+    /// created, even with no rows, and rows are checked against constraints
+    /// like `INSERT`. Data of any other shape fails with `CorruptData`. If
+    /// anything is rejected, nothing changes.
+    pub fn load_value(&mut self, value: serde_json::Value) -> Result<usize> {
+        use serde_json::Value;
+
+        self.check_writable()?;
+        let corrupt = |what: String| LocalDBError::CorruptData(what);
+        let Value::Object(tables) = value else {
+            return Err(corrupt(format!("expected an object of tables, got {}", value)));
+        };
+
+        let checkpoint = self.checkpoint();
+        let mut loaded = 0;
+        for (table, rows) in tables {
+            let result = match rows {
+                Value::Array(rows) => self.load_rows(&table, rows),
+                other => Err(corrupt(format!("table {} is not an array of rows: {}", table, other))),
+            };
+            match result {
+                Ok(n) => loaded += n,
+                Err(e) => {
+                    self.restore(checkpoint);
+                    return Err(e);
+                }
+            }
+        }
+        self.save_or_undo(Some(checkpoint))?;
+
+        Ok(loaded)
+    }
+
+    /// `load_value`'s rows of one table, inserted without saving
+    fn load_rows(&mut self, table: &str, rows: Vec<serde_json::Value>) -> Result<usize> {
+        check_table_name(table)?;
+        let rows = rows
+            .into_iter()
+            .enumerate()
+            .map(|(n, row)| match row {
+                serde_json::Value::Object(fields) => self
+                    .typed_fields(table, fields)
+                    .map_err(|e| LocalDBError::CorruptData(format!("{}[{}]: {}", table, n, e))),
+                other => Err(LocalDBError::CorruptData(format!("{}[{}] is not a row object: {}", table, n, other))),
+            })
+            .collect::<Result<Vec<Row>>>()?;
+
+        self.tables.entry(table.to_string()).or_default();
+        self.invalidate_cache(table);
+        self.insert_rows(table, rows)
+    }
+
+    /// Write `table` to `path` as JSON Lines: one row object per line, values
+    /// tagged by type as in the database file. Returns the number of rows.
+    pub fn export_ndjson(&self, table: &str, path: &str) -> Result<usize> {
+        let rows = self.table_rows(table)?;
+
+        let file = fs::File::create(path).map_err(LocalDBError::io(path))?;
+        let mut out = std::io::BufWriter::new(file);
+        for row in rows {
+            // through Value so every line has its keys sorted
+            let line = serde_json::to_value(row).map_err(std::io::Error::from).map_err(LocalDBError::io(path))?;
+            writeln!(out, "{}", line).map_err(LocalDBError::io(path))?;
+        }
+        out.flush().map_err(LocalDBError::io(path))?;
+
+        Ok(rows.len())
+    }
+
+    /// Append the rows of a JSON Lines file written by `export_ndjson` to
+    /// `table`, with a single save. Blank lines are skipped; a malformed
+    /// line, or a row breaking a constraint, imports nothing.
+    pub fn import_ndjson(&mut self, table: &str, path: &str) -> Result<usize> {
+        use std::io::BufRead;
+
+        self.check_writable()?;
+
+        let file = fs::File::open(path).map_err(LocalDBError::io(path))?;
+        let mut rows = Vec::new();
+        for (n, line) in std::io::BufReader::new(file).lines().enumerate() {
+            let line = line.map_err(LocalDBError::io(path))?;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let row: Row = serde_json::from_str(&line).map_err(|e| {
+                let source = std::io::Error::new(std::io::ErrorKind::InvalidData, format!("line {}: {}", n + 1, e));
+                LocalDBError::io(path)(source)
+            })?;
+            rows.push(row);
+        }
+
+        self.insert_many(table, rows)
+    }
+
+    /// Mutate every row of `table` matching `predicate` in place.
+    ///
+    /// Returns the number of rows changed. The changed rows are checked like
+    /// an `UPDATE`'s, against the declared types, NOT NULL, PRIMARY KEY and
+    /// UNIQUE constraints; if any fails, or the save does, nothing changes.
+    pub fn update_where<P, M>(&mut self, table: &str, predicate: P, mut mutate: M) -> Result<usize>
+    where
+        P: Fn(&Row) -> bool,
+        M: FnMut(&mut Row),
+    {
+        self.check_writable()?;
+        self.table_rows(table)?;
+        let checkpoint = self.checkpoint();
+
+        let rows = self.tables.get_mut(table).expect("table checked above");
+        let mut changed = Vec::new();
+        for (pos, row) in rows.iter_mut().enumerate().filter(|(_, row)| predicate(row)) {
+            mutate(row);
+            changed.push(pos);
+        }
+        if changed.is_empty() {
+            return Ok(0);
+        }
+
+        // checked once every row has changed, so rows may trade key values
+        let rows = self.table_rows(table)?;
+        let failed = changed
+            .iter()
+            .find_map(|&pos| check_arrays(&rows[pos]).and_then(|_| self.check_constraints(table, rows, &rows[pos], Some(pos))).err());
+        if let Some(e) = failed {
+            self.restore(checkpoint);
+            return Err(e);
+        }
+
+        let updated = if self.listeners.active() { changed.iter().map(|&pos| rows[pos].clone()).collect() } else { Vec::new() };
+        self.rebuild_indexes(table);
+        self.invalidate_cache(table);
+        self.listeners.push(ChangeEvent { kind: ChangeKind::Update, table: table.to_string(), rows: updated });
+        match self.save_and_notify() {
+            Ok(()) => Ok(changed.len()),
+            Err(e) => {
+                self.restore(checkpoint);
+                Err(e)
+            }
+        }
+    }
+
+    /// Convert the value of `column` in every row of `table` to `target`
+    /// with a single save, e.g. TEXT `'42'` to INT `42`, and declare the
+    /// column as `target` if the table has a schema. Returns how many values
+    /// changed type; NULLs and values already of `target` are left as they
+    /// are. If any value can't be converted without loss, or a converted
+    /// value breaks a PRIMARY KEY or UNIQUE constraint, nothing changes.
+    pub fn cast_column(&mut self, table: &str, column: &str, target: ColType) -> Result<usize> {
+        self.check_writable()?;
+        let rows = self.table_rows(table)?;
+        let declared = self.schema.get(table).map(|def| def.columns.iter().position(|c| c.name == column));
+        if declared == Some(None) {
+            return Err(LocalDBError::ColumnNotFound(format!("{}.{}", table, column)));
+        }
+
+        let mut converted = Vec::new();
+        for (pos, row) in rows.iter().enumerate() {
+            let Some(value) = row.get(column).filter(|v| !matches!(v, LocalDBValue::NULL) && ColType::of(v) != Some(target)) else {
+                continue;
+            };
+            let cast = target.cast(value).ok_or_else(|| {
+                LocalDBError::TypeMismatch(format!(
+                    "Cannot cast {}.{} value {} in row {} to {}",
+                    table,
+                    column,
+                    sql::render_literal(value),
+                    pos,
+                    target
+                ))
+            })?;
+            converted.push((pos, cast));
+        }
+
+        let checkpoint = self.checkpoint();
+        if let Some(Some(index)) = declared {
+            self.schema.get_mut(table).expect("declared above").columns[index].col_type = target;
+        }
+        if !converted.is_empty() {
+            let rows = self.tables.get_mut(table).expect("table checked above");
+            for (pos, cast) in &converted {
+                rows[*pos].insert(column.to_string(), cast.clone());
+            }
+            // values that differed before may be equal now
+            let rows = self.table_rows(table)?;
+            for (pos, _) in &converted {
+                if let Err(e) = self.check_constraints(table, rows, &rows[*pos], Some(*pos)) {
+                    self.restore(checkpoint);
+                    return Err(e);
+                }
+            }
+
+            let changed = match self.listeners.active() {
+                true => converted.iter().map(|(pos, _)| rows[*pos].clone()).collect(),
+                false => Vec::new(),
+            };
+            self.rebuild_indexes(table);
+            self.invalidate_cache(table);
+            self.listeners.push(ChangeEvent { kind: ChangeKind::Update, table: table.to_string(), rows: changed });
+        }
+
+        if let Err(e) = self.save_and_notify() {
+            self.restore(checkpoint);
+            return Err(e);
+        }
+        Ok(converted.len())
+    }
+
+    /// Replace the row of `table` whose key is `id` with `new`, but only if
+    /// it still equals `expected`. Returns whether the row was replaced.
+    ///
+    /// The key is the PRIMARY KEY column, or `id` for tables without one.
+    /// Rows are equal when they have the same columns holding values of the
+    /// same types, so a row read earlier can be written back only if nothing
+    /// changed it in the meantime.
+    pub fn update_if(&mut self, table: &str, id: impl Into<LocalDBValue>, expected: &Row, new: Row) -> Result<bool> {
+        self.check_writable()?;
+
+        let rows = self.table_rows(table)?;
+        let Some(pos) = self.key_positions(table, id.into())?.into_iter().find(|&pos| rows[pos] == *expected) else {
+            return Ok(false);
+        };
+        self.replace_row(table, pos, new)?;
+
+        Ok(true)
+    }
+
+    /// Merge `fields` into the row of `table` whose key is `id`, leaving its
+    /// other columns as they are, and return whether a row matched. The key
+    /// is the PRIMARY KEY column, or `id`, looked up through its index when
+    /// there is one; the merged row is checked like an `UPDATE`.
+    pub fn update_fields(&mut self, table: &str, id: impl Into<LocalDBValue>, fields: HashMap<String, LocalDBValue>) -> Result<bool> {
+        self.check_writable()?;
+
+        let rows = self.table_rows(table)?;
+        let Some(&pos) = self.key_positions(table, id.into())?.first() else {
+            return Ok(false);
+        };
+        let mut row = rows[pos].clone();
+        row.extend(fields);
+        self.replace_row(table, pos, row)?;
+
+        Ok(true)
+    }
+
+    /// `update_fields` for many rows with a single save: each entry of
+    /// `updates` maps a key to the fields merged into its row. Returns how
+    /// many rows were updated; keys matching no row are skipped. A key is
+    /// read as the declared type of the key column, or for a table without
+    /// one as TEXT, then INT if it is a number. If any merged row is
+    /// rejected, no row changes.
+    pub fn update_many(&mut self, table: &str, updates: HashMap<String, HashMap<String, LocalDBValue>>) -> Result<usize> {
+        self.check_writable()?;
+        self.table_rows(table)?;
+
+        let key_type = self.schema.get(table).and_then(|def| def.column(self.key_column(table))).map(|c| c.col_type);
+        let mut updates: Vec<_> = updates.into_iter().collect();
+        updates.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        let checkpoint = self.checkpoint();
+        let mut changed = Vec::new();
+        for (key, fields) in updates {
+            let ids = match key_type {
+                Some(ColType::Uuid) => vec![LocalDBValue::UUID(key)],
+                Some(ColType::Int) => key.parse().map(LocalDBValue::INT).into_iter().collect(),
+                _ => [Some(LocalDBValue::TEXT(key.clone())), key.parse().ok().map(LocalDBValue::INT)].into_iter().flatten().collect(),
+            };
+            let mut found = None;
+            for id in ids {
+                found = found.or(self.key_positions(table, id)?.first().copied());
+            }
+            let Some(pos) = found else { continue };
+
+            let rows = self.table_rows(table)?;
+            let mut row = rows[pos].clone();
+            let reindex = self.indexes.iter().any(|index| {
+                index.table == table
+                    && fields.keys().any(|c| *c == index.column || index.filter.as_ref().is_some_and(|f| f.columns().contains(&c.as_str())))
+            });
+            row.extend(fields);
+            if let Err(e) = check_arrays(&row).and_then(|_| self.check_constraints(table, rows, &row, Some(pos))) {
+                self.restore(checkpoint);
+                return Err(e);
+            }
+
+            self.tables.get_mut(table).expect("table checked above")[pos] = row;
+            if reindex {
+                self.rebuild_indexes(table);
+            }
+            changed.push(pos);
+        }
+        if changed.is_empty() {
+            return Ok(0);
+        }
+
+        changed.sort_unstable();
+        changed.dedup();
+        let count = changed.len();
+        let rows = self.table_rows(table)?;
+        let updated = if self.listeners.active() { changed.iter().map(|&pos| rows[pos].clone()).collect() } else { Vec::new() };
+        self.invalidate_cache(table);
+        self.listeners.push(ChangeEvent { kind: ChangeKind::Update, table: table.to_string(), rows: updated });
+        match self.save_and_notify() {
+            Ok(()) => Ok(count),
+            Err(e) => {
+                self.restore(checkpoint);
+                Err(e)
+            }
+        }
+    }
+
+    /// Store `row` under key `id` in `table`, replacing the row with that key
+    /// or inserting it if there is none. The key column (the PRIMARY KEY, or
+    /// `id`) is set to `id`; lookups use the key's index when there is one.
+    pub fn put(&mut self, table: &str, id: impl Into<LocalDBValue>, mut row: Row) -> Result<()> {
+        self.check_writable()?;
+
+        let id = id.into();
+        row.insert(self.key_column(table).to_string(), id.clone());
+
+        match self.key_positions(table, id)?.first() {
+            Some(&pos) => self.replace_row(table, pos, row),
+            None => self.insert_many(table, [row]).map(drop),
+        }
+    }
+
+    /// Delete every row of `table` whose key is one of `ids` with a single
+    /// save, returning how many were removed. The key is the PRIMARY KEY
+    /// column, or `id`; ids matching no row are skipped.
+    pub fn delete_by_ids<V: Clone + Into<LocalDBValue>>(&mut self, table: &str, ids: &[V]) -> Result<usize> {
+        self.check_writable()?;
+        self.table_rows(table)?;
+
+        let mut positions = Vec::new();
+        for id in ids {
+            positions.extend(self.key_positions(table, id.clone().into())?);
+        }
+        positions.sort_unstable();
+        positions.dedup();
+        if positions.is_empty() {
+            return Ok(0);
+        }
+
+        let checkpoint = self.size_checkpoint();
+        let removed = self.remove_positions(table, positions);
+        let count = removed.len();
+        self.listeners.push(ChangeEvent { kind: ChangeKind::Delete, table: table.to_string(), rows: removed });
+        self.save_or_undo(checkpoint)?;
+
+        Ok(count)
+    }
+
+    /// Remove every table, with its rows, schema and indexes, in one save,
+    /// leaving an empty database with just its format header.
+    ///
+    /// Listeners get a delete event for each non-empty table. If the save
+    /// fails, nothing is removed.
+    pub fn clear(&mut self) -> Result<()> {
+        self.check_writable()?;
+
+        let checkpoint = self.checkpoint();
+        self.tables.mark_all_dirty();
+        let dirty = self.tables.take_dirty();
+        for (table, rows) in std::mem::take(&mut self.tables) {
+            self.listeners.push(ChangeEvent { kind: ChangeKind::Delete, table, rows });
+        }
+        self.tables.mark_dirty(dirty);
+        self.schema.clear();
+        self.indexes.clear();
+        self.clear_cache();
+
+        if let Err(e) = self.save_and_notify() {
+            self.restore(checkpoint);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Column rows are looked up by in `update_if`, `put` and `delete_by_ids`
+    fn key_column(&self, table: &str) -> &str {
+        self.schema.get(table).and_then(TableDef::primary_key).map_or("id", |c| c.name.as_str())
+    }
+
+    /// Positions of the rows of `table` whose key column equals `id`
+    fn key_positions(&self, table: &str, id: LocalDBValue) -> Result<Vec<usize>> {
+        let by_id = Predicate::Compare { column: self.key_column(table).to_string(), op: sql::CmpOp::Eq, value: id };
+        self.matching_positions(table, Some(&by_id))
+    }
+
+    /// Swap the row at `pos` of `table` for `new`, then save
+    fn replace_row(&mut self, table: &str, pos: usize, new: Row) -> Result<()> {
+        check_arrays(&new)?;
+        self.check_constraints(table, self.table_rows(table)?, &new, Some(pos))?;
+
+        let checkpoint = self.size_checkpoint();
+        let rows = self.tables.get_mut(table).expect("table checked above");
+        rows[pos] = new;
+        let changed = if self.listeners.active() { vec![rows[pos].clone()] } else { Vec::new() };
+
+        self.rebuild_indexes(table);
+        self.invalidate_cache(table);
+        self.listeners.push(ChangeEvent { kind: ChangeKind::Update, table: table.to_string(), rows: changed });
+        self.save_or_undo(checkpoint)
+    }
+
+    /// Release the spare capacity `table` holds after large deletes and
+    /// rebuild its indexes, returning roughly how many bytes were freed.
+    ///
+    /// Only row storage is counted; the data itself is unchanged and nothing
+    /// is saved.
+    pub fn optimize(&mut self, table: &str) -> Result<usize> {
+        let rows = self.tables.get_mut(table).ok_or_else(|| {
+            LocalDBError::TableNotFound(table.to_string())
+        })?;
+
+        let entry_size = std::mem::size_of::<(String, LocalDBValue)>();
+        let footprint = |rows: &Vec<Row>| {
+            rows.capacity() * std::mem::size_of::<Row>() + rows.iter().map(|row| row.capacity() * entry_size).sum::<usize>()
+        };
+
+        let before = footprint(rows);
+        rows.iter_mut().for_each(Row::shrink_to_fit);
+        rows.shrink_to_fit();
+        let freed = before.saturating_sub(footprint(rows));
+
+        self.rebuild_indexes(table);
+
+        Ok(freed)
+    }
+
+    /// `name` written as an identifier for SQL built at run time: always
+    /// double-quoted, with any `"` in it doubled, so a table or column name
+    /// with spaces, quotes or a keyword's spelling reads back as itself
+    pub fn quote_ident(name: &str) -> String {
+        format!("\"{}\"", name.replace('"', "\"\""))
+    }
+
+    /// `value` written as a SQL literal that reads back as the same value:
+    /// TEXT single-quoted with any `'` doubled, other types in their literal
+    /// form (`UUID '...'`, `TIMESTAMP '...'`, `[1, 2]`, `NULL`)
+    pub fn quote_literal(value: &LocalDBValue) -> String {
+        sql::render_literal(value)
+    }
+
+    /// Dump the whole database as SQL statements that recreate it via `exec`.
+    ///
+    /// Tables are recreated from their declared schema, comments included;
+    /// tables without one get column types from the first value seen for
+    /// each column. String values are quoted losslessly.
+    pub fn dump_sql(&self) -> String {
+        self.render_dump(false)
+    }
+
+    /// `dump_sql` laid out for people: each `CREATE TABLE` column on its own
+    /// line with names and types aligned, each `INSERT` split before
+    /// `VALUES`, and a blank line between tables, so schema changes read
+    /// well in a diff. It runs through `exec` just the same.
+    pub fn dump_sql_pretty(&self) -> String {
+        self.render_dump(true)
+    }
+
+    fn render_dump(&self, pretty: bool) -> String {
+        let mut out = String::new();
+
+        for (name, rows) in &self.tables {
+            if pretty && !out.is_empty() {
+                out.push('\n');
+            }
+
+            match self.schema.get(name) {
+                Some(def) => out.push_str(&sql::render_table_def(def, pretty)),
+                None => {
+                    // the type of the first value, widened so every value fits it
+                    let mut types: BTreeMap<&str, ColType> = BTreeMap::new();
+                    for row in rows {
+                        for (column, value) in row {
+                            let Some(col_type) = ColType::of(value) else { continue };
+                            let declared = types.entry(column).or_insert(col_type);
+                            if !declared.holds(value) {
+                                *declared = match (*declared, col_type) {
+                                    (ColType::Int, ColType::Real | ColType::Decimal | ColType::Timestamp) => col_type,
+                                    _ => ColType::Json,
+                                };
+                            }
+                        }
+                    }
+
+                    let mut def = TableDef::new(name);
+                    def.columns = types.iter().map(|(c, t)| ColumnDef::new(c, *t)).collect();
+                    out.push_str(&sql::render_table_def(&def, pretty));
+                }
+            }
+
+            for row in rows {
+                let mut columns: Vec<&String> = row.keys().collect();
+                columns.sort();
+
+                let values: Vec<String> = columns.iter().map(|c| sql::render_literal(&row[*c])).collect();
+                let columns: Vec<String> = columns.iter().map(|c| sql::quote_ident(c)).collect();
+
+                out.push_str(&format!(
+                    "INSERT INTO {} ({}){}VALUES ({});\n",
+                    sql::quote_ident(name),
+                    columns.join(", "),
+                    if pretty { "\n    " } else { " " },
+                    values.join(", ")
+                ));
+            }
+        }
+
+        out
+    }
+
+    // ========================= INTERNAL HANDLERS =============================
+
+    fn check_writable(&self) -> Result<()> {
+        if self.read_only {
+            return Err(LocalDBError::ReadOnly);
+        }
+        Ok(())
+    }
+
+    fn handle_create_table(&mut self, sql: &str) -> Result<()> {
+        let def = Parser::new(sql)?.parse_create_table()?;
+        self.define_table(def)
+    }
+
+    /// Create a table from its definition; an existing table is left as-is
+    pub(crate) fn define_table(&mut self, def: TableDef) -> Result<()> {
+        self.check_writable()?;
+
+        check_table_name(&def.name)?;
+        let duplicate = def.columns.iter().enumerate().find(|&(i, c)| def.columns[..i].iter().any(|d| d.name == c.name));
+        if let Some((_, column)) = duplicate {
+            return Err(LocalDBError::SqlError(format!("Table {} declares column {} more than once", def.name, column.name)));
+        }
+        if def.columns.iter().filter(|c| c.primary_key).count() > 1 {
+            return Err(LocalDBError::SqlError(format!("Table {} has more than one PRIMARY KEY", def.name)));
+        }
+        if let Some(column) = def.unique.iter().flatten().find(|c| def.column(c).is_none()) {
+            return Err(LocalDBError::ColumnNotFound(format!("{}.{}", def.name, column)));
+        }
+
+        self.tables.entry(def.name.clone()).or_default();
+        self.invalidate_cache(&def.name);
+        if !self.schema.contains_key(&def.name) {
+            self.index_primary_key(&def);
+            self.schema.insert(def.name.clone(), def);
+        }
+
+        Ok(())
+    }
+
+    /// PRIMARY KEY columns get an index, named `<table>_pkey`, so uniqueness
+    /// checks and key lookups don't scan the table
+    fn index_primary_key(&mut self, def: &TableDef) {
+        let Some(pk) = def.primary_key() else {
+            return;
+        };
+
+        let rows = self.tables.get(&def.name).map(Vec::as_slice).unwrap_or_default();
+        let index = Index::build(format!("{}_pkey", def.name), def.name.clone(), pk.name.clone(), None, rows);
+        self.indexes.push(index);
+    }
+
+    /// Reject `row` if it breaks a NOT NULL, PRIMARY KEY or UNIQUE constraint of
+    /// `table`, whose current rows are `rows`; the row at position `skip` is
+    /// the one being replaced and doesn't count as a duplicate
+    fn check_constraints(&self, table: &str, rows: &[Row], row: &Row, skip: Option<usize>) -> Result<()> {
+        match self.constraint_violations(table, rows, row, skip).into_iter().next() {
+            Some((_, e)) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    /// Positions of the rows of `table` that hold `row`'s values in its
+    /// PRIMARY KEY column, or in every column of one of its UNIQUE sets
+    fn conflicting_positions(&self, table: &str, row: &Row) -> Vec<usize> {
+        let Some(def) = self.schema.get(table) else {
+            return Vec::new();
+        };
+        let primary = def.primary_key().map(|c| vec![c.name.clone()]);
+        let keys: Vec<Vec<String>> = primary.into_iter().chain(def.unique.iter().cloned()).collect();
+
+        let same = |stored: &Row, column: &String| match row.get(column) {
+            None | Some(LocalDBValue::NULL) => false,
+            Some(value) => stored
+                .get(column)
+                .and_then(|v| sql::compare_values(v, value, Comparison::STRICT))
+                .is_some_and(|o| o == Ordering::Equal),
+        };
+        let rows = self.tables.get(table).map(Vec::as_slice).unwrap_or_default();
+        rows.iter()
+            .enumerate()
+            .filter(|(_, stored)| keys.iter().any(|key| !key.is_empty() && key.iter().all(|c| same(stored, c))))
+            .map(|(pos, _)| pos)
+            .collect()
+    }
+
+    /// `row` with the `DEFAULT` of each declared column it leaves out
+    fn with_defaults(&self, table: &str, mut row: Row) -> Row {
+        if let Some(def) = self.schema.get(table) {
+            for column in &def.columns {
+                if let (Some(default), false) = (&column.default, row.contains_key(&column.name)) {
+                    row.insert(column.name.clone(), default.value());
+                }
+            }
+        }
+        row
+    }
+
+    /// Every constraint `row` breaks, like `check_constraints`, each with
+    /// the columns involved
+    fn constraint_violations(&self, table: &str, rows: &[Row], row: &Row, skip: Option<usize>) -> Vec<(Vec<String>, LocalDBError)> {
+        let mut violations = Vec::new();
+        let Some(def) = self.schema.get(table) else {
+            return violations;
+        };
+
+        for column in &def.columns {
+            if let Some(value) = row.get(&column.name).filter(|value| !column.col_type.holds(value)) {
+                let e = LocalDBError::TypeMismatch(format!(
+                    "Value of {}.{} is not of type {}: {}",
+                    table,
+                    column.name,
+                    column.col_type,
+                    sql::render_literal(value)
+                ));
+                violations.push((vec![column.name.clone()], e));
+                continue;
+            }
+            let value = match row.get(&column.name) {
+                Some(LocalDBValue::NULL) | None if column.not_null || column.primary_key => {
+                    let e = LocalDBError::ConstraintViolation(format!("NOT NULL constraint failed: {}.{}", table, column.name));
+                    violations.push((vec![column.name.clone()], e));
+                    continue;
+                }
+                Some(value) if column.primary_key => value,
+                _ => continue,
+            };
+
+            let same = |r: &Row| {
+                r.get(&column.name)
+                    .and_then(|v| sql::compare_values(v, value, Comparison::STRICT))
+                    .is_some_and(|o| o == Ordering::Equal)
+            };
+
+            // the index reflects the stored rows, not a working copy being edited
+            let key = Predicate::Compare { column: column.name.clone(), op: sql::CmpOp::Eq, value: value.clone() };
+            let candidates = match skip {
+                None => self
+                    .indexes
+                    .iter()
+                    .find(|index| index.table == table && index.can_serve(&key))
+                    .and_then(|index| index.lookup(&key)),
+                Some(_) => None,
+            };
+
+            let taken = match candidates {
+                Some(positions) => positions.into_iter().any(|pos| same(&rows[pos])),
+                None => rows.iter().enumerate().any(|(pos, r)| Some(pos) != skip && same(r)),
+            };
+            if taken {
+                let e = LocalDBError::ConstraintViolation(format!("PRIMARY KEY constraint failed: {}.{}", table, column.name));
+                violations.push((vec![column.name.clone()], e));
+            }
+        }
+
+        // as in SQL, a NULL in any of the key's columns never conflicts
+        for key in &def.unique {
+            let Some(values) = key
+                .iter()
+                .map(|c| row.get(c).filter(|v| !matches!(v, LocalDBValue::NULL)))
+                .collect::<Option<Vec<_>>>()
+            else {
+                continue;
+            };
+
+            let same = |r: &Row| {
+                key.iter().zip(&values).all(|(c, value)| {
+                    r.get(c)
+                        .and_then(|v| sql::compare_values(v, value, Comparison::STRICT))
+                        .is_some_and(|o| o == Ordering::Equal)
+                })
+            };
+            if rows.iter().enumerate().any(|(pos, r)| Some(pos) != skip && same(r)) {
+                let columns: Vec<String> = key.iter().map(|c| format!("{}.{}", table, c)).collect();
+                let e = LocalDBError::ConstraintViolation(format!("UNIQUE constraint failed: {}", columns.join(", ")));
+                violations.push((key.clone(), e));
+            }
+        }
+
+        violations
+    }
+
+    fn handle_comment(&mut self, sql: &str) -> Result<()> {
+        let (table, column, text) = match Parser::new(sql)?.parse_comment()? {
+            Comment::Table { table, text } => (table, None, text),
+            Comment::Column { table, column, text } => (table, Some(column), text),
+        };
+
+        let def = self.schema.get_mut(&table).ok_or_else(|| {
+            LocalDBError::SqlError(format!("Table has no schema: {}", table))
+        })?;
+
+        match column {
+            None => def.comment = Some(text),
+            Some(column) => {
+                let col = def.columns.iter_mut().find(|c| c.name == column).ok_or_else(|| {
+                    LocalDBError::ColumnNotFound(format!("{}.{}", table, column))
+                })?;
+                col.comment = Some(text);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn handle_create_index(&mut self, sql: &str) -> Result<()> {
+        let tokens = sql::tokenize(sql)?;
+        if sql::calls_now(&tokens) {
+            return Err(LocalDBError::SqlError("Function now cannot be used in an index filter".to_string()));
+        }
+        let mut def = Parser::from_tokens(tokens).parse_create_index()?;
+        if let Some(filter) = &mut def.filter {
+            filter.walk_exprs_mut(&mut functions::reject_calls("an index filter"))?;
+        }
+
+        if self.indexes.iter().any(|index| index.name == def.name) {
+            return Err(LocalDBError::SqlError(format!("Index already exists: {}", def.name)));
+        }
+
+        let rows = self.tables.get(&def.table).ok_or_else(|| {
+            LocalDBError::TableNotFound(def.table.to_string())
+        })?;
+
+        let index = if def.fulltext {
+            Index::build_fulltext(def.name, def.table, def.column, rows)
+        } else {
+            Index::build(def.name, def.table, def.column, def.filter, rows)
+        };
+        self.indexes.push(index);
+
+        Ok(())
+    }
+
+    fn handle_insert(&mut self, sql: &str) -> Result<Outcome> {
+        let sql_fixed = sql.replace("INSET", "INSERT");
+
+        let mut insert = Parser::new(&sql_fixed)?.parse_insert()?;
+        if let Some(returning) = &mut insert.returning {
+            returning.walk_exprs_mut(&mut functions::reject_calls("RETURNING"))?;
+        }
+        let before = self.tables.get(&insert.table).map_or(0, Vec::len);
+
+        match insert.source {
+            InsertSource::Values(values) => self.insert_values(&insert.table, insert.columns, values)?,
+            InsertSource::Select(mut select) => {
+                let failure = self.bind_calls(|bind| select.walk_exprs_mut(bind))?;
+                self.insert_from_select(&insert.table, insert.columns, &select, &failure)?
+            }
+        }
+
+        // an INSERT ... SELECT of no rows never creates the table
+        let inserted = self.tables.get(&insert.table).map_or(&[][..], |rows| &rows[before..]);
+        Ok(Outcome { affected: inserted.len(), returning: insert.returning.map(|returning| returning.project(inserted)) })
+    }
+
+    /// INSERT INTO table [(columns)] VALUES (values)
+    ///
+    /// Without a column list, values go to the declared columns in order, a
+    /// string given for a UUID column becoming a UUID. Tables with no schema
+    /// keep the classic (id UUID, name TEXT) layout.
+    fn insert_values(&mut self, table: &str, columns: Option<Vec<String>>, mut values: Vec<LocalDBValue>) -> Result<()> {
+
+        let columns = match columns {
+            Some(columns) => columns,
+            None if self.schema.contains_key(table) => {
+                let def = &self.schema[table];
+                if values.len() != def.columns.len() {
+                    let names: Vec<&str> = def.columns.iter().map(|c| c.name.as_str()).collect();
+                    return Err(LocalDBError::SqlError(format!(
+                        "INSERT without a column list expects {} values for {} ({}), got {}",
+                        names.len(),
+                        table,
+                        names.join(", "),
+                        values.len()
+                    )));
+                }
+                for (value, column) in values.iter_mut().zip(&def.columns) {
+                    if let (LocalDBValue::TEXT(text), ColType::Uuid) = (&*value, column.col_type) {
+                        *value = LocalDBValue::UUID(text.clone());
+                    }
+                }
+                def.columns.iter().map(|c| c.name.clone()).collect()
+            }
+            None => {
+                // positional VALUES map onto the classic (id UUID, name TEXT) layout
+                if values.len() != 2 {
+                    return Err(LocalDBError::SqlError(
+                        "INSERT without a column list expects (id, name)".into(),
+                    ));
+                }
+                if let LocalDBValue::TEXT(id) = &values[0] {
+                    values[0] = LocalDBValue::UUID(id.clone());
+                }
+                vec!["id".to_string(), "name".to_string()]
+            }
+        };
+
+        if columns.len() != values.len() {
+            return Err(LocalDBError::SqlError(format!(
+                "INSERT has {} columns but {} values",
+                columns.len(),
+                values.len()
+            )));
+        }
+
+        let row = columns.into_iter().zip(values).collect();
+        let before = self.tables.get(table).map_or(0, Vec::len);
+        self.insert_row(table, row)?;
+        self.record_inserts(table, before);
+
+        Ok(())
+    }
+
+    /// INSERT INTO table [(columns)] SELECT ...
+    ///
+    /// Without a column list rows are copied as-is; with one, the SELECT must
+    /// project the same number of columns, which are renamed positionally.
+    fn insert_from_select(&mut self, table: &str, columns: Option<Vec<String>>, select: &Select, failure: &Failure) -> Result<()> {
+        let rows = failure.check(self.run_select(select))?;
+
+        let rows: Vec<Row> = match columns {
+            None => rows,
+            Some(columns) => {
+                let source = match &select.columns {
+                    Some(source) if source.len() == columns.len() => source,
+                    _ => {
+                        return Err(LocalDBError::SqlError(format!(
+                            "INSERT into {} lists {} columns; the SELECT must project the same number",
+                            table,
+                            columns.len()
+                        )))
+                    }
+                };
+
+                rows.into_iter()
+                    .map(|mut row| {
+                        source
+                            .iter()
+                            .zip(&columns)
+                            .filter_map(|(from, to)| row.remove(&from.name).map(|v| (to.clone(), v)))
+                            .collect()
+                    })
+                    .collect()
+            }
+        };
+
+        self.insert_rows(table, rows).map(|_| ())
+    }
+
+    /// Insert `rows` all or nothing: if one is rejected, the rows already
+    /// added are taken out again
+    fn insert_rows(&mut self, table: &str, rows: impl IntoIterator<Item = Row>) -> Result<usize> {
+        let before = self.tables.get(table).map_or(0, Vec::len);
+
+        for row in rows {
+            if let Err(e) = self.insert_row(table, row) {
+                if let Some(rows) = self.tables.get_mut(table) {
+                    rows.truncate(before);
+                }
+                self.rebuild_indexes(table);
+                return Err(e);
+            }
+        }
+        self.record_inserts(table, before);
+
+        Ok(self.tables.get(table).map_or(0, Vec::len) - before)
+    }
+
+    /// DELETE FROM table [WHERE predicate] [LIMIT n]
+    ///
+    /// With LIMIT, only the first n matching rows (in insertion order) go.
+    fn handle_delete(&mut self, sql: &str) -> Result<Outcome> {
+        let mut delete = Parser::new(sql)?.parse_delete()?;
+        self.table_rows(&delete.table)?;
+        let failure = self.bind_calls(|bind| delete.filter.iter_mut().try_for_each(|filter| filter.walk_exprs_mut(bind)))?;
+        if let Some(returning) = &mut delete.returning {
+            returning.walk_exprs_mut(&mut functions::reject_calls("RETURNING"))?;
+        }
+
+        let filter = self.bind_subqueries(delete.filter.as_ref())?;
+        let mut positions = failure.check(self.matching_positions(&delete.table, filter.as_ref()))?;
+        if let Some(limit) = delete.limit {
+            positions.truncate(limit);
+        }
+        if positions.is_empty() {
+            return Ok(Outcome { affected: 0, returning: delete.returning.map(|_| Vec::new()) });
+        }
+
+        let removed = self.remove_positions(&delete.table, positions);
+        let outcome = Outcome {
+            affected: removed.len(),
+            returning: delete.returning.map(|returning| returning.project(&removed)),
+        };
+        self.listeners.push(ChangeEvent { kind: ChangeKind::Delete, table: delete.table, rows: removed });
+
+        Ok(outcome)
+    }
+
+    /// Remove the rows at the ascending `positions` of `table`, returning them
+    fn remove_positions(&mut self, table: &str, positions: Vec<usize>) -> Vec<Row> {
+        let Some(rows) = self.tables.get_mut(table) else {
+            return Vec::new();
+        };
+        let mut targets = positions.into_iter().peekable();
+        let (mut kept, mut removed) = (Vec::new(), Vec::new());
+        for (pos, row) in std::mem::take(rows).into_iter().enumerate() {
+            if targets.next_if_eq(&pos).is_some() {
+                removed.push(row);
+            } else {
+                kept.push(row);
+            }
+        }
+        *rows = kept;
+
+        self.rebuild_indexes(table);
+        self.invalidate_cache(table);
+        removed
+    }
+
+    /// UPDATE table SET column = expr, ... [WHERE predicate] [LIMIT n]
+    ///
+    /// Expressions see each row as it was before the statement. If any
+    /// updated row breaks a constraint, no row is changed.
+    fn handle_update(&mut self, sql: &str) -> Result<Outcome> {
+        let mut update = Parser::new(sql)?.parse_update()?;
+        let failure = self.bind_calls(|bind| {
+            update.assignments.iter_mut().try_for_each(|(_, expr)| expr.walk_mut(bind))?;
+            update.filter.iter_mut().try_for_each(|filter| filter.walk_exprs_mut(bind))
+        })?;
+        if let Some(returning) = &mut update.returning {
+            returning.walk_exprs_mut(&mut functions::reject_calls("RETURNING"))?;
+        }
+        let table = update.table.as_str();
+
+        let filter = self.bind_subqueries(update.filter.as_ref())?;
+        let mut positions = failure.check(self.matching_positions(table, filter.as_ref()))?;
+        if let Some(limit) = update.limit {
+            positions.truncate(limit);
+        }
+
+        let mut rows = self.table_rows(table)?.to_vec();
+        for &pos in &positions {
+            let mut row = rows[pos].clone();
+            for (column, expr) in &update.assignments {
+                row.insert(column.clone(), expr.eval(&rows[pos]));
+            }
+            failure.check(Ok(()))?;
+            self.check_constraints(table, &rows, &row, Some(pos))?;
+            rows[pos] = row;
+        }
+
+        if positions.is_empty() {
+            return Ok(Outcome { affected: 0, returning: update.returning.map(|_| Vec::new()) });
+        }
+
+        let changed: Vec<Row> = if self.listeners.active() || update.returning.is_some() {
+            positions.iter().map(|&pos| rows[pos].clone()).collect()
+        } else {
+            Vec::new()
+        };
+        let outcome = Outcome {
+            affected: positions.len(),
+            returning: update.returning.map(|returning| returning.project(&changed)),
+        };
+
+        self.tables.insert(table.to_string(), rows);
+        self.rebuild_indexes(table);
+        self.invalidate_cache(table);
+        self.listeners.push(ChangeEvent { kind: ChangeKind::Update, table: table.to_string(), rows: changed });
+
+        Ok(outcome)
+    }
+
+    /// Rows of an existing `table`
+    fn table_rows(&self, table: &str) -> Result<&[Row]> {
+        self.tables
+            .get(table)
+            .map(Vec::as_slice)
+            .ok_or_else(|| LocalDBError::TableNotFound(table.to_string()))
+    }
+
+    fn rebuild_indexes(&mut self, table: &str) {
+        let rows = self.tables.get(table).map(Vec::as_slice).unwrap_or_default();
+
+        for index in self.indexes.iter_mut().filter(|index| index.table == table) {
+            index.rebuild(rows);
+        }
+    }
+
+    fn insert_row(&mut self, table: &str, row: Row) -> Result<()> {
+        check_table_name(table)?;
+        check_arrays(&row)?;
+        let row = self.with_defaults(table, row);
+
+        let rows = self.tables.get(table).map(Vec::as_slice).unwrap_or_default();
+        self.check_constraints(table, rows, &row, None)?;
+        if let Some(max) = self.limits.max_rows {
+            if rows.len() >= max {
+                return Err(LocalDBError::LimitExceeded(format!("table {} is limited to {} rows", table, max)));
+            }
+        }
+        self.invalidate_cache(table);
+
+        let rows = self.tables.entry(table.to_string()).or_default();
+        let pos = rows.len();
+
+        for index in self.indexes.iter_mut().filter(|index| index.table == table) {
+            index.insert(&row, pos);
+        }
+
+        rows.push(row);
+
+        Ok(())
+    }
+}
+
+/// Reject ARRAY elements that are NULL, nested arrays or of mixed types
+fn check_array(items: &[LocalDBValue]) -> Result<()> {
+    let mut first = None;
+    for item in items {
+        let col_type = match ColType::of(item) {
+            None => return Err(LocalDBError::TypeMismatch("ARRAY elements cannot be NULL".into())),
+            Some(ColType::Array) => return Err(LocalDBError::TypeMismatch("ARRAY elements cannot be arrays".into())),
+            Some(col_type) => col_type,
+        };
+        match first {
+            None => first = Some(col_type),
+            Some(first) if first != col_type => {
+                return Err(LocalDBError::TypeMismatch(format!(
+                    "ARRAY elements must share one type, got {} and {}",
+                    first, col_type
+                )));
+            }
+            Some(_) => {}
+        }
+    }
+    Ok(())
+}
+
+/// `insert_typed` value of a serialized field of `table.column`, shaped by
+/// the column's declared type if any
+fn typed_value(table: &str, column: &str, field: serde_json::Value, declared: Option<ColType>) -> Result<LocalDBValue> {
+    use serde_json::Value;
+
+    let value = match (field, declared) {
+        (Value::Null, _) => LocalDBValue::NULL,
+        (field, Some(ColType::Json)) => LocalDBValue::JSON(field),
+        (Value::String(s), Some(ColType::Decimal)) => LocalDBValue::decimal(&s)
+            .map_err(|_| LocalDBError::TypeMismatch(format!("Value of {}.{} is not a DECIMAL: {}", table, column, s)))?,
+        (Value::Number(n), Some(ColType::Decimal)) => LocalDBValue::decimal(&n.to_string())
+            .map_err(|_| LocalDBError::TypeMismatch(format!("Value of {}.{} is not a DECIMAL: {}", table, column, n)))?,
+        (Value::Bool(b), _) => LocalDBValue::BOOL(b),
+        (Value::String(s), Some(ColType::Uuid)) => LocalDBValue::UUID(s),
+        (Value::String(s), _) => LocalDBValue::TEXT(s),
+        (Value::Number(n), Some(ColType::Real)) => LocalDBValue::REAL(n.as_f64().unwrap_or(f64::NAN)),
+        (Value::Number(n), Some(ColType::Timestamp)) if n.is_i64() => LocalDBValue::TIMESTAMP(n.as_i64().unwrap_or_default()),
+        (Value::Number(n), _) => match n.as_i64() {
+            Some(i) => LocalDBValue::INT(i),
+            None if n.is_u64() => {
+                return Err(LocalDBError::TypeMismatch(format!("Value of {}.{} is out of INT range: {}", table, column, n)))
+            }
+            None => LocalDBValue::REAL(n.as_f64().unwrap_or(f64::NAN)),
+        },
+        (Value::Array(items), _) => {
+            let items = items.into_iter().map(|item| typed_value(table, column, item, None)).collect::<Result<_>>()?;
+            LocalDBValue::array(items)?
+        }
+        (other, _) => {
+            return Err(LocalDBError::TypeMismatch(format!(
+                "Field {}.{} is not a scalar value: {}",
+                table, column, other
+            )))
+        }
+    };
+    Ok(value)
+}
+
+/// `row`, at position `pos` of its table, with its virtual `ROWID`
+fn with_rowid(row: &Row, pos: usize) -> Row {
+    let mut row = row.clone();
+    row.insert(sql::ROWID.to_string(), LocalDBValue::INT(pos as i64 + 1));
+    row
+}
+
+/// Plain JSON for a value, without the variant tags of its serde form:
+/// INT and TIMESTAMP (millis) are integers, REAL a number (null if not
+/// finite), TEXT, UUID and DECIMAL strings, JSON itself and ARRAY an array
+pub(crate) fn plain_json(value: &LocalDBValue) -> serde_json::Value {
+    use serde_json::Value as Json;
+
+    match value {
+        LocalDBValue::INT(n) | LocalDBValue::TIMESTAMP(n) => Json::from(*n),
+        LocalDBValue::REAL(x) => Json::from(*x),
+        LocalDBValue::TEXT(s) | LocalDBValue::UUID(s) | LocalDBValue::DECIMAL(s) => Json::from(s.as_str()),
+        LocalDBValue::BOOL(b) => Json::from(*b),
+        LocalDBValue::JSON(v) => v.clone(),
+        LocalDBValue::ARRAY(items) => items.iter().map(plain_json).collect(),
+        LocalDBValue::NULL => Json::Null,
+    }
+}
+
+/// `check_array` for every ARRAY value of `row`
+fn check_arrays(row: &Row) -> Result<()> {
+    for value in row.values() {
+        if let LocalDBValue::ARRAY(items) = value {
+            check_array(items)?;
+        }
+    }
+    Ok(())
+}
+
+/// Reject creating a table under one of the file's reserved keys
+fn check_table_name(table: &str) -> Result<()> {
+    if RESERVED_KEYS.contains(&table) {
+        return Err(LocalDBError::SqlError(format!("Table name {} is reserved", table)));
+    }
+    Ok(())
+}
+
+/// Non-empty `;`-separated statements of `sql`; a `;` inside quotes doesn't split
+fn statements(sql: &str) -> impl Iterator<Item = &str> {
+    sql::split_statements(sql).into_iter()
+}
+
+/// The columns each NATURAL JOIN of `select` joins on, given its `sources`:
+/// those of the joined table that the tables before it have. Failing if
+/// one of them is in two of those tables and no NATURAL JOIN merged it.
+fn natural_columns<'a>(select: &Select, sources: &[JoinSource<'a>]) -> Result<NaturalColumns<'a>> {
+    let (first, rest) = sources.split_first().expect("FROM table is always a source");
+    // each column's copies so far: a table's, or `None` for a merged one
+    let mut copies: HashMap<&str, Vec<Option<&'a str>>> = HashMap::new();
+    for column in &first.1 {
+        copies.entry(column).or_default().push(Some(first.0));
+    }
+
+    let mut natural = NaturalColumns::default();
+    for (join, (qualifier, columns, _)) in select.joins.iter().zip(rest) {
+        let mut keys = Vec::new();
+        for column in columns {
+            let seen = copies.entry(column).or_default();
+            if join.on.is_some() || seen.is_empty() {
+                seen.push(Some(*qualifier));
+                continue;
+            }
+
+            let left = match seen.as_slice() {
+                [Some(table)] => {
+                    natural.merged.push((column.clone(), vec![*table]));
+                    format!("{}.{}", table, column)
+                }
+                [None] => column.clone(),
+                _ => {
+                    return Err(LocalDBError::SqlError(format!(
+                        "Column {} is in more than one table before NATURAL JOIN {}",
+                        column, join.table
+                    )))
+                }
+            };
+            if let Some((_, tables)) = natural.merged.iter_mut().find(|(c, _)| c == column) {
+                tables.push(qualifier);
+            }
+            *seen = vec![None];
+            keys.push((column.clone(), left));
+        }
+        natural.keys.push(keys);
+    }
+    Ok(natural)
+}
+
+/// Parse `sql` as a query, rejecting anything but SELECT up front
+fn parse_select_query(sql: &str) -> Result<Query> {
+    let sql = sql.trim();
+
+    if !sql.starts_with("SELECT") {
+        return Err(LocalDBError::Parse("Only SELECT is supported".into()));
+    }
+
+    Parser::new(sql)?.parse_query()
 }
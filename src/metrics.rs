@@ -0,0 +1,56 @@
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Which call an [`OpMetrics`] measures
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    /// `exec` or `exec_returning`, including the saves it makes
+    Exec,
+    /// `query` or `query_result`
+    Query,
+    /// Writing the database file
+    Save,
+}
+
+/// Timing of one successful operation.
+///
+/// `rows` is what the operation touched: rows inserted, updated or deleted
+/// for `Exec`, rows returned for `Query`, rows written for `Save`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpMetrics {
+    pub op: Operation,
+    pub elapsed: Duration,
+    pub rows: usize,
+}
+
+type Sink = Box<dyn Fn(OpMetrics) + Send>;
+
+/// The `set_metrics` sink, if one is registered
+#[derive(Default)]
+pub(crate) struct Metrics {
+    sink: Option<Sink>,
+}
+
+impl Metrics {
+    pub fn set(&mut self, sink: Sink) {
+        self.sink = Some(sink);
+    }
+
+    /// Start timing an operation; `None`, and no clock read, without a sink
+    pub fn start(&self) -> Option<Instant> {
+        self.sink.as_ref().map(|_| Instant::now())
+    }
+
+    /// Report an operation timed from `started`
+    pub fn record(&self, op: Operation, started: Option<Instant>, rows: usize) {
+        if let (Some(sink), Some(started)) = (&self.sink, started) {
+            sink(OpMetrics { op, elapsed: started.elapsed(), rows });
+        }
+    }
+}
+
+impl fmt::Debug for Metrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Metrics").field("sink", &self.sink.is_some()).finish()
+    }
+}
@@ -0,0 +1,138 @@
+use crate::decimal::{Decimal, Rounding};
+use crate::functions::UserFunction;
+use crate::sql::{compare_values, render_literal, Comparison};
+use crate::{LocalDBError, LocalDBValue, Result};
+use std::cmp::Ordering;
+use std::sync::Arc;
+
+type Numeric = fn(&[LocalDBValue]) -> Result<LocalDBValue>;
+
+/// The built-in numeric function `name` (lowercased), called like a
+/// registered function, with the fewest and most arguments it takes;
+/// `usize::MAX` for no most
+pub(crate) fn built_in(name: &str) -> Option<(UserFunction, usize, usize)> {
+    let (func, min, max): (Numeric, usize, usize) = match name {
+        "abs" => (abs, 1, 1),
+        "round" => (round, 1, 2),
+        "ceil" | "ceiling" => (ceil, 1, 1),
+        "floor" => (floor, 1, 1),
+        "greatest" => (greatest, 1, usize::MAX),
+        "least" => (least, 1, usize::MAX),
+        _ => return None,
+    };
+    Some((Arc::new(func), min, max))
+}
+
+/// `ABS(x)`; NULL when the INT result overflows
+fn abs(args: &[LocalDBValue]) -> Result<LocalDBValue> {
+    Ok(match number("ABS", &args[0])? {
+        LocalDBValue::INT(n) => n.checked_abs().map_or(LocalDBValue::NULL, LocalDBValue::INT),
+        LocalDBValue::REAL(x) => LocalDBValue::REAL(x.abs()),
+        LocalDBValue::DECIMAL(s) => decimal(Decimal::parse(&s).and_then(Decimal::checked_abs)),
+        _ => LocalDBValue::NULL,
+    })
+}
+
+/// `ROUND(x[, digits])`: to `digits` fraction digits, 0 by default, or to
+/// tens, hundreds and so on when negative; halves round away from zero.
+/// The result keeps the argument's type.
+fn round(args: &[LocalDBValue]) -> Result<LocalDBValue> {
+    let digits = match args.get(1) {
+        None => 0,
+        Some(LocalDBValue::INT(n)) => *n,
+        Some(LocalDBValue::NULL) => return Ok(LocalDBValue::NULL),
+        Some(other) => {
+            return Err(LocalDBError::TypeMismatch(format!("ROUND takes INT digits, not {}", render_literal(other))))
+        }
+    };
+
+    Ok(match number("ROUND", &args[0])? {
+        LocalDBValue::INT(n) if digits >= 0 => LocalDBValue::INT(n),
+        LocalDBValue::INT(n) => {
+            let rounded = Decimal::from_int(n).rounded(digits, Rounding::HalfAway);
+            rounded.and_then(Decimal::to_i64).map_or(LocalDBValue::NULL, LocalDBValue::INT)
+        }
+        LocalDBValue::REAL(x) => LocalDBValue::REAL(round_real(x, digits)),
+        LocalDBValue::DECIMAL(s) => decimal(Decimal::parse(&s).and_then(|d| d.rounded(digits, Rounding::HalfAway))),
+        _ => LocalDBValue::NULL,
+    })
+}
+
+fn round_real(x: f64, digits: i64) -> f64 {
+    // past about 300 digits either way the scale factor itself overflows
+    let factor = 10f64.powi(digits.clamp(-300, 300) as i32);
+    let scaled = x * factor;
+    if !scaled.is_finite() {
+        return x;
+    }
+    scaled.round() / factor
+}
+
+/// `CEIL(x)`, also spelled `CEILING`: the smallest whole number not below `x`
+fn ceil(args: &[LocalDBValue]) -> Result<LocalDBValue> {
+    whole("CEIL", &args[0], f64::ceil, Rounding::Ceil)
+}
+
+/// `FLOOR(x)`: the largest whole number not above `x`
+fn floor(args: &[LocalDBValue]) -> Result<LocalDBValue> {
+    whole("FLOOR", &args[0], f64::floor, Rounding::Floor)
+}
+
+fn whole(name: &str, value: &LocalDBValue, real: fn(f64) -> f64, rounding: Rounding) -> Result<LocalDBValue> {
+    Ok(match number(name, value)? {
+        LocalDBValue::INT(n) => LocalDBValue::INT(n),
+        LocalDBValue::REAL(x) => LocalDBValue::REAL(real(x)),
+        LocalDBValue::DECIMAL(s) => decimal(Decimal::parse(&s).and_then(|d| d.rounded(0, rounding))),
+        _ => LocalDBValue::NULL,
+    })
+}
+
+/// `GREATEST(a, b, ...)`: the largest argument, NULLs ignored
+fn greatest(args: &[LocalDBValue]) -> Result<LocalDBValue> {
+    extreme("GREATEST", args, Ordering::Greater)
+}
+
+/// `LEAST(a, b, ...)`: the smallest argument, NULLs ignored
+fn least(args: &[LocalDBValue]) -> Result<LocalDBValue> {
+    extreme("LEAST", args, Ordering::Less)
+}
+
+/// The argument that compares `wanted` against all the others, as it is,
+/// so `GREATEST(1, 2.5)` is REAL and `GREATEST(3, 2.5)` INT; the first of
+/// equal ones. NULL only when every argument is. Numbers compare
+/// numerically and TEXT by code point; other mixes fail `name`.
+fn extreme(name: &str, args: &[LocalDBValue], wanted: Ordering) -> Result<LocalDBValue> {
+    let mut best: Option<&LocalDBValue> = None;
+    for value in args.iter().filter(|value| !matches!(value, LocalDBValue::NULL)) {
+        let Some(current) = best else {
+            best = Some(value);
+            continue;
+        };
+        match compare_values(value, current, Comparison::STRICT) {
+            Some(ord) if ord == wanted => best = Some(value),
+            Some(_) => {}
+            None => {
+                return Err(LocalDBError::TypeMismatch(format!(
+                    "{} can't compare {} with {}",
+                    name,
+                    render_literal(current),
+                    render_literal(value)
+                )))
+            }
+        }
+    }
+    Ok(best.cloned().unwrap_or(LocalDBValue::NULL))
+}
+
+/// `value` if it is INT, REAL, DECIMAL or NULL; anything else fails `name`
+fn number(name: &str, value: &LocalDBValue) -> Result<LocalDBValue> {
+    match value {
+        LocalDBValue::INT(_) | LocalDBValue::REAL(_) | LocalDBValue::DECIMAL(_) | LocalDBValue::NULL => Ok(value.clone()),
+        other => Err(LocalDBError::TypeMismatch(format!("{} takes a number, not {}", name, render_literal(other)))),
+    }
+}
+
+/// A DECIMAL result, or NULL if it couldn't be computed exactly
+fn decimal(value: Option<Decimal>) -> LocalDBValue {
+    value.map_or(LocalDBValue::NULL, |d| LocalDBValue::DECIMAL(d.to_string()))
+}
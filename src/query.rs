@@ -0,0 +1,197 @@
+use crate::sql::{self, CmpOp, Predicate, Select, SelectItem};
+use crate::{LocalDB, LocalDBValue, Result, Row};
+use std::io::Write;
+
+/// A WHERE condition for [`SelectBuilder`]
+#[derive(Debug, Clone)]
+pub struct Filter(pub(crate) Predicate);
+
+impl Filter {
+    fn compare(column: &str, op: CmpOp, value: impl Into<LocalDBValue>) -> Self {
+        Filter(Predicate::Compare { column: column.to_string(), op, value: value.into() })
+    }
+
+    /// `column = value`
+    pub fn eq(column: &str, value: impl Into<LocalDBValue>) -> Self {
+        Self::compare(column, CmpOp::Eq, value)
+    }
+
+    /// `column != value`
+    pub fn ne(column: &str, value: impl Into<LocalDBValue>) -> Self {
+        Self::compare(column, CmpOp::Ne, value)
+    }
+
+    /// `column < value`
+    pub fn lt(column: &str, value: impl Into<LocalDBValue>) -> Self {
+        Self::compare(column, CmpOp::Lt, value)
+    }
+
+    /// `column <= value`
+    pub fn le(column: &str, value: impl Into<LocalDBValue>) -> Self {
+        Self::compare(column, CmpOp::Le, value)
+    }
+
+    /// `column > value`
+    pub fn gt(column: &str, value: impl Into<LocalDBValue>) -> Self {
+        Self::compare(column, CmpOp::Gt, value)
+    }
+
+    /// `column >= value`
+    pub fn ge(column: &str, value: impl Into<LocalDBValue>) -> Self {
+        Self::compare(column, CmpOp::Ge, value)
+    }
+
+    /// `column BETWEEN low AND high`
+    pub fn between(column: &str, low: impl Into<LocalDBValue>, high: impl Into<LocalDBValue>) -> Self {
+        Filter(Predicate::Between { column: column.to_string(), low: low.into(), high: high.into() })
+    }
+
+    /// `column LIKE pattern`
+    pub fn like(column: &str, pattern: &str) -> Self {
+        Filter(Predicate::Like {
+            column: column.to_string(),
+            pattern: pattern.to_string(),
+            case_insensitive: false,
+            negated: false,
+        })
+    }
+
+    /// `column ILIKE pattern`
+    pub fn ilike(column: &str, pattern: &str) -> Self {
+        Filter(Predicate::Like {
+            column: column.to_string(),
+            pattern: pattern.to_string(),
+            case_insensitive: true,
+            negated: false,
+        })
+    }
+}
+
+/// Rows returned by [`LocalDB::query_result`], with their column order
+#[derive(Debug, Clone)]
+pub struct QueryResult {
+    pub columns: Vec<String>,
+    pub rows: Vec<Row>,
+}
+
+impl QueryResult {
+    /// Each row's values in column order; `None` where a row lacks a column
+    pub fn values(&self) -> impl Iterator<Item = Vec<Option<&LocalDBValue>>> + '_ {
+        self.rows
+            .iter()
+            .map(|row| self.columns.iter().map(|c| row.get(c)).collect())
+    }
+
+    /// The rows as an HTML `<table>` for debugging, one header cell per
+    /// column. Values appear in their display form, NULL and ARRAYs as SQL
+    /// literals, and a missing column as an empty cell; all text is escaped.
+    pub fn to_html(&self) -> String {
+        let mut html = String::from("<table>\n  <tr>");
+        for column in &self.columns {
+            html.push_str(&format!("<th>{}</th>", escape_html(column)));
+        }
+        html.push_str("</tr>\n");
+
+        for values in self.values() {
+            html.push_str("  <tr>");
+            for value in values {
+                let text = value.map(|v| sql::display_text(v).unwrap_or_else(|| sql::render_literal(v)));
+                html.push_str(&format!("<td>{}</td>", escape_html(&text.unwrap_or_default())));
+            }
+            html.push_str("</tr>\n");
+        }
+
+        html.push_str("</table>\n");
+        html
+    }
+
+    /// Write the rows to `writer` as CSV, a header line of column names
+    /// first, one `\n`-terminated line per row as it is formatted. Values
+    /// appear in their display form and ARRAYs as SQL literals; NULL and a
+    /// missing column are empty fields. Fields holding a comma, quote or line
+    /// break are double-quoted.
+    pub fn write_csv(&self, mut writer: impl Write) -> std::io::Result<()> {
+        let header: Vec<String> = self.columns.iter().map(|c| escape_csv(c)).collect();
+        writeln!(writer, "{}", header.join(","))?;
+
+        for values in self.values() {
+            let fields: Vec<String> = values
+                .into_iter()
+                .map(|value| match value {
+                    None | Some(LocalDBValue::NULL) => String::new(),
+                    Some(v) => escape_csv(&sql::display_text(v).unwrap_or_else(|| sql::render_literal(v))),
+                })
+                .collect();
+            writeln!(writer, "{}", fields.join(","))?;
+        }
+        writer.flush()
+    }
+}
+
+fn escape_csv(text: &str) -> String {
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Fluent SELECT, built with [`LocalDB::select`]
+#[derive(Debug)]
+pub struct SelectBuilder<'a> {
+    db: &'a LocalDB,
+    select: Select,
+}
+
+impl<'a> SelectBuilder<'a> {
+    pub(crate) fn new(db: &'a LocalDB, table: &str) -> Self {
+        Self {
+            db,
+            select: Select { table: table.to_string(), alias: None, joins: Vec::new(), distinct_on: Vec::new(), columns: None, filter: None, group_by: Vec::new(), having: None, order_by: Vec::new(), limit: None, offset: None },
+        }
+    }
+
+    /// Project only these columns instead of `*`
+    pub fn columns(mut self, columns: &[&str]) -> Self {
+        self.select.columns = Some(columns.iter().map(|c| SelectItem::column(c)).collect());
+        self
+    }
+
+    /// Set the WHERE condition, replacing any previous one
+    pub fn filter(mut self, filter: Filter) -> Self {
+        self.select.filter = Some(filter.0);
+        self
+    }
+
+    /// Return at most `n` rows
+    pub fn limit(mut self, n: usize) -> Self {
+        self.select.limit = Some(n);
+        self
+    }
+
+    /// Skip the first `n` rows
+    pub fn offset(mut self, n: usize) -> Self {
+        self.select.offset = Some(n);
+        self
+    }
+
+    /// Execute the query
+    pub fn run(self) -> Result<Vec<Row>> {
+        self.db.scan_limited(|| self.db.run_select(&self.select))
+    }
+}
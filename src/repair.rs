@@ -0,0 +1,93 @@
+use crate::{check_arrays, ChangeEvent, ChangeKind, LocalDB, LocalDBError, Result, Row};
+use std::collections::BTreeMap;
+
+/// Rows that break their table's schema, by table: those found by
+/// [`LocalDB::verify`] or removed by [`LocalDB::repair`]
+#[derive(Debug, Default)]
+pub struct RepairReport {
+    /// Each table's invalid rows, in table order
+    pub removed: BTreeMap<String, Vec<RemovedRow>>,
+}
+
+impl RepairReport {
+    /// Whether every row was valid
+    pub fn is_empty(&self) -> bool {
+        self.removed.is_empty()
+    }
+
+    /// Number of invalid rows across all tables
+    pub fn count(&self) -> usize {
+        self.removed.values().map(Vec::len).sum()
+    }
+}
+
+/// A row breaking its table's schema; one [`LocalDB::repair`] removed is
+/// kept here so it can be fixed and put back
+#[derive(Debug)]
+pub struct RemovedRow {
+    /// Position of the row in its table, before any repair
+    pub position: usize,
+    pub row: Row,
+    /// The first problem found with the row
+    pub error: LocalDBError,
+}
+
+impl LocalDB {
+    /// Remove every row that breaks its table's schema, in one save: a
+    /// value that isn't of its column's declared type, a missing NOT NULL
+    /// or PRIMARY KEY value, a malformed ARRAY, or a PRIMARY KEY or UNIQUE
+    /// value an earlier row already has. Tables without a schema are left
+    /// alone. For salvaging a file edited by hand or written before a schema
+    /// change; nothing is repaired unless this is called.
+    ///
+    /// Listeners get a delete event for each table rows were removed from.
+    /// If the save fails, nothing is removed.
+    pub fn repair(&mut self) -> Result<RepairReport> {
+        self.check_writable()?;
+
+        let report = self.verify();
+        if report.is_empty() {
+            return Ok(report);
+        }
+
+        let checkpoint = self.checkpoint();
+        for (table, removed) in &report.removed {
+            let rows = self.remove_positions(table, removed.iter().map(|r| r.position).collect());
+            self.listeners.push(ChangeEvent { kind: ChangeKind::Delete, table: table.clone(), rows });
+        }
+        if let Err(e) = self.save_and_notify() {
+            self.restore(checkpoint);
+            return Err(e);
+        }
+        Ok(report)
+    }
+
+    /// The rows `repair` would remove, changing nothing: for checking data
+    /// added without validation, such as by `bulk_load_unchecked`. Every
+    /// other write checks rows by the same rules, so data written through
+    /// them is always valid.
+    pub fn verify(&self) -> RepairReport {
+        let mut report = RepairReport::default();
+        for table in self.schema.keys() {
+            let Some(rows) = self.tables.get(table) else { continue };
+
+            let (mut kept, mut removed) = (Vec::new(), Vec::new());
+            for (position, row) in rows.iter().enumerate() {
+                // the checks every write makes, against the rows kept so far,
+                // scanning rather than using the indexes
+                let error = check_arrays(row).err().or_else(|| {
+                    self.constraint_violations(table, &kept, row, Some(usize::MAX)).into_iter().next().map(|(_, e)| e)
+                });
+                match error {
+                    Some(error) => removed.push(RemovedRow { position, row: row.clone(), error }),
+                    None => kept.push(row.clone()),
+                }
+            }
+
+            if !removed.is_empty() {
+                report.removed.insert(table.clone(), removed);
+            }
+        }
+        report
+    }
+}
@@ -0,0 +1,323 @@
+use crate::decimal::Decimal;
+use crate::{sql, LocalDB, LocalDBError, LocalDBValue, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Declared type of a column
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ColType {
+    Int,
+    Real,
+    Text,
+    Uuid,
+    Bool,
+    Json,
+    Timestamp,
+    Decimal,
+    Array,
+}
+
+impl ColType {
+    /// Parse a SQL type name such as `INT` or `TEXT`
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_uppercase().as_str() {
+            "INT" | "INTEGER" => Some(ColType::Int),
+            "REAL" | "FLOAT" | "DOUBLE" => Some(ColType::Real),
+            "TEXT" => Some(ColType::Text),
+            "UUID" => Some(ColType::Uuid),
+            "BOOL" | "BOOLEAN" => Some(ColType::Bool),
+            "JSON" => Some(ColType::Json),
+            "TIMESTAMP" => Some(ColType::Timestamp),
+            "DECIMAL" | "NUMERIC" => Some(ColType::Decimal),
+            "ARRAY" => Some(ColType::Array),
+            _ => None,
+        }
+    }
+
+    /// Type that naturally holds `value`; `None` for NULL
+    pub fn of(value: &LocalDBValue) -> Option<Self> {
+        match value {
+            LocalDBValue::INT(_) => Some(ColType::Int),
+            LocalDBValue::REAL(_) => Some(ColType::Real),
+            LocalDBValue::TEXT(_) => Some(ColType::Text),
+            LocalDBValue::UUID(_) => Some(ColType::Uuid),
+            LocalDBValue::BOOL(_) => Some(ColType::Bool),
+            LocalDBValue::JSON(_) => Some(ColType::Json),
+            LocalDBValue::TIMESTAMP(_) => Some(ColType::Timestamp),
+            LocalDBValue::DECIMAL(_) => Some(ColType::Decimal),
+            LocalDBValue::ARRAY(_) => Some(ColType::Array),
+            LocalDBValue::NULL => None,
+        }
+    }
+
+    /// Whether a column of this type may hold `value`, NULL included: of
+    /// the type itself, or of one the database stores in such a column,
+    /// as TEXT in UUID columns and INT in REAL, DECIMAL and TIMESTAMP ones
+    pub(crate) fn holds(self, value: &LocalDBValue) -> bool {
+        match (self, ColType::of(value)) {
+            (_, None) | (ColType::Json, _) => true,
+            (ColType::Uuid | ColType::Text, Some(ColType::Text | ColType::Uuid)) => true,
+            (ColType::Real | ColType::Decimal | ColType::Timestamp, Some(ColType::Int)) => true,
+            (declared, Some(actual)) => declared == actual,
+        }
+    }
+
+    /// `value` converted to this type, for `cast_column`: numbers between
+    /// each other when nothing is lost, text parsed, BOOL as 1 or 0, and
+    /// scalars to TEXT as displayed. NULL stays NULL; `None` if it can't convert.
+    pub(crate) fn cast(self, value: &LocalDBValue) -> Option<LocalDBValue> {
+        use LocalDBValue::*;
+
+        let whole = |x: f64| (x.fract() == 0.0 && x.abs() < 9.0e18).then_some(x as i64);
+        let cast = match (self, value) {
+            (_, NULL) => NULL,
+            (declared, value) if ColType::of(value) == Some(declared) => value.clone(),
+            (ColType::Int, TEXT(s)) => INT(s.trim().parse().ok()?),
+            (ColType::Int, REAL(x)) => INT(whole(*x)?),
+            (ColType::Int, DECIMAL(s)) => INT(Decimal::parse(s)?.to_i64()?),
+            (ColType::Int, BOOL(b)) => INT(i64::from(*b)),
+            (ColType::Int, TIMESTAMP(ms)) => INT(*ms),
+            (ColType::Real, INT(n)) => REAL(*n as f64),
+            (ColType::Real, DECIMAL(s)) => REAL(Decimal::parse(s)?.to_f64()),
+            (ColType::Real, TEXT(s)) => REAL(s.trim().parse::<f64>().ok().filter(|x| x.is_finite())?),
+            (ColType::Decimal, INT(n)) => DECIMAL(n.to_string()),
+            (ColType::Decimal, REAL(x)) if x.is_finite() => DECIMAL(Decimal::parse(&x.to_string())?.to_string()),
+            (ColType::Decimal, TEXT(s)) => DECIMAL(Decimal::parse(s)?.to_string()),
+            (ColType::Bool, INT(0)) => BOOL(false),
+            (ColType::Bool, INT(1)) => BOOL(true),
+            (ColType::Bool, TEXT(s)) if s.trim().eq_ignore_ascii_case("true") => BOOL(true),
+            (ColType::Bool, TEXT(s)) if s.trim().eq_ignore_ascii_case("false") => BOOL(false),
+            (ColType::Timestamp, INT(n)) => TIMESTAMP(*n),
+            (ColType::Timestamp, TEXT(s)) => TIMESTAMP(s.trim().parse().ok()?),
+            (ColType::Uuid, TEXT(s)) => UUID(s.clone()),
+            (ColType::Json, TEXT(s)) => JSON(serde_json::from_str(s).ok()?),
+            (ColType::Json, INT(n)) => JSON((*n).into()),
+            (ColType::Json, REAL(x)) => JSON(serde_json::Number::from_f64(*x)?.into()),
+            (ColType::Json, BOOL(b)) => JSON((*b).into()),
+            (ColType::Text, value) => TEXT(sql::display_text(value)?),
+            _ => return None,
+        };
+        Some(cast)
+    }
+}
+
+impl fmt::Display for ColType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ColType::Int => "INT",
+            ColType::Real => "REAL",
+            ColType::Text => "TEXT",
+            ColType::Uuid => "UUID",
+            ColType::Bool => "BOOL",
+            ColType::Json => "JSON",
+            ColType::Timestamp => "TIMESTAMP",
+            ColType::Decimal => "DECIMAL",
+            ColType::Array => "ARRAY",
+        })
+    }
+}
+
+/// A column as declared by `CREATE TABLE`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ColumnDef {
+    pub name: String,
+    pub col_type: ColType,
+    /// Values must be unique and not NULL
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub primary_key: bool,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub not_null: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+    /// Value stored when an insert leaves the column out
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default: Option<ColumnDefault>,
+}
+
+impl ColumnDef {
+    pub fn new(name: &str, col_type: ColType) -> Self {
+        Self { name: name.to_string(), col_type, primary_key: false, not_null: false, comment: None, default: None }
+    }
+}
+
+/// A column's `DEFAULT`: a fixed value, or `now()` for the insert time
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ColumnDefault {
+    Value(LocalDBValue),
+    Now,
+}
+
+impl ColumnDefault {
+    /// Value to store for a row inserted now
+    pub fn value(&self) -> LocalDBValue {
+        match self {
+            ColumnDefault::Value(value) => value.clone(),
+            ColumnDefault::Now => LocalDBValue::now(),
+        }
+    }
+}
+
+impl fmt::Display for ColumnDefault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ColumnDefault::Value(value) => f.write_str(&sql::render_literal(value)),
+            ColumnDefault::Now => f.write_str("now()"),
+        }
+    }
+}
+
+fn is_false(b: &bool) -> bool {
+    !*b
+}
+
+/// A table as declared by `CREATE TABLE`, columns in declaration order
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TableDef {
+    pub name: String,
+    pub columns: Vec<ColumnDef>,
+    /// `UNIQUE` column sets; no two rows may share values in all of a set's columns
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub unique: Vec<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+}
+
+impl TableDef {
+    pub fn new(name: &str) -> Self {
+        Self { name: name.to_string(), columns: Vec::new(), unique: Vec::new(), comment: None }
+    }
+
+    /// Declared column named `name`
+    pub fn column(&self, name: &str) -> Option<&ColumnDef> {
+        self.columns.iter().find(|c| c.name == name)
+    }
+
+    /// The PRIMARY KEY column, if one is declared
+    pub fn primary_key(&self) -> Option<&ColumnDef> {
+        self.columns.iter().find(|c| c.primary_key)
+    }
+}
+
+/// How strictly [`LocalDB::assert_schema_with`] compares tables
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SchemaCheck {
+    /// Stored tables may have columns the expected definition doesn't list
+    #[default]
+    AllowExtra,
+    /// Stored tables must have exactly the expected columns
+    Exact,
+}
+
+impl TableDef {
+    /// Ways `stored` differs from this expected definition; comments and
+    /// column order are not compared
+    pub(crate) fn drift(&self, stored: &TableDef, check: SchemaCheck) -> Vec<String> {
+        let mut problems = Vec::new();
+
+        for expected in &self.columns {
+            let Some(column) = stored.column(&expected.name) else {
+                problems.push(format!("missing column {}.{}", self.name, expected.name));
+                continue;
+            };
+            if column.col_type != expected.col_type {
+                problems.push(format!(
+                    "column {}.{} is {}, expected {}",
+                    self.name, expected.name, column.col_type, expected.col_type
+                ));
+            }
+            if column.primary_key != expected.primary_key {
+                problems.push(format!(
+                    "column {}.{} {} PRIMARY KEY",
+                    self.name,
+                    expected.name,
+                    if expected.primary_key { "is not" } else { "is unexpectedly" }
+                ));
+            }
+            if column.not_null != expected.not_null {
+                problems.push(format!(
+                    "column {}.{} {} NOT NULL",
+                    self.name,
+                    expected.name,
+                    if expected.not_null { "is not" } else { "is unexpectedly" }
+                ));
+            }
+        }
+
+        for key in self.unique.iter().filter(|key| !stored.unique.contains(key)) {
+            problems.push(format!("missing UNIQUE ({}) on {}", key.join(", "), self.name));
+        }
+
+        if check == SchemaCheck::Exact {
+            for column in stored.columns.iter().filter(|c| self.column(&c.name).is_none()) {
+                problems.push(format!("unexpected column {}.{}", self.name, column.name));
+            }
+        }
+
+        problems
+    }
+}
+
+/// Programmatic `CREATE TABLE`, built with [`LocalDB::create_table`]
+#[derive(Debug)]
+pub struct TableBuilder<'a> {
+    db: &'a mut LocalDB,
+    def: TableDef,
+    unknown: Vec<String>,
+}
+
+impl<'a> TableBuilder<'a> {
+    pub(crate) fn new(db: &'a mut LocalDB, table: &str) -> Self {
+        Self { db, def: TableDef::new(table), unknown: Vec::new() }
+    }
+
+    /// Declare a column; columns keep the order they are declared in
+    pub fn column(mut self, name: &str, col_type: ColType) -> Self {
+        self.def.columns.push(ColumnDef::new(name, col_type));
+        self
+    }
+
+    /// Make a declared column the PRIMARY KEY
+    pub fn primary_key(self, name: &str) -> Self {
+        self.flag(name, |c| c.primary_key = true)
+    }
+
+    /// Make a declared column NOT NULL
+    pub fn not_null(self, name: &str) -> Self {
+        self.flag(name, |c| c.not_null = true)
+    }
+
+    /// Make a set of declared columns UNIQUE together
+    pub fn unique(mut self, columns: &[&str]) -> Self {
+        self.def.unique.push(columns.iter().map(|c| c.to_string()).collect());
+        self
+    }
+
+    /// Give a declared column a `DEFAULT`
+    pub fn default(self, name: &str, default: ColumnDefault) -> Self {
+        self.flag(name, |c| c.default = Some(default))
+    }
+
+    /// Set the table comment
+    pub fn comment(mut self, text: &str) -> Self {
+        self.def.comment = Some(text.to_string());
+        self
+    }
+
+    fn flag(mut self, name: &str, set: impl FnOnce(&mut ColumnDef)) -> Self {
+        match self.def.columns.iter_mut().find(|c| c.name == name) {
+            Some(column) => set(column),
+            None => self.unknown.push(name.to_string()),
+        }
+        self
+    }
+
+    /// Create the table, exactly as the equivalent `CREATE TABLE` would
+    pub fn build(self) -> Result<()> {
+        if let Some(name) = self.unknown.first() {
+            return Err(LocalDBError::ColumnNotFound(format!("{}.{}", self.def.name, name)));
+        }
+
+        self.db.define_table(self.def)?;
+        self.db.save()
+    }
+}
@@ -0,0 +1,2849 @@
+use crate::decimal::Decimal;
+use crate::functions::BoundCall;
+use crate::index::IndexKey;
+use crate::{Collation, ColType, ColumnDef, ColumnDefault, Coercion, LocalDBError, LocalDBValue, Result, Row, TableDef};
+use std::cmp::Ordering;
+use std::collections::BTreeSet;
+use std::fmt;
+
+// ========================= TOKENIZER =============================
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Token {
+    Ident(String),
+    /// "double-quoted" identifier, never treated as a keyword
+    Quoted(String),
+    Str(String),
+    Number(String),
+    Symbol(&'static str),
+}
+
+const SYMBOLS: [&str; 22] = [
+    "->>", "->", "<=", ">=", "!=", "<>", "||", "=", "<", ">", "*", "/", "%", "+", "-", ",", "(", ")", "[", "]", ";", ".",
+];
+
+pub(crate) fn tokenize(sql: &str) -> Result<Vec<Token>> {
+    let chars: Vec<char> = sql.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+
+        if c.is_whitespace() {
+            i += 1;
+        } else if c == '\'' || c == '"' {
+            // quotes are escaped by doubling them, in strings and identifiers alike
+            let mut s = String::new();
+            i += 1;
+            loop {
+                match chars.get(i) {
+                    Some(&q) if q == c && chars.get(i + 1) == Some(&c) => {
+                        s.push(c);
+                        i += 2;
+                    }
+                    Some(&q) if q == c => {
+                        i += 1;
+                        break;
+                    }
+                    Some(&ch) => {
+                        s.push(ch);
+                        i += 1;
+                    }
+                    None if c == '"' => return Err(LocalDBError::Parse("Unterminated quoted identifier".into())),
+                    None => return Err(LocalDBError::Parse("Unterminated string literal".into())),
+                }
+            }
+
+            if c == '\'' {
+                tokens.push(Token::Str(s));
+            } else if s.is_empty() {
+                return Err(LocalDBError::Parse("Empty quoted identifier".into()));
+            } else {
+                tokens.push(Token::Quoted(s));
+            }
+        } else if c.is_ascii_digit() {
+            let start = i;
+            while i < chars.len() && chars[i].is_ascii_digit() {
+                i += 1;
+            }
+            if chars.get(i) == Some(&'.') && chars.get(i + 1).is_some_and(|n| n.is_ascii_digit()) {
+                i += 1;
+                while i < chars.len() && chars[i].is_ascii_digit() {
+                    i += 1;
+                }
+            }
+            tokens.push(Token::Number(chars[start..i].iter().collect()));
+        } else if c.is_alphanumeric() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            tokens.push(Token::Ident(chars[start..i].iter().collect()));
+        } else {
+            let rest: String = chars[i..chars.len().min(i + 3)].iter().collect();
+            let symbol = SYMBOLS.iter().find(|s| rest.starts_with(**s)).ok_or_else(|| {
+                LocalDBError::Parse(format!("Unexpected character '{}'", c))
+            })?;
+            tokens.push(Token::Symbol(symbol));
+            i += symbol.len();
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// Whether `tokens` call `now()`, whose result changes from run to run
+pub(crate) fn calls_now(tokens: &[Token]) -> bool {
+    tokens.windows(2).any(|pair| {
+        matches!(&pair[0], Token::Ident(word) if word.eq_ignore_ascii_case("now")) && pair[1] == Token::Symbol("(")
+    })
+}
+
+/// Split `sql` at each `;` outside string literals and quoted identifiers,
+/// returning the trimmed, non-empty statements
+pub(crate) fn split_statements(sql: &str) -> Vec<&str> {
+    let mut statements = Vec::new();
+    let (mut start, mut quote) = (0, None);
+
+    for (i, c) in sql.char_indices() {
+        match quote {
+            // a doubled quote closes and reopens, so escapes need no special case
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c == '\'' || c == '"' => quote = Some(c),
+            None if c == ';' => {
+                statements.push(&sql[start..i]);
+                start = i + 1;
+            }
+            None => {}
+        }
+    }
+    statements.push(&sql[start..]);
+
+    statements.into_iter().map(str::trim).filter(|stmt| !stmt.is_empty()).collect()
+}
+
+// ========================= AST =============================
+
+/// Virtual column holding a row's 1-based position in its table
+pub(crate) const ROWID: &str = "ROWID";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum ArithOp {
+    Add,
+    Sub,
+    Mul,
+    /// `/`: INT with INT truncates, anything else is REAL
+    Div,
+    /// `DIV`: always truncates to an INT
+    IntDiv,
+    /// `%`: remainder, with the sign of the dividend
+    Mod,
+    /// `||`: text concatenation; see `concat`
+    Concat,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum AggFunc {
+    Count,
+    Sum,
+    Avg,
+    Min,
+    Max,
+}
+
+/// Functions computing one value from one argument, row by row
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum ScalarFunc {
+    Length,
+    Upper,
+    Lower,
+    /// Whitespace stripped from both ends, or only the start (`LTrim`) or end (`RTrim`)
+    Trim,
+    LTrim,
+    RTrim,
+}
+
+/// Arithmetic over columns and literals, as used in projections
+#[derive(Debug, Clone)]
+pub(crate) enum Expr {
+    Column(String),
+    Literal(LocalDBValue),
+    Neg(Box<Expr>),
+    Binary { op: ArithOp, left: Box<Expr>, right: Box<Expr> },
+    /// `FUNC(expr)` over all selected rows; `COUNT(*)` has no argument
+    Aggregate { func: AggFunc, arg: Option<Box<Expr>> },
+    /// `COALESCE(expr, ...)`: the first argument that isn't NULL
+    Coalesce(Vec<Expr>),
+    /// `NULLIF(a, b)`: NULL when `a = b`, otherwise `a`
+    NullIf(Box<Expr>, Box<Expr>),
+    /// `FUNC(expr)` for a scalar function such as `LENGTH`
+    Scalar { func: ScalarFunc, arg: Box<Expr> },
+    /// `SUBSTR(expr, start[, length])`, also spelled `SUBSTRING`
+    Substr { value: Box<Expr>, start: Box<Expr>, length: Option<Box<Expr>> },
+    /// `expr -> step` (JSON result) or `expr ->> step` (SQL value result)
+    JsonPath { value: Box<Expr>, step: JsonStep, as_text: bool },
+    /// `expr[n]`: element `n`, from 0, of an ARRAY, or a JSON array's as by
+    /// `->> n`; NULL when out of bounds or not an array
+    Element { value: Box<Expr>, index: usize },
+    /// `CASE WHEN predicate THEN expr ... [ELSE expr] END`; NULL when no
+    /// branch matches and there is no ELSE
+    Case { branches: Vec<(Predicate, Expr)>, otherwise: Option<Box<Expr>> },
+    /// `*` listed among other projected items, as in `SELECT ROWID, *`
+    All,
+    /// `name(expr, ...)` for a function registered with `register_function`;
+    /// `func` is filled in just before the statement runs
+    Call { name: String, args: Vec<Expr>, func: Option<BoundCall> },
+}
+
+/// One step into a JSON value: `'key'` of an object or `n` of an array
+#[derive(Debug, Clone)]
+pub(crate) enum JsonStep {
+    Key(String),
+    Index(usize),
+}
+
+/// One projected column: an expression and the name it is returned under
+#[derive(Debug, Clone)]
+pub(crate) struct SelectItem {
+    pub expr: Expr,
+    pub name: String,
+}
+
+impl SelectItem {
+    pub fn column(name: &str) -> Self {
+        Self { expr: Expr::Column(name.to_string()), name: name.to_string() }
+    }
+}
+
+/// `row` projected onto `columns`; a `*` item copies the columns `shown` accepts
+pub fn project(columns: &[SelectItem], row: &Row, shown: impl Fn(&str) -> bool) -> Row {
+    let mut out = Row::new();
+    for item in columns {
+        match &item.expr {
+            Expr::All => out.extend(row.iter().filter(|(k, _)| shown(k)).map(|(k, v)| (k.clone(), v.clone()))),
+            _ => out.extend(item.value(row).map(|v| (item.name.clone(), v))),
+        }
+    }
+    out
+}
+
+impl Returning {
+    pub fn walk_exprs_mut(&mut self, f: &mut dyn FnMut(&mut Expr) -> Result<()>) -> Result<()> {
+        self.columns.iter_mut().flatten().try_for_each(|item| item.expr.walk_mut(f))
+    }
+
+    /// The RETURNING columns of each affected row
+    pub fn project(&self, rows: &[Row]) -> Vec<Row> {
+        rows.iter()
+            .map(|row| match &self.columns {
+                Some(columns) => project(columns, row, |_| true),
+                None => row.clone(),
+            })
+            .collect()
+    }
+}
+
+impl Select {
+    /// Every table the query reads, subqueries included
+    pub fn tables(&self) -> Vec<String> {
+        let mut tables = vec![self.table.clone()];
+        tables.extend(self.joins.iter().map(|join| join.table.clone()));
+        for select in self.filter.iter().flat_map(Predicate::subqueries) {
+            tables.extend(select.tables());
+        }
+        tables
+    }
+
+    /// Whether rows are folded into groups (GROUP BY, HAVING or an aggregate)
+    pub fn is_aggregate(&self) -> bool {
+        !self.group_by.is_empty()
+            || self.having.is_some()
+            || self.columns.iter().flatten().any(|c| c.expr.has_aggregate())
+    }
+
+    /// Whether the projection, GROUP BY, HAVING or ORDER BY reads `ROWID`
+    pub fn reads_rowid(&self) -> bool {
+        self.columns.iter().flatten().any(|c| c.expr.mentions(ROWID))
+            || self.distinct_on.iter().chain(&self.group_by).any(|e| e.mentions(ROWID))
+            || self.having.as_ref().is_some_and(|h| h.columns().contains(&ROWID))
+            || self.order_by.iter().any(|item| item.expr.mentions(ROWID))
+    }
+
+    /// `Expr::walk_mut` over every expression in the query, subqueries included
+    pub fn walk_exprs_mut(&mut self, f: &mut dyn FnMut(&mut Expr) -> Result<()>) -> Result<()> {
+        for item in self.columns.iter_mut().flatten() {
+            item.expr.walk_mut(f)?;
+        }
+        for expr in self.distinct_on.iter_mut().chain(&mut self.group_by).chain(self.order_by.iter_mut().map(|item| &mut item.expr)) {
+            expr.walk_mut(f)?;
+        }
+        for predicate in self.joins.iter_mut().filter_map(|join| join.on.as_mut()).chain(&mut self.filter).chain(&mut self.having) {
+            predicate.walk_exprs_mut(f)?;
+        }
+        Ok(())
+    }
+
+    /// How many matching rows are enough to answer a plain `LIMIT` query
+    /// (LIMIT plus OFFSET), so the scan can stop there; `None` when the
+    /// query sorts, groups, dedups or joins, and every match is needed
+    pub fn stop_after(&self) -> Option<usize> {
+        let plain = self.order_by.is_empty() && self.distinct_on.is_empty() && self.joins.is_empty() && !self.is_aggregate();
+        let limit = self.limit.filter(|_| plain)?;
+        Some(limit.saturating_add(self.offset.unwrap_or(0)))
+    }
+
+    /// The result rows `OFFSET` and `LIMIT` keep, from rows in result order
+    pub fn page<T>(&self, rows: impl IntoIterator<Item = T>) -> impl Iterator<Item = T> {
+        rows.into_iter().skip(self.offset.unwrap_or(0)).take(self.limit.unwrap_or(usize::MAX))
+    }
+}
+
+impl Query {
+    pub fn select(&self) -> &Select {
+        match self {
+            Query::Select(select) | Query::Exists(select) | Query::Count(select) => select,
+        }
+    }
+
+    pub fn select_mut(&mut self) -> &mut Select {
+        match self {
+            Query::Select(select) | Query::Exists(select) | Query::Count(select) => select,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Select {
+    pub table: String,
+    /// `FROM table AS alias`
+    pub alias: Option<String>,
+    pub joins: Vec<Join>,
+    /// `DISTINCT ON (expr, ...)`: only the first row, in result order, of
+    /// each set of rows with equal values is kept
+    pub distinct_on: Vec<Expr>,
+    /// Projected columns, `None` for `*`
+    pub columns: Option<Vec<SelectItem>>,
+    pub filter: Option<Predicate>,
+    /// `GROUP BY` expressions; rows with equal values form one result row
+    pub group_by: Vec<Expr>,
+    /// `HAVING expr op value`, checked against each group's aggregates
+    pub having: Option<Predicate>,
+    pub order_by: Vec<OrderItem>,
+    pub limit: Option<usize>,
+    /// Result rows to skip before `LIMIT` counts
+    pub offset: Option<usize>,
+}
+
+/// `[INNER] JOIN table [AS alias] ON predicate`, or `LEFT [OUTER] JOIN`;
+/// either may be `NATURAL`, without the `ON`
+#[derive(Debug, Clone)]
+pub(crate) struct Join {
+    pub table: String,
+    pub alias: Option<String>,
+    /// Keep rows with no match, with this table's columns NULL
+    pub left: bool,
+    /// `None` for a NATURAL JOIN, which joins on the column names this
+    /// table shares with the tables before it
+    pub on: Option<Predicate>,
+}
+
+impl Join {
+    /// Name this table's columns are qualified with
+    pub fn qualifier(&self) -> &str {
+        qualifier(&self.table, self.alias.as_deref())
+    }
+}
+
+/// The alias, else the table name without any attached database prefix
+pub(crate) fn qualifier<'a>(table: &'a str, alias: Option<&'a str>) -> &'a str {
+    alias.unwrap_or_else(|| table.rsplit('.').next().unwrap_or(table))
+}
+
+/// One `ORDER BY` key
+#[derive(Debug, Clone)]
+pub(crate) struct OrderItem {
+    pub expr: Expr,
+    pub descending: bool,
+    /// `COLLATE`, overriding the database's collation for this key
+    pub collation: Option<Collation>,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum Query {
+    Select(Select),
+    /// SELECT EXISTS(select)
+    Exists(Select),
+    /// SELECT COUNT(*) FROM ...
+    Count(Select),
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Insert {
+    pub table: String,
+    pub columns: Option<Vec<String>>,
+    pub source: InsertSource,
+    pub returning: Option<Returning>,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum InsertSource {
+    Values(Vec<LocalDBValue>),
+    Select(Box<Select>),
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Delete {
+    pub table: String,
+    pub filter: Option<Predicate>,
+    pub limit: Option<usize>,
+    pub returning: Option<Returning>,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Update {
+    pub table: String,
+    pub assignments: Vec<(String, Expr)>,
+    pub filter: Option<Predicate>,
+    pub limit: Option<usize>,
+    pub returning: Option<Returning>,
+}
+
+/// `RETURNING` clause of INSERT, UPDATE and DELETE
+#[derive(Debug, Clone)]
+pub(crate) struct Returning {
+    /// Projected columns, `None` for `*`
+    pub columns: Option<Vec<SelectItem>>,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum Comment {
+    Table { table: String, text: String },
+    Column { table: String, column: String, text: String },
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct CreateIndex {
+    pub name: String,
+    /// `CREATE FULLTEXT INDEX`: indexes the words of TEXT values
+    pub fulltext: bool,
+    pub table: String,
+    pub column: String,
+    /// Partial index: only rows matching this are indexed
+    pub filter: Option<Predicate>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum CmpOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum Predicate {
+    Compare { column: String, op: CmpOp, value: LocalDBValue },
+    /// `[NOT] LIKE` / `[NOT] ILIKE`; never matches a non-text value, negated or not
+    Like { column: String, pattern: String, case_insensitive: bool, negated: bool },
+    Between { column: String, low: LocalDBValue, high: LocalDBValue },
+    /// Bare `column` (or `NOT column`): matches BOOL(true) (or BOOL(false))
+    Truthy { column: String, negated: bool },
+    /// `column [NOT] IN (values)`; NOT IN never matches a missing or NULL value
+    In { column: String, values: Vec<LocalDBValue>, negated: bool },
+    /// `column [NOT] IN (SELECT ...)`, replaced by `In` before evaluation
+    InSelect { column: String, select: Box<Select>, negated: bool },
+    /// `column op (SELECT ...)`, replaced by `Compare` before evaluation
+    CompareSelect { column: String, op: CmpOp, select: Box<Select> },
+    /// `column CONTAINS value`: an ARRAY column holding an element equal to `value`
+    Contains { column: String, value: LocalDBValue },
+    /// `column MATCH 'words'`: a TEXT column holding every one of the words,
+    /// compared as `text_words` splits them; no words match nothing
+    Match { column: String, query: String },
+    /// `column op ANY (values)` (or `SOME`) holds if the comparison holds for
+    /// some value, `column op ALL (values)` if it holds for every value;
+    /// ALL over no values always holds
+    Quantified { column: String, op: CmpOp, all: bool, values: Vec<LocalDBValue> },
+    /// `column op ANY|ALL (SELECT ...)`, replaced by `Quantified` before evaluation
+    QuantifiedSelect { column: String, op: CmpOp, all: bool, select: Box<Select> },
+    /// `column op other`, comparing two columns of the same row
+    CompareColumns { column: String, op: CmpOp, other: String },
+    /// `FUNC(...) op value`, for a left side computed from the row
+    CompareExpr { expr: Expr, op: CmpOp, value: LocalDBValue },
+    /// `expr op expr` in HAVING, where either side may be an aggregate
+    CompareExprs { left: Expr, op: CmpOp, right: Expr },
+    /// `column IS [NOT] DISTINCT FROM other`, where `other` is a literal or
+    /// a column: `!=` (or `=`) with NULL, or a missing value, equal to NULL
+    Distinct { column: String, other: Expr, negated: bool },
+    /// `left AND right`; binds tighter than OR
+    And(Box<Predicate>, Box<Predicate>),
+    /// `left OR right`
+    Or(Box<Predicate>, Box<Predicate>),
+    /// `NOT (predicate)`
+    Not(Box<Predicate>),
+}
+
+// ========================= PARSER =============================
+
+pub(crate) struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    /// What `now()` reads as, fixed at its first use
+    now: Option<LocalDBValue>,
+}
+
+impl Parser {
+    pub fn new(sql: &str) -> Result<Self> {
+        Ok(Self::from_tokens(tokenize(sql)?))
+    }
+
+    pub fn from_tokens(tokens: Vec<Token>) -> Self {
+        Self { tokens, pos: 0, now: None }
+    }
+
+    /// SELECT ... | SELECT EXISTS(SELECT ...)
+    pub fn parse_query(&mut self) -> Result<Query> {
+        let query = if self.peek_keyword_at(1, "EXISTS") {
+            self.expect_keyword("SELECT")?;
+            self.expect_keyword("EXISTS")?;
+            self.expect_symbol("(")?;
+            let inner = self.parse_select()?;
+            self.expect_symbol(")")?;
+            Query::Exists(inner)
+        } else if self.peek_keyword_at(1, "COUNT")
+            && self.peek_symbol_at(2, "(")
+            && self.peek_symbol_at(3, "*")
+            && self.peek_symbol_at(4, ")")
+            && self.peek_keyword_at(5, "FROM")
+        {
+            self.expect_keyword("SELECT")?;
+            self.expect_keyword("COUNT")?;
+            self.expect_symbol("(")?;
+            self.expect_symbol("*")?;
+            self.expect_symbol(")")?;
+            let select = self.parse_select_tail(None)?;
+            if select.group_by.is_empty() && select.having.is_none() && select.joins.is_empty() {
+                Query::Count(select)
+            } else {
+                // one count per group, or over joined rows: an ordinary aggregate query
+                let count = SelectItem { expr: Expr::Aggregate { func: AggFunc::Count, arg: None }, name: "COUNT(*)".into() };
+                Query::Select(Select { columns: Some(vec![count]), ..select })
+            }
+        } else {
+            Query::Select(self.parse_select()?)
+        };
+
+        self.finish()?;
+        Ok(query)
+    }
+
+    /// SELECT [DISTINCT ON (expr, ...)] * | expr [AS alias], ... FROM table [WHERE predicate] [LIMIT n]
+    fn parse_select(&mut self) -> Result<Select> {
+        self.expect_keyword("SELECT")?;
+        let distinct_on = self.parse_distinct_on()?;
+        let columns = self.parse_projection()?;
+
+        let select = self.parse_select_tail(columns)?;
+        Ok(Select { distinct_on, ..select })
+    }
+
+    /// [DISTINCT ON (expr, ...)]
+    fn parse_distinct_on(&mut self) -> Result<Vec<Expr>> {
+        if !self.eat_keyword("DISTINCT") {
+            return Ok(Vec::new());
+        }
+        self.expect_keyword("ON")?;
+        self.expect_symbol("(")?;
+
+        let mut exprs = vec![self.parse_expr()?];
+        while self.eat_symbol(",") {
+            exprs.push(self.parse_expr()?);
+        }
+        self.expect_symbol(")")?;
+        Ok(exprs)
+    }
+
+    /// * | expr [AS alias] | *, ...
+    fn parse_projection(&mut self) -> Result<Option<Vec<SelectItem>>> {
+        if self.peek_symbol_at(0, "*") && !self.peek_symbol_at(1, ",") {
+            self.expect_symbol("*")?;
+            return Ok(None);
+        }
+
+        let mut columns = vec![self.parse_select_item()?];
+        while self.eat_symbol(",") {
+            columns.push(self.parse_select_item()?);
+        }
+        Ok(Some(columns))
+    }
+
+    /// [RETURNING * | expr [AS alias], ...]
+    fn parse_returning(&mut self) -> Result<Option<Returning>> {
+        if !self.eat_keyword("RETURNING") {
+            return Ok(None);
+        }
+
+        let columns = self.parse_projection()?;
+        if columns.iter().flatten().any(|c| c.expr.has_aggregate()) {
+            return Err(self.error("Aggregates are not allowed in RETURNING"));
+        }
+        Ok(Some(Returning { columns }))
+    }
+
+    fn parse_select_item(&mut self) -> Result<SelectItem> {
+        if self.eat_symbol("*") {
+            return Ok(SelectItem { expr: Expr::All, name: "*".to_string() });
+        }
+
+        let expr = self.parse_expr()?;
+        let name = match &expr {
+            _ if self.eat_keyword("AS") => self.ident()?,
+            Expr::Column(column) => column.clone(),
+            _ => expr.to_string(),
+        };
+
+        Ok(SelectItem { expr, name })
+    }
+
+    /// FROM table [alias] [[LEFT [OUTER] | INNER] JOIN table [alias] ON predicate
+    /// | NATURAL [LEFT [OUTER] | INNER] JOIN table [alias] ...]
+    /// [WHERE predicate] [GROUP BY expr, ... [HAVING having]]
+    /// [ORDER BY expr [ASC | DESC], ...] [LIMIT n]
+    fn parse_select_tail(&mut self, columns: Option<Vec<SelectItem>>) -> Result<Select> {
+        self.expect_keyword("FROM")?;
+        let table = self.table_ref()?;
+        let alias = self.parse_alias()?;
+
+        let mut joins = Vec::new();
+        loop {
+            let natural = self.eat_keyword("NATURAL");
+            let left = self.eat_keyword("LEFT");
+            if left {
+                self.eat_keyword("OUTER");
+            }
+            let inner = !left && self.eat_keyword("INNER");
+            if !self.eat_keyword("JOIN") {
+                if natural || left || inner {
+                    return Err(self.error("Expected JOIN"));
+                }
+                break;
+            }
+            let table = self.table_ref()?;
+            let alias = self.parse_alias()?;
+            if natural {
+                joins.push(Join { table, alias, left, on: None });
+                continue;
+            }
+            self.expect_keyword("ON")?;
+            let on = self.parse_predicate()?;
+            if on.has_subquery() {
+                return Err(self.error("Subqueries are not allowed in ON"));
+            }
+            joins.push(Join { table, alias, left, on: Some(on) });
+        }
+
+        let filter = self.parse_where()?;
+
+        let mut group_by = Vec::new();
+        if self.eat_keyword("GROUP") {
+            self.expect_keyword("BY")?;
+            group_by.push(self.parse_expr()?);
+            while self.eat_symbol(",") {
+                group_by.push(self.parse_expr()?);
+            }
+        }
+
+        let having = if self.eat_keyword("HAVING") { Some(self.parse_having()?) } else { None };
+
+        let order_by = self.parse_order_by()?;
+        let limit = self.parse_limit()?;
+        let offset = self.parse_offset()?;
+
+        Ok(Select { table, alias, joins, distinct_on: Vec::new(), columns, filter, group_by, having, order_by, limit, offset })
+    }
+
+    /// table | alias.table
+    fn table_ref(&mut self) -> Result<String> {
+        let table = self.ident()?;
+        if self.eat_symbol(".") {
+            return Ok(format!("{}.{}", table, self.ident()?));
+        }
+        Ok(table)
+    }
+
+    /// [AS] alias, where a bare alias can't be a keyword
+    fn parse_alias(&mut self) -> Result<Option<String>> {
+        if self.eat_keyword("AS") {
+            return Ok(Some(self.ident()?));
+        }
+        match self.peek() {
+            Some(Token::Ident(word)) if !KEYWORDS.iter().any(|kw| kw.eq_ignore_ascii_case(word)) => Ok(Some(self.ident()?)),
+            Some(Token::Quoted(_)) => Ok(Some(self.ident()?)),
+            _ => Ok(None),
+        }
+    }
+
+    /// column | qualifier.column
+    fn column_ref(&mut self) -> Result<String> {
+        let column = self.ident()?;
+        if self.eat_symbol(".") {
+            return Ok(format!("{}.{}", column, self.ident()?));
+        }
+        Ok(column)
+    }
+
+    /// [WHERE predicate] [LIMIT n]
+    fn parse_where_limit(&mut self) -> Result<(Option<Predicate>, Option<usize>)> {
+        Ok((self.parse_where()?, self.parse_limit()?))
+    }
+
+    fn parse_where(&mut self) -> Result<Option<Predicate>> {
+        if self.eat_keyword("WHERE") {
+            Ok(Some(self.parse_predicate()?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn parse_order_by(&mut self) -> Result<Vec<OrderItem>> {
+        let mut order_by = Vec::new();
+        if !self.eat_keyword("ORDER") {
+            return Ok(order_by);
+        }
+        self.expect_keyword("BY")?;
+
+        loop {
+            let expr = self.parse_expr()?;
+            let collation = if self.eat_keyword("COLLATE") { Some(self.collation()?) } else { None };
+            let descending = if self.eat_keyword("DESC") {
+                true
+            } else {
+                self.eat_keyword("ASC");
+                false
+            };
+            order_by.push(OrderItem { expr, descending, collation });
+
+            if !self.eat_symbol(",") {
+                return Ok(order_by);
+            }
+        }
+    }
+
+    /// BINARY | NOCASE | FOLD, after COLLATE
+    fn collation(&mut self) -> Result<Collation> {
+        let name = self.ident()?;
+        match name.to_ascii_uppercase().as_str() {
+            "BINARY" => Ok(Collation::Binary),
+            "NOCASE" => Ok(Collation::NoCase),
+            "FOLD" => Ok(Collation::Fold),
+            _ => Err(self.error(&format!("Unknown collation {}", name))),
+        }
+    }
+
+    fn parse_limit(&mut self) -> Result<Option<usize>> {
+        let limit = if self.eat_keyword("LIMIT") {
+            match self.next() {
+                Some(Token::Number(n)) => Some(n.parse().map_err(|_| {
+                    LocalDBError::Parse(format!("Invalid LIMIT: {}", n))
+                })?),
+                _ => return Err(self.error("Expected number after LIMIT")),
+            }
+        } else {
+            None
+        };
+
+        Ok(limit)
+    }
+
+    fn parse_offset(&mut self) -> Result<Option<usize>> {
+        if !self.eat_keyword("OFFSET") {
+            return Ok(None);
+        }
+        match self.next() {
+            Some(Token::Number(n)) => {
+                Ok(Some(n.parse().map_err(|_| LocalDBError::Parse(format!("Invalid OFFSET: {}", n)))?))
+            }
+            _ => Err(self.error("Expected number after OFFSET")),
+        }
+    }
+
+    /// DELETE FROM table [WHERE predicate] [LIMIT n] [RETURNING ...]
+    pub fn parse_delete(&mut self) -> Result<Delete> {
+        self.expect_keyword("DELETE")?;
+        self.expect_keyword("FROM")?;
+        let table = self.ident()?;
+        let (filter, limit) = self.parse_where_limit()?;
+        let returning = self.parse_returning()?;
+
+        self.finish()?;
+        Ok(Delete { table, filter, limit, returning })
+    }
+
+    /// UPDATE table SET column = expr, ... [WHERE predicate] [LIMIT n] [RETURNING ...]
+    pub fn parse_update(&mut self) -> Result<Update> {
+        self.expect_keyword("UPDATE")?;
+        let table = self.ident()?;
+        self.expect_keyword("SET")?;
+
+        let mut assignments = Vec::new();
+        loop {
+            let column = self.ident()?;
+            self.expect_symbol("=")?;
+            assignments.push((column, self.parse_expr()?));
+
+            if !self.eat_symbol(",") {
+                break;
+            }
+        }
+
+        let (filter, limit) = self.parse_where_limit()?;
+        let returning = self.parse_returning()?;
+
+        self.finish()?;
+        Ok(Update { table, assignments, filter, limit, returning })
+    }
+
+    /// INSERT INTO table [(columns)] VALUES (values) | SELECT ... [RETURNING ...]
+    pub fn parse_insert(&mut self) -> Result<Insert> {
+        self.expect_keyword("INSERT")?;
+        self.expect_keyword("INTO")?;
+        let table = self.ident()?;
+
+        let columns = if self.eat_symbol("(") {
+            let mut columns = vec![self.ident()?];
+            while self.eat_symbol(",") {
+                columns.push(self.ident()?);
+            }
+            self.expect_symbol(")")?;
+            Some(columns)
+        } else {
+            None
+        };
+
+        let source = if self.peek_keyword_at(0, "SELECT") {
+            InsertSource::Select(Box::new(self.parse_select()?))
+        } else {
+            self.expect_keyword("VALUES")?;
+            self.expect_symbol("(")?;
+            let mut values = vec![self.literal()?];
+            while self.eat_symbol(",") {
+                values.push(self.literal()?);
+            }
+            self.expect_symbol(")")?;
+            InsertSource::Values(values)
+        };
+        let returning = self.parse_returning()?;
+
+        self.finish()?;
+        Ok(Insert { table, columns, source, returning })
+    }
+
+    /// CREATE TABLE name (column TYPE [PRIMARY KEY] [NOT NULL] [UNIQUE] [DEFAULT literal | DEFAULT now()]
+    /// [COMMENT 'text'], ... [, UNIQUE (column, ...)]) [COMMENT 'text']
+    pub fn parse_create_table(&mut self) -> Result<TableDef> {
+        self.expect_keyword("CREATE")?;
+        self.expect_keyword("TABLE")?;
+        let mut table = TableDef::new(&self.ident()?);
+
+        if self.eat_symbol("(") {
+            loop {
+                if self.peek_keyword_at(0, "UNIQUE") && self.peek_symbol_at(1, "(") {
+                    self.pos += 1;
+                    self.expect_symbol("(")?;
+                    let mut key = vec![self.ident()?];
+                    while self.eat_symbol(",") {
+                        key.push(self.ident()?);
+                    }
+                    self.expect_symbol(")")?;
+                    table.unique.push(key);
+                    if !self.eat_symbol(",") {
+                        break;
+                    }
+                    continue;
+                }
+
+                let column = self.ident()?;
+                let type_name = self.ident()?;
+                let col_type = ColType::parse(&type_name).ok_or_else(|| {
+                    LocalDBError::Parse(format!("Unknown column type: {}", type_name))
+                })?;
+                let mut def = ColumnDef::new(&column, col_type);
+                loop {
+                    if self.eat_keyword("PRIMARY") {
+                        self.expect_keyword("KEY")?;
+                        def.primary_key = true;
+                    } else if self.eat_keyword("NOT") {
+                        self.expect_keyword("NULL")?;
+                        def.not_null = true;
+                    } else if self.eat_keyword("UNIQUE") {
+                        table.unique.push(vec![column.clone()]);
+                    } else if let Some(comment) = self.parse_comment_clause()? {
+                        def.comment = Some(comment);
+                    } else if self.eat_keyword("DEFAULT") {
+                        def.default = Some(self.parse_default()?);
+                    } else {
+                        break;
+                    }
+                }
+                table.columns.push(def);
+
+                if !self.eat_symbol(",") {
+                    break;
+                }
+            }
+            self.expect_symbol(")")?;
+        }
+
+        table.comment = self.parse_comment_clause()?;
+
+        self.finish()?;
+        Ok(table)
+    }
+
+    /// literal | now()
+    fn parse_default(&mut self) -> Result<ColumnDefault> {
+        if self.peek_keyword_at(0, "NOW") && self.peek_symbol_at(1, "(") {
+            self.pos += 1;
+            self.expect_symbol("(")?;
+            self.expect_symbol(")")?;
+            return Ok(ColumnDefault::Now);
+        }
+        Ok(ColumnDefault::Value(self.literal()?))
+    }
+
+    fn parse_comment_clause(&mut self) -> Result<Option<String>> {
+        if !self.eat_keyword("COMMENT") {
+            return Ok(None);
+        }
+        match self.next() {
+            Some(Token::Str(text)) => Ok(Some(text)),
+            _ => Err(self.error("Expected string after COMMENT")),
+        }
+    }
+
+    /// COMMENT ON TABLE table IS 'text' | COMMENT ON COLUMN table.column IS 'text'
+    pub fn parse_comment(&mut self) -> Result<Comment> {
+        self.expect_keyword("COMMENT")?;
+        self.expect_keyword("ON")?;
+
+        let (table, column) = if self.eat_keyword("TABLE") {
+            (self.ident()?, None)
+        } else {
+            self.expect_keyword("COLUMN")?;
+            let table = self.ident()?;
+            self.expect_symbol(".")?;
+            (table, Some(self.ident()?))
+        };
+
+        self.expect_keyword("IS")?;
+        let text = match self.next() {
+            Some(Token::Str(text)) => text,
+            _ => return Err(self.error("Expected string after IS")),
+        };
+
+        self.finish()?;
+        Ok(match column {
+            None => Comment::Table { table, text },
+            Some(column) => Comment::Column { table, column, text },
+        })
+    }
+
+    /// CREATE INDEX name ON table (column) [WHERE predicate]
+    /// | CREATE FULLTEXT INDEX [name] ON table (column)
+    pub fn parse_create_index(&mut self) -> Result<CreateIndex> {
+        self.expect_keyword("CREATE")?;
+        let fulltext = self.eat_keyword("FULLTEXT");
+        self.expect_keyword("INDEX")?;
+        let name = if fulltext && self.peek_keyword_at(0, "ON") { None } else { Some(self.ident()?) };
+        self.expect_keyword("ON")?;
+        let table = self.ident()?;
+        self.expect_symbol("(")?;
+        let column = self.ident()?;
+        self.expect_symbol(")")?;
+        let name = name.unwrap_or_else(|| format!("{}_{}_fulltext", table, column));
+
+        let filter = if !fulltext && self.eat_keyword("WHERE") {
+            let filter = self.parse_predicate()?;
+            if filter.has_subquery() {
+                return Err(self.error("Subqueries are not allowed in an index filter"));
+            }
+            Some(filter)
+        } else {
+            None
+        };
+
+        self.finish()?;
+        Ok(CreateIndex { name, fulltext, table, column, filter })
+    }
+
+    /// conjunction (OR conjunction)*
+    fn parse_predicate(&mut self) -> Result<Predicate> {
+        let mut predicate = self.parse_conjunction()?;
+        while self.eat_keyword("OR") {
+            let right = self.parse_conjunction()?;
+            predicate = Predicate::Or(Box::new(predicate), Box::new(right));
+        }
+        Ok(predicate)
+    }
+
+    /// condition (AND condition)*
+    fn parse_conjunction(&mut self) -> Result<Predicate> {
+        let mut predicate = self.parse_condition()?;
+        while self.eat_keyword("AND") {
+            let right = self.parse_condition()?;
+            predicate = Predicate::And(Box::new(predicate), Box::new(right));
+        }
+        Ok(predicate)
+    }
+
+    /// NOT column | NOT (predicate) | (predicate) | comparison
+    fn parse_condition(&mut self) -> Result<Predicate> {
+        if self.eat_keyword("NOT") {
+            if self.eat_symbol("(") {
+                let inner = self.parse_predicate()?;
+                self.expect_symbol(")")?;
+                return Ok(Predicate::Not(Box::new(inner)));
+            }
+            let column = self.column_ref()?;
+            return Ok(Predicate::Truthy { column, negated: true });
+        }
+        if self.eat_symbol("(") {
+            let inner = self.parse_predicate()?;
+            self.expect_symbol(")")?;
+            return Ok(inner);
+        }
+
+        let call = matches!(self.peek(), Some(Token::Ident(_))) && self.peek_symbol_at(1, "(");
+        let after = if self.peek_symbol_at(1, ".") { 3 } else { 1 };
+        let path = matches!(self.peek(), Some(Token::Ident(_) | Token::Quoted(_)))
+            && (self.peek_symbol_at(after, "->") || self.peek_symbol_at(after, "->>") || self.peek_symbol_at(after, "["));
+        if call || path {
+            let expr = self.parse_factor()?;
+            if expr.has_aggregate() {
+                return Err(self.error("Aggregates are not allowed in WHERE"));
+            }
+            let op = self.parse_cmp_op()?;
+            let value = self.literal()?;
+            return Ok(Predicate::CompareExpr { expr, op, value });
+        }
+
+        let column = self.column_ref()?;
+
+        let bare = match self.peek() {
+            None | Some(Token::Symbol(";")) | Some(Token::Symbol(")")) => true,
+            Some(Token::Ident(word)) => {
+                ["LIMIT", "ORDER", "GROUP", "RETURNING", "THEN", "AND", "OR"].iter().any(|kw| word.eq_ignore_ascii_case(kw))
+            }
+            _ => false,
+        };
+        if bare {
+            return Ok(Predicate::Truthy { column, negated: false });
+        }
+
+        if self.eat_keyword("IS") {
+            let negated = self.eat_keyword("NOT");
+            self.expect_keyword("DISTINCT")?;
+            self.expect_keyword("FROM")?;
+            let other = if matches!(self.peek(), Some(Token::Ident(_) | Token::Quoted(_))) && !self.peek_literal_keyword() {
+                Expr::Column(self.column_ref()?)
+            } else {
+                Expr::Literal(self.literal()?)
+            };
+            return Ok(Predicate::Distinct { column, other, negated });
+        }
+
+        if self.eat_keyword("BETWEEN") {
+            let low = self.literal()?;
+            self.expect_keyword("AND")?;
+            let high = self.literal()?;
+            return Ok(Predicate::Between { column, low, high });
+        }
+
+        let negated = self.eat_keyword("NOT");
+        if self.eat_keyword("IN") {
+            self.expect_symbol("(")?;
+            if self.peek_keyword_at(0, "SELECT") {
+                let select = Box::new(self.parse_select()?);
+                self.expect_symbol(")")?;
+                return Ok(Predicate::InSelect { column, select, negated });
+            }
+
+            let mut values = vec![self.literal()?];
+            while self.eat_symbol(",") {
+                values.push(self.literal()?);
+            }
+            self.expect_symbol(")")?;
+            return Ok(Predicate::In { column, values, negated });
+        }
+
+        if !negated && self.eat_keyword("CONTAINS") {
+            let value = self.literal()?;
+            return Ok(Predicate::Contains { column, value });
+        }
+        if !negated && self.eat_keyword("MATCH") {
+            let query = match self.next() {
+                Some(Token::Str(s)) => s,
+                _ => return Err(self.error("Expected string after MATCH")),
+            };
+            return Ok(Predicate::Match { column, query });
+        }
+
+        for (keyword, case_insensitive) in [("LIKE", false), ("ILIKE", true)] {
+            if self.eat_keyword(keyword) {
+                let pattern = match self.next() {
+                    Some(Token::Str(s)) => s,
+                    _ => return Err(self.error(&format!("Expected pattern after {}", keyword))),
+                };
+                return Ok(Predicate::Like { column, pattern, case_insensitive, negated });
+            }
+        }
+        if negated {
+            return Err(self.error("Expected IN, LIKE or ILIKE after NOT"));
+        }
+
+        let op = self.parse_cmp_op()?;
+
+        let quantifier = ["ANY", "SOME", "ALL"].into_iter().find(|kw| self.peek_keyword_at(0, kw));
+        if let Some(quantifier) = quantifier.filter(|_| self.peek_symbol_at(1, "(")) {
+            self.pos += 2;
+            let all = quantifier == "ALL";
+            if self.peek_keyword_at(0, "SELECT") {
+                let select = Box::new(self.parse_select()?);
+                self.expect_symbol(")")?;
+                return Ok(Predicate::QuantifiedSelect { column, op, all, select });
+            }
+
+            let mut values = vec![self.literal()?];
+            while self.eat_symbol(",") {
+                values.push(self.literal()?);
+            }
+            self.expect_symbol(")")?;
+            return Ok(Predicate::Quantified { column, op, all, values });
+        }
+
+        if self.peek_symbol_at(0, "(") && self.peek_keyword_at(1, "SELECT") {
+            self.expect_symbol("(")?;
+            let select = Box::new(self.parse_select()?);
+            self.expect_symbol(")")?;
+            return Ok(Predicate::CompareSelect { column, op, select });
+        }
+
+        if matches!(self.peek(), Some(Token::Ident(_) | Token::Quoted(_))) && !self.peek_literal_keyword() {
+            let other = self.column_ref()?;
+            return Ok(Predicate::CompareColumns { column, op, other });
+        }
+
+        let value = self.literal()?;
+        Ok(Predicate::Compare { column, op, value })
+    }
+
+    /// having_conjunction (OR having_conjunction)*
+    fn parse_having(&mut self) -> Result<Predicate> {
+        let mut predicate = self.parse_having_conjunction()?;
+        while self.eat_keyword("OR") {
+            let right = self.parse_having_conjunction()?;
+            predicate = Predicate::Or(Box::new(predicate), Box::new(right));
+        }
+        Ok(predicate)
+    }
+
+    /// having_condition (AND having_condition)*
+    fn parse_having_conjunction(&mut self) -> Result<Predicate> {
+        let mut predicate = self.parse_having_condition()?;
+        while self.eat_keyword("AND") {
+            let right = self.parse_having_condition()?;
+            predicate = Predicate::And(Box::new(predicate), Box::new(right));
+        }
+        Ok(predicate)
+    }
+
+    /// NOT having_condition | (having) | expr op expr
+    fn parse_having_condition(&mut self) -> Result<Predicate> {
+        if self.eat_keyword("NOT") {
+            return Ok(Predicate::Not(Box::new(self.parse_having_condition()?)));
+        }
+        // `(` opens either a nested predicate or an expression like `(a + b) > 1`
+        let start = self.pos;
+        if self.eat_symbol("(") {
+            if let Ok(inner) = self.parse_having() {
+                if self.eat_symbol(")") && !self.peek_operator() {
+                    return Ok(inner);
+                }
+            }
+            self.pos = start;
+        }
+
+        let left = self.parse_expr()?;
+        let op = self.parse_cmp_op()?;
+        match self.parse_expr()? {
+            Expr::Literal(value) => Ok(Predicate::CompareExpr { expr: left, op, value }),
+            right => Ok(Predicate::CompareExprs { left, op, right }),
+        }
+    }
+
+    /// Whether the next token continues an expression or a comparison
+    fn peek_operator(&self) -> bool {
+        ["=", "!=", "<>", "<", "<=", ">", ">=", "+", "-", "*", "/", "%", "||", "->", "->>", "["]
+            .iter()
+            .any(|op| self.peek_symbol_at(0, op))
+            || self.peek_keyword_at(0, "DIV")
+    }
+
+    fn parse_cmp_op(&mut self) -> Result<CmpOp> {
+        match self.next() {
+            Some(Token::Symbol("=")) => Ok(CmpOp::Eq),
+            Some(Token::Symbol("!=")) | Some(Token::Symbol("<>")) => Ok(CmpOp::Ne),
+            Some(Token::Symbol("<")) => Ok(CmpOp::Lt),
+            Some(Token::Symbol("<=")) => Ok(CmpOp::Le),
+            Some(Token::Symbol(">")) => Ok(CmpOp::Gt),
+            Some(Token::Symbol(">=")) => Ok(CmpOp::Ge),
+            _ => Err(self.error("Expected comparison operator")),
+        }
+    }
+
+    /// sum ('||' sum)*
+    fn parse_expr(&mut self) -> Result<Expr> {
+        let mut expr = self.parse_sum()?;
+        while self.eat_symbol("||") {
+            let right = self.parse_sum()?;
+            expr = Expr::Binary { op: ArithOp::Concat, left: Box::new(expr), right: Box::new(right) };
+        }
+        Ok(expr)
+    }
+
+    /// term (('+' | '-') term)*
+    fn parse_sum(&mut self) -> Result<Expr> {
+        let mut expr = self.parse_term()?;
+        loop {
+            let op = if self.eat_symbol("+") {
+                ArithOp::Add
+            } else if self.eat_symbol("-") {
+                ArithOp::Sub
+            } else {
+                return Ok(expr);
+            };
+            let right = self.parse_term()?;
+            expr = Expr::Binary { op, left: Box::new(expr), right: Box::new(right) };
+        }
+    }
+
+    /// factor (('*' | '/' | DIV | '%') factor)*
+    fn parse_term(&mut self) -> Result<Expr> {
+        let mut expr = self.parse_factor()?;
+        loop {
+            let op = if self.eat_symbol("*") {
+                ArithOp::Mul
+            } else if self.eat_symbol("/") {
+                ArithOp::Div
+            } else if self.eat_keyword("DIV") {
+                ArithOp::IntDiv
+            } else if self.eat_symbol("%") {
+                ArithOp::Mod
+            } else {
+                return Ok(expr);
+            };
+            let right = self.parse_factor()?;
+            expr = Expr::Binary { op, left: Box::new(expr), right: Box::new(right) };
+        }
+    }
+
+    /// primary (('->' | '->>') ('key' | n) | '[' n ']')*
+    fn parse_factor(&mut self) -> Result<Expr> {
+        let mut expr = self.parse_primary()?;
+        loop {
+            if self.eat_symbol("[") {
+                let index = match self.next() {
+                    Some(Token::Number(n)) => n.parse().map_err(|_| LocalDBError::Parse(format!("Invalid array index: {}", n)))?,
+                    _ => return Err(self.error("Expected array index after [")),
+                };
+                self.expect_symbol("]")?;
+                expr = Expr::Element { value: Box::new(expr), index };
+                continue;
+            }
+
+            let as_text = if self.eat_symbol("->>") {
+                true
+            } else if self.eat_symbol("->") {
+                false
+            } else {
+                return Ok(expr);
+            };
+
+            let step = match self.next() {
+                Some(Token::Str(key)) => JsonStep::Key(key),
+                Some(Token::Number(n)) => JsonStep::Index(n.parse().map_err(|_| {
+                    LocalDBError::Parse(format!("Invalid JSON array index: {}", n))
+                })?),
+                _ => return Err(self.error("Expected 'key' or array index after -> / ->>")),
+            };
+            expr = Expr::JsonPath { value: Box::new(expr), step, as_text };
+        }
+    }
+
+    /// -factor | (expr) | FUNC(expr) | COUNT(*) | COALESCE(expr, ...) | NULLIF(expr, expr)
+    /// | LENGTH(expr) | UPPER(expr) | LOWER(expr) | [L|R]TRIM(expr) | SUBSTR(expr, expr[, expr])
+    /// | literal | column
+    fn parse_primary(&mut self) -> Result<Expr> {
+        if self.eat_symbol("-") {
+            return Ok(Expr::Neg(Box::new(self.parse_factor()?)));
+        }
+
+        if self.eat_symbol("(") {
+            let expr = self.parse_expr()?;
+            self.expect_symbol(")")?;
+            return Ok(expr);
+        }
+
+        if self.eat_keyword("CASE") {
+            return self.parse_case();
+        }
+
+        if let (Some(Token::Ident(name)), true) = (self.peek(), self.peek_symbol_at(1, "(")) {
+            if name.eq_ignore_ascii_case("COALESCE") {
+                self.pos += 2;
+                let mut args = vec![self.parse_expr()?];
+                while self.eat_symbol(",") {
+                    args.push(self.parse_expr()?);
+                }
+                self.expect_symbol(")")?;
+                return Ok(Expr::Coalesce(args));
+            }
+
+            if name.eq_ignore_ascii_case("NULLIF") {
+                self.pos += 2;
+                let value = self.parse_expr()?;
+                self.expect_symbol(",")?;
+                let other = self.parse_expr()?;
+                self.expect_symbol(")")?;
+                return Ok(Expr::NullIf(Box::new(value), Box::new(other)));
+            }
+
+            if name.eq_ignore_ascii_case("SUBSTR") || name.eq_ignore_ascii_case("SUBSTRING") {
+                self.pos += 2;
+                let value = Box::new(self.parse_expr()?);
+                self.expect_symbol(",")?;
+                let start = Box::new(self.parse_expr()?);
+                let length = if self.eat_symbol(",") { Some(Box::new(self.parse_expr()?)) } else { None };
+                self.expect_symbol(")")?;
+                return Ok(Expr::Substr { value, start, length });
+            }
+
+            let scalar = match name.to_ascii_uppercase().as_str() {
+                "LENGTH" => Some(ScalarFunc::Length),
+                "UPPER" => Some(ScalarFunc::Upper),
+                "LOWER" => Some(ScalarFunc::Lower),
+                "TRIM" => Some(ScalarFunc::Trim),
+                "LTRIM" => Some(ScalarFunc::LTrim),
+                "RTRIM" => Some(ScalarFunc::RTrim),
+                _ => None,
+            };
+            if let Some(func) = scalar {
+                self.pos += 2;
+                let arg = Box::new(self.parse_expr()?);
+                self.expect_symbol(")")?;
+                return Ok(Expr::Scalar { func, arg });
+            }
+
+            let func = match name.to_ascii_uppercase().as_str() {
+                "COUNT" => AggFunc::Count,
+                "SUM" => AggFunc::Sum,
+                "AVG" => AggFunc::Avg,
+                "MIN" => AggFunc::Min,
+                "MAX" => AggFunc::Max,
+                _ => return self.parse_call(),
+            };
+            self.pos += 2;
+
+            let arg = if func == AggFunc::Count && self.eat_symbol("*") {
+                None
+            } else {
+                Some(Box::new(self.parse_expr()?))
+            };
+            self.expect_symbol(")")?;
+            return Ok(Expr::Aggregate { func, arg });
+        }
+
+        if matches!(self.peek(), Some(Token::Ident(_) | Token::Quoted(_))) && !self.peek_literal_keyword() {
+            return Ok(Expr::Column(self.column_ref()?));
+        }
+
+        Ok(Expr::Literal(self.literal()?))
+    }
+
+    /// name([expr, ...]) for a function not built in, checked when bound;
+    /// `now()` reads as its TIMESTAMP
+    fn parse_call(&mut self) -> Result<Expr> {
+        let name = self.ident()?;
+        self.expect_symbol("(")?;
+        let mut args = Vec::new();
+        if !self.eat_symbol(")") {
+            args.push(self.parse_expr()?);
+            while self.eat_symbol(",") {
+                args.push(self.parse_expr()?);
+            }
+            self.expect_symbol(")")?;
+        }
+        if name.eq_ignore_ascii_case("now") {
+            if !args.is_empty() {
+                return Err(LocalDBError::SqlError(format!("NOW takes no arguments, got {}", args.len())));
+            }
+            return Ok(Expr::Literal(self.now()));
+        }
+        Ok(Expr::Call { name, args, func: None })
+    }
+
+    /// WHEN predicate THEN expr ... [ELSE expr] END, after CASE
+    fn parse_case(&mut self) -> Result<Expr> {
+        let mut branches = Vec::new();
+        while self.eat_keyword("WHEN") {
+            let condition = self.parse_predicate()?;
+            if condition.has_subquery() {
+                return Err(self.error("Subqueries are not allowed in CASE"));
+            }
+            self.expect_keyword("THEN")?;
+            branches.push((condition, self.parse_expr()?));
+        }
+        if branches.is_empty() {
+            return Err(self.error("Expected WHEN"));
+        }
+
+        let otherwise = if self.eat_keyword("ELSE") { Some(Box::new(self.parse_expr()?)) } else { None };
+        self.expect_keyword("END")?;
+
+        Ok(Expr::Case { branches, otherwise })
+    }
+
+    /// Whether the next identifier starts a literal (`TRUE`, `NULL`, `UUID '...'`, `now()`)
+    fn peek_literal_keyword(&self) -> bool {
+        ["TRUE", "FALSE", "NULL"].iter().any(|kw| self.peek_keyword_at(0, kw))
+            || (self.peek_keyword_at(0, "NOW") && self.peek_symbol_at(1, "("))
+            || (["UUID", "JSON", "TIMESTAMP", "DECIMAL"].iter().any(|kw| self.peek_keyword_at(0, kw))
+                && matches!(self.tokens.get(self.pos + 1), Some(Token::Str(_))))
+    }
+
+    fn literal(&mut self) -> Result<LocalDBValue> {
+        if self.eat_symbol("-") {
+            return match self.next() {
+                Some(Token::Number(n)) => number(&format!("-{}", n)),
+                _ => Err(self.error("Expected number after '-'")),
+            };
+        }
+
+        if self.peek_keyword_at(0, "NOW") && self.peek_symbol_at(1, "(") {
+            self.pos += 2;
+            self.expect_symbol(")")?;
+            return Ok(self.now());
+        }
+
+        match self.next() {
+            Some(Token::Str(s)) => Ok(LocalDBValue::TEXT(s)),
+            Some(Token::Ident(word)) if word.eq_ignore_ascii_case("UUID") => match self.next() {
+                Some(Token::Str(s)) => Ok(LocalDBValue::UUID(s)),
+                _ => Err(self.error("Expected string after UUID")),
+            },
+            Some(Token::Ident(word)) if word.eq_ignore_ascii_case("JSON") => match self.next() {
+                Some(Token::Str(s)) => serde_json::from_str(&s)
+                    .map(LocalDBValue::JSON)
+                    .map_err(|e| LocalDBError::Parse(format!("Invalid JSON literal: {}", e))),
+                _ => Err(self.error("Expected string after JSON")),
+            },
+            Some(Token::Ident(word)) if word.eq_ignore_ascii_case("TIMESTAMP") => match self.next() {
+                Some(Token::Str(s)) => s
+                    .trim()
+                    .parse()
+                    .map(LocalDBValue::TIMESTAMP)
+                    .map_err(|_| LocalDBError::Parse(format!("Invalid TIMESTAMP literal: {}", s))),
+                _ => Err(self.error("Expected string after TIMESTAMP")),
+            },
+            Some(Token::Ident(word)) if word.eq_ignore_ascii_case("DECIMAL") => match self.next() {
+                Some(Token::Str(s)) => LocalDBValue::decimal(&s)
+                    .map_err(|_| LocalDBError::SqlError(format!("Invalid DECIMAL literal: {}", s))),
+                _ => Err(self.error("Expected string after DECIMAL")),
+            },
+            Some(Token::Ident(word)) if word.eq_ignore_ascii_case("TRUE") => Ok(LocalDBValue::BOOL(true)),
+            Some(Token::Ident(word)) if word.eq_ignore_ascii_case("FALSE") => Ok(LocalDBValue::BOOL(false)),
+            Some(Token::Ident(word)) if word.eq_ignore_ascii_case("NULL") => Ok(LocalDBValue::NULL),
+            Some(Token::Number(n)) => number(&n),
+            Some(Token::Symbol("[")) => {
+                let mut items = Vec::new();
+                if !self.eat_symbol("]") {
+                    loop {
+                        items.push(self.literal()?);
+                        if !self.eat_symbol(",") {
+                            break;
+                        }
+                    }
+                    self.expect_symbol("]")?;
+                }
+                LocalDBValue::array(items)
+            }
+            _ => Err(self.error("Expected literal value")),
+        }
+    }
+
+    /// `now()` as a TIMESTAMP: the time of its first use, so every use in
+    /// one statement, and every row it's compared with, sees the same time
+    fn now(&mut self) -> LocalDBValue {
+        self.now.get_or_insert_with(LocalDBValue::now).clone()
+    }
+
+    // ========================= TOKEN HELPERS =============================
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn ident(&mut self) -> Result<String> {
+        match self.next() {
+            Some(Token::Ident(name)) | Some(Token::Quoted(name)) => Ok(name),
+            _ => Err(self.error("Expected identifier")),
+        }
+    }
+
+    fn peek_symbol_at(&self, offset: usize, symbol: &str) -> bool {
+        matches!(self.tokens.get(self.pos + offset), Some(Token::Symbol(s)) if *s == symbol)
+    }
+
+    fn peek_keyword_at(&self, offset: usize, keyword: &str) -> bool {
+        matches!(self.tokens.get(self.pos + offset), Some(Token::Ident(word)) if word.eq_ignore_ascii_case(keyword))
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        match self.peek() {
+            Some(Token::Ident(word)) if word.eq_ignore_ascii_case(keyword) => {
+                self.pos += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<()> {
+        if self.eat_keyword(keyword) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("Expected {}", keyword)))
+        }
+    }
+
+    fn eat_symbol(&mut self, symbol: &'static str) -> bool {
+        if self.peek() == Some(&Token::Symbol(symbol)) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect_symbol(&mut self, symbol: &'static str) -> Result<()> {
+        if self.eat_symbol(symbol) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("Expected '{}'", symbol)))
+        }
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.eat_symbol(";");
+        match self.peek() {
+            None => Ok(()),
+            Some(token) => Err(LocalDBError::Parse(format!("Unexpected token {:?}", token))),
+        }
+    }
+
+    fn error(&self, message: &str) -> LocalDBError {
+        LocalDBError::Parse(format!("{} at token {}", message, self.pos))
+    }
+}
+
+/// INT for whole numbers, REAL for numbers with a decimal point
+fn number(n: &str) -> Result<LocalDBValue> {
+    let value = if n.contains('.') {
+        n.parse().ok().filter(|x: &f64| x.is_finite()).map(LocalDBValue::REAL)
+    } else {
+        n.parse().ok().map(LocalDBValue::INT)
+    };
+
+    value.ok_or_else(|| LocalDBError::Parse(format!("Invalid number: {}", n)))
+}
+
+// ========================= RENDERING =============================
+
+/// `CREATE TABLE` statement (plus trailing comment) recreating `def`; `pretty`
+/// puts each column on its own line, with names and types aligned
+pub(crate) fn render_table_def(def: &TableDef, pretty: bool) -> String {
+    let columns: Vec<(String, String, String)> = def
+        .columns
+        .iter()
+        .map(|c| {
+            let mut constraints = String::new();
+            if c.primary_key {
+                constraints.push_str(" PRIMARY KEY");
+            }
+            if c.not_null {
+                constraints.push_str(" NOT NULL");
+            }
+            if let Some(default) = &c.default {
+                constraints.push_str(&format!(" DEFAULT {}", default));
+            }
+            if let Some(comment) = &c.comment {
+                constraints.push_str(&format!(" COMMENT {}", quote(comment)));
+            }
+            (quote_ident(&c.name), c.col_type.to_string(), constraints)
+        })
+        .collect();
+    let unique = def.unique.iter().map(|key| {
+        let key: Vec<String> = key.iter().map(|c| quote_ident(c)).collect();
+        format!("UNIQUE ({})", key.join(", "))
+    });
+
+    let body = if pretty {
+        let name_width = columns.iter().map(|(name, _, _)| name.chars().count()).max().unwrap_or(0);
+        let type_width = columns.iter().map(|(_, t, _)| t.len()).max().unwrap_or(0);
+        let lines: Vec<String> = columns
+            .iter()
+            .map(|(name, col_type, constraints)| {
+                let line = format!("    {:nw$} {:tw$}{}", name, col_type, constraints, nw = name_width, tw = type_width);
+                line.trim_end().to_string()
+            })
+            .chain(unique.map(|key| format!("    {}", key)))
+            .collect();
+        if lines.is_empty() { String::new() } else { format!("\n{}\n", lines.join(",\n")) }
+    } else {
+        let parts: Vec<String> =
+            columns.iter().map(|(name, col_type, constraints)| format!("{} {}{}", name, col_type, constraints)).collect();
+        parts.into_iter().chain(unique).collect::<Vec<_>>().join(", ")
+    };
+
+    match &def.comment {
+        Some(comment) => format!("CREATE TABLE {} ({}) COMMENT {};\n", quote_ident(&def.name), body, quote(comment)),
+        None => format!("CREATE TABLE {} ({});\n", quote_ident(&def.name), body),
+    }
+}
+
+/// Render a value as a SQL literal that parses back to the same value
+pub(crate) fn render_literal(value: &LocalDBValue) -> String {
+    match value {
+        LocalDBValue::INT(n) => n.to_string(),
+        LocalDBValue::REAL(x) => {
+            // Display is exact and never uses an exponent; keep a '.' so it reads back as REAL
+            let s = x.to_string();
+            if s.contains('.') { s } else { format!("{}.0", s) }
+        }
+        LocalDBValue::TEXT(s) => quote(s),
+        LocalDBValue::UUID(s) => format!("UUID {}", quote(s)),
+        LocalDBValue::BOOL(b) => if *b { "TRUE" } else { "FALSE" }.to_string(),
+        LocalDBValue::JSON(v) => format!("JSON {}", quote(&v.to_string())),
+        LocalDBValue::TIMESTAMP(ms) => format!("TIMESTAMP '{}'", ms),
+        LocalDBValue::DECIMAL(s) => format!("DECIMAL '{}'", s),
+        LocalDBValue::ARRAY(items) => {
+            let items: Vec<String> = items.iter().map(render_literal).collect();
+            format!("[{}]", items.join(", "))
+        }
+        LocalDBValue::NULL => "NULL".to_string(),
+    }
+}
+
+fn quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "''"))
+}
+
+/// Words the parser gives a meaning to, which must be quoted as identifiers
+const KEYWORDS: &[&str] = &[
+    "ALL", "AND", "ANY", "AS", "ASC", "BETWEEN", "BY", "CASE", "COLLATE", "COLUMN", "COMMENT", "CONTAINS",
+    "COUNT", "CREATE", "DECIMAL", "DEFAULT", "DELETE", "DESC", "DISTINCT", "DIV", "ELSE", "END", "EXISTS",
+    "FALSE", "FROM", "FULLTEXT", "GROUP", "HAVING", "ILIKE", "IN", "INDEX", "INNER", "INSERT", "INTO", "IS",
+    "JOIN", "JSON", "KEY", "LEFT", "LIKE", "LIMIT", "MATCH", "NATURAL", "NOT", "NOW", "NULL", "OFFSET", "ON",
+    "OR", "ORDER", "OUTER", "PRIMARY", "RETURNING", "SELECT", "SET", "SOME", "TABLE", "THEN", "TIMESTAMP",
+    "TRUE", "UNIQUE", "UPDATE", "UUID", "VALUES", "WHEN", "WHERE",
+];
+
+/// Render an identifier, double-quoting it unless it reads back as-is
+pub(crate) fn quote_ident(name: &str) -> String {
+    let plain = name.chars().next().is_some_and(|c| c.is_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_alphanumeric() || c == '_')
+        && !KEYWORDS.iter().any(|kw| kw.eq_ignore_ascii_case(name));
+
+    if plain {
+        name.to_string()
+    } else {
+        format!("\"{}\"", name.replace('"', "\"\""))
+    }
+}
+
+impl fmt::Display for CmpOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            CmpOp::Eq => "=",
+            CmpOp::Ne => "!=",
+            CmpOp::Lt => "<",
+            CmpOp::Le => "<=",
+            CmpOp::Gt => ">",
+            CmpOp::Ge => ">=",
+        })
+    }
+}
+
+impl ArithOp {
+    fn precedence(self) -> u8 {
+        match self {
+            ArithOp::Concat => 0,
+            ArithOp::Add | ArithOp::Sub => 1,
+            ArithOp::Mul | ArithOp::Div | ArithOp::IntDiv | ArithOp::Mod => 2,
+        }
+    }
+}
+
+impl fmt::Display for ArithOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ArithOp::Add => "+",
+            ArithOp::Sub => "-",
+            ArithOp::Mul => "*",
+            ArithOp::Div => "/",
+            ArithOp::IntDiv => "DIV",
+            ArithOp::Mod => "%",
+            ArithOp::Concat => "||",
+        })
+    }
+}
+
+impl fmt::Display for AggFunc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            AggFunc::Count => "COUNT",
+            AggFunc::Sum => "SUM",
+            AggFunc::Avg => "AVG",
+            AggFunc::Min => "MIN",
+            AggFunc::Max => "MAX",
+        })
+    }
+}
+
+impl fmt::Display for ScalarFunc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ScalarFunc::Length => "LENGTH",
+            ScalarFunc::Upper => "UPPER",
+            ScalarFunc::Lower => "LOWER",
+            ScalarFunc::Trim => "TRIM",
+            ScalarFunc::LTrim => "LTRIM",
+            ScalarFunc::RTrim => "RTRIM",
+        })
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expr::Column(column) => f.write_str(&quote_ident(column)),
+            Expr::All => f.write_str("*"),
+            Expr::Literal(value) => f.write_str(&render_literal(value)),
+            Expr::Aggregate { func, arg: None } => write!(f, "{}(*)", func),
+            Expr::Aggregate { func, arg: Some(arg) } => write!(f, "{}({})", func, arg),
+            Expr::Coalesce(args) => {
+                let args: Vec<String> = args.iter().map(Expr::to_string).collect();
+                write!(f, "COALESCE({})", args.join(", "))
+            }
+            Expr::NullIf(value, other) => write!(f, "NULLIF({}, {})", value, other),
+            Expr::Scalar { func, arg } => write!(f, "{}({})", func, arg),
+            Expr::Substr { value, start, length: None } => write!(f, "SUBSTR({}, {})", value, start),
+            Expr::Substr { value, start, length: Some(length) } => write!(f, "SUBSTR({}, {}, {})", value, start, length),
+            Expr::Call { name, args, .. } => {
+                let args: Vec<String> = args.iter().map(Expr::to_string).collect();
+                write!(f, "{}({})", name, args.join(", "))
+            }
+            Expr::JsonPath { value, step, as_text } => {
+                write!(f, "{}{}", value, if *as_text { "->>" } else { "->" })?;
+                match step {
+                    JsonStep::Key(key) => f.write_str(&quote(key)),
+                    JsonStep::Index(n) => write!(f, "{}", n),
+                }
+            }
+            Expr::Element { value, index } => write!(f, "{}[{}]", value, index),
+            Expr::Case { branches, otherwise } => {
+                f.write_str("CASE")?;
+                for (condition, value) in branches {
+                    write!(f, " WHEN {} THEN {}", condition, value)?;
+                }
+                if let Some(otherwise) = otherwise {
+                    write!(f, " ELSE {}", otherwise)?;
+                }
+                f.write_str(" END")
+            }
+            Expr::Neg(inner) => match **inner {
+                Expr::Column(_) | Expr::Literal(_) | Expr::Aggregate { .. } | Expr::Coalesce(_) | Expr::NullIf(..) | Expr::Scalar { .. }
+                | Expr::JsonPath { .. } | Expr::Element { .. } | Expr::Case { .. } | Expr::Call { .. } | Expr::Substr { .. } => {
+                    write!(f, "-{}", inner)
+                }
+                _ => write!(f, "-({})", inner),
+            },
+            Expr::Binary { op, left, right } => {
+                // parenthesize only where precedence or left-associativity requires it
+                let needs_parens = |side: &Expr, is_right: bool| match side {
+                    Expr::Binary { op: inner, .. } => {
+                        inner.precedence() < op.precedence() || (is_right && inner.precedence() == op.precedence())
+                    }
+                    _ => false,
+                };
+
+                if needs_parens(left, false) {
+                    write!(f, "({})", left)?;
+                } else {
+                    write!(f, "{}", left)?;
+                }
+                write!(f, " {} ", op)?;
+                if needs_parens(right, true) {
+                    write!(f, "({})", right)
+                } else {
+                    write!(f, "{}", right)
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for SelectItem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let expr = self.expr.to_string();
+        let unnamed = match &self.expr {
+            Expr::Column(column) => *column == self.name,
+            _ => expr == self.name,
+        };
+
+        if unnamed {
+            f.write_str(&expr)
+        } else {
+            write!(f, "{} AS {}", expr, quote_ident(&self.name))
+        }
+    }
+}
+
+impl fmt::Display for OrderItem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let collation = match self.collation {
+            None => "",
+            Some(Collation::Binary) => " COLLATE BINARY",
+            Some(Collation::NoCase) => " COLLATE NOCASE",
+            Some(Collation::Fold) => " COLLATE FOLD",
+        };
+        write!(f, "{}{}{}", self.expr, collation, if self.descending { " DESC" } else { "" })
+    }
+}
+
+impl fmt::Display for Join {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let natural = if self.on.is_none() { "NATURAL " } else { "" };
+        write!(f, "{}{}JOIN {}", natural, if self.left { "LEFT " } else { "" }, self.table)?;
+        if let Some(alias) = &self.alias {
+            write!(f, " AS {}", quote_ident(alias))?;
+        }
+        match &self.on {
+            Some(on) => write!(f, " ON {}", on),
+            None => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for Select {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SELECT ")?;
+        if !self.distinct_on.is_empty() {
+            let keys: Vec<String> = self.distinct_on.iter().map(Expr::to_string).collect();
+            write!(f, "DISTINCT ON ({}) ", keys.join(", "))?;
+        }
+        match &self.columns {
+            Some(columns) => {
+                let columns: Vec<String> = columns.iter().map(|c| c.to_string()).collect();
+                write!(f, "{} FROM {}", columns.join(", "), self.table)?;
+            }
+            None => write!(f, "* FROM {}", self.table)?,
+        }
+        if let Some(alias) = &self.alias {
+            write!(f, " AS {}", quote_ident(alias))?;
+        }
+        for join in &self.joins {
+            write!(f, " {}", join)?;
+        }
+        if let Some(filter) = &self.filter {
+            write!(f, " WHERE {}", filter)?;
+        }
+        if !self.group_by.is_empty() {
+            let keys: Vec<String> = self.group_by.iter().map(Expr::to_string).collect();
+            write!(f, " GROUP BY {}", keys.join(", "))?;
+        }
+        if let Some(having) = &self.having {
+            write!(f, " HAVING {}", having)?;
+        }
+        if !self.order_by.is_empty() {
+            let keys: Vec<String> = self.order_by.iter().map(OrderItem::to_string).collect();
+            write!(f, " ORDER BY {}", keys.join(", "))?;
+        }
+        if let Some(limit) = self.limit {
+            write!(f, " LIMIT {}", limit)?;
+        }
+        if let Some(offset) = self.offset {
+            write!(f, " OFFSET {}", offset)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Predicate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Predicate::Compare { column, op, value } => {
+                write!(f, "{} {} {}", quote_ident(column), op, render_literal(value))
+            }
+            Predicate::Like { column, pattern, case_insensitive, negated } => {
+                let op = if *case_insensitive { "ILIKE" } else { "LIKE" };
+                let not = if *negated { "NOT " } else { "" };
+                write!(f, "{} {}{} {}", quote_ident(column), not, op, quote(pattern))
+            }
+            Predicate::Between { column, low, high } => {
+                write!(f, "{} BETWEEN {} AND {}", quote_ident(column), render_literal(low), render_literal(high))
+            }
+            Predicate::Truthy { column, negated } => {
+                write!(f, "{}{}", if *negated { "NOT " } else { "" }, quote_ident(column))
+            }
+            Predicate::In { column, values, negated } => {
+                let values: Vec<String> = values.iter().map(render_literal).collect();
+                let not = if *negated { "NOT " } else { "" };
+                write!(f, "{} {}IN ({})", quote_ident(column), not, values.join(", "))
+            }
+            Predicate::InSelect { column, select, negated } => {
+                let not = if *negated { "NOT " } else { "" };
+                write!(f, "{} {}IN ({})", quote_ident(column), not, select)
+            }
+            Predicate::CompareSelect { column, op, select } => {
+                write!(f, "{} {} ({})", quote_ident(column), op, select)
+            }
+            Predicate::Contains { column, value } => {
+                write!(f, "{} CONTAINS {}", quote_ident(column), render_literal(value))
+            }
+            Predicate::Match { column, query } => {
+                write!(f, "{} MATCH {}", quote_ident(column), render_literal(&LocalDBValue::TEXT(query.clone())))
+            }
+            Predicate::Quantified { column, op, all, values } => {
+                let values: Vec<String> = values.iter().map(render_literal).collect();
+                let quantifier = if *all { "ALL" } else { "ANY" };
+                write!(f, "{} {} {} ({})", quote_ident(column), op, quantifier, values.join(", "))
+            }
+            Predicate::QuantifiedSelect { column, op, all, select } => {
+                let quantifier = if *all { "ALL" } else { "ANY" };
+                write!(f, "{} {} {} ({})", quote_ident(column), op, quantifier, select)
+            }
+            Predicate::CompareColumns { column, op, other } => {
+                write!(f, "{} {} {}", quote_ident(column), op, quote_ident(other))
+            }
+            Predicate::CompareExpr { expr, op, value } => write!(f, "{} {} {}", expr, op, render_literal(value)),
+            Predicate::CompareExprs { left, op, right } => write!(f, "{} {} {}", left, op, right),
+            Predicate::Distinct { column, other, negated } => {
+                let not = if *negated { "NOT " } else { "" };
+                write!(f, "{} IS {}DISTINCT FROM {}", quote_ident(column), not, other)
+            }
+            Predicate::And(left, right) => {
+                let group = |p: &Predicate| match p {
+                    Predicate::Or(..) => format!("({})", p),
+                    p => p.to_string(),
+                };
+                write!(f, "{} AND {}", group(left), group(right))
+            }
+            Predicate::Or(left, right) => write!(f, "{} OR {}", left, right),
+            Predicate::Not(inner) => write!(f, "NOT ({})", inner),
+        }
+    }
+}
+
+// ========================= EVALUATION =============================
+
+impl Expr {
+    /// Value of the expression for `row`; missing columns read as NULL
+    pub fn eval(&self, row: &Row) -> LocalDBValue {
+        match self {
+            Expr::Column(column) => row.get(column).cloned().unwrap_or(LocalDBValue::NULL),
+            // expanded by `project`; not a value of its own
+            Expr::All => LocalDBValue::NULL,
+            Expr::Literal(value) => value.clone(),
+            Expr::Neg(inner) => negate(inner.eval(row)),
+            Expr::Binary { op, left, right } => arithmetic(*op, &left.eval(row), &right.eval(row)),
+            Expr::Aggregate { .. } => self.eval_aggregate(std::slice::from_ref(&row)),
+            Expr::Coalesce(args) => coalesce(args.iter().map(|arg| arg.eval(row))),
+            Expr::NullIf(value, other) => null_if(value.eval(row), &other.eval(row)),
+            Expr::Scalar { func, arg } => func.apply(arg.eval(row)),
+            Expr::Substr { value, start, length } => substr(value.eval(row), start.eval(row), length.as_ref().map(|e| e.eval(row))),
+            Expr::JsonPath { value, step, as_text } => json_step(value.eval(row), step, *as_text),
+            Expr::Element { value, index } => element(value.eval(row), *index),
+            Expr::Call { func, args, .. } => {
+                let args: Vec<LocalDBValue> = args.iter().map(|arg| arg.eval(row)).collect();
+                func.as_ref().map_or(LocalDBValue::NULL, |func| func.call(&args))
+            }
+            // conditions compare strictly, like an index lookup
+            Expr::Case { branches, otherwise } => match branches.iter().find(|(c, _)| c.matches(row, Comparison::STRICT)) {
+                Some((_, value)) => value.eval(row),
+                None => otherwise.as_ref().map_or(LocalDBValue::NULL, |e| e.eval(row)),
+            },
+        }
+    }
+
+    /// Value of the expression over a set of rows: aggregates see every
+    /// row, anything outside an aggregate is read from the first one
+    pub fn eval_aggregate(&self, rows: &[&Row]) -> LocalDBValue {
+        match self {
+            Expr::Aggregate { func, arg } => {
+                let values: Vec<LocalDBValue> = match arg {
+                    None => return LocalDBValue::INT(rows.len() as i64),
+                    Some(arg) => rows
+                        .iter()
+                        .map(|row| arg.eval(row))
+                        .filter(|v| !matches!(v, LocalDBValue::NULL))
+                        .collect(),
+                };
+                aggregate(*func, values)
+            }
+            Expr::Neg(inner) => negate(inner.eval_aggregate(rows)),
+            Expr::Binary { op, left, right } => arithmetic(*op, &left.eval_aggregate(rows), &right.eval_aggregate(rows)),
+            Expr::Coalesce(args) => coalesce(args.iter().map(|arg| arg.eval_aggregate(rows))),
+            Expr::NullIf(value, other) => null_if(value.eval_aggregate(rows), &other.eval_aggregate(rows)),
+            Expr::Scalar { func, arg } => func.apply(arg.eval_aggregate(rows)),
+            Expr::Substr { value, start, length } => substr(
+                value.eval_aggregate(rows),
+                start.eval_aggregate(rows),
+                length.as_ref().map(|e| e.eval_aggregate(rows)),
+            ),
+            Expr::JsonPath { value, step, as_text } => json_step(value.eval_aggregate(rows), step, *as_text),
+            Expr::Element { value, index } => element(value.eval_aggregate(rows), *index),
+            Expr::Call { func, args, .. } => {
+                let args: Vec<LocalDBValue> = args.iter().map(|arg| arg.eval_aggregate(rows)).collect();
+                func.as_ref().map_or(LocalDBValue::NULL, |func| func.call(&args))
+            }
+            Expr::Case { branches, otherwise } => {
+                match branches.iter().find(|(c, _)| c.matches_group(rows, Comparison::STRICT)) {
+                    Some((_, value)) => value.eval_aggregate(rows),
+                    None => otherwise.as_ref().map_or(LocalDBValue::NULL, |e| e.eval_aggregate(rows)),
+                }
+            }
+            expr => rows.first().map_or(LocalDBValue::NULL, |row| expr.eval(row)),
+        }
+    }
+
+    pub fn has_aggregate(&self) -> bool {
+        match self {
+            Expr::Aggregate { .. } => true,
+            Expr::Neg(inner) => inner.has_aggregate(),
+            Expr::Binary { left, right, .. } => left.has_aggregate() || right.has_aggregate(),
+            Expr::Coalesce(args) | Expr::Call { args, .. } => args.iter().any(Expr::has_aggregate),
+            Expr::NullIf(value, other) => value.has_aggregate() || other.has_aggregate(),
+            Expr::Substr { value, start, length } => {
+                value.has_aggregate() || start.has_aggregate() || length.as_ref().is_some_and(|e| e.has_aggregate())
+            }
+            Expr::Scalar { arg, .. } | Expr::JsonPath { value: arg, .. } | Expr::Element { value: arg, .. } => arg.has_aggregate(),
+            Expr::Case { branches, otherwise } => {
+                branches.iter().any(|(_, value)| value.has_aggregate()) || otherwise.as_ref().is_some_and(|e| e.has_aggregate())
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether the expression reads `column` anywhere, aggregates included
+    pub fn mentions(&self, column: &str) -> bool {
+        match self {
+            Expr::Column(name) => name == column,
+            Expr::Aggregate { arg, .. } => arg.as_ref().is_some_and(|arg| arg.mentions(column)),
+            Expr::Neg(inner) | Expr::Scalar { arg: inner, .. } | Expr::JsonPath { value: inner, .. } | Expr::Element { value: inner, .. } => {
+                inner.mentions(column)
+            }
+            Expr::Binary { left, right, .. } | Expr::NullIf(left, right) => left.mentions(column) || right.mentions(column),
+            Expr::Coalesce(args) | Expr::Call { args, .. } => args.iter().any(|arg| arg.mentions(column)),
+            Expr::Substr { value, start, length } => {
+                value.mentions(column) || start.mentions(column) || length.as_ref().is_some_and(|e| e.mentions(column))
+            }
+            Expr::Case { branches, otherwise } => {
+                branches.iter().any(|(condition, value)| condition.columns().contains(&column) || value.mentions(column))
+                    || otherwise.as_ref().is_some_and(|e| e.mentions(column))
+            }
+            Expr::Literal(_) | Expr::All => false,
+        }
+    }
+
+    /// Call `f` on this expression, then on each expression inside it
+    pub fn walk_mut(&mut self, f: &mut dyn FnMut(&mut Expr) -> Result<()>) -> Result<()> {
+        f(self)?;
+        match self {
+            Expr::Neg(inner) | Expr::Scalar { arg: inner, .. } | Expr::JsonPath { value: inner, .. } | Expr::Element { value: inner, .. } => {
+                inner.walk_mut(f)
+            }
+            Expr::Aggregate { arg: Some(arg), .. } => arg.walk_mut(f),
+            Expr::Binary { left, right, .. } | Expr::NullIf(left, right) => {
+                left.walk_mut(f)?;
+                right.walk_mut(f)
+            }
+            Expr::Coalesce(args) | Expr::Call { args, .. } => args.iter_mut().try_for_each(|arg| arg.walk_mut(f)),
+            Expr::Substr { value, start, length } => {
+                value.walk_mut(f)?;
+                start.walk_mut(f)?;
+                length.as_mut().map_or(Ok(()), |e| e.walk_mut(f))
+            }
+            Expr::Case { branches, otherwise } => {
+                for (condition, value) in branches {
+                    condition.walk_exprs_mut(f)?;
+                    value.walk_mut(f)?;
+                }
+                otherwise.as_mut().map_or(Ok(()), |e| e.walk_mut(f))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Columns referenced outside of any aggregate
+    pub fn bare_columns(&self) -> Vec<&str> {
+        match self {
+            Expr::Column(column) => vec![column],
+            Expr::Neg(inner) => inner.bare_columns(),
+            Expr::Binary { left, right, .. } => [left.bare_columns(), right.bare_columns()].concat(),
+            Expr::Coalesce(args) | Expr::Call { args, .. } => args.iter().flat_map(Expr::bare_columns).collect(),
+            Expr::NullIf(value, other) => [value.bare_columns(), other.bare_columns()].concat(),
+            Expr::Substr { value, start, length } => {
+                [value.bare_columns(), start.bare_columns(), length.as_ref().map_or_else(Vec::new, |e| e.bare_columns())].concat()
+            }
+            Expr::Scalar { arg, .. } | Expr::JsonPath { value: arg, .. } | Expr::Element { value: arg, .. } => arg.bare_columns(),
+            Expr::Case { branches, otherwise } => branches
+                .iter()
+                .flat_map(|(condition, value)| [condition.columns(), value.bare_columns()].concat())
+                .chain(otherwise.iter().flat_map(|e| e.bare_columns()))
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// Fold the non-NULL `values` of one aggregate. COUNT counts them; SUM and
+/// AVG use the numeric ones; MIN and MAX order values like an index does.
+/// Anything but COUNT is NULL when no value qualifies.
+fn aggregate(func: AggFunc, values: Vec<LocalDBValue>) -> LocalDBValue {
+    let numbers = || values.iter().filter(|v| matches!(v, LocalDBValue::INT(_) | LocalDBValue::REAL(_) | LocalDBValue::DECIMAL(_)));
+
+    match func {
+        AggFunc::Count => LocalDBValue::INT(values.len() as i64),
+        AggFunc::Sum => numbers()
+            .cloned()
+            .reduce(|total, v| arithmetic(ArithOp::Add, &total, &v))
+            .unwrap_or(LocalDBValue::NULL),
+        AggFunc::Avg => {
+            let count = numbers().count();
+            if count == 0 {
+                return LocalDBValue::NULL;
+            }
+            LocalDBValue::REAL(numbers().map(as_real).sum::<f64>() / count as f64)
+        }
+        AggFunc::Min => values.into_iter().map(IndexKey).min().map_or(LocalDBValue::NULL, |k| k.0),
+        AggFunc::Max => values.into_iter().map(IndexKey).max().map_or(LocalDBValue::NULL, |k| k.0),
+    }
+}
+
+impl SelectItem {
+    /// Projected value for `row`; `None` drops a plain column the row lacks
+    pub fn value(&self, row: &Row) -> Option<LocalDBValue> {
+        match &self.expr {
+            Expr::Column(column) => row.get(column).cloned(),
+            expr => Some(expr.eval(row)),
+        }
+    }
+}
+
+/// Whether `bound` is at least as tight as `limit`; `inward` is the
+/// ordering of a tighter value against the limit (Greater for lower bounds)
+fn within(bound: Bound, limit: Bound, inward: Ordering) -> bool {
+    match (bound, limit) {
+        (_, None) => true,
+        (None, Some(_)) => false,
+        (Some((value, included)), Some((limit, limit_included))) => {
+            match compare_values(value, limit, Comparison::STRICT) {
+                Some(Ordering::Equal) => limit_included || !included,
+                Some(ord) => ord == inward,
+                None => false,
+            }
+        }
+    }
+}
+
+/// First value that isn't NULL, or NULL
+fn coalesce(mut values: impl Iterator<Item = LocalDBValue>) -> LocalDBValue {
+    values.find(|v| !matches!(v, LocalDBValue::NULL)).unwrap_or(LocalDBValue::NULL)
+}
+
+impl ScalarFunc {
+    /// Result for one argument value; NULL for arguments of the wrong type
+    fn apply(self, value: LocalDBValue) -> LocalDBValue {
+        match (self, value) {
+            (ScalarFunc::Length, LocalDBValue::TEXT(s) | LocalDBValue::UUID(s)) => {
+                LocalDBValue::INT(s.chars().count() as i64)
+            }
+            (ScalarFunc::Upper, LocalDBValue::TEXT(s)) => LocalDBValue::TEXT(s.to_uppercase()),
+            (ScalarFunc::Upper, LocalDBValue::UUID(s)) => LocalDBValue::UUID(s.to_uppercase()),
+            (ScalarFunc::Lower, LocalDBValue::TEXT(s)) => LocalDBValue::TEXT(s.to_lowercase()),
+            (ScalarFunc::Lower, LocalDBValue::UUID(s)) => LocalDBValue::UUID(s.to_lowercase()),
+            (ScalarFunc::Trim, LocalDBValue::TEXT(s)) => LocalDBValue::TEXT(s.trim().to_string()),
+            (ScalarFunc::LTrim, LocalDBValue::TEXT(s)) => LocalDBValue::TEXT(s.trim_start().to_string()),
+            (ScalarFunc::RTrim, LocalDBValue::TEXT(s)) => LocalDBValue::TEXT(s.trim_end().to_string()),
+            _ => LocalDBValue::NULL,
+        }
+    }
+}
+
+/// `SUBSTR` of a TEXT value: `length` characters from the 1-based `start`,
+/// or all the rest without a length. Characters before the first or after
+/// the last are simply not there, so `SUBSTR('abc', 0, 2)` is `'a'`; NULL
+/// for anything but TEXT with INT bounds, or a negative length.
+fn substr(value: LocalDBValue, start: LocalDBValue, length: Option<LocalDBValue>) -> LocalDBValue {
+    let (LocalDBValue::TEXT(s), LocalDBValue::INT(start)) = (value, start) else {
+        return LocalDBValue::NULL;
+    };
+    let end = match length {
+        None => i64::MAX,
+        Some(LocalDBValue::INT(length)) if length >= 0 => start.saturating_add(length),
+        Some(_) => return LocalDBValue::NULL,
+    };
+
+    let skip = start.max(1) - 1;
+    let take = end.saturating_sub(start.max(1)).max(0);
+    LocalDBValue::TEXT(s.chars().skip(skip as usize).take(take as usize).collect())
+}
+
+/// Follow one path step into a JSON value; NULL if `value` isn't JSON or
+/// has nothing there. With `as_text` (`->>`) strings, numbers and booleans
+/// come out as TEXT, INT/REAL and BOOL, and objects and arrays as their
+/// JSON text.
+fn json_step(value: LocalDBValue, step: &JsonStep, as_text: bool) -> LocalDBValue {
+    use serde_json::Value;
+
+    let LocalDBValue::JSON(json) = value else {
+        return LocalDBValue::NULL;
+    };
+    let found = match step {
+        JsonStep::Key(key) => json.get(key),
+        JsonStep::Index(n) => json.get(n),
+    };
+
+    match found {
+        None => LocalDBValue::NULL,
+        Some(found) if !as_text => LocalDBValue::JSON(found.clone()),
+        Some(Value::Null) => LocalDBValue::NULL,
+        Some(Value::Bool(b)) => LocalDBValue::BOOL(*b),
+        Some(Value::String(s)) => LocalDBValue::TEXT(s.clone()),
+        Some(Value::Number(n)) => match n.as_i64() {
+            Some(i) => LocalDBValue::INT(i),
+            None => n.as_f64().map_or(LocalDBValue::NULL, LocalDBValue::REAL),
+        },
+        Some(nested) => LocalDBValue::TEXT(nested.to_string()),
+    }
+}
+
+/// Element `index` of an ARRAY, or of a JSON array as a plain value
+fn element(value: LocalDBValue, index: usize) -> LocalDBValue {
+    match value {
+        LocalDBValue::ARRAY(mut items) if index < items.len() => items.swap_remove(index),
+        json @ LocalDBValue::JSON(_) => json_step(json, &JsonStep::Index(index), true),
+        _ => LocalDBValue::NULL,
+    }
+}
+
+impl OrderItem {
+    /// Sort key of a result row: a bare name reads the projected column of
+    /// that name if there is one, anything else is computed from `source`
+    pub fn key(&self, source: &Row, projected: &Row) -> IndexKey {
+        self.group_key(std::slice::from_ref(&source), projected)
+    }
+
+    /// `key` for a grouped result row, computed over the group's rows
+    pub fn group_key(&self, rows: &[&Row], projected: &Row) -> IndexKey {
+        match &self.expr {
+            Expr::Column(name) if projected.contains_key(name) => IndexKey(projected[name].clone()),
+            expr => IndexKey(expr.eval_aggregate(rows)),
+        }
+    }
+}
+
+/// Order two rows' sort keys by `order_by`, first key first; text sorts by
+/// each key's `COLLATE`, else by `collation`
+pub(crate) fn compare_keys(order_by: &[OrderItem], a: &[IndexKey], b: &[IndexKey], collation: Collation) -> Ordering {
+    order_by
+        .iter()
+        .zip(a.iter().zip(b))
+        .map(|(item, (a, b))| {
+            let ord = a.collated_cmp(b, item.collation.unwrap_or(collation));
+            if item.descending { ord.reverse() } else { ord }
+        })
+        .find(|ord| ord.is_ne())
+        .unwrap_or(Ordering::Equal)
+}
+
+/// NULL if `value` equals `other` (compared strictly), else `value`
+fn null_if(value: LocalDBValue, other: &LocalDBValue) -> LocalDBValue {
+    match compare_values(&value, other, Comparison::STRICT) {
+        Some(Ordering::Equal) => LocalDBValue::NULL,
+        _ => value,
+    }
+}
+
+fn negate(value: LocalDBValue) -> LocalDBValue {
+    match value {
+        LocalDBValue::INT(n) => n.checked_neg().map_or(LocalDBValue::NULL, LocalDBValue::INT),
+        LocalDBValue::REAL(x) => LocalDBValue::REAL(-x),
+        _ => LocalDBValue::NULL,
+    }
+}
+
+/// `a op b`: INT when both sides are INT, REAL when either is REAL. NULL when
+/// a side is not a number, on division by zero and on overflow.
+fn arithmetic(op: ArithOp, a: &LocalDBValue, b: &LocalDBValue) -> LocalDBValue {
+    use LocalDBValue::*;
+
+    if op == ArithOp::Concat {
+        return concat(a, b);
+    }
+
+    match (a, b) {
+        (INT(x), INT(y)) => {
+            let result = match op {
+                ArithOp::Add => x.checked_add(*y),
+                ArithOp::Sub => x.checked_sub(*y),
+                ArithOp::Mul => x.checked_mul(*y),
+                ArithOp::Div | ArithOp::IntDiv => x.checked_div(*y),
+                ArithOp::Mod => x.checked_rem(*y),
+                ArithOp::Concat => unreachable!("|| is handled before arithmetic"),
+            };
+            result.map_or(NULL, INT)
+        }
+        (DECIMAL(_), DECIMAL(_) | INT(_)) | (INT(_), DECIMAL(_)) if matches!(op, ArithOp::Add | ArithOp::Sub | ArithOp::Mul) => {
+            let exact = |value: &LocalDBValue| match value {
+                DECIMAL(s) => Decimal::parse(s),
+                INT(n) => Some(Decimal::from_int(*n)),
+                _ => None,
+            };
+            let (Some(x), Some(y)) = (exact(a), exact(b)) else {
+                return NULL;
+            };
+            let result = match op {
+                ArithOp::Add => x.checked_add(y),
+                ArithOp::Sub => x.checked_sub(y),
+                _ => x.checked_mul(y),
+            };
+            result.map_or(NULL, |d| DECIMAL(d.to_string()))
+        }
+        (INT(_) | REAL(_) | DECIMAL(_), INT(_) | REAL(_) | DECIMAL(_)) => {
+            let (x, y) = (as_real(a), as_real(b));
+            let result = match op {
+                ArithOp::Add => x + y,
+                ArithOp::Sub => x - y,
+                ArithOp::Mul => x * y,
+                ArithOp::Div | ArithOp::IntDiv | ArithOp::Mod if y == 0.0 => return NULL,
+                ArithOp::Div => x / y,
+                ArithOp::IntDiv => {
+                    let quotient = (x / y).trunc();
+                    // the range check also rejects NaN
+                    return if (i64::MIN as f64..i64::MAX as f64).contains(&quotient) { INT(quotient as i64) } else { NULL };
+                }
+                ArithOp::Mod => x % y,
+                ArithOp::Concat => unreachable!("|| is handled before arithmetic"),
+            };
+            if result.is_finite() { REAL(result) } else { NULL }
+        }
+        _ => NULL,
+    }
+}
+
+/// Display form of a scalar: text as is, numbers as written, TIMESTAMP as
+/// its milliseconds, BOOL as `true`/`false` and JSON as its JSON text; `None`
+/// for NULL and ARRAY
+pub(crate) fn display_text(value: &LocalDBValue) -> Option<String> {
+    match value {
+        LocalDBValue::TEXT(s) | LocalDBValue::UUID(s) | LocalDBValue::DECIMAL(s) => Some(s.clone()),
+        LocalDBValue::INT(n) | LocalDBValue::TIMESTAMP(n) => Some(n.to_string()),
+        LocalDBValue::REAL(x) => Some(x.to_string()),
+        LocalDBValue::BOOL(b) => Some(b.to_string()),
+        LocalDBValue::JSON(v) => Some(v.to_string()),
+        LocalDBValue::ARRAY(_) | LocalDBValue::NULL => None,
+    }
+}
+
+/// `a || b` as TEXT, joining the operands' `display_text`. NULL or an ARRAY
+/// on either side gives NULL.
+fn concat(a: &LocalDBValue, b: &LocalDBValue) -> LocalDBValue {
+    match (display_text(a), display_text(b)) {
+        (Some(a), Some(b)) => LocalDBValue::TEXT(a + &b),
+        _ => LocalDBValue::NULL,
+    }
+}
+
+fn as_real(value: &LocalDBValue) -> f64 {
+    match value {
+        LocalDBValue::INT(n) => *n as f64,
+        LocalDBValue::REAL(x) => *x,
+        LocalDBValue::DECIMAL(s) => Decimal::parse(s).map_or(f64::NAN, Decimal::to_f64),
+        _ => f64::NAN,
+    }
+}
+
+impl CmpOp {
+    /// Whether a comparison that came out `ord` satisfies the operator
+    fn holds(self, ord: Ordering) -> bool {
+        match self {
+            CmpOp::Eq => ord == Ordering::Equal,
+            CmpOp::Ne => ord != Ordering::Equal,
+            CmpOp::Lt => ord == Ordering::Less,
+            CmpOp::Le => ord != Ordering::Greater,
+            CmpOp::Gt => ord == Ordering::Greater,
+            CmpOp::Ge => ord != Ordering::Less,
+        }
+    }
+}
+
+/// One end of the range of values a predicate accepts; the flag is whether
+/// the bound itself is included
+type Bound<'a> = Option<(&'a LocalDBValue, bool)>;
+
+impl Predicate {
+    /// Whether a group of rows passes a `HAVING` predicate: aggregates see
+    /// every row of the group, anything else reads the first
+    pub fn matches_group(&self, rows: &[&Row], comparison: Comparison) -> bool {
+        self.group_truth(rows, comparison) == Some(true)
+    }
+
+    /// `truth` over a group of rows, as `matches_group` reads it
+    fn group_truth(&self, rows: &[&Row], comparison: Comparison) -> Option<bool> {
+        match self {
+            Predicate::And(left, right) => match (left.group_truth(rows, comparison), right.group_truth(rows, comparison)) {
+                (Some(false), _) | (_, Some(false)) => Some(false),
+                (Some(true), Some(true)) => Some(true),
+                _ => None,
+            },
+            Predicate::Or(left, right) => match (left.group_truth(rows, comparison), right.group_truth(rows, comparison)) {
+                (Some(true), _) | (_, Some(true)) => Some(true),
+                (Some(false), Some(false)) => Some(false),
+                _ => None,
+            },
+            Predicate::Not(inner) => inner.group_truth(rows, comparison).map(|b| !b),
+            Predicate::CompareExpr { expr, op, value } => {
+                compare_values(&expr.eval_aggregate(rows), value, comparison).map(|ord| op.holds(ord))
+            }
+            Predicate::CompareExprs { left, op, right } => {
+                compare_values(&left.eval_aggregate(rows), &right.eval_aggregate(rows), comparison).map(|ord| op.holds(ord))
+            }
+            predicate => rows.first().and_then(|row| predicate.truth(row, comparison)),
+        }
+    }
+
+    /// `Expr::walk_mut` over every expression in the predicate, subqueries included
+    pub fn walk_exprs_mut(&mut self, f: &mut dyn FnMut(&mut Expr) -> Result<()>) -> Result<()> {
+        match self {
+            Predicate::CompareExpr { expr, .. } => expr.walk_mut(f),
+            Predicate::CompareExprs { left, right, .. } => {
+                left.walk_mut(f)?;
+                right.walk_mut(f)
+            }
+            Predicate::And(left, right) | Predicate::Or(left, right) => {
+                left.walk_exprs_mut(f)?;
+                right.walk_exprs_mut(f)
+            }
+            Predicate::Not(inner) => inner.walk_exprs_mut(f),
+            Predicate::InSelect { select, .. }
+            | Predicate::CompareSelect { select, .. }
+            | Predicate::QuantifiedSelect { select, .. } => select.walk_exprs_mut(f),
+            _ => Ok(()),
+        }
+    }
+
+    /// Whether the predicate runs a subquery
+    pub fn has_subquery(&self) -> bool {
+        match self {
+            Predicate::InSelect { .. } | Predicate::CompareSelect { .. } | Predicate::QuantifiedSelect { .. } => true,
+            Predicate::And(left, right) | Predicate::Or(left, right) => left.has_subquery() || right.has_subquery(),
+            Predicate::Not(inner) => inner.has_subquery(),
+            _ => false,
+        }
+    }
+
+    /// The subqueries the predicate runs
+    pub fn subqueries(&self) -> Vec<&Select> {
+        match self {
+            Predicate::InSelect { select, .. }
+            | Predicate::CompareSelect { select, .. }
+            | Predicate::QuantifiedSelect { select, .. } => vec![select],
+            Predicate::And(left, right) | Predicate::Or(left, right) => {
+                let mut selects = left.subqueries();
+                selects.extend(right.subqueries());
+                selects
+            }
+            Predicate::Not(inner) => inner.subqueries(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Columns the predicate reads
+    pub fn columns(&self) -> Vec<&str> {
+        match self {
+            Predicate::Compare { column, .. }
+            | Predicate::Like { column, .. }
+            | Predicate::Between { column, .. }
+            | Predicate::Truthy { column, .. }
+            | Predicate::In { column, .. }
+            | Predicate::InSelect { column, .. }
+            | Predicate::CompareSelect { column, .. }
+            | Predicate::Contains { column, .. }
+            | Predicate::Match { column, .. }
+            | Predicate::Quantified { column, .. }
+            | Predicate::QuantifiedSelect { column, .. } => vec![column],
+            Predicate::CompareColumns { column, other, .. } => vec![column, other],
+            Predicate::CompareExpr { expr, .. } => expr.bare_columns(),
+            Predicate::CompareExprs { left, right, .. } => {
+                let mut columns = left.bare_columns();
+                columns.extend(right.bare_columns());
+                columns
+            }
+            Predicate::Distinct { column, other, .. } => {
+                let mut columns = vec![column.as_str()];
+                columns.extend(other.bare_columns());
+                columns
+            }
+            Predicate::And(left, right) | Predicate::Or(left, right) => {
+                let mut columns = left.columns();
+                columns.extend(right.columns());
+                columns
+            }
+            Predicate::Not(inner) => inner.columns(),
+        }
+    }
+
+    /// Whether every row matching `self` is sure to match `other`, as far
+    /// as can be told without looking at rows. Used to decide if a partial
+    /// index covers a query.
+    pub fn implies(&self, other: &Predicate) -> bool {
+        if self.to_string() == other.to_string() {
+            return true;
+        }
+        if let Predicate::And(left, right) = self {
+            return left.implies(other) || right.implies(other);
+        }
+
+        match (self.range(), other.range()) {
+            (Some((column, low, high)), Some((other_column, other_low, other_high))) if column == other_column => {
+                within(low, other_low, Ordering::Greater) && within(high, other_high, Ordering::Less)
+            }
+            _ => false,
+        }
+    }
+
+    /// Column and (lower, upper) bounds of a range predicate
+    fn range(&self) -> Option<(&str, Bound<'_>, Bound<'_>)> {
+        match self {
+            Predicate::Compare { column, op, value } => {
+                let (low, high) = match op {
+                    CmpOp::Eq => (Some((value, true)), Some((value, true))),
+                    CmpOp::Lt => (None, Some((value, false))),
+                    CmpOp::Le => (None, Some((value, true))),
+                    CmpOp::Gt => (Some((value, false)), None),
+                    CmpOp::Ge => (Some((value, true)), None),
+                    CmpOp::Ne => return None,
+                };
+                Some((column, low, high))
+            }
+            Predicate::Between { column, low, high } => Some((column, Some((low, true)), Some((high, true)))),
+            _ => None,
+        }
+    }
+
+    /// Three-valued truth of the predicate on `row`: `None` (unknown) when a
+    /// value it reads is missing or NULL, or can't be compared. AND, OR and
+    /// NOT follow SQL, so `NOT (a = 1)` doesn't match a row where `a` is NULL.
+    pub fn truth(&self, row: &Row, comparison: Comparison) -> Option<bool> {
+        let compare = |a: &LocalDBValue, b: &LocalDBValue, op: CmpOp| compare_values(a, b, comparison).map(|ord| op.holds(ord));
+        match self {
+            Predicate::And(left, right) => match (left.truth(row, comparison), right.truth(row, comparison)) {
+                (Some(false), _) | (_, Some(false)) => Some(false),
+                (Some(true), Some(true)) => Some(true),
+                _ => None,
+            },
+            Predicate::Or(left, right) => match (left.truth(row, comparison), right.truth(row, comparison)) {
+                (Some(true), _) | (_, Some(true)) => Some(true),
+                (Some(false), Some(false)) => Some(false),
+                _ => None,
+            },
+            Predicate::Not(inner) => inner.truth(row, comparison).map(|b| !b),
+            Predicate::Compare { column, op, value } => row.get(column).and_then(|v| compare(v, value, *op)),
+            Predicate::CompareColumns { column, op, other } => {
+                row.get(column).zip(row.get(other)).and_then(|(a, b)| compare(a, b, *op))
+            }
+            Predicate::CompareExpr { expr, op, value } => compare(&expr.eval(row), value, *op),
+            Predicate::CompareExprs { left, op, right } => compare(&left.eval(row), &right.eval(row), *op),
+            Predicate::Between { column, low, high } => row.get(column).and_then(|v| {
+                let above = compare(v, low, CmpOp::Ge)?;
+                let below = compare(v, high, CmpOp::Le)?;
+                Some(above && below)
+            }),
+            Predicate::Like { column, .. } => match row.get(column) {
+                Some(LocalDBValue::TEXT(_)) | Some(LocalDBValue::UUID(_)) => Some(self.matches(row, comparison)),
+                _ => None,
+            },
+            Predicate::Truthy { column, negated } => match row.get(column) {
+                Some(LocalDBValue::BOOL(b)) => Some(*b != *negated),
+                _ => None,
+            },
+            Predicate::Contains { column, .. } => match row.get(column) {
+                Some(LocalDBValue::ARRAY(_)) => Some(self.matches(row, comparison)),
+                _ => None,
+            },
+            Predicate::Match { column, .. } => match row.get(column) {
+                Some(LocalDBValue::TEXT(_)) => Some(self.matches(row, comparison)),
+                _ => None,
+            },
+            Predicate::Distinct { .. } => Some(self.matches(row, comparison)),
+            predicate => {
+                let unknown = predicate.columns().iter().any(|c| matches!(row.get(*c), None | Some(LocalDBValue::NULL)));
+                (!unknown).then(|| predicate.matches(row, comparison))
+            }
+        }
+    }
+
+    pub fn matches(&self, row: &Row, comparison: Comparison) -> bool {
+        match self {
+            Predicate::And(..) | Predicate::Or(..) | Predicate::Not(..) => self.truth(row, comparison) == Some(true),
+            Predicate::Distinct { column, other, negated } => {
+                let other = other.eval(row);
+                let distinct = match (row.get(column).unwrap_or(&LocalDBValue::NULL), &other) {
+                    (LocalDBValue::NULL, LocalDBValue::NULL) => false,
+                    (LocalDBValue::NULL, _) | (_, LocalDBValue::NULL) => true,
+                    (a, b) => compare_values(a, b, comparison) != Some(Ordering::Equal),
+                };
+                distinct != *negated
+            }
+            Predicate::Compare { column, op, value } => {
+                row.get(column).and_then(|v| compare_values(v, value, comparison)).is_some_and(|ord| op.holds(ord))
+            }
+            Predicate::CompareColumns { column, op, other } => match (row.get(column), row.get(other)) {
+                (Some(a), Some(b)) => compare_values(a, b, comparison).is_some_and(|ord| op.holds(ord)),
+                _ => false,
+            },
+            Predicate::CompareExpr { expr, op, value } => {
+                compare_values(&expr.eval(row), value, comparison).is_some_and(|ord| op.holds(ord))
+            }
+            Predicate::CompareExprs { left, op, right } => {
+                compare_values(&left.eval(row), &right.eval(row), comparison).is_some_and(|ord| op.holds(ord))
+            }
+            Predicate::Like { column, pattern, case_insensitive, negated } => match row.get(column) {
+                Some(LocalDBValue::TEXT(s)) | Some(LocalDBValue::UUID(s)) => {
+                    let matched = if *case_insensitive {
+                        like(&s.to_lowercase(), &pattern.to_lowercase())
+                    } else {
+                        like(s, pattern)
+                    };
+                    matched != *negated
+                }
+                _ => false,
+            },
+            Predicate::Between { column, low, high } => row.get(column).is_some_and(|v| {
+                compare_values(v, low, comparison).is_some_and(|o| o != Ordering::Less)
+                    && compare_values(v, high, comparison).is_some_and(|o| o != Ordering::Greater)
+            }),
+            Predicate::Truthy { column, negated } => {
+                matches!(row.get(column), Some(LocalDBValue::BOOL(b)) if *b != *negated)
+            }
+            Predicate::In { column, values, negated } => match row.get(column) {
+                None | Some(LocalDBValue::NULL) => false,
+                Some(v) => {
+                    let found = values
+                        .iter()
+                        .any(|value| compare_values(v, value, comparison) == Some(Ordering::Equal));
+                    found != *negated
+                }
+            },
+            Predicate::Contains { column, value } => match row.get(column) {
+                Some(LocalDBValue::ARRAY(items)) => {
+                    items.iter().any(|item| compare_values(item, value, comparison) == Some(Ordering::Equal))
+                }
+                _ => false,
+            },
+            Predicate::Match { column, query } => match row.get(column) {
+                Some(LocalDBValue::TEXT(s)) => {
+                    let (words, wanted) = (text_words(s), text_words(query));
+                    !wanted.is_empty() && wanted.is_subset(&words)
+                }
+                _ => false,
+            },
+            Predicate::Quantified { column, op, all, values } => {
+                let holds = |value: &LocalDBValue| {
+                    row.get(column).and_then(|v| compare_values(v, value, comparison)).is_some_and(|ord| op.holds(ord))
+                };
+                if *all {
+                    values.iter().all(holds)
+                } else {
+                    values.iter().any(holds)
+                }
+            }
+            // bound to literal values by the database before rows are matched
+            Predicate::InSelect { .. } | Predicate::CompareSelect { .. } | Predicate::QuantifiedSelect { .. } => false,
+        }
+    }
+}
+
+/// How values compare: `coercion` across types, `collation` between texts
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Comparison {
+    pub coercion: Coercion,
+    pub collation: Collation,
+}
+
+impl Comparison {
+    /// Exact comparison, for keys, indexes and constraints
+    pub const STRICT: Self = Self { coercion: Coercion::Strict, collation: Collation::Binary };
+}
+
+impl Collation {
+    pub(crate) fn compare(self, a: &str, b: &str) -> Ordering {
+        match self {
+            Collation::Binary => a.cmp(b),
+            Collation::NoCase => a.chars().flat_map(char::to_lowercase).cmp(b.chars().flat_map(char::to_lowercase)),
+            Collation::Fold => fold(a).cmp(&fold(b)),
+        }
+    }
+}
+
+/// Lowercase ASCII-folded characters of `text`, for `Collation::Fold`:
+/// accented Latin letters lose their accents, and `ß`, `æ`, `œ` and `þ`
+/// become two letters
+fn fold(text: &str) -> String {
+    let mut folded = String::with_capacity(text.len());
+    for c in text.chars().flat_map(char::to_lowercase) {
+        let ascii = match c {
+            'à'..='å' | 'ā' | 'ă' | 'ą' => "a",
+            'ç' | 'ć' | 'č' => "c",
+            'ď' | 'đ' | 'ð' => "d",
+            'è'..='ë' | 'ē' | 'ė' | 'ę' | 'ě' => "e",
+            'ì'..='ï' | 'ī' | 'į' | 'ı' => "i",
+            'ł' => "l",
+            'ñ' | 'ń' | 'ň' => "n",
+            'ò'..='ö' | 'ø' | 'ō' | 'ő' => "o",
+            'ř' => "r",
+            'ś' | 'š' | 'ş' => "s",
+            'ť' | 'ţ' => "t",
+            'ù'..='ü' | 'ū' | 'ů' | 'ű' | 'ų' => "u",
+            'ý' | 'ÿ' => "y",
+            'ź' | 'ż' | 'ž' => "z",
+            'ß' => "ss",
+            'æ' => "ae",
+            'œ' => "oe",
+            'þ' => "th",
+            _ => {
+                folded.push(c);
+                continue;
+            }
+        };
+        folded.push_str(ascii);
+    }
+    folded
+}
+
+/// Order two values; `None` when the types are not comparable under the
+/// comparison's coercion
+pub(crate) fn compare_values(a: &LocalDBValue, b: &LocalDBValue, comparison: Comparison) -> Option<Ordering> {
+    use LocalDBValue::*;
+
+    match (a, b) {
+        (TIMESTAMP(x), _) => return compare_values(&INT(*x), b, comparison),
+        (_, TIMESTAMP(y)) => return compare_values(a, &INT(*y), comparison),
+        _ => {}
+    }
+
+    match (a, b) {
+        (DECIMAL(x), DECIMAL(y)) => compare_decimals(x, y),
+        (DECIMAL(x), INT(y)) => Decimal::parse(x)?.compare(Decimal::from_int(*y)),
+        (INT(x), DECIMAL(y)) => Decimal::from_int(*x).compare(Decimal::parse(y)?),
+        (DECIMAL(_), REAL(y)) => as_real(a).partial_cmp(y),
+        (REAL(x), DECIMAL(_)) => x.partial_cmp(&as_real(b)),
+        (INT(x), INT(y)) => Some(x.cmp(y)),
+        (REAL(x), REAL(y)) => x.partial_cmp(y),
+        (INT(x), REAL(y)) => (*x as f64).partial_cmp(y),
+        (REAL(x), INT(y)) => x.partial_cmp(&(*y as f64)),
+        (BOOL(x), BOOL(y)) => Some(x.cmp(y)),
+        (TEXT(x) | UUID(x), TEXT(y) | UUID(y)) => Some(comparison.collation.compare(x, y)),
+        (ARRAY(x), ARRAY(y)) => {
+            for (a, b) in x.iter().zip(y) {
+                match compare_values(a, b, comparison)? {
+                    Ordering::Equal => continue,
+                    ord => return Some(ord),
+                }
+            }
+            Some(x.len().cmp(&y.len()))
+        }
+        _ if comparison.coercion == Coercion::Strict => None,
+        (INT(x), TEXT(y) | UUID(y)) => y.trim().parse::<i64>().ok().map(|y| x.cmp(&y)),
+        (TEXT(x) | UUID(x), INT(y)) => x.trim().parse::<i64>().ok().map(|x| x.cmp(y)),
+        (REAL(x), TEXT(y) | UUID(y)) => y.trim().parse::<f64>().ok().and_then(|y| x.partial_cmp(&y)),
+        (TEXT(x) | UUID(x), REAL(y)) => x.trim().parse::<f64>().ok().and_then(|x| x.partial_cmp(y)),
+        (BOOL(x), TEXT(y) | UUID(y)) => parse_bool(y).map(|y| x.cmp(&y)),
+        (TEXT(x) | UUID(x), BOOL(y)) => parse_bool(x).map(|x| x.cmp(y)),
+        _ => None,
+    }
+}
+
+fn compare_decimals(a: &str, b: &str) -> Option<Ordering> {
+    Decimal::parse(a)?.compare(Decimal::parse(b)?)
+}
+
+fn parse_bool(s: &str) -> Option<bool> {
+    match s.trim().to_ascii_lowercase().as_str() {
+        "true" => Some(true),
+        "false" => Some(false),
+        _ => None,
+    }
+}
+
+/// The literal start of a LIKE pattern, before its first `%` or `_`
+pub(crate) fn like_prefix(pattern: &str) -> &str {
+    pattern.find(['%', '_']).map_or(pattern, |end| &pattern[..end])
+}
+
+/// The words of `text` for full-text search: split on whitespace, stripped
+/// of leading and trailing punctuation, and lowercased
+pub(crate) fn text_words(text: &str) -> BTreeSet<String> {
+    text.split_whitespace()
+        .map(|word| word.trim_matches(|c: char| !c.is_alphanumeric()).to_lowercase())
+        .filter(|word| !word.is_empty())
+        .collect()
+}
+
+/// SQL LIKE matching: `%` matches any run of characters, `_` exactly one
+fn like(value: &str, pattern: &str) -> bool {
+    let value: Vec<char> = value.chars().collect();
+    let pattern: Vec<char> = pattern.chars().collect();
+
+    // matched[j] is true when value[..i] matches pattern[..j]
+    let mut matched = vec![false; pattern.len() + 1];
+    matched[0] = true;
+    for j in 1..=pattern.len() {
+        matched[j] = matched[j - 1] && pattern[j - 1] == '%';
+    }
+
+    for c in &value {
+        let mut next = vec![false; pattern.len() + 1];
+        for j in 1..=pattern.len() {
+            next[j] = match pattern[j - 1] {
+                '%' => next[j - 1] || matched[j],
+                '_' => matched[j - 1],
+                p => matched[j - 1] && p == *c,
+            };
+        }
+        matched = next;
+    }
+
+    matched[pattern.len()]
+}
@@ -0,0 +1,85 @@
+use crate::{plain_json, ColType, ColumnDef, LocalDB, LocalDBError, LocalDBValue, Result};
+use rusqlite::types::Value;
+use rusqlite::{params_from_iter, Connection};
+
+impl LocalDB {
+    /// Copy every table into the SQLite database at `path`, creating it if
+    /// needed, and return how many rows were written.
+    ///
+    /// Declared tables keep their column types, `PRIMARY KEY` and `NOT NULL`;
+    /// other tables get untyped columns for every column their rows use.
+    /// INT, BOOL (0 or 1) and TIMESTAMP (milliseconds) become INTEGER; REAL
+    /// stays REAL; TEXT, UUID and JSON become TEXT, as do ARRAYs, written as
+    /// JSON arrays. Everything is written in
+    /// one SQLite transaction, so a failure leaves nothing behind. Tables
+    /// without any columns are skipped.
+    pub fn export_sqlite(&self, path: &str) -> Result<usize> {
+        let fail = |e: rusqlite::Error| LocalDBError::io(path)(std::io::Error::other(e));
+
+        let mut conn = Connection::open(path).map_err(fail)?;
+        let tx = conn.transaction().map_err(fail)?;
+        let mut written = 0;
+
+        for (table, rows) in &self.tables {
+            let columns = self.column_order(table, rows);
+            if columns.is_empty() {
+                continue;
+            }
+
+            let def = self.schema.get(table);
+            let column_defs: Vec<String> = columns.iter().map(|c| column_sql(c, def.and_then(|d| d.column(c)))).collect();
+            tx.execute(&format!("CREATE TABLE {} ({})", quote(table), column_defs.join(", ")), []).map_err(fail)?;
+
+            let names: Vec<String> = columns.iter().map(|c| quote(c)).collect();
+            let placeholders = vec!["?"; columns.len()].join(", ");
+            let mut insert = tx
+                .prepare(&format!("INSERT INTO {} ({}) VALUES ({})", quote(table), names.join(", "), placeholders))
+                .map_err(fail)?;
+            for row in rows {
+                insert.execute(params_from_iter(columns.iter().map(|c| sqlite_value(row.get(c))))).map_err(fail)?;
+                written += 1;
+            }
+        }
+
+        tx.commit().map_err(fail)?;
+        Ok(written)
+    }
+}
+
+/// SQLite column definition for `name`, typed from its declaration if any
+fn column_sql(name: &str, def: Option<&ColumnDef>) -> String {
+    let Some(def) = def else {
+        return quote(name);
+    };
+
+    let col_type = match def.col_type {
+        ColType::Int | ColType::Bool | ColType::Timestamp => "INTEGER",
+        ColType::Real => "REAL",
+        ColType::Text | ColType::Uuid | ColType::Json | ColType::Decimal | ColType::Array => "TEXT",
+    };
+    let mut column = format!("{} {}", quote(name), col_type);
+    if def.primary_key {
+        column.push_str(" PRIMARY KEY");
+    }
+    if def.not_null {
+        column.push_str(" NOT NULL");
+    }
+    column
+}
+
+fn sqlite_value(value: Option<&LocalDBValue>) -> Value {
+    match value {
+        Some(LocalDBValue::INT(n)) | Some(LocalDBValue::TIMESTAMP(n)) => Value::Integer(*n),
+        Some(LocalDBValue::REAL(x)) => Value::Real(*x),
+        Some(LocalDBValue::TEXT(s)) | Some(LocalDBValue::UUID(s)) | Some(LocalDBValue::DECIMAL(s)) => Value::Text(s.clone()),
+        Some(LocalDBValue::BOOL(b)) => Value::Integer(*b as i64),
+        Some(LocalDBValue::JSON(v)) => Value::Text(v.to_string()),
+        Some(array @ LocalDBValue::ARRAY(_)) => Value::Text(plain_json(array).to_string()),
+        Some(LocalDBValue::NULL) | None => Value::Null,
+    }
+}
+
+/// Double-quote an identifier; SQLite's keywords differ from ours, so always quote
+fn quote(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
@@ -0,0 +1,294 @@
+use crate::Row;
+use serde::de::{DeserializeSeed, IgnoredAny, MapAccess, SeqAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::value::RawValue;
+use std::cell::{OnceCell, RefCell};
+use std::collections::{btree_map, BTreeMap, BTreeSet};
+use std::fmt;
+
+/// A table read from disk as raw JSON, parsed the first time it is used
+#[derive(Debug, Clone)]
+struct LazyTable {
+    raw: Box<RawValue>,
+    rows: OnceCell<Vec<Row>>,
+}
+
+impl LazyTable {
+    /// The table's rows; its JSON was checked to parse on open, but a table
+    /// whose values don't read back as rows is empty
+    fn rows(&self) -> &Vec<Row> {
+        self.rows.get_or_init(|| serde_json::from_str(self.raw.get()).unwrap_or_default())
+    }
+
+    fn into_rows(mut self) -> Vec<Row> {
+        self.rows();
+        self.rows.take().expect("rows parsed above")
+    }
+}
+
+/// Every table's rows by name. Tables left out by `open_tables` stay raw
+/// JSON until read; reads parse them in place, and writes move them into
+/// `loaded` first.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Tables {
+    loaded: BTreeMap<String, Vec<Row>>,
+    lazy: BTreeMap<String, LazyTable>,
+    /// Tables written to, created or removed since the last save; taken by
+    /// the save, which takes `&self`
+    dirty: RefCell<BTreeSet<String>>,
+}
+
+impl Tables {
+    /// Tables with only the `names` ones parsed; the rest are kept as raw JSON
+    pub fn partial(raw: BTreeMap<String, Box<RawValue>>, names: &[&str]) -> Self {
+        let mut tables = Self::default();
+        for (name, raw) in raw {
+            let table = LazyTable { raw, rows: OnceCell::new() };
+            if names.contains(&name.as_str()) {
+                tables.loaded.insert(name, table.into_rows());
+            } else {
+                tables.lazy.insert(name, table);
+            }
+        }
+        tables
+    }
+
+    /// Whether `name` is still raw JSON, never read
+    pub fn is_unread(&self, name: &str) -> bool {
+        self.lazy.get(name).is_some_and(|table| table.rows.get().is_none())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Vec<Row>> {
+        self.loaded.get(name).or_else(|| self.lazy.get(name).map(LazyTable::rows))
+    }
+
+    /// The rows of `name` with only the `columns` values. An unread table
+    /// is parsed for those values alone, skipping the others, and stays unread.
+    pub fn project(&self, name: &str, columns: &[&str]) -> Option<Vec<Row>> {
+        match self.lazy.get(name) {
+            Some(table) if table.rows.get().is_none() => {
+                let mut json = serde_json::Deserializer::from_str(table.raw.get());
+                Some(Projection(columns).deserialize(&mut json).unwrap_or_default())
+            }
+            _ => {
+                let rows = self.get(name)?;
+                let keep = |row: &Row| {
+                    row.iter().filter(|(c, _)| columns.contains(&c.as_str())).map(|(c, v)| (c.clone(), v.clone())).collect()
+                };
+                Some(rows.iter().map(keep).collect())
+            }
+        }
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut Vec<Row>> {
+        self.load(name);
+        self.touch(name);
+        self.loaded.get_mut(name)
+    }
+
+    pub fn contains_key(&self, name: &str) -> bool {
+        self.loaded.contains_key(name) || self.lazy.contains_key(name)
+    }
+
+    pub fn entry(&mut self, name: String) -> btree_map::Entry<'_, String, Vec<Row>> {
+        self.load(&name);
+        self.touch(&name);
+        self.loaded.entry(name)
+    }
+
+    pub fn insert(&mut self, name: String, rows: Vec<Row>) -> Option<Vec<Row>> {
+        let old = self.remove(&name);
+        self.loaded.insert(name, rows);
+        old
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<Vec<Row>> {
+        self.load(name);
+        self.touch(name);
+        self.loaded.remove(name)
+    }
+
+    /// Names of the tables changed since this was last called, present or not
+    pub fn take_dirty(&self) -> BTreeSet<String> {
+        std::mem::take(&mut *self.dirty.borrow_mut())
+    }
+
+    /// Count `names` as changed, e.g. after a failed save
+    pub fn mark_dirty(&self, names: impl IntoIterator<Item = String>) {
+        self.dirty.borrow_mut().extend(names);
+    }
+
+    /// Count every table as changed, so the next save writes them all
+    pub fn mark_all_dirty(&self) {
+        let names: Vec<String> = self.loaded.keys().chain(self.lazy.keys()).cloned().collect();
+        self.mark_dirty(names);
+    }
+
+    fn touch(&mut self, name: &str) {
+        self.dirty.get_mut().insert(name.to_string());
+    }
+
+    /// Tables and their rows in name order, parsing any still raw
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Vec<Row>)> {
+        let mut all: Vec<(&String, &Vec<Row>)> = self.loaded.iter().chain(self.lazy.iter().map(|(name, table)| (name, table.rows()))).collect();
+        all.sort_by_key(|(name, _)| *name);
+        all.into_iter()
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &Vec<Row>> {
+        self.iter().map(|(_, rows)| rows)
+    }
+
+    fn load(&mut self, name: &str) {
+        if let Some(table) = self.lazy.remove(name) {
+            self.loaded.insert(name.to_string(), table.into_rows());
+        }
+    }
+}
+
+impl<'a> IntoIterator for &'a Tables {
+    type Item = (&'a String, &'a Vec<Row>);
+    type IntoIter = std::vec::IntoIter<Self::Item>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter().collect::<Vec<_>>().into_iter()
+    }
+}
+
+impl IntoIterator for Tables {
+    type Item = (String, Vec<Row>);
+    type IntoIter = btree_map::IntoIter<String, Vec<Row>>;
+
+    fn into_iter(mut self) -> Self::IntoIter {
+        for (name, table) in std::mem::take(&mut self.lazy) {
+            self.loaded.insert(name, table.into_rows());
+        }
+        self.loaded.into_iter()
+    }
+}
+
+impl Serialize for Tables {
+    /// Unread tables are written back as the JSON they were read as
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap;
+
+        let mut map = serializer.serialize_map(Some(self.loaded.len() + self.lazy.len()))?;
+        for (name, rows) in &self.loaded {
+            map.serialize_entry(name, rows)?;
+        }
+        for (name, table) in &self.lazy {
+            match table.rows.get() {
+                Some(rows) => map.serialize_entry(name, rows)?,
+                None => map.serialize_entry(name, &table.raw)?,
+            }
+        }
+        map.end()
+    }
+}
+
+/// A table laid out as `serde_json::to_value` gives it, row keys sorted,
+/// but serialized a row at a time rather than built as one `Value`
+pub(crate) enum SortedTable<'a> {
+    Rows(&'a [Row]),
+    Raw(&'a RawValue),
+}
+
+impl Tables {
+    /// Every table by name, to serialize in sorted form
+    pub fn sorted(&self) -> BTreeMap<&str, SortedTable<'_>> {
+        let loaded = self.loaded.iter().map(|(name, rows)| (name.as_str(), SortedTable::Rows(rows)));
+        let lazy = self.lazy.iter().map(|(name, table)| {
+            let table = match table.rows.get() {
+                Some(rows) => SortedTable::Rows(rows),
+                None => SortedTable::Raw(&table.raw),
+            };
+            (name.as_str(), table)
+        });
+        loaded.chain(lazy).collect()
+    }
+}
+
+impl Serialize for SortedTable<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::{Error, SerializeSeq};
+
+        match self {
+            SortedTable::Rows(rows) => {
+                let mut seq = serializer.serialize_seq(Some(rows.len()))?;
+                for row in *rows {
+                    seq.serialize_element(&serde_json::to_value(row).map_err(S::Error::custom)?)?;
+                }
+                seq.end()
+            }
+            // an unread table is kept whole as its JSON text anyway; only its layout changes
+            SortedTable::Raw(raw) => {
+                serde_json::from_str::<serde_json::Value>(raw.get()).map_err(S::Error::custom)?.serialize(serializer)
+            }
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Tables {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(Self { loaded: BTreeMap::deserialize(deserializer)?, ..Self::default() })
+    }
+}
+
+/// Rows of a table's JSON with only some columns; the other values are
+/// skipped without being built
+#[derive(Clone, Copy)]
+struct Projection<'a>(&'a [&'a str]);
+
+impl<'de> DeserializeSeed<'de> for Projection<'_> {
+    type Value = Vec<Row>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Vec<Row>, D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de> Visitor<'de> for Projection<'_> {
+    type Value = Vec<Row>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("an array of rows")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Vec<Row>, A::Error> {
+        let mut rows = Vec::new();
+        while let Some(row) = seq.next_element_seed(ProjectedRow(self.0))? {
+            rows.push(row);
+        }
+        Ok(rows)
+    }
+}
+
+struct ProjectedRow<'a>(&'a [&'a str]);
+
+impl<'de> DeserializeSeed<'de> for ProjectedRow<'_> {
+    type Value = Row;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Row, D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de> Visitor<'de> for ProjectedRow<'_> {
+    type Value = Row;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a row object")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Row, A::Error> {
+        let mut row = Row::new();
+        while let Some(column) = map.next_key::<String>()? {
+            if self.0.contains(&column.as_str()) {
+                row.insert(column, map.next_value()?);
+            } else {
+                map.next_value::<IgnoredAny>()?;
+            }
+        }
+        Ok(row)
+    }
+}
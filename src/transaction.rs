@@ -0,0 +1,96 @@
+use crate::{statements, Checkpoint, LocalDB, LocalDBError, Result, Row};
+use std::fmt;
+
+/// Statements applied together or not at all, started with
+/// [`LocalDB::transaction`].
+///
+/// Changes are visible to the transaction's own queries at once but are
+/// only saved, and change events only delivered, by `commit`. Savepoints
+/// mark states the transaction can later return to without starting over.
+pub struct Transaction<'a> {
+    db: &'a mut LocalDB,
+    /// State when the transaction began; `None` once it has ended
+    start: Option<Checkpoint>,
+    savepoints: Vec<(String, Checkpoint)>,
+}
+
+impl<'a> Transaction<'a> {
+    pub(crate) fn new(db: &'a mut LocalDB) -> Self {
+        let start = Some(db.checkpoint());
+        Self { db, start, savepoints: Vec::new() }
+    }
+
+    /// Run `;`-separated statements as `exec` does, without saving. A failed
+    /// statement leaves the ones before it applied.
+    pub fn exec(&mut self, sql: String) -> Result<()> {
+        for stmt in statements(&sql) {
+            self.db.run_statement(stmt)?;
+        }
+        Ok(())
+    }
+
+    /// Run a SELECT against the transaction's current state
+    pub fn query(&self, sql: &str) -> Result<Vec<Row>> {
+        self.db.query(sql)
+    }
+
+    /// Mark the current state as `name`; a later savepoint may reuse a name,
+    /// hiding the earlier one until it is released or rolled back past
+    pub fn savepoint(&mut self, name: &str) {
+        self.savepoints.push((name.to_string(), self.db.checkpoint()));
+    }
+
+    /// Undo every change made since savepoint `name`, which stays set so it
+    /// can be rolled back to again; savepoints made after it are dropped
+    pub fn rollback_to(&mut self, name: &str) -> Result<()> {
+        let pos = self.find(name)?;
+        self.savepoints.truncate(pos + 1);
+        self.db.restore(self.savepoints[pos].1.clone());
+        Ok(())
+    }
+
+    /// Forget savepoint `name` and those made after it, keeping their changes
+    pub fn release(&mut self, name: &str) -> Result<()> {
+        let pos = self.find(name)?;
+        self.savepoints.truncate(pos);
+        Ok(())
+    }
+
+    fn find(&self, name: &str) -> Result<usize> {
+        self.savepoints
+            .iter()
+            .rposition(|(n, _)| n == name)
+            .ok_or_else(|| LocalDBError::SqlError(format!("No savepoint named {}", name)))
+    }
+
+    /// Save every change and deliver their change events. If saving fails
+    /// on a size limit the whole transaction is undone.
+    pub fn commit(mut self) -> Result<()> {
+        let start = self.start.take();
+        self.db.save_or_undo(start)
+    }
+
+    /// Undo every change the transaction made
+    pub fn rollback(mut self) {
+        self.undo();
+    }
+
+    fn undo(&mut self) {
+        if let Some(start) = self.start.take() {
+            self.db.restore(start);
+        }
+    }
+}
+
+impl Drop for Transaction<'_> {
+    fn drop(&mut self) {
+        self.undo();
+    }
+}
+
+impl fmt::Debug for Transaction<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: Vec<&str> = self.savepoints.iter().map(|(name, _)| name.as_str()).collect();
+        f.debug_struct("Transaction").field("active", &self.start.is_some()).field("savepoints", &names).finish()
+    }
+}
@@ -40,3 +40,3362 @@ fn test_create_insert_select() {
     // cleanup
     let _ = fs::remove_file(path);
 }
+
+#[test]
+fn test_reader_writer_roundtrip() {
+    let mut db = LocalDB::from_reader("{}".as_bytes()).expect("failed to load DB");
+
+    let sql = db.add_lines([
+        "CREATE TABLE users (id UUID, name TEXT);",
+        "INSERT INTO users VALUES ('22222222-2222-2222-2222-222222222222', 'mem');"
+    ]);
+
+    db.exec(sql).expect("SQL exec failed");
+    assert!(db.path.is_empty());
+    assert_eq!(db.path(), None);
+
+    let mut buf = Vec::new();
+    db.write_to(&mut buf).expect("write failed");
+
+    let copy = LocalDB::from_reader(buf.as_slice()).expect("reload failed");
+    let rows = copy.query("SELECT * FROM users;").expect("query failed");
+
+    assert_eq!(rows.len(), 1);
+    match rows[0].get("name").unwrap() {
+        LocalDBValue::TEXT(name) => assert_eq!(name, "mem"),
+        _ => panic!("name should be TEXT"),
+    }
+}
+
+#[test]
+fn test_where_ilike() {
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+
+    db.exec(db.add_lines([
+        "INSERT INTO users VALUES ('11111111-1111-1111-1111-111111111111', 'kk');",
+        "INSERT INTO users VALUES ('22222222-2222-2222-2222-222222222222', 'Kevin');"
+    ])).unwrap();
+
+    assert_eq!(db.query("SELECT * FROM users WHERE name ILIKE 'KK';").unwrap().len(), 1);
+    assert_eq!(db.query("SELECT * FROM users WHERE name LIKE 'KK';").unwrap().len(), 0);
+    assert_eq!(db.query("SELECT * FROM users WHERE name ILIKE 'k%';").unwrap().len(), 2);
+    assert_eq!(db.query("SELECT * FROM users WHERE name = 'Kevin';").unwrap().len(), 1);
+}
+
+#[test]
+fn test_range_index() {
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+
+    db.exec("CREATE TABLE people (id UUID, name TEXT, age INT);".to_string()).unwrap();
+    for (name, age) in [("ann", 25), ("bob", 31), ("cid", 40), ("dee", 30)] {
+        db.exec(format!("INSERT INTO people (name, age) VALUES ('{}', {});", name, age)).unwrap();
+    }
+
+    // rows inserted after the index is built must be indexed too
+    db.exec("CREATE INDEX idx_age ON people (age);".to_string()).unwrap();
+    db.exec("INSERT INTO people (name, age) VALUES ('eve', 55);".to_string()).unwrap();
+
+    let names = |sql: &str| -> Vec<String> {
+        db.query(sql)
+            .unwrap()
+            .iter()
+            .map(|row| match row.get("name").unwrap() {
+                LocalDBValue::TEXT(name) => name.clone(),
+                _ => panic!("name should be TEXT"),
+            })
+            .collect()
+    };
+
+    assert_eq!(names("SELECT * FROM people WHERE age > 30;"), ["bob", "cid", "eve"]);
+    assert_eq!(names("SELECT * FROM people WHERE age <= 30;"), ["ann", "dee"]);
+    assert_eq!(names("SELECT * FROM people WHERE age BETWEEN 30 AND 40;"), ["bob", "cid", "dee"]);
+    assert_eq!(names("SELECT * FROM people WHERE age BETWEEN 40 AND 30;"), Vec::<String>::new());
+
+    assert!(db.exec("CREATE INDEX idx_age ON people (age);".to_string()).is_err());
+}
+
+#[test]
+fn test_stable_key_order() {
+    let path = "test_stable_order.db";
+    let _ = fs::remove_file(path);
+
+    let mut db = LocalDB::create(path).unwrap();
+    db.exec(db.add_lines([
+        "INSERT INTO zebras (name, age, id) VALUES ('z', 1, 'a');",
+        "INSERT INTO apes (name, id) VALUES ('a', 'b');"
+    ])).unwrap();
+
+    let content = fs::read_to_string(path).unwrap();
+    assert!(content.find("apes").unwrap() < content.find("zebras").unwrap());
+
+    let zebras = &content[content.find("zebras").unwrap()..];
+    assert!(zebras.find("\"age\"").unwrap() < zebras.find("\"id\"").unwrap());
+    assert!(zebras.find("\"id\"").unwrap() < zebras.find("\"name\"").unwrap());
+
+    // saving again produces the identical file
+    db.save().unwrap();
+    assert_eq!(fs::read_to_string(path).unwrap(), content);
+
+    let _ = fs::remove_file(path);
+}
+
+#[test]
+fn test_query_builder() {
+    use localdb::Filter;
+
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    db.exec(db.add_lines([
+        "INSERT INTO users (id, name, age) VALUES ('1', 'kk', 30);",
+        "INSERT INTO users (id, name, age) VALUES ('2', 'it''s', 40);"
+    ])).unwrap();
+
+    let rows = db
+        .select("users")
+        .columns(&["id", "name"])
+        .filter(Filter::eq("name", "it's"))
+        .limit(10)
+        .run()
+        .unwrap();
+
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0].len(), 2);
+    assert!(!rows[0].contains_key("age"));
+
+    let sql = db.query("SELECT id, name FROM users WHERE age >= 30 LIMIT 1;").unwrap();
+    let built = db.select("users").columns(&["id", "name"]).filter(Filter::ge("age", 30)).limit(1).run().unwrap();
+    assert_eq!(sql.len(), 1);
+    assert_eq!(sql[0].get("id"), built[0].get("id"));
+}
+
+#[test]
+fn test_attach_detach() {
+    let path = "test_attach_ref.db";
+    let _ = fs::remove_file(path);
+
+    let mut reference = LocalDB::create(path).unwrap();
+    reference.exec("INSERT INTO countries (code, name) VALUES ('de', 'Germany');".to_string()).unwrap();
+
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    db.attach("ref", path).unwrap();
+    assert!(db.attach("ref", path).is_err());
+
+    let rows = db.query("SELECT * FROM ref.countries WHERE code = 'de';").unwrap();
+    assert_eq!(rows.len(), 1);
+
+    db.detach("ref").unwrap();
+    assert!(db.query("SELECT * FROM ref.countries;").is_err());
+    assert!(db.detach("ref").is_err());
+
+    let _ = fs::remove_file(path);
+}
+
+#[test]
+fn test_select_exists() {
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    db.exec("INSERT INTO users (id, name, active) VALUES ('1', 'kk', TRUE);".to_string()).unwrap();
+
+    let exists = |sql: &str| match db.query(sql).unwrap()[0].get("exists").unwrap() {
+        LocalDBValue::BOOL(b) => *b,
+        _ => panic!("exists should be BOOL"),
+    };
+
+    assert!(exists("SELECT EXISTS(SELECT * FROM users WHERE id = '1');"));
+    assert!(!exists("SELECT EXISTS(SELECT * FROM users WHERE id = '2');"));
+    assert!(exists("SELECT EXISTS(SELECT * FROM users WHERE active = TRUE);"));
+    assert!(!exists("SELECT EXISTS(SELECT * FROM missing);"));
+}
+
+#[test]
+fn test_lossless_text_roundtrip() {
+    let path = "test_lossless_text.db";
+    let _ = fs::remove_file(path);
+
+    let tricky = [
+        "line one\nline two",
+        "it's a \"quoted\" value",
+        "back\\slash \\n not a newline",
+        "tab\there, emoji 🦀, accents éü, 日本語",
+        "'', '",
+    ];
+
+    let mut db = LocalDB::create(path).unwrap();
+    for (i, text) in tricky.iter().enumerate() {
+        db.exec(format!(
+            "INSERT INTO notes (id, body) VALUES ({}, '{}');",
+            i,
+            text.replace('\'', "''")
+        ))
+        .unwrap();
+    }
+    db.exec("INSERT INTO notes VALUES ('33333333-3333-3333-3333-333333333333', 'a, b');".to_string()).unwrap();
+
+    let bodies = |db: &LocalDB| -> Vec<String> {
+        db.query("SELECT * FROM notes;")
+            .unwrap()
+            .iter()
+            .map(|row| match row.get("body").or(row.get("name")).unwrap() {
+                LocalDBValue::TEXT(s) => s.clone(),
+                _ => panic!("body should be TEXT"),
+            })
+            .collect()
+    };
+
+    let mut expected: Vec<String> = tricky.iter().map(|s| s.to_string()).collect();
+    expected.push("a, b".to_string());
+
+    // through the JSON file
+    let reopened = LocalDB::open(path).unwrap();
+    assert_eq!(bodies(&reopened), expected);
+
+    // through dump_sql
+    let mut restored = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    restored.exec(reopened.dump_sql()).unwrap();
+    assert_eq!(bodies(&restored), expected);
+    assert_eq!(restored.dump_sql(), reopened.dump_sql());
+
+    match restored.query("SELECT * FROM notes WHERE name = 'a, b';").unwrap()[0].get("id").unwrap() {
+        LocalDBValue::UUID(id) => assert_eq!(id, "33333333-3333-3333-3333-333333333333"),
+        _ => panic!("id should stay UUID"),
+    }
+
+    let _ = fs::remove_file(path);
+}
+
+#[test]
+fn test_update_where_closure() {
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    for (name, age) in [("ann", 17), ("bob", 18), ("cid", 30)] {
+        db.exec(format!("INSERT INTO people (name, age) VALUES ('{}', {});", name, age)).unwrap();
+    }
+    db.exec("CREATE INDEX idx_age ON people (age);".to_string()).unwrap();
+
+    let changed = db
+        .update_where(
+            "people",
+            |row| matches!(row.get("age"), Some(LocalDBValue::INT(age)) if *age >= 18),
+            |row| {
+                if let Some(LocalDBValue::INT(age)) = row.get_mut("age") {
+                    *age += 100;
+                }
+            },
+        )
+        .unwrap();
+
+    assert_eq!(changed, 2);
+    assert_eq!(db.query("SELECT * FROM people WHERE age > 100;").unwrap().len(), 2);
+    assert_eq!(db.query("SELECT * FROM people WHERE age = 17;").unwrap().len(), 1);
+    assert!(db.update_where("missing", |_| true, |_| {}).is_err());
+}
+
+#[test]
+fn test_iter_tables() {
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    db.exec(db.add_lines([
+        "INSERT INTO b_table (n) VALUES (1); INSERT INTO b_table (n) VALUES (2);",
+        "INSERT INTO a_table (n) VALUES (3);"
+    ])).unwrap();
+
+    let summary: Vec<(String, usize)> = db
+        .iter_tables()
+        .map(|(name, rows)| (name, rows.count()))
+        .collect();
+
+    assert_eq!(summary, [("a_table".to_string(), 1), ("b_table".to_string(), 2)]);
+}
+
+#[test]
+fn test_coercion_policy_matrix() {
+    use localdb::Coercion;
+
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    db.exec(db.add_lines([
+        "INSERT INTO v (kind, val) VALUES ('int', 5); INSERT INTO v (kind, val) VALUES ('text', '5');",
+        "INSERT INTO v (kind, val) VALUES ('uuid', UUID '5'); INSERT INTO v (kind, val) VALUES ('bool', TRUE); INSERT INTO v (kind, val) VALUES ('booltext', 'true');"
+    ])).unwrap();
+
+    let kinds = |db: &LocalDB, literal: &str| -> Vec<String> {
+        db.query(&format!("SELECT kind FROM v WHERE val = {};", literal))
+            .unwrap()
+            .iter()
+            .map(|row| match row.get("kind").unwrap() {
+                LocalDBValue::TEXT(k) => k.clone(),
+                _ => panic!("kind should be TEXT"),
+            })
+            .collect()
+    };
+
+    // strict: only the same type family matches (TEXT and UUID are one family)
+    assert_eq!(kinds(&db, "5"), ["int"]);
+    assert_eq!(kinds(&db, "'5'"), ["text", "uuid"]);
+    assert_eq!(kinds(&db, "UUID '5'"), ["text", "uuid"]);
+    assert_eq!(kinds(&db, "TRUE"), ["bool"]);
+    assert_eq!(kinds(&db, "'true'"), ["booltext"]);
+
+    // coercive: text parses as INT or BOOL when compared to one
+    db.set_coercion(Coercion::Coerce);
+    assert_eq!(kinds(&db, "5"), ["int", "text", "uuid"]);
+    assert_eq!(kinds(&db, "'5'"), ["int", "text", "uuid"]);
+    assert_eq!(kinds(&db, "UUID '5'"), ["int", "text", "uuid"]);
+    assert_eq!(kinds(&db, "TRUE"), ["bool", "booltext"]);
+    assert_eq!(kinds(&db, "'true'"), ["bool", "booltext"]);
+
+    // INT and BOOL never compare, even when coercing
+    assert_eq!(kinds(&db, "1"), Vec::<String>::new());
+    assert_eq!(db.query("SELECT kind FROM v WHERE val > 4;").unwrap().len(), 3);
+}
+
+#[test]
+fn test_insert_select() {
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    db.exec(db.add_lines([
+        "INSERT INTO users (id, name, active) VALUES ('1', 'kk', TRUE); INSERT INTO users (id, name, active) VALUES ('2', 'old', FALSE);",
+        "INSERT INTO users (id, name, active) VALUES ('3', 'gone', FALSE);"
+    ])).unwrap();
+
+    db.exec("INSERT INTO archive SELECT * FROM users WHERE active = FALSE;".to_string()).unwrap();
+    let archived = db.query("SELECT * FROM archive;").unwrap();
+    assert_eq!(archived.len(), 2);
+    assert_eq!(archived[0].len(), 3);
+
+    db.exec("INSERT INTO names (label) SELECT name FROM users WHERE id = '1';".to_string()).unwrap();
+    match db.query("SELECT * FROM names;").unwrap()[0].get("label").unwrap() {
+        LocalDBValue::TEXT(label) => assert_eq!(label, "kk"),
+        _ => panic!("label should be TEXT"),
+    }
+
+    assert!(db.exec("INSERT INTO names (a, b) SELECT name FROM users;".to_string()).is_err());
+    assert!(db.exec("INSERT INTO names (a) SELECT * FROM users;".to_string()).is_err());
+
+    // selecting nothing into a table that doesn't exist yet inserts nothing
+    assert_eq!(db.exec_one("INSERT INTO nothere SELECT * FROM users WHERE id = 'none';").unwrap(), 0);
+    db.exec("DELETE FROM users;".to_string()).unwrap();
+    db.exec("INSERT INTO nothere SELECT * FROM users;".to_string()).unwrap();
+    assert!(db.count("nothere").is_err());
+}
+
+#[test]
+fn test_save_options() {
+    use localdb::SaveOptions;
+
+    let path = "test_save_options.db";
+    let _ = fs::remove_file(path);
+
+    let mut db = LocalDB::create_with(path, SaveOptions::pretty(4)).unwrap();
+    db.exec("INSERT INTO t (b, a) VALUES ('x y', 1);".to_string()).unwrap();
+    assert!(fs::read_to_string(path).unwrap().contains("\n    \"t\": ["));
+
+    let mut db = LocalDB::open_with(path, SaveOptions::canonical()).unwrap();
+    db.save().unwrap();
+    assert_eq!(
+        fs::read_to_string(path).unwrap(),
+        format!(r#"{{"__meta__":{{"format":1,"localdb":"{}"}},"t":[{{"a":{{"INT":1}},"b":{{"TEXT":"x y"}}}}]}}"#, env!("CARGO_PKG_VERSION"))
+    );
+
+    db.set_save_options(SaveOptions::default());
+    db.save().unwrap();
+    assert!(fs::read_to_string(path).unwrap().contains("\n  \"t\": ["));
+
+    let _ = fs::remove_file(path);
+}
+
+#[test]
+fn test_io_error_carries_path() {
+    use localdb::LocalDBError;
+
+    let missing = "test_no_such_dir/missing.db";
+    match LocalDB::open(missing) {
+        Err(LocalDBError::IoError { path, source }) => {
+            assert_eq!(path, missing);
+            assert_eq!(source.kind(), std::io::ErrorKind::NotFound);
+        }
+        other => panic!("expected IoError, got {:?}", other),
+    }
+
+    let err = LocalDB::create(missing).unwrap_err();
+    assert!(err.to_string().contains(missing));
+}
+
+#[test]
+fn test_explain() {
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    db.exec("INSERT INTO people (name, age) VALUES ('ann', 30);".to_string()).unwrap();
+
+    assert_eq!(
+        db.explain("SELECT name FROM people WHERE age > 30 LIMIT 5;").unwrap(),
+        "SELECT people\naccess: full scan\nfilter: age > 30\ncolumns: name\nlimit: 5"
+    );
+
+    db.exec("CREATE INDEX idx_age ON people (age);".to_string()).unwrap();
+    assert_eq!(
+        db.explain("SELECT * FROM people WHERE age BETWEEN 1 AND 9;").unwrap(),
+        "SELECT people\naccess: index idx_age (age)\nfilter: age BETWEEN 1 AND 9\ncolumns: *"
+    );
+    assert!(db.explain("SELECT * FROM people WHERE age != 3;").unwrap().contains("full scan"));
+    assert!(db.explain("SELECT EXISTS(SELECT * FROM people WHERE name ILIKE 'a%');").unwrap().contains("stop: first match"));
+}
+
+#[test]
+fn test_bare_boolean_filter() {
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    db.exec(db.add_lines([
+        "INSERT INTO users (name, active) VALUES ('on', TRUE); INSERT INTO users (name, active) VALUES ('off', FALSE);",
+        "INSERT INTO users (name, active) VALUES ('text', 'true'); INSERT INTO users (name) VALUES ('none');"
+    ])).unwrap();
+
+    let names = |sql: &str| -> Vec<String> {
+        db.query(sql)
+            .unwrap()
+            .iter()
+            .map(|row| match row.get("name").unwrap() {
+                LocalDBValue::TEXT(name) => name.clone(),
+                _ => panic!("name should be TEXT"),
+            })
+            .collect()
+    };
+
+    assert_eq!(names("SELECT * FROM users WHERE active;"), ["on"]);
+    assert_eq!(names("SELECT * FROM users WHERE NOT active;"), ["off"]);
+    assert_eq!(names("SELECT name FROM users WHERE active LIMIT 1"), ["on"]);
+    assert_eq!(db.query("SELECT * FROM users WHERE NOT active LIMIT 5;").unwrap().len(), 1);
+}
+
+#[test]
+fn test_row_counts() {
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    db.exec(db.add_lines([
+        "CREATE TABLE empty (id UUID);",
+        "INSERT INTO people (age) VALUES (10); INSERT INTO people (age) VALUES (20); INSERT INTO people (age) VALUES (30);"
+    ])).unwrap();
+
+    assert_eq!(db.count("people").unwrap(), 3);
+    assert_eq!(db.count("empty").unwrap(), 0);
+    assert!(db.count("missing").is_err());
+
+    let count = |sql: &str| match db.query(sql).unwrap()[0].get("COUNT(*)").unwrap() {
+        LocalDBValue::INT(n) => *n,
+        _ => panic!("COUNT(*) should be INT"),
+    };
+
+    assert_eq!(count("SELECT COUNT(*) FROM people;"), 3);
+    assert_eq!(count("SELECT COUNT(*) FROM people WHERE age >= 20;"), 2);
+    assert!(db.explain("SELECT COUNT(*) FROM people;").unwrap().contains("access: row count"));
+}
+
+#[test]
+fn test_open_readonly() {
+    use localdb::LocalDBError;
+
+    let path = "test_readonly.db";
+    let _ = fs::remove_file(path);
+
+    let mut db = LocalDB::create(path).unwrap();
+    db.exec("INSERT INTO users (name) VALUES ('kk');".to_string()).unwrap();
+    let before = fs::read_to_string(path).unwrap();
+
+    let mut ro = LocalDB::open_readonly(path).unwrap();
+    assert_eq!(ro.query("SELECT * FROM users;").unwrap().len(), 1);
+
+    assert!(matches!(
+        ro.exec("INSERT INTO users (name) VALUES ('no');".to_string()),
+        Err(LocalDBError::ReadOnly)
+    ));
+    assert!(matches!(ro.update_where("users", |_| true, |_| {}), Err(LocalDBError::ReadOnly)));
+    assert!(matches!(ro.save(), Err(LocalDBError::ReadOnly)));
+
+    assert_eq!(ro.query("SELECT * FROM users;").unwrap().len(), 1);
+    assert_eq!(fs::read_to_string(path).unwrap(), before);
+
+    let _ = fs::remove_file(path);
+}
+
+#[test]
+fn test_schema_comments() {
+    use localdb::ColType;
+
+    let path = "test_schema_comments.db";
+    let _ = fs::remove_file(path);
+
+    let mut db = LocalDB::create(path).unwrap();
+    db.exec(db.add_lines([
+        "CREATE TABLE users (id UUID, name TEXT COMMENT 'display name') COMMENT 'app users';",
+        "COMMENT ON COLUMN users.id IS 'primary id';"
+    ])).unwrap();
+    assert!(db.exec("COMMENT ON COLUMN users.nope IS 'x';".to_string()).is_err());
+    assert!(db.exec("COMMENT ON TABLE missing IS 'x';".to_string()).is_err());
+
+    let reopened = LocalDB::open(path).unwrap();
+    let def = reopened.schema("users").expect("schema should be tracked");
+    assert_eq!(def.comment.as_deref(), Some("app users"));
+    assert_eq!(def.columns.len(), 2);
+    assert_eq!(def.columns[0].col_type, ColType::Uuid);
+    assert_eq!(def.columns[0].comment.as_deref(), Some("primary id"));
+    assert_eq!(def.column("name").unwrap().comment.as_deref(), Some("display name"));
+
+    assert!(reopened.dump_sql().starts_with(
+        "CREATE TABLE users (id UUID COMMENT 'primary id', name TEXT COMMENT 'display name') COMMENT 'app users';"
+    ));
+    assert!(reopened.query("SELECT * FROM users;").unwrap().is_empty());
+
+    let _ = fs::remove_file(path);
+}
+
+#[test]
+fn test_projection_arithmetic() {
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    db.exec(db.add_lines([
+        "INSERT INTO line_items (sku, price, quantity) VALUES ('a', 2.5, 4);",
+        "INSERT INTO line_items (sku, price, quantity) VALUES ('b', 3, 0);"
+    ])).unwrap();
+
+    let rows = db.query("SELECT sku, price * quantity AS total, quantity - 1, 7 / quantity FROM line_items;").unwrap();
+    assert_eq!(rows[0].get("total"), Some(&LocalDBValue::REAL(10.0)));
+    assert_eq!(rows[1].get("total"), Some(&LocalDBValue::INT(0)));
+    assert_eq!(rows[0].get("quantity - 1"), Some(&LocalDBValue::INT(3)));
+    assert_eq!(rows[0].get("7 / quantity"), Some(&LocalDBValue::INT(1)));
+    assert_eq!(rows[1].get("7 / quantity"), Some(&LocalDBValue::NULL));
+
+    let rows = db.query("SELECT (price + 1) * -2 AS v, sku * 2 AS bad FROM line_items WHERE price > 2.7;").unwrap();
+    assert_eq!(rows[0].get("v"), Some(&LocalDBValue::INT(-8)));
+    assert_eq!(rows[0].get("bad"), Some(&LocalDBValue::NULL));
+
+    assert_eq!(
+        db.explain("SELECT price * (quantity + 1) AS t, sku FROM line_items;").unwrap(),
+        "SELECT line_items\naccess: full scan\ncolumns: price * (quantity + 1) AS t, sku"
+    );
+
+    let mut copy = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    copy.exec(db.dump_sql()).unwrap();
+    assert_eq!(copy.dump_sql(), db.dump_sql());
+}
+
+#[test]
+fn test_table_builder() {
+    use localdb::{ColType, LocalDBError};
+
+    let mut built = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    built.create_table("users")
+        .column("id", ColType::Uuid)
+        .primary_key("id")
+        .column("name", ColType::Text)
+        .not_null("name")
+        .build()
+        .unwrap();
+
+    let mut parsed = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    parsed.exec("CREATE TABLE users (id UUID PRIMARY KEY, name TEXT NOT NULL);".to_string()).unwrap();
+    assert_eq!(built.schema("users"), parsed.schema("users"));
+    assert!(built.dump_sql().starts_with("CREATE TABLE users (id UUID PRIMARY KEY, name TEXT NOT NULL);"));
+
+    assert!(built.create_table("bad").column("a", ColType::Int).not_null("b").build().is_err());
+
+    built.exec("INSERT INTO users VALUES ('u1', 'kk');".to_string()).unwrap();
+    let dup = built.exec("INSERT INTO users VALUES ('u1', 'other');".to_string());
+    assert!(matches!(dup, Err(LocalDBError::ConstraintViolation(msg)) if msg.contains("PRIMARY KEY")));
+    let null = built.exec("INSERT INTO users (id, name) VALUES ('u2', NULL);".to_string());
+    assert!(matches!(null, Err(LocalDBError::ConstraintViolation(msg)) if msg.contains("NOT NULL")));
+    assert!(built.exec("INSERT INTO users (name) VALUES ('no id');".to_string()).is_err());
+    assert_eq!(built.count("users").unwrap(), 1);
+}
+
+#[test]
+fn test_change_events() {
+    use localdb::{ChangeEvent, ChangeKind};
+    use std::sync::{Arc, Mutex};
+
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    let seen: Arc<Mutex<Vec<ChangeEvent>>> = Arc::default();
+    let sink = Arc::clone(&seen);
+    db.on_change(move |event| sink.lock().unwrap().push(event));
+
+    db.exec(db.add_lines([
+        "CREATE TABLE people (name TEXT PRIMARY KEY, age INT);",
+        "INSERT INTO people (name, age) VALUES ('kk', 30);"
+    ])).unwrap();
+    db.exec("INSERT INTO archive SELECT * FROM people;".to_string()).unwrap();
+    assert!(db.exec("INSERT INTO people (name, age) VALUES ('kk', 31);".to_string()).is_err());
+    db.update_where("people", |_| true, |row| { row.insert("age".into(), LocalDBValue::INT(31)); }).unwrap();
+
+    let seen = seen.lock().unwrap();
+    let summary: Vec<(ChangeKind, &str, usize)> = seen.iter().map(|e| (e.kind, e.table.as_str(), e.rows.len())).collect();
+    assert_eq!(summary, vec![
+        (ChangeKind::Insert, "people", 1),
+        (ChangeKind::Insert, "archive", 1),
+        (ChangeKind::Update, "people", 1),
+    ]);
+    assert_eq!(seen[2].rows[0].get("age"), Some(&LocalDBValue::INT(31)));
+}
+
+#[test]
+fn test_delete_update_limit() {
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    db.exec("CREATE TABLE logs (id INT PRIMARY KEY, old BOOL, hits INT);".to_string()).unwrap();
+    for id in 1..=5 {
+        db.exec(format!("INSERT INTO logs (id, old, hits) VALUES ({}, {}, 0);", id, id <= 4)).unwrap();
+    }
+    db.exec("CREATE INDEX idx_id ON logs (id);".to_string()).unwrap();
+
+    db.exec("DELETE FROM logs WHERE old = TRUE LIMIT 3;".to_string()).unwrap();
+    let ids: Vec<LocalDBValue> = db.query("SELECT id FROM logs;").unwrap().iter().map(|r| r["id"].clone()).collect();
+    assert_eq!(ids, [LocalDBValue::INT(4), LocalDBValue::INT(5)]);
+    assert_eq!(db.query("SELECT * FROM logs WHERE id = 5;").unwrap().len(), 1);
+
+    db.exec("UPDATE logs SET hits = hits + 1, old = FALSE LIMIT 1;".to_string()).unwrap();
+    let rows = db.query("SELECT * FROM logs WHERE hits = 1;").unwrap();
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0].get("id"), Some(&LocalDBValue::INT(4)));
+    assert_eq!(rows[0].get("old"), Some(&LocalDBValue::BOOL(false)));
+
+    // a constraint violation leaves every row untouched
+    assert!(db.exec("UPDATE logs SET id = 9;".to_string()).is_err());
+    assert_eq!(db.query("SELECT * FROM logs WHERE id = 9;").unwrap().len(), 0);
+    assert!(db.exec("DELETE FROM missing;".to_string()).is_err());
+
+    db.exec("DELETE FROM logs;".to_string()).unwrap();
+    assert_eq!(db.count("logs").unwrap(), 0);
+}
+
+#[test]
+fn test_migrations() {
+    use localdb::Migration;
+
+    let path = "test_migrations.db";
+    let _ = fs::remove_file(path);
+
+    let migrations = [
+        Migration { version: 2, up_sql: "INSERT INTO people (name, age) VALUES ('kk', 30);" },
+        Migration { version: 1, up_sql: "CREATE TABLE people (name TEXT PRIMARY KEY, age INT);" },
+    ];
+
+    let mut db = LocalDB::create(path).unwrap();
+    assert_eq!(db.migrate(&migrations).unwrap(), 2);
+    assert_eq!(db.migrate(&migrations).unwrap(), 0);
+
+    let mut db = LocalDB::open(path).unwrap();
+    assert_eq!(db.count("people").unwrap(), 1);
+    assert_eq!(db.count("schema_migrations").unwrap(), 2);
+
+    // a failing migration is rolled back entirely and not recorded
+    let bad = Migration {
+        version: 3,
+        up_sql: "INSERT INTO people (name, age) VALUES ('jo', 20); INSERT INTO people (name, age) VALUES ('kk', 1);",
+    };
+    assert!(db.migrate(&[bad]).is_err());
+    assert_eq!(LocalDB::open(path).unwrap().count("people").unwrap(), 1);
+    assert_eq!(db.count("people").unwrap(), 1);
+    assert_eq!(db.count("schema_migrations").unwrap(), 2);
+
+    let _ = fs::remove_file(path);
+}
+
+#[test]
+fn test_not_like() {
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    db.exec(db.add_lines([
+        "INSERT INTO users (name) VALUES ('test_a'); INSERT INTO users (name) VALUES ('Test_b');",
+        "INSERT INTO users (name) VALUES ('kk'); INSERT INTO users (name) VALUES (42);"
+    ])).unwrap();
+
+    assert_eq!(db.query("SELECT * FROM users WHERE name NOT LIKE 'test%';").unwrap().len(), 2);
+    assert_eq!(db.query("SELECT * FROM users WHERE name NOT ILIKE 'test%';").unwrap().len(), 1);
+    // non-text values match neither LIKE nor NOT LIKE
+    assert_eq!(db.query("SELECT * FROM users WHERE name LIKE '%';").unwrap().len(), 3);
+    assert_eq!(db.query("SELECT * FROM users WHERE name NOT LIKE 'zzz';").unwrap().len(), 3);
+    assert!(db.query("SELECT * FROM users WHERE name NOT = 'kk';").is_err());
+    assert!(db.explain("SELECT * FROM users WHERE name NOT LIKE 't%';").unwrap().contains("filter: name NOT LIKE 't%'"));
+}
+
+#[test]
+fn test_query_result_columns() {
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    db.exec(db.add_lines([
+        "CREATE TABLE users (name TEXT, id UUID);",
+        "INSERT INTO users (id, name, zeta, alpha) VALUES (UUID 'u1', 'kk', 1, 2);"
+    ])).unwrap();
+
+    let result = db.query_result("SELECT * FROM users;").unwrap();
+    assert_eq!(result.columns, vec!["name", "id", "alpha", "zeta"]);
+
+    let result = db.query_result("SELECT zeta, name AS n FROM users;").unwrap();
+    assert_eq!(result.columns, vec!["zeta", "n"]);
+    let values: Vec<Vec<Option<&LocalDBValue>>> = result.values().collect();
+    assert_eq!(values, [[Some(&LocalDBValue::INT(1)), Some(&LocalDBValue::from("kk"))]]);
+
+    assert_eq!(db.query_result("SELECT COUNT(*) FROM users;").unwrap().columns, vec!["COUNT(*)"]);
+}
+
+#[test]
+fn test_quoted_identifiers() {
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    db.exec(db.add_lines([
+        "CREATE TABLE \"user data\" (\"full name\" TEXT, \"select\" INT, \"say \"\"hi\"\"\" BOOL);",
+        "INSERT INTO \"user data\" (\"full name\", \"select\", \"say \"\"hi\"\"\") VALUES ('kk', 1, TRUE);"
+    ])).unwrap();
+
+    let rows = db.query("SELECT \"full name\", \"select\" AS \"the pick\" FROM \"user data\" WHERE \"select\" = 1;").unwrap();
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0].get("full name"), Some(&LocalDBValue::from("kk")));
+    assert_eq!(rows[0].get("the pick"), Some(&LocalDBValue::INT(1)));
+    assert!(db.query("SELECT * FROM \"user data\" WHERE \"say \"\"hi\"\"\";").unwrap().len() == 1);
+    assert!(db.query("SELECT * FROM \"user data;").is_err());
+
+    let dump = db.dump_sql();
+    assert!(dump.starts_with("CREATE TABLE \"user data\" (\"full name\" TEXT, \"select\" INT, \"say \"\"hi\"\"\" BOOL);"));
+    let mut copy = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    copy.exec(dump.clone()).unwrap();
+    assert_eq!(copy.dump_sql(), dump);
+
+    // words the parser reads specially come back quoted
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    db.exec(db.add_lines([
+        "CREATE TABLE \"index\" (\"in\" INT, \"desc\" TEXT, \"uuid\" TEXT, \"now\" INT, \"all\" BOOL);",
+        "INSERT INTO \"index\" (\"in\", \"desc\", \"uuid\", \"now\", \"all\") VALUES (1, 'a', 'b', 2, TRUE);"
+    ])).unwrap();
+    let dump = db.dump_sql();
+    assert!(dump.starts_with("CREATE TABLE \"index\" (\"in\" INT, \"desc\" TEXT, \"uuid\" TEXT, \"now\" INT, \"all\" BOOL);"));
+    let mut copy = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    copy.exec(dump.clone()).unwrap();
+    assert_eq!(copy.dump_sql(), dump);
+    assert_eq!(copy.query("SELECT \"desc\" FROM \"index\" WHERE \"in\" = 1 AND \"all\";").unwrap().len(), 1);
+}
+
+#[test]
+fn test_insert_many() {
+    let path = "test_insert_many.db";
+    let _ = fs::remove_file(path);
+
+    let mut db = LocalDB::create(path).unwrap();
+    db.exec("CREATE TABLE nums (n INT PRIMARY KEY);".to_string()).unwrap();
+
+    let rows = (0..10_000).map(|n| localdb::Row::from([("n".to_string(), LocalDBValue::INT(n))]));
+    assert_eq!(db.insert_many("nums", rows).unwrap(), 10_000);
+    assert_eq!(LocalDB::open(path).unwrap().count("nums").unwrap(), 10_000);
+
+    // one duplicate rejects the whole batch
+    let rows = [5, 10_000].map(|n| localdb::Row::from([("n".to_string(), LocalDBValue::INT(n))]));
+    assert!(db.insert_many("nums", rows).is_err());
+    let rows = [10_000, 9_999].map(|n| localdb::Row::from([("n".to_string(), LocalDBValue::INT(n))]));
+    assert!(db.insert_many("nums", rows).is_err());
+    assert_eq!(db.count("nums").unwrap(), 10_000);
+
+    let _ = fs::remove_file(path);
+}
+
+#[test]
+fn test_subqueries() {
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    db.exec(db.add_lines([
+        "INSERT INTO active_users (id) VALUES (1); INSERT INTO active_users (id) VALUES (3);",
+        "INSERT INTO orders (user_id, total) VALUES (1, 10); INSERT INTO orders (user_id, total) VALUES (2, 20); INSERT INTO orders (user_id, total) VALUES (3, 60);"
+    ])).unwrap();
+
+    let totals = |sql: &str| -> Vec<LocalDBValue> {
+        db.query(sql).unwrap().iter().map(|r| r["total"].clone()).collect()
+    };
+
+    assert_eq!(totals("SELECT total FROM orders WHERE user_id IN (SELECT id FROM active_users);"), [LocalDBValue::INT(10), LocalDBValue::INT(60)]);
+    assert_eq!(totals("SELECT total FROM orders WHERE user_id NOT IN (SELECT id FROM active_users);"), [LocalDBValue::INT(20)]);
+    assert_eq!(totals("SELECT total FROM orders WHERE user_id IN (2, 3);"), [LocalDBValue::INT(20), LocalDBValue::INT(60)]);
+    assert_eq!(totals("SELECT total FROM orders WHERE total > (SELECT AVG(total) FROM orders);"), [LocalDBValue::INT(60)]);
+    assert!(totals("SELECT total FROM orders WHERE total = (SELECT id FROM active_users WHERE id > 5);").is_empty());
+
+    assert!(db.query("SELECT * FROM orders WHERE total = (SELECT id FROM active_users);").is_err());
+    assert!(db.query("SELECT * FROM orders WHERE total IN (SELECT * FROM active_users);").is_err());
+
+    let stats = &db.query("SELECT COUNT(*), SUM(total), AVG(total), MIN(total), MAX(total) * 2 AS top FROM orders;").unwrap()[0];
+    assert_eq!(stats["COUNT(*)"], LocalDBValue::INT(3));
+    assert_eq!(stats["SUM(total)"], LocalDBValue::INT(90));
+    assert_eq!(stats["AVG(total)"], LocalDBValue::REAL(30.0));
+    assert_eq!(stats["MIN(total)"], LocalDBValue::INT(10));
+    assert_eq!(stats["top"], LocalDBValue::INT(120));
+    assert!(db.query("SELECT user_id, SUM(total) FROM orders;").is_err());
+
+    db.exec("DELETE FROM orders WHERE user_id IN (SELECT id FROM active_users);".to_string()).unwrap();
+    assert_eq!(db.count("orders").unwrap(), 1);
+}
+
+#[test]
+fn test_snapshot() {
+    let (path, copy_path) = ("test_snapshot.db", "test_snapshot_copy.db");
+    let _ = fs::remove_file(path);
+    let _ = fs::remove_file(copy_path);
+
+    let mut db = LocalDB::create(path).unwrap();
+    db.exec("INSERT INTO users (name) VALUES ('kk');".to_string()).unwrap();
+
+    let mut copy = db.snapshot(copy_path).unwrap();
+    assert_eq!(copy.path, copy_path);
+    assert_eq!(copy.path(), Some(copy_path));
+    copy.exec("INSERT INTO users (name) VALUES ('branch');".to_string()).unwrap();
+
+    assert_eq!(db.count("users").unwrap(), 1);
+    assert_eq!(LocalDB::open(path).unwrap().count("users").unwrap(), 1);
+    assert_eq!(LocalDB::open(copy_path).unwrap().count("users").unwrap(), 2);
+
+    let _ = fs::remove_file(path);
+    let _ = fs::remove_file(copy_path);
+}
+
+#[test]
+fn test_angle_bracket_inequality() {
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    db.exec("INSERT INTO t (n) VALUES (1); INSERT INTO t (n) VALUES (2);".to_string()).unwrap();
+
+    assert_eq!(db.query("SELECT * FROM t WHERE n <> 1;").unwrap().len(), 1);
+    assert_eq!(db.query("SELECT * FROM t WHERE n<>1;").unwrap().len(), 1);
+    assert_eq!(db.explain("SELECT * FROM t WHERE n <> 1;").unwrap(), db.explain("SELECT * FROM t WHERE n != 1;").unwrap());
+    assert!(db.query("SELECT * FROM t WHERE n < > 1;").is_err());
+}
+
+#[test]
+fn test_query_cache_invalidation() {
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    db.enable_query_cache(2);
+    db.exec("INSERT INTO orders (user_id) VALUES (1); INSERT INTO vip (id) VALUES (1);".to_string()).unwrap();
+
+    let vip_orders = "SELECT * FROM orders WHERE user_id IN (SELECT id FROM vip);";
+    assert_eq!(db.query(vip_orders).unwrap().len(), 1);
+    assert_eq!(db.query("SELECT   *  FROM orders WHERE user_id IN (SELECT id FROM vip);").unwrap().len(), 1);
+
+    // writes to a table read only by the subquery still invalidate
+    db.exec("INSERT INTO orders (user_id) VALUES (2); INSERT INTO vip (id) VALUES (2);".to_string()).unwrap();
+    assert_eq!(db.query(vip_orders).unwrap().len(), 2);
+
+    db.update_where("vip", |_| true, |row| { row.insert("id".into(), LocalDBValue::INT(9)); }).unwrap();
+    assert_eq!(db.query(vip_orders).unwrap().len(), 0);
+
+    let count = |db: &LocalDB| match db.query("SELECT COUNT(*) FROM orders;").unwrap()[0]["COUNT(*)"] {
+        LocalDBValue::INT(n) => n,
+        _ => panic!("COUNT(*) should be INT"),
+    };
+    assert_eq!(count(&db), 2);
+    db.exec("DELETE FROM orders WHERE user_id = 1;".to_string()).unwrap();
+    assert_eq!(count(&db), 1);
+}
+
+#[test]
+fn test_ndjson_roundtrip() {
+    let path = "test_ndjson_roundtrip.ndjson";
+    let _ = fs::remove_file(path);
+
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    db.exec(db.add_lines([
+        "INSERT INTO events (id, kind, score) VALUES (UUID 'e1', 'click', 1.5);",
+        "INSERT INTO events (id, kind, ok) VALUES (UUID 'e2', 'line\nbreak', TRUE);"
+    ])).unwrap();
+
+    assert_eq!(db.export_ndjson("events", path).unwrap(), 2);
+    let content = fs::read_to_string(path).unwrap();
+    assert_eq!(content.lines().count(), 2);
+    assert!(content.starts_with(r#"{"id":{"UUID":"e1"},"kind":{"TEXT":"click"},"score":{"REAL":1.5}}"#));
+
+    let mut copy = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    assert_eq!(copy.import_ndjson("events", path).unwrap(), 2);
+    assert_eq!(copy.dump_sql(), db.dump_sql());
+
+    fs::write(path, "{\"a\":{\"INT\":1}}\n\nnot json\n").unwrap();
+    assert!(matches!(copy.import_ndjson("events", path), Err(localdb::LocalDBError::IoError { .. })));
+    assert_eq!(copy.count("events").unwrap(), 2);
+    assert!(db.export_ndjson("missing", path).is_err());
+
+    let _ = fs::remove_file(path);
+}
+
+#[test]
+fn test_limits() {
+    use localdb::{LocalDBError, Limits};
+
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    db.set_limits(Limits { max_rows: Some(2), ..Limits::default() });
+    db.exec("INSERT INTO t (a) VALUES (1); INSERT INTO t (a) VALUES (2);".to_string()).unwrap();
+    assert!(matches!(db.exec("INSERT INTO t (a) VALUES (3);".to_string()), Err(LocalDBError::LimitExceeded(_))));
+    assert_eq!(db.count("t").unwrap(), 2);
+
+    let path = "test_limits.db";
+    let _ = fs::remove_file(path);
+    let mut db = LocalDB::create(path).unwrap();
+    db.exec("INSERT INTO t (a) VALUES ('small');".to_string()).unwrap();
+    let size = fs::metadata(path).unwrap().len();
+
+    db.set_limits(Limits { max_file_bytes: Some(size + 10), ..Limits::default() });
+    let big = format!("INSERT INTO t (a) VALUES ('{}');", "x".repeat(100));
+    assert!(matches!(db.exec(big.clone()), Err(LocalDBError::LimitExceeded(_))));
+    assert_eq!(db.count("t").unwrap(), 1);
+    assert_eq!(fs::metadata(path).unwrap().len(), size);
+
+    db.set_limits(Limits::default());
+    db.exec(big).unwrap();
+    assert_eq!(db.count("t").unwrap(), 2);
+
+    let _ = fs::remove_file(path);
+}
+
+#[test]
+fn test_returning() {
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    db.exec("CREATE TABLE users (id INT PRIMARY KEY, name TEXT, age INT);".to_string()).unwrap();
+
+    let rows = db.exec_returning("INSERT INTO users (id, name, age) VALUES (1, 'kk', 30) RETURNING id, name;".to_string()).unwrap();
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0].len(), 2);
+    assert_eq!(rows[0]["name"], LocalDBValue::from("kk"));
+
+    db.exec("INSERT INTO users (id, name, age) VALUES (2, 'jj', 40);".to_string()).unwrap();
+    let rows = db.exec_returning("UPDATE users SET age = age + 1 WHERE id = 2 RETURNING *;".to_string()).unwrap();
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0]["age"], LocalDBValue::INT(41));
+
+    let rows = db.exec_returning(db.add_lines([
+        "DELETE FROM users WHERE age > 100 RETURNING *;",
+        "DELETE FROM users WHERE id = 1 RETURNING age * 2 AS double;"
+    ])).unwrap();
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0]["double"], LocalDBValue::INT(60));
+    assert_eq!(db.count("users").unwrap(), 1);
+
+    assert!(db.exec_returning("INSERT INTO users (id) VALUES (3);".to_string()).unwrap().is_empty());
+    assert!(db.exec_returning("INSERT INTO missing SELECT * FROM users WHERE id > 100 RETURNING *;".to_string()).unwrap().is_empty());
+    assert!(db.exec("DELETE FROM users RETURNING COUNT(*);".to_string()).is_err());
+    assert_eq!(db.count("users").unwrap(), 2);
+}
+
+#[test]
+fn test_assert_schema() {
+    use localdb::{ColType, ColumnDef, LocalDBError, SchemaCheck, TableDef};
+
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    db.exec("CREATE TABLE users (id INT PRIMARY KEY, name TEXT, bio TEXT COMMENT 'about');".to_string()).unwrap();
+
+    let mut id = ColumnDef::new("id", ColType::Int);
+    id.primary_key = true;
+    let users = TableDef { name: "users".into(), columns: vec![id, ColumnDef::new("name", ColType::Text)], unique: Vec::new(), comment: None };
+    db.assert_schema(std::slice::from_ref(&users)).unwrap();
+
+    let err = db.assert_schema_with(std::slice::from_ref(&users), SchemaCheck::Exact).unwrap_err();
+    assert!(matches!(err, LocalDBError::SchemaMismatch(ref m) if m == "unexpected column users.bio"));
+
+    let mut drifted = users.clone();
+    drifted.columns[1].col_type = ColType::Int;
+    drifted.columns.push(ColumnDef::new("email", ColType::Text));
+    let orders = TableDef { name: "orders".into(), columns: Vec::new(), unique: Vec::new(), comment: None };
+    match db.assert_schema(&[drifted, orders]) {
+        Err(LocalDBError::SchemaMismatch(m)) => {
+            assert!(m.contains("column users.name is TEXT, expected INT"));
+            assert!(m.contains("missing column users.email"));
+            assert!(m.contains("missing table orders"));
+        }
+        other => panic!("expected a schema mismatch, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_coalesce() {
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    db.exec(db.add_lines([
+        "INSERT INTO people (name, nickname) VALUES ('Katherine', 'kk'); INSERT INTO people (name) VALUES ('Jo');",
+        "INSERT INTO people (name, nickname) VALUES ('Al', NULL);"
+    ])).unwrap();
+
+    let rows = db.query("SELECT COALESCE(nickname, name) AS shown FROM people;").unwrap();
+    let shown: Vec<_> = rows.iter().map(|r| match &r["shown"] { LocalDBValue::TEXT(s) => s.as_str(), _ => panic!() }).collect();
+    assert_eq!(shown, ["kk", "Jo", "Al"]);
+
+    assert_eq!(db.query("SELECT * FROM people WHERE COALESCE(nickname, name) = 'Jo';").unwrap().len(), 1);
+    assert_eq!(db.query("SELECT * FROM people WHERE COALESCE(nickname, 'none') != 'none';").unwrap().len(), 1);
+
+    let rows = db.query("SELECT COALESCE(MAX(age), 0) AS oldest FROM people;").unwrap();
+    assert_eq!(rows[0]["oldest"], LocalDBValue::INT(0));
+    assert!(db.query("SELECT * FROM people WHERE COALESCE(COUNT(*), 0) = 1;").is_err());
+}
+
+#[test]
+fn test_optimize() {
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    db.exec("CREATE TABLE nums (n INT PRIMARY KEY, tag TEXT); CREATE INDEX idx_tag ON nums (tag);".to_string()).unwrap();
+    db.insert_many("nums", (0..1000).map(|n| {
+        localdb::Row::from([("n".to_string(), LocalDBValue::INT(n)), ("tag".to_string(), LocalDBValue::from(if n % 2 == 0 { "even" } else { "odd" }))])
+    })).unwrap();
+    db.exec("DELETE FROM nums WHERE n >= 10;".to_string()).unwrap();
+
+    assert!(db.optimize("nums").unwrap() > 0);
+    assert_eq!(db.count("nums").unwrap(), 10);
+    assert_eq!(db.query("SELECT * FROM nums WHERE tag = 'odd';").unwrap().len(), 5);
+    assert_eq!(db.query("SELECT * FROM nums WHERE n = 3;").unwrap().len(), 1);
+    assert!(db.optimize("missing").is_err());
+}
+
+#[test]
+fn test_compare_columns() {
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    db.exec(db.add_lines([
+        "INSERT INTO items (sku, price, cost) VALUES ('a', 10, 4); INSERT INTO items (sku, price, cost) VALUES ('b', 3, 5);",
+        "INSERT INTO items (sku, price, cost) VALUES ('c', 5, 5.0); INSERT INTO items (sku, price) VALUES ('d', 1);"
+    ])).unwrap();
+
+    let skus = |db: &LocalDB, sql: &str| -> Vec<String> {
+        db.query(sql).unwrap().iter().map(|r| match &r["sku"] { LocalDBValue::TEXT(s) => s.clone(), _ => panic!() }).collect()
+    };
+    assert_eq!(skus(&db, "SELECT sku FROM items WHERE price > cost;"), ["a"]);
+    assert_eq!(skus(&db, "SELECT sku FROM items WHERE price < cost;"), ["b"]);
+    assert_eq!(skus(&db, "SELECT sku FROM items WHERE price = cost;"), ["c"]);
+    assert_eq!(skus(&db, "SELECT sku FROM items WHERE price <> cost;"), ["a", "b"]);
+    assert_eq!(skus(&db, "SELECT sku FROM items WHERE price = TRUE;").len(), 0);
+}
+
+#[test]
+fn test_insert_typed() {
+    #[derive(serde::Serialize)]
+    struct User<'a> {
+        id: &'a str,
+        name: &'a str,
+        score: i64,
+        nickname: Option<&'a str>,
+        active: bool,
+    }
+
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    db.exec("CREATE TABLE users (id UUID PRIMARY KEY, name TEXT, score REAL, nickname TEXT, active BOOL);".to_string()).unwrap();
+    let user = User { id: "11111111-1111-1111-1111-111111111111", name: "kk", score: 3, nickname: None, active: true };
+    db.insert_typed("users", &user).unwrap();
+
+    let rows = db.query("SELECT * FROM users;").unwrap();
+    assert!(matches!(rows[0]["id"], LocalDBValue::UUID(_)));
+    assert_eq!(rows[0]["score"], LocalDBValue::REAL(3.0));
+    assert_eq!(rows[0]["nickname"], LocalDBValue::NULL);
+    assert_eq!(rows[0]["active"], LocalDBValue::BOOL(true));
+
+    assert!(db.insert_typed("users", &user).is_err());
+    assert!(db.insert_typed("users", &5).is_err());
+    assert!(db.insert_typed("users", &std::collections::HashMap::from([("tags", vec![1, 2])])).is_err());
+    assert_eq!(db.count("users").unwrap(), 1);
+}
+
+#[test]
+fn test_partial_index() {
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    db.exec(db.add_lines([
+        "INSERT INTO orders (id, total) VALUES (1, 50); INSERT INTO orders (id, total) VALUES (2, 150);",
+        "INSERT INTO orders (id, total) VALUES (3, 500); CREATE INDEX idx_big ON orders (total) WHERE total > 100;"
+    ])).unwrap();
+    db.exec("INSERT INTO orders (id, total) VALUES (4, 900); INSERT INTO orders (id, total) VALUES (5, 80);".to_string()).unwrap();
+
+    let plan = db.explain("SELECT * FROM orders WHERE total >= 200;").unwrap();
+    assert!(plan.contains("access: index idx_big (total) WHERE total > 100"), "{}", plan);
+    assert_eq!(db.query("SELECT * FROM orders WHERE total >= 200;").unwrap().len(), 2);
+    assert!(db.explain("SELECT * FROM orders WHERE total BETWEEN 101 AND 1000;").unwrap().contains("idx_big"));
+
+    // not implied by the filter: these would miss unindexed rows
+    for sql in ["SELECT * FROM orders WHERE total > 60;", "SELECT * FROM orders WHERE total >= 100;"] {
+        assert!(db.explain(sql).unwrap().contains("full scan"), "{}", sql);
+    }
+    assert_eq!(db.query("SELECT * FROM orders WHERE total > 60;").unwrap().len(), 4);
+
+    db.exec("UPDATE orders SET total = 120 WHERE id = 1;".to_string()).unwrap();
+    assert_eq!(db.query("SELECT * FROM orders WHERE total = 120;").unwrap().len(), 1);
+    assert!(db.exec("CREATE INDEX idx_bad ON orders (total) WHERE id IN (SELECT id FROM orders);".to_string()).is_err());
+}
+
+#[test]
+fn test_nullif() {
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    db.exec("INSERT INTO people (name, nickname) VALUES ('Jo', ''); INSERT INTO people (name, nickname) VALUES ('Al', 'al');".to_string()).unwrap();
+
+    let rows = db.query("SELECT NULLIF(nickname, '') AS nick, COALESCE(NULLIF(nickname, ''), name) AS shown FROM people;").unwrap();
+    assert_eq!(rows[0]["nick"], LocalDBValue::NULL);
+    assert_eq!(rows[0]["shown"], LocalDBValue::from("Jo"));
+    assert_eq!(rows[1]["nick"], LocalDBValue::from("al"));
+
+    let rows = db.query("SELECT NULLIF(1, 1.0) AS a, NULLIF(1, '1') AS b FROM people LIMIT 1;").unwrap();
+    assert_eq!(rows[0]["a"], LocalDBValue::NULL);
+    assert_eq!(rows[0]["b"], LocalDBValue::INT(1));
+    assert!(db.query("SELECT NULLIF(name) FROM people;").is_err());
+}
+
+#[test]
+fn test_metrics() {
+    use localdb::{OpMetrics, Operation};
+    use std::sync::{Arc, Mutex};
+
+    let path = "test_metrics.db";
+    let _ = fs::remove_file(path);
+    let mut db = LocalDB::create(path).unwrap();
+    db.exec("INSERT INTO t (a) VALUES (0);".to_string()).unwrap();
+
+    let seen: Arc<Mutex<Vec<OpMetrics>>> = Arc::default();
+    let sink = Arc::clone(&seen);
+    db.set_metrics(move |m| sink.lock().unwrap().push(m));
+
+    db.exec("INSERT INTO t (a) VALUES (1); INSERT INTO t (a) VALUES (2); UPDATE t SET a = 5 WHERE a > 0;".to_string()).unwrap();
+    db.query("SELECT * FROM t WHERE a = 5;").unwrap();
+    assert!(db.exec("INSERT INTO t (a) VALUES (;".to_string()).is_err());
+
+    let ops: Vec<(Operation, usize)> = seen.lock().unwrap().iter().map(|m| (m.op, m.rows)).collect();
+    assert_eq!(ops, [
+        (Operation::Save, 2),
+        (Operation::Save, 3),
+        (Operation::Save, 3),
+        (Operation::Exec, 4),
+        (Operation::Query, 2),
+    ]);
+
+    let _ = fs::remove_file(path);
+}
+
+#[test]
+fn test_length() {
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    db.exec(db.add_lines([
+        "INSERT INTO users (name) VALUES ('Bartholomew Smith'); INSERT INTO users (name) VALUES ('Zoë');",
+        "INSERT INTO users (name) VALUES (NULL); INSERT INTO users (name) VALUES (7);"
+    ])).unwrap();
+
+    assert_eq!(db.query("SELECT * FROM users WHERE LENGTH(name) > 10;").unwrap().len(), 1);
+    let rows = db.query("SELECT LENGTH(name) AS len FROM users;").unwrap();
+    assert_eq!(rows[0]["len"], LocalDBValue::INT(17));
+    assert_eq!(rows[1]["len"], LocalDBValue::INT(3));
+    assert_eq!(rows[2]["len"], LocalDBValue::NULL);
+    assert_eq!(rows[3]["len"], LocalDBValue::NULL);
+    assert!(db.query("SELECT * FROM users WHERE LENGTH(name) = 3;").unwrap().len() == 1);
+}
+
+#[test]
+fn test_upper_lower() {
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    db.exec("INSERT INTO users (name) VALUES ('KK'); INSERT INTO users (name) VALUES ('Jo'); INSERT INTO users (name) VALUES (5);".to_string()).unwrap();
+
+    assert_eq!(db.query("SELECT * FROM users WHERE LOWER(name) = 'kk';").unwrap().len(), 1);
+    assert_eq!(db.query("SELECT * FROM users WHERE UPPER(name) = 'JO';").unwrap().len(), 1);
+    let rows = db.query("SELECT UPPER(name) AS up, LOWER(name) AS low FROM users;").unwrap();
+    assert_eq!(rows[1]["up"], LocalDBValue::from("JO"));
+    assert_eq!(rows[0]["low"], LocalDBValue::from("kk"));
+    assert_eq!(rows[2]["up"], LocalDBValue::NULL);
+}
+
+#[test]
+fn test_strict_tables() {
+    use localdb::LocalDBError;
+
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    db.exec("CREATE TABLE empty (id INT); INSERT INTO users (name) VALUES ('kk');".to_string()).unwrap();
+    assert!(db.query("SELECT * FROM usres;").unwrap().is_empty());
+
+    db.set_strict_tables(true);
+    assert!(matches!(db.query("SELECT * FROM usres;"), Err(LocalDBError::TableNotFound(ref t)) if t == "usres"));
+    assert!(matches!(db.query("SELECT COUNT(*) FROM usres;"), Err(LocalDBError::TableNotFound(_))));
+    assert!(db.query("SELECT * FROM users WHERE name IN (SELECT name FROM usres);").is_err());
+    assert!(db.select("usres").run().is_err());
+    assert_eq!(db.query("SELECT * FROM users;").unwrap().len(), 1);
+    assert!(db.query("SELECT * FROM empty;").unwrap().is_empty());
+
+    db.set_strict_tables(false);
+    assert!(db.query("SELECT * FROM usres;").unwrap().is_empty());
+}
+
+#[test]
+fn test_update_if() {
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    db.exec("CREATE TABLE docs (key TEXT PRIMARY KEY, body TEXT, rev INT);".to_string()).unwrap();
+    db.exec("INSERT INTO docs (key, body, rev) VALUES ('a', 'one', 1); INSERT INTO docs (key, body, rev) VALUES ('b', 'x', 1);".to_string()).unwrap();
+
+    let read = db.query("SELECT * FROM docs WHERE key = 'a';").unwrap().remove(0);
+    let mut edit = read.clone();
+    edit.insert("body".into(), LocalDBValue::from("two"));
+    edit.insert("rev".into(), LocalDBValue::INT(2));
+
+    assert!(db.update_if("docs", "a", &read, edit.clone()).unwrap());
+    // a second writer holding the stale copy loses
+    assert!(!db.update_if("docs", "a", &read, edit.clone()).unwrap());
+    assert!(!db.update_if("docs", "zzz", &read, edit.clone()).unwrap());
+    let rows = db.query("SELECT * FROM docs WHERE key = 'a';").unwrap();
+    assert_eq!(rows[0]["rev"], LocalDBValue::INT(2));
+
+    let b = db.query("SELECT * FROM docs WHERE key = 'b';").unwrap().remove(0);
+    let mut clash = b.clone();
+    clash.insert("key".into(), LocalDBValue::from("a"));
+    assert!(db.update_if("docs", "b", &b, clash).is_err());
+    assert_eq!(db.query("SELECT * FROM docs WHERE key = 'b';").unwrap().len(), 1);
+}
+
+#[test]
+fn test_json_values() {
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    db.exec(db.add_lines([
+        r#"CREATE TABLE users (name TEXT, meta JSON); INSERT INTO users (name, meta) VALUES ('kk', JSON '{"role": "admin", "tags": ["a", "b"], "age": 30}');"#,
+        r#"INSERT INTO users (name, meta) VALUES ('jo', JSON '{"role": "guest", "prefs": {"theme": "dark"}}'); INSERT INTO users (name) VALUES ('al');"#
+    ])).unwrap();
+
+    assert_eq!(db.query("SELECT * FROM users WHERE meta->>'role' = 'admin';").unwrap().len(), 1);
+    assert_eq!(db.query("SELECT * FROM users WHERE meta->>'age' >= 18;").unwrap().len(), 1);
+    assert_eq!(db.query("SELECT * FROM users WHERE meta->'prefs'->>'theme' = 'dark';").unwrap().len(), 1);
+
+    let rows = db.query("SELECT name, meta->'tags'->>1 AS second, meta->'tags' AS tags, meta->>'tags' AS raw FROM users;").unwrap();
+    assert_eq!(rows[0]["second"], LocalDBValue::from("b"));
+    assert!(matches!(rows[0]["tags"], LocalDBValue::JSON(ref v) if v.as_array().is_some_and(|a| a.len() == 2)));
+    assert_eq!(rows[0]["raw"], LocalDBValue::from(r#"["a","b"]"#));
+    assert_eq!(rows[1]["second"], LocalDBValue::NULL);
+    assert_eq!(rows[2]["tags"], LocalDBValue::NULL);
+
+    // stored inline and reloaded intact
+    let mut out = Vec::new();
+    db.write_to(&mut out).unwrap();
+    assert!(String::from_utf8_lossy(&out).contains(r#""JSON": {"#));
+    let copy = LocalDB::from_reader(out.as_slice()).unwrap();
+    assert_eq!(copy.dump_sql(), db.dump_sql());
+    let mut replay = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    replay.exec(db.dump_sql()).unwrap();
+    assert_eq!(replay.dump_sql(), db.dump_sql());
+
+    assert!(db.exec("INSERT INTO users (meta) VALUES (JSON '{oops');".to_string()).is_err());
+}
+
+#[test]
+fn test_put() {
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    db.exec("CREATE TABLE settings (key TEXT PRIMARY KEY, value TEXT);".to_string()).unwrap();
+
+    let value = |v: &str| localdb::Row::from([("value".to_string(), LocalDBValue::from(v))]);
+    db.put("settings", "theme", value("dark")).unwrap();
+    db.put("settings", "lang", value("en")).unwrap();
+    db.put("settings", "theme", value("light")).unwrap();
+
+    assert_eq!(db.count("settings").unwrap(), 2);
+    let rows = db.query("SELECT value FROM settings WHERE key = 'theme';").unwrap();
+    assert_eq!(rows[0]["value"], LocalDBValue::from("light"));
+
+    // tables without a PRIMARY KEY are keyed by `id`
+    db.put("notes", 1, value("a")).unwrap();
+    db.put("notes", 1, value("b")).unwrap();
+    let rows = db.query("SELECT * FROM notes;").unwrap();
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0]["id"], LocalDBValue::INT(1));
+}
+
+#[test]
+fn test_order_by() {
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    db.exec(db.add_lines([
+        "INSERT INTO items (sku, price, qty) VALUES ('a', 2, 10); INSERT INTO items (sku, price, qty) VALUES ('b', 5, 1);",
+        "INSERT INTO items (sku, price, qty) VALUES ('c', 1.5, 20); INSERT INTO items (sku, price) VALUES ('d', 3);"
+    ])).unwrap();
+
+    let skus = |sql: &str| -> Vec<String> {
+        db.query(sql).unwrap().iter().map(|r| match &r["sku"] { LocalDBValue::TEXT(s) => s.clone(), _ => panic!() }).collect()
+    };
+    assert_eq!(skus("SELECT * FROM items ORDER BY price;"), ["c", "a", "d", "b"]);
+    assert_eq!(skus("SELECT * FROM items ORDER BY price * qty DESC;"), ["d", "c", "a", "b"]);
+    assert_eq!(skus("SELECT sku, price * qty AS total FROM items WHERE qty > 0 ORDER BY total DESC LIMIT 2;"), ["c", "a"]);
+    assert_eq!(skus("SELECT sku FROM items ORDER BY qty, sku DESC;"), ["b", "a", "c", "d"]);
+
+    let rows = db.query("SELECT COUNT(*) FROM items ORDER BY COUNT(*) DESC;").unwrap();
+    assert_eq!(rows[0]["COUNT(*)"], LocalDBValue::INT(4));
+    assert!(db.query("SELECT * FROM items ORDER BY COUNT(*);").is_err());
+}
+
+#[test]
+fn test_group_by_having() {
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    db.exec(db.add_lines([
+        "INSERT INTO orders (user, total) VALUES ('kk', 10); INSERT INTO orders (user, total) VALUES ('jo', 5); INSERT INTO orders (user, total) VALUES ('kk', 30);",
+        "INSERT INTO orders (user, total) VALUES ('al', 7); INSERT INTO orders (user, total) VALUES ('jo', 1); INSERT INTO orders (user, total) VALUES ('kk', 2);"
+    ])).unwrap();
+
+    let rows = db.query("SELECT user, COUNT(*) AS n, SUM(total) AS spent FROM orders GROUP BY user;").unwrap();
+    assert_eq!(rows.len(), 3);
+    assert_eq!(rows[0]["user"], LocalDBValue::from("kk"));
+    assert_eq!(rows[0]["n"], LocalDBValue::INT(3));
+    assert_eq!(rows[1]["spent"], LocalDBValue::INT(6));
+
+    let users = |sql: &str| -> Vec<String> {
+        db.query(sql).unwrap().iter().map(|r| match &r["user"] { LocalDBValue::TEXT(s) => s.clone(), _ => panic!() }).collect()
+    };
+    assert_eq!(users("SELECT user FROM orders GROUP BY user HAVING COUNT(*) > 1;"), ["kk", "jo"]);
+    assert_eq!(users("SELECT user, SUM(total) AS spent FROM orders GROUP BY user ORDER BY spent;"), ["jo", "al", "kk"]);
+    assert_eq!(users("SELECT user FROM orders GROUP BY user ORDER BY COUNT(*) DESC, user LIMIT 2;"), ["kk", "jo"]);
+    assert_eq!(users("SELECT user FROM orders WHERE total > 4 GROUP BY user HAVING MAX(total) < 20;"), ["jo", "al"]);
+    assert_eq!(users("SELECT user FROM orders GROUP BY user HAVING COUNT(*) > 1 AND SUM(total) > 10;"), ["kk"]);
+    assert_eq!(users("SELECT user FROM orders GROUP BY user HAVING COUNT(*) = 1 OR SUM(total) < 10;"), ["jo", "al"]);
+    assert_eq!(users("SELECT user FROM orders GROUP BY user HAVING NOT (COUNT(*) > 1);"), ["al"]);
+    assert_eq!(users("SELECT user FROM orders GROUP BY user HAVING MAX(total) > 2 * MIN(total);"), ["kk", "jo"]);
+    assert_eq!(users("SELECT user FROM orders GROUP BY user HAVING (SUM(total) - 1) < 7;"), ["jo", "al"]);
+    assert_eq!(users("SELECT user FROM orders GROUP BY user HAVING (COUNT(*) > 1 OR MAX(total) = 7) AND MIN(total) > 1;"), ["kk", "al"]);
+    assert_eq!(users("SELECT user FROM orders GROUP BY user HAVING SUM(total) > -1 ORDER BY user;"), ["al", "jo", "kk"]);
+    assert!(db.query("SELECT user FROM orders GROUP BY user HAVING COUNT(*);").is_err());
+
+    let rows = db.query("SELECT COUNT(*) FROM orders GROUP BY user;").unwrap();
+    assert_eq!(rows.len(), 3);
+    assert!(db.query("SELECT COUNT(*) FROM orders HAVING COUNT(*) > 10;").unwrap().is_empty());
+    assert!(db.query("SELECT user, total FROM orders GROUP BY user;").is_err());
+    assert!(db.query("SELECT * FROM orders GROUP BY user;").is_err());
+}
+
+#[test]
+fn test_left_join() {
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    db.exec(db.add_lines([
+        "INSERT INTO users (id, name) VALUES (1, 'kk'); INSERT INTO users (id, name) VALUES (2, 'jo'); INSERT INTO users (id, name) VALUES (3, 'al');",
+        "INSERT INTO orders (user_id, total) VALUES (1, 10); INSERT INTO orders (user_id, total) VALUES (1, 30); INSERT INTO orders (user_id, total) VALUES (2, 5);"
+    ])).unwrap();
+
+    let rows = db.query("SELECT u.name, o.total FROM users u JOIN orders o ON o.user_id = u.id;").unwrap();
+    assert_eq!(rows.len(), 3);
+    assert_eq!(rows[2]["u.name"], LocalDBValue::from("jo"));
+
+    let rows = db.query("SELECT name, total FROM users LEFT OUTER JOIN orders ON user_id = id WHERE name = 'al';").unwrap();
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0]["total"], LocalDBValue::NULL);
+
+    let rows = db.query("SELECT u.name, COUNT(o.total) AS n FROM users AS u LEFT JOIN orders AS o ON o.user_id = u.id GROUP BY u.name ORDER BY n DESC, u.name;").unwrap();
+    assert_eq!(rows.len(), 3);
+    assert_eq!(rows[2]["u.name"], LocalDBValue::from("al"));
+    assert_eq!(rows[2]["n"], LocalDBValue::INT(0));
+
+    let result = db.query_result("SELECT * FROM users u LEFT JOIN orders o ON o.user_id = u.id;").unwrap();
+    assert_eq!(result.rows.len(), 4);
+    assert!(result.columns.contains(&"o.total".to_string()) && !result.rows[3].contains_key("total"));
+    let rows = db.query("SELECT COUNT(*) FROM users LEFT JOIN orders ON user_id = id;").unwrap();
+    assert_eq!(rows[0]["COUNT(*)"], LocalDBValue::INT(4));
+    assert!(db.query("SELECT * FROM users LEFT orders ON user_id = id;").is_err());
+    assert!(db.query("SELECT * FROM users JOIN users ON id = id;").is_err());
+}
+
+#[test]
+fn test_semicolon_in_string() {
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    db.exec("INSERT INTO notes (id, body) VALUES (1, 'a;b'); INSERT INTO \"odd;table\" (x) VALUES ('it''s; fine');".to_string()).unwrap();
+
+    let rows = db.query("SELECT * FROM notes WHERE body = 'a;b';").unwrap();
+    assert_eq!(rows.len(), 1);
+    let rows = db.query("SELECT * FROM \"odd;table\";").unwrap();
+    assert_eq!(rows[0]["x"], LocalDBValue::from("it's; fine"));
+}
+
+#[test]
+fn test_format_version() {
+    let path = "test_format_version.db";
+    let _ = fs::remove_file(path);
+
+    LocalDB::create(path).unwrap();
+    assert!(fs::read_to_string(path).unwrap().contains("\"__meta__\""));
+    assert!(LocalDB::open(path).unwrap().query("SELECT * FROM __meta__;").unwrap().is_empty());
+
+    // files from before the header are format 1
+    assert!(LocalDB::from_reader(r#"{"t":[{"a":{"INT":1}}]}"#.as_bytes()).is_ok());
+
+    fs::write(path, r#"{"__meta__":{"format":99,"localdb":"9.0.0"},"t":[]}"#).unwrap();
+    assert!(matches!(
+        LocalDB::open(path),
+        Err(localdb::LocalDBError::UnsupportedFormat { found: 99, supported: 1 })
+    ));
+
+    let _ = fs::remove_file(path);
+}
+
+#[test]
+fn test_move_to() {
+    let (from, to) = ("test_move_to_a.db", "test_move_to_b.db");
+    let _ = fs::remove_file(from);
+    let _ = fs::remove_file(to);
+
+    let mut db = LocalDB::create(from).unwrap();
+    db.exec("INSERT INTO t (a) VALUES (1);".to_string()).unwrap();
+    db.move_to(to).unwrap();
+    assert!(!std::path::Path::new(from).exists());
+
+    db.exec("INSERT INTO t (a) VALUES (2);".to_string()).unwrap();
+    assert_eq!(LocalDB::open(to).unwrap().count("t").unwrap(), 2);
+    assert!(db.move_to("no_such_dir/test_move_to.db").is_err());
+    assert_eq!(db.query("SELECT * FROM t;").unwrap().len(), 2);
+    db.exec("INSERT INTO t (a) VALUES (3);".to_string()).unwrap();
+    assert_eq!(LocalDB::open(to).unwrap().count("t").unwrap(), 3);
+
+    let _ = fs::remove_file(to);
+}
+
+#[test]
+fn test_modulo_and_int_div() {
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    db.exec("INSERT INTO n (a, b, x) VALUES (7, 2, 7.5); INSERT INTO n (a, b, x) VALUES (-7, 0, -7.5);".to_string()).unwrap();
+
+    let rows = db.query("SELECT a / b AS q, a DIV b AS d, a % b AS m, x / 2 AS r, x DIV 2 AS rd, x % 2 AS rm FROM n;").unwrap();
+    assert_eq!(rows[0]["q"], LocalDBValue::INT(3));
+    assert_eq!(rows[0]["d"], LocalDBValue::INT(3));
+    assert_eq!(rows[0]["m"], LocalDBValue::INT(1));
+    assert_eq!(rows[0]["r"], LocalDBValue::REAL(3.75));
+    assert_eq!(rows[0]["rd"], LocalDBValue::INT(3));
+    assert_eq!(rows[0]["rm"], LocalDBValue::REAL(1.5));
+    assert_eq!(rows[1]["m"], LocalDBValue::NULL);
+    assert_eq!(rows[1]["d"], LocalDBValue::NULL);
+    assert_eq!(rows[1]["rd"], LocalDBValue::INT(-3));
+    assert_eq!(rows[1]["rm"], LocalDBValue::REAL(-1.5));
+
+    let rows = db.query("SELECT 1 + a % 4 * 2 FROM n WHERE a > 0;").unwrap();
+    assert_eq!(rows[0]["1 + a % 4 * 2"], LocalDBValue::INT(7));
+}
+
+#[test]
+fn test_case_when() {
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    db.exec(db.add_lines([
+        "INSERT INTO people (name, age, vip) VALUES ('kk', 30, TRUE); INSERT INTO people (name, age, vip) VALUES ('jo', 12, FALSE);",
+        "INSERT INTO people (name, age) VALUES ('al', 70);"
+    ])).unwrap();
+
+    let rows = db.query("SELECT name, CASE WHEN age >= 65 THEN 'senior' WHEN age >= 18 THEN 'adult' ELSE 'minor' END AS category FROM people;").unwrap();
+    let categories: Vec<&LocalDBValue> = rows.iter().map(|r| &r["category"]).collect();
+    assert_eq!(categories, [&LocalDBValue::from("adult"), &LocalDBValue::from("minor"), &LocalDBValue::from("senior")]);
+
+    let rows = db.query("SELECT CASE WHEN vip THEN age * 2 END AS points FROM people;").unwrap();
+    assert_eq!(rows[0]["points"], LocalDBValue::INT(60));
+    assert_eq!(rows[1]["points"], LocalDBValue::NULL);
+
+    let rows = db.query("SELECT CASE WHEN age < 18 THEN 'minor' ELSE 'adult' END AS category, COUNT(*) AS n FROM people GROUP BY CASE WHEN age < 18 THEN 'minor' ELSE 'adult' END;").unwrap();
+    assert_eq!(rows.len(), 2);
+    assert_eq!(rows[0]["n"], LocalDBValue::INT(2));
+    assert!(db.query("SELECT CASE ELSE 1 END FROM people;").is_err());
+    assert!(db.query("SELECT CASE WHEN age > 1 THEN 1 FROM people;").is_err());
+}
+
+#[test]
+fn test_find() {
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    db.exec("INSERT INTO users (name) VALUES ('kk'); INSERT INTO users (name) VALUES ('Jo-99'); INSERT INTO users (age) VALUES (3);".to_string()).unwrap();
+
+    let digits = db.find("users", |row| matches!(row.get("name"), Some(LocalDBValue::TEXT(s)) if s.chars().any(|c| c.is_ascii_digit()))).unwrap();
+    assert_eq!(digits.len(), 1);
+    assert_eq!(digits[0]["name"], LocalDBValue::from("Jo-99"));
+    assert_eq!(db.find("users", |_| true).unwrap().len(), 3);
+    assert!(db.find("nope", |_| true).unwrap().is_empty());
+
+    db.set_strict_tables(true);
+    assert!(matches!(db.find("nope", |_| true), Err(localdb::LocalDBError::TableNotFound(_))));
+}
+
+#[test]
+fn test_order_by_ties_keep_insertion_order() {
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    for (name, rank) in [("e", 2), ("a", 1), ("d", 2), ("b", 1), ("c", 2), ("f", 1)] {
+        db.exec(format!("INSERT INTO t (name, rank) VALUES ('{}', {});", name, rank)).unwrap();
+    }
+
+    let names = |db: &LocalDB, sql: &str| -> String {
+        db.query(sql).unwrap().iter().map(|r| match &r["name"] { LocalDBValue::TEXT(s) => s.clone(), _ => panic!() }).collect()
+    };
+    assert_eq!(names(&db, "SELECT * FROM t ORDER BY rank;"), "abfedc");
+    assert_eq!(names(&db, "SELECT * FROM t ORDER BY rank DESC;"), "edcabf");
+
+    db.exec("CREATE INDEX t_rank ON t (rank);".to_string()).unwrap();
+    for _ in 0..5 {
+        assert_eq!(names(&db, "SELECT * FROM t WHERE rank >= 1 ORDER BY rank DESC;"), "edcabf");
+    }
+}
+
+#[test]
+fn test_transaction_savepoints() {
+    let path = "test_transaction_savepoints.db";
+    let _ = fs::remove_file(path);
+
+    let mut db = LocalDB::create(path).unwrap();
+    db.exec("INSERT INTO t (a) VALUES (1);".to_string()).unwrap();
+
+    let mut tx = db.transaction().unwrap();
+    tx.exec("INSERT INTO t (a) VALUES (2);".to_string()).unwrap();
+    tx.savepoint("sp1");
+    tx.exec("INSERT INTO t (a) VALUES (3); DELETE FROM t WHERE a = 1;".to_string()).unwrap();
+    tx.savepoint("sp2");
+    tx.exec("INSERT INTO t (a) VALUES (4);".to_string()).unwrap();
+    assert_eq!(tx.query("SELECT * FROM t;").unwrap().len(), 3);
+
+    tx.rollback_to("sp1").unwrap();
+    assert_eq!(tx.query("SELECT * FROM t;").unwrap().len(), 2);
+    assert!(tx.rollback_to("sp2").is_err());
+    tx.exec("INSERT INTO t (a) VALUES (5);".to_string()).unwrap();
+    tx.rollback_to("sp1").unwrap();
+    assert_eq!(LocalDB::open(path).unwrap().count("t").unwrap(), 1);
+    tx.commit().unwrap();
+
+    assert_eq!(LocalDB::open(path).unwrap().count("t").unwrap(), 2);
+
+    let mut tx = db.transaction().unwrap();
+    tx.exec("DELETE FROM t;".to_string()).unwrap();
+    drop(tx);
+    assert_eq!(db.count("t").unwrap(), 2);
+
+    let _ = fs::remove_file(path);
+}
+
+#[test]
+fn test_observed_columns() {
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    db.exec("INSERT INTO t (b, a) VALUES (1, 2); INSERT INTO t (c) VALUES (3); INSERT INTO t (a) VALUES (4);".to_string()).unwrap();
+
+    assert_eq!(db.columns("t").unwrap(), ["a", "b", "c"]);
+    assert!(db.columns("nope").is_err());
+}
+
+#[test]
+fn test_default_now() {
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    db.exec("CREATE TABLE events (name TEXT, created_at TIMESTAMP DEFAULT now(), level INT DEFAULT 1);".to_string()).unwrap();
+
+    let before = match LocalDBValue::now() { LocalDBValue::TIMESTAMP(ms) => ms, _ => unreachable!() };
+    db.exec("INSERT INTO events (name) VALUES ('boot'); INSERT INTO events (name, created_at, level) VALUES ('old', TIMESTAMP '1000', NULL);".to_string()).unwrap();
+
+    let rows = db.query("SELECT * FROM events;").unwrap();
+    assert!(matches!(rows[0]["created_at"], LocalDBValue::TIMESTAMP(ms) if ms >= before));
+    assert_eq!(rows[0]["level"], LocalDBValue::INT(1));
+    assert_eq!(rows[1]["created_at"], LocalDBValue::TIMESTAMP(1000));
+    assert_eq!(rows[1]["level"], LocalDBValue::NULL);
+
+    assert_eq!(db.query("SELECT * FROM events WHERE created_at < 2000;").unwrap().len(), 1);
+    assert_eq!(db.query("SELECT * FROM events WHERE created_at > TIMESTAMP '2000';").unwrap().len(), 1);
+
+    let dump = db.dump_sql();
+    assert!(dump.contains("created_at TIMESTAMP DEFAULT now()") && dump.contains("TIMESTAMP '1000'"));
+    let mut copy = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    copy.exec(dump).unwrap();
+    assert_eq!(copy.query("SELECT * FROM events WHERE created_at = TIMESTAMP '1000';").unwrap().len(), 1);
+}
+
+#[test]
+fn test_delete_by_ids() {
+    let path = "test_delete_by_ids.db";
+    let _ = fs::remove_file(path);
+
+    let mut db = LocalDB::create(path).unwrap();
+    db.exec("INSERT INTO t (id, v) VALUES ('a', 1); INSERT INTO t (id, v) VALUES ('b', 2); INSERT INTO t (id, v) VALUES ('c', 3);".to_string()).unwrap();
+
+    assert_eq!(db.delete_by_ids("t", &["a", "c", "zz", "a"]).unwrap(), 2);
+    let rows = LocalDB::open(path).unwrap().query("SELECT * FROM t;").unwrap();
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0]["id"], LocalDBValue::from("b"));
+    assert_eq!(db.delete_by_ids::<&str>("t", &[]).unwrap(), 0);
+
+    db.exec("CREATE TABLE n (k INT PRIMARY KEY); INSERT INTO n (k) VALUES (1); INSERT INTO n (k) VALUES (2);".to_string()).unwrap();
+    assert_eq!(db.delete_by_ids("n", &[2_i64]).unwrap(), 1);
+    assert!(db.delete_by_ids("nope", &["a"]).is_err());
+
+    // a failed save leaves the rows in place
+    db.set_limits(localdb::Limits { max_file_bytes: Some(10), ..localdb::Limits::default() });
+    assert!(matches!(db.delete_by_ids("n", &[1_i64]), Err(localdb::LocalDBError::LimitExceeded(_))));
+    assert_eq!(db.count("n").unwrap(), 1);
+    assert_eq!(db.query("SELECT * FROM n WHERE k = 1;").unwrap().len(), 1);
+
+    let _ = fs::remove_file(path);
+}
+
+#[test]
+fn test_find_by() {
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    db.exec("INSERT INTO users (name, age) VALUES ('kk', 30); INSERT INTO users (name, age) VALUES ('it''s', 30); INSERT INTO users (name, age) VALUES ('kk', 4);".to_string()).unwrap();
+
+    assert_eq!(db.find_by("users", "name", "kk").unwrap().len(), 2);
+    assert_eq!(db.find_by("users", "name", "it's").unwrap().len(), 1);
+    assert!(db.find_by("users", "age", "30").unwrap().is_empty());
+
+    db.exec("CREATE INDEX users_age ON users (age);".to_string()).unwrap();
+    let rows = db.find_by("users", "age", 30).unwrap();
+    assert_eq!(rows.len(), 2);
+    assert_eq!(rows[1]["name"], LocalDBValue::from("it's"));
+    assert!(db.find_by("nope", "a", LocalDBValue::NULL).unwrap().is_empty());
+}
+
+#[test]
+fn test_explain_analyze() {
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    for age in [10, 20, 30, 40, 50] {
+        db.exec(format!("INSERT INTO people (age) VALUES ({});", age)).unwrap();
+    }
+
+    let report = db.explain_analyze("SELECT * FROM people WHERE age > 30;").unwrap();
+    assert!(report.starts_with(&db.explain("SELECT * FROM people WHERE age > 30;").unwrap()));
+    assert!(report.contains("rows scanned: 5\nrows returned: 2\nindexes used: none\nelapsed: "));
+
+    db.exec("CREATE INDEX people_age ON people (age);".to_string()).unwrap();
+    let report = db.explain_analyze("SELECT * FROM people WHERE age > 30;").unwrap();
+    assert!(report.contains("rows scanned: 2\nrows returned: 2\nindexes used: people_age\n"));
+
+    let report = db.explain_analyze("SELECT EXISTS(SELECT * FROM people WHERE age != 10);").unwrap();
+    assert!(report.contains("rows scanned: 2\nrows returned: 1\n"));
+    assert!(db.explain_analyze("DELETE FROM people;").is_err());
+    assert_eq!(db.count("people").unwrap(), 5);
+}
+
+#[test]
+fn test_reserved_table_names() {
+    let path = "test_reserved_table_names.db";
+    let _ = fs::remove_file(path);
+
+    let mut db = LocalDB::create(path).unwrap();
+    db.exec("CREATE TABLE users (id INT PRIMARY KEY);".to_string()).unwrap();
+    assert!(db.exec("CREATE TABLE __schema__ (id INT);".to_string()).is_err());
+    assert!(db.exec("INSERT INTO __meta__ (format) VALUES (99);".to_string()).is_err());
+    assert!(db.insert_many("__schema__", [localdb::Row::new()]).is_err());
+
+    let reopened = LocalDB::open(path).unwrap();
+    assert!(reopened.schema("users").is_some());
+    assert!(reopened.query("SELECT * FROM __schema__;").unwrap().is_empty());
+    assert!(reopened.query("SELECT * FROM __meta__;").unwrap().is_empty());
+    assert_eq!(reopened.iter_tables().map(|(name, _)| name).collect::<Vec<_>>(), ["users"]);
+
+    let _ = fs::remove_file(path);
+}
+
+#[cfg(feature = "rusqlite")]
+#[test]
+fn test_export_sqlite() {
+    let path = "test_export_sqlite.sqlite";
+    let _ = fs::remove_file(path);
+
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    db.exec(db.add_lines([
+        "CREATE TABLE users (id INT PRIMARY KEY, name TEXT NOT NULL, vip BOOL);",
+        "INSERT INTO users (id, name, vip) VALUES (1, 'kk', TRUE); INSERT INTO users (id, name) VALUES (2, 'jo');"
+    ])).unwrap();
+    db.exec("INSERT INTO notes (body, data) VALUES ('hi', JSON '{\"a\": 1}');".to_string()).unwrap();
+
+    assert_eq!(db.export_sqlite(path).unwrap(), 3);
+
+    let conn = rusqlite::Connection::open(path).unwrap();
+    let vip: Option<i64> = conn.query_row("SELECT vip FROM users WHERE name = 'kk'", [], |r| r.get(0)).unwrap();
+    assert_eq!(vip, Some(1));
+    let missing: Option<i64> = conn.query_row("SELECT vip FROM users WHERE id = 2", [], |r| r.get(0)).unwrap();
+    assert_eq!(missing, None);
+    let data: String = conn.query_row("SELECT data FROM notes", [], |r| r.get(0)).unwrap();
+    assert_eq!(data, r#"{"a":1}"#);
+    assert!(conn.execute("INSERT INTO users (id, name) VALUES (1, 'dup')", []).is_err());
+    drop(conn);
+
+    assert!(db.export_sqlite(path).is_err());
+    let _ = fs::remove_file(path);
+}
+
+#[test]
+fn test_composite_unique() {
+    let path = "test_composite_unique.db";
+    let _ = fs::remove_file(path);
+
+    let mut db = LocalDB::create(path).unwrap();
+    db.exec(db.add_lines([
+        "CREATE TABLE members (user_id INT, group_id INT, email TEXT UNIQUE, UNIQUE (user_id, group_id));",
+        "INSERT INTO members (user_id, group_id) VALUES (1, 1); INSERT INTO members (user_id, group_id) VALUES (1, 2);"
+    ])).unwrap();
+
+    let err = db.exec("INSERT INTO members (user_id, group_id) VALUES (1, 2);".to_string()).unwrap_err();
+    assert!(err.to_string().contains("UNIQUE constraint failed: members.user_id, members.group_id"));
+    db.exec("INSERT INTO members (user_id, group_id) VALUES (1, NULL); INSERT INTO members (user_id, group_id) VALUES (1, NULL);".to_string()).unwrap();
+    assert!(db.exec("UPDATE members SET group_id = 1 WHERE group_id = 2;".to_string()).is_err());
+
+    db.exec("UPDATE members SET email = 'a@x' WHERE group_id = 1;".to_string()).unwrap();
+    assert!(db.exec("UPDATE members SET email = 'a@x' WHERE group_id = 2;".to_string()).is_err());
+    assert!(db.exec("CREATE TABLE bad (a INT, UNIQUE (a, b));".to_string()).is_err());
+
+    assert!(db.dump_sql().contains("UNIQUE (user_id, group_id)"));
+    let mut reopened = LocalDB::open(path).unwrap();
+    assert_eq!(reopened.schema("members").unwrap().unique.len(), 2);
+    assert!(reopened.exec("INSERT INTO members (user_id, group_id) VALUES (1, 1);".to_string()).is_err());
+    assert_eq!(reopened.query("SELECT * FROM members;").unwrap().len(), 4);
+
+    let _ = fs::remove_file(path);
+}
+
+#[test]
+fn test_clear() {
+    let path = "test_clear.db";
+    let _ = fs::remove_file(path);
+
+    let mut db = LocalDB::create(path).unwrap();
+    db.exec(db.add_lines([
+        "CREATE TABLE users (id INT PRIMARY KEY, name TEXT); CREATE TABLE empty (id INT);",
+        "INSERT INTO users (id, name) VALUES (1, 'kk'); CREATE INDEX users_name ON users (name);"
+    ])).unwrap();
+
+    let deleted = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let seen = deleted.clone();
+    db.on_change(move |event| seen.lock().unwrap().push((event.table, event.rows.len())));
+
+    db.clear().unwrap();
+    assert_eq!(*deleted.lock().unwrap(), [("users".to_string(), 1)]);
+    assert_eq!(db.iter_tables().count(), 0);
+    assert!(db.schema("users").is_none());
+
+    db.exec("CREATE TABLE users (id INT PRIMARY KEY); INSERT INTO users (id) VALUES (1);".to_string()).unwrap();
+    db.clear().unwrap();
+    let reopened = LocalDB::open(path).unwrap();
+    assert_eq!(reopened.iter_tables().count(), 0);
+    assert!(fs::read_to_string(path).unwrap().contains("__meta__"));
+
+    let _ = fs::remove_file(path);
+}
+
+#[test]
+fn test_order_by_limit_uses_index() {
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    db.exec("CREATE TABLE events (id INT, ts INT, kind TEXT);".to_string()).unwrap();
+    let rows = (0..100).map(|i| {
+        let mut row = localdb::Row::new();
+        row.insert("id".to_string(), LocalDBValue::INT(i));
+        row.insert("ts".to_string(), LocalDBValue::INT((i * 37) % 50));
+        row.insert("kind".to_string(), LocalDBValue::TEXT(if i % 2 == 0 { "a" } else { "b" }.to_string()));
+        row
+    });
+    db.insert_many("events", rows).unwrap();
+
+    let queries = [
+        "SELECT id FROM events ORDER BY ts DESC LIMIT 5;",
+        "SELECT * FROM events ORDER BY ts LIMIT 3 OFFSET 2;",
+        "SELECT id, ts FROM events WHERE kind = 'b' ORDER BY ts DESC LIMIT 4 OFFSET 1;",
+    ];
+    let run = |db: &LocalDB, sql: &str| -> Vec<String> {
+        db.query(sql).unwrap().iter().map(|row| format!("{:?} {:?}", row.get("id"), row.get("ts"))).collect()
+    };
+    let sorted: Vec<_> = queries.iter().map(|sql| run(&db, sql)).collect();
+
+    db.exec("CREATE INDEX events_ts ON events (ts);".to_string()).unwrap();
+    for (sql, expected) in queries.iter().zip(&sorted) {
+        assert_eq!(&run(&db, sql), expected, "{}", sql);
+    }
+    assert_eq!(db.query("SELECT id FROM events ORDER BY ts LIMIT 2 OFFSET 98;").unwrap().len(), 2);
+
+    let report = db.explain_analyze(queries[0]).unwrap();
+    assert!(report.contains("access: index events_ts (ts) in ORDER BY order"), "{}", report);
+    assert!(report.contains("rows scanned: 5\n"), "{}", report);
+    assert!(report.contains("indexes used: events_ts"), "{}", report);
+    let report = db.explain_analyze("SELECT id FROM events ORDER BY kind LIMIT 5;").unwrap();
+    assert!(report.contains("rows scanned: 100\n"), "{}", report);
+}
+
+#[test]
+fn test_any_all_subqueries() {
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    db.exec(db.add_lines([
+        "INSERT INTO products (sku, price) VALUES ('a', 5); INSERT INTO products (sku, price) VALUES ('b', 12); INSERT INTO products (sku, price) VALUES ('c', 30);",
+        "INSERT INTO discounted (price) VALUES (10); INSERT INTO discounted (price) VALUES (12);"
+    ])).unwrap();
+
+    let skus = |db: &LocalDB, sql: &str| -> Vec<LocalDBValue> {
+        db.query(sql).unwrap().iter().map(|row| row["sku"].clone()).collect()
+    };
+    assert_eq!(skus(&db, "SELECT sku FROM products WHERE price > ALL (SELECT price FROM discounted);"), [LocalDBValue::from("c")]);
+    assert_eq!(skus(&db, "SELECT sku FROM products WHERE price = ANY (SELECT price FROM discounted);"), [LocalDBValue::from("b")]);
+    assert_eq!(skus(&db, "SELECT sku FROM products WHERE price <= SOME (SELECT price FROM discounted);").len(), 2);
+    assert_eq!(skus(&db, "SELECT sku FROM products WHERE price <= ANY (5, 6);"), [LocalDBValue::from("a")]);
+    assert_eq!(skus(&db, "SELECT sku FROM products WHERE price > ALL (SELECT price FROM discounted WHERE price > 100);").len(), 3);
+    assert!(skus(&db, "SELECT sku FROM products WHERE price > ANY (SELECT price FROM discounted WHERE price > 100);").is_empty());
+
+    db.exec("INSERT INTO discounted (price) VALUES (NULL);".to_string()).unwrap();
+    assert!(skus(&db, "SELECT sku FROM products WHERE price > ALL (SELECT price FROM discounted);").is_empty());
+    assert!(db.query("SELECT sku FROM products WHERE price > ALL (SELECT sku, price FROM discounted);").is_err());
+    assert!(db.explain("SELECT * FROM products WHERE price > ALL (1, 2);").unwrap().contains("filter: price > ALL (1, 2)"));
+}
+
+#[test]
+fn test_error_kinds() {
+    use localdb::LocalDBError;
+
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    db.exec("CREATE TABLE users (id INT PRIMARY KEY, name TEXT NOT NULL);".to_string()).unwrap();
+    db.exec("INSERT INTO users (id, name) VALUES (1, 'kk');".to_string()).unwrap();
+
+    assert!(matches!(db.query("SELECT * FROM users WHERE;"), Err(LocalDBError::Parse(_))));
+    assert!(matches!(db.exec("INSERT INTO users (id, name VALUES (2, 'x');".to_string()), Err(LocalDBError::Parse(_))));
+    assert!(matches!(db.exec("DROP TABLE users;".to_string()), Err(LocalDBError::Parse(_))));
+    assert!(matches!(db.count("nope"), Err(LocalDBError::TableNotFound(ref t)) if t == "nope"));
+    assert!(matches!(
+        db.exec("COMMENT ON COLUMN users.bio IS 'x';".to_string()),
+        Err(LocalDBError::ColumnNotFound(ref c)) if c == "users.bio"
+    ));
+    assert!(matches!(
+        db.exec("INSERT INTO users (id, name) VALUES (1, 'again');".to_string()),
+        Err(LocalDBError::ConstraintViolation(_))
+    ));
+    assert!(matches!(db.insert_typed("users", &vec![1, 2]), Err(LocalDBError::TypeMismatch(_))));
+    assert!(matches!(db.query("SELECT name FROM users GROUP BY id;"), Err(LocalDBError::SqlError(_))));
+}
+
+#[test]
+fn test_array_values() {
+    let path = "test_array_values.db";
+    let _ = fs::remove_file(path);
+
+    let mut db = LocalDB::create(path).unwrap();
+    db.exec(db.add_lines([
+        "CREATE TABLE posts (id TEXT PRIMARY KEY, tags ARRAY);",
+        "INSERT INTO posts (id, tags) VALUES ('p1', ['a', 'b', 'c']); INSERT INTO posts (id, tags) VALUES ('p2', ['b']);"
+    ])).unwrap();
+    db.exec("INSERT INTO posts (id, tags) VALUES ('p3', []);".to_string()).unwrap();
+
+    let ids = |db: &LocalDB, sql: &str| -> Vec<LocalDBValue> {
+        db.query(sql).unwrap().iter().map(|row| row["id"].clone()).collect()
+    };
+    assert_eq!(ids(&db, "SELECT id FROM posts WHERE tags CONTAINS 'a';"), [LocalDBValue::from("p1")]);
+    assert_eq!(ids(&db, "SELECT id FROM posts WHERE tags CONTAINS 'b';").len(), 2);
+    assert_eq!(ids(&db, "SELECT id FROM posts WHERE tags = ['b'];"), [LocalDBValue::from("p2")]);
+    assert!(ids(&db, "SELECT id FROM posts WHERE id CONTAINS 'p';").is_empty());
+
+    for bad in ["['a', 1]", "['a', NULL]", "[['a']]"] {
+        let sql = format!("INSERT INTO posts (id, tags) VALUES ('bad', {});", bad);
+        assert!(matches!(db.exec(sql), Err(localdb::LocalDBError::TypeMismatch(_))), "{}", bad);
+    }
+    let mut row = localdb::Row::new();
+    row.insert("tags".to_string(), LocalDBValue::ARRAY(vec![LocalDBValue::INT(1), LocalDBValue::REAL(2.0)]));
+    assert!(db.insert_many("posts", [row]).is_err());
+
+    #[derive(serde::Serialize)]
+    struct Post {
+        id: String,
+        tags: Vec<String>,
+    }
+    db.insert_typed("posts", &Post { id: "p4".into(), tags: vec!["x".into(), "a".into()] }).unwrap();
+    assert_eq!(ids(&db, "SELECT id FROM posts WHERE tags CONTAINS 'a';").len(), 2);
+
+    let reopened = LocalDB::open(path).unwrap();
+    assert_eq!(ids(&reopened, "SELECT id FROM posts WHERE tags CONTAINS 'c';"), [LocalDBValue::from("p1")]);
+    let mut copy = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    copy.exec(reopened.dump_sql()).unwrap();
+    assert_eq!(ids(&copy, "SELECT id FROM posts WHERE tags CONTAINS 'x';"), [LocalDBValue::from("p4")]);
+
+    let _ = fs::remove_file(path);
+}
+
+#[test]
+fn test_seed() {
+    let path = "test_seed.db";
+    let _ = fs::remove_file(path);
+
+    let row = |id: i64, name: &str| -> localdb::Row {
+        let mut row = localdb::Row::new();
+        row.insert("id".to_string(), LocalDBValue::INT(id));
+        row.insert("name".to_string(), LocalDBValue::TEXT(name.to_string()));
+        row
+    };
+
+    let mut db = LocalDB::create(path).unwrap();
+    db.seed("users", vec![row(1, "kk"), row(2, "jo")]).unwrap();
+    db.seed("empty", Vec::new()).unwrap();
+    assert_eq!(db.count("users").unwrap(), 2);
+    assert_eq!(db.count("empty").unwrap(), 0);
+
+    db.exec("CREATE TABLE keyed (id INT PRIMARY KEY, name TEXT);".to_string()).unwrap();
+    assert!(db.seed("keyed", vec![row(1, "a"), row(1, "b")]).is_err());
+    assert_eq!(db.count("keyed").unwrap(), 0);
+    assert!(db.seed("__meta__", vec![row(1, "a")]).is_err());
+    let mut bad = row(3, "x");
+    bad.insert("tags".to_string(), LocalDBValue::ARRAY(vec![LocalDBValue::INT(1), LocalDBValue::TEXT("a".into())]));
+    assert!(db.seed("fresh", vec![row(1, "a"), bad]).is_err());
+    assert!(db.count("fresh").is_err());
+
+    let reopened = LocalDB::open(path).unwrap();
+    assert_eq!(reopened.count("users").unwrap(), 2);
+    assert_eq!(reopened.count("empty").unwrap(), 0);
+
+    let _ = fs::remove_file(path);
+}
+
+#[test]
+fn test_concat_operator() {
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    db.exec(db.add_lines([
+        "INSERT INTO people (first, last, age, vip) VALUES ('Ada', 'Lovelace', 36, TRUE);",
+        "INSERT INTO people (first, age) VALUES ('Bob', 2.5);"
+    ])).unwrap();
+
+    let rows = db.query("SELECT first || ' ' || last AS full, first || ':' || age + 1 AS tag, vip || '' AS v FROM people;").unwrap();
+    assert_eq!(rows[0]["full"], LocalDBValue::from("Ada Lovelace"));
+    assert_eq!(rows[0]["tag"], LocalDBValue::from("Ada:37"));
+    assert_eq!(rows[0]["v"], LocalDBValue::from("true"));
+    assert_eq!(rows[1]["full"], LocalDBValue::NULL);
+    assert_eq!(rows[1]["tag"], LocalDBValue::from("Bob:3.5"));
+
+    let plan = db.explain("SELECT (first || last) || '!' AS a, first || (age + 1) AS b FROM people;").unwrap();
+    assert!(plan.contains("columns: first || last || '!' AS a, first || age + 1 AS b"), "{}", plan);
+}
+
+#[test]
+fn test_dump_sql_pretty() {
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    db.exec(db.add_lines([
+        "CREATE TABLE users (id INT PRIMARY KEY, name TEXT NOT NULL, created_at TIMESTAMP, UNIQUE (name)) COMMENT 'app users';",
+        "INSERT INTO users (id, name) VALUES (1, 'kk'); INSERT INTO tags (label) VALUES ('x');"
+    ])).unwrap();
+
+    let pretty = db.dump_sql_pretty();
+    let expected = "\
+CREATE TABLE tags (
+    label TEXT
+);
+INSERT INTO tags (label)
+    VALUES ('x');
+
+CREATE TABLE users (
+    id         INT       PRIMARY KEY,
+    name       TEXT      NOT NULL,
+    created_at TIMESTAMP,
+    UNIQUE (name)
+) COMMENT 'app users';
+INSERT INTO users (id, name)
+    VALUES (1, 'kk');
+";
+    assert_eq!(pretty, expected);
+
+    let mut copy = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    copy.exec(pretty).unwrap();
+    assert_eq!(copy.dump_sql(), db.dump_sql());
+}
+
+#[test]
+fn test_update_fields() {
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    db.exec(db.add_lines([
+        "CREATE TABLE users (id INT PRIMARY KEY, name TEXT NOT NULL, email TEXT, UNIQUE (email));",
+        "INSERT INTO users (id, name, email) VALUES (1, 'kk', 'kk@x'); INSERT INTO users (id, name, email) VALUES (2, 'jo', 'jo@x');"
+    ])).unwrap();
+
+    let fields = std::collections::HashMap::from([("email".to_string(), LocalDBValue::from("kk@y"))]);
+    assert!(db.update_fields("users", 1, fields).unwrap());
+    let rows = db.query("SELECT name, email FROM users WHERE id = 1;").unwrap();
+    assert_eq!(rows[0]["name"], LocalDBValue::from("kk"));
+    assert_eq!(rows[0]["email"], LocalDBValue::from("kk@y"));
+
+    let fields = std::collections::HashMap::from([("name".to_string(), LocalDBValue::from("nobody"))]);
+    assert!(!db.update_fields("users", 9, fields).unwrap());
+    assert_eq!(db.count("users").unwrap(), 2);
+
+    let fields = std::collections::HashMap::from([("email".to_string(), LocalDBValue::from("jo@x"))]);
+    assert!(matches!(db.update_fields("users", 1, fields), Err(localdb::LocalDBError::ConstraintViolation(_))));
+    let fields = std::collections::HashMap::from([("name".to_string(), LocalDBValue::NULL)]);
+    assert!(matches!(db.update_fields("users", 2, fields), Err(localdb::LocalDBError::ConstraintViolation(_))));
+    let rows = db.query("SELECT email FROM users WHERE id = 1;").unwrap();
+    assert_eq!(rows[0]["email"], LocalDBValue::from("kk@y"));
+}
+
+#[test]
+fn test_scan_limit() {
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    for i in 0..10 {
+        db.exec(format!("INSERT INTO items (id, qty) VALUES ({}, {});", i, i % 3)).unwrap();
+    }
+    db.exec("CREATE INDEX idx_items_id ON items (id);".to_string()).unwrap();
+    db.set_scan_limit(5);
+
+    let err = db.query("SELECT * FROM items WHERE qty = 1;").unwrap_err();
+    assert!(matches!(err, localdb::LocalDBError::ScanLimitExceeded(5)));
+    assert!(db.select("items").run().is_err());
+    assert!(db.find_by("items", "qty", 1).is_err());
+    assert!(db.query("SELECT * FROM items i JOIN items j ON i.id = j.id WHERE i.id = 1;").is_err());
+
+    // index lookups and counts without WHERE stay under the limit
+    assert_eq!(db.query("SELECT * FROM items WHERE id < 3;").unwrap().len(), 3);
+    assert_eq!(db.query("SELECT COUNT(*) FROM items;").unwrap().len(), 1);
+
+    // writes are not limited, and 0 turns the limit off
+    db.exec("UPDATE items SET qty = 0 WHERE qty = 1;".to_string()).unwrap();
+    db.set_scan_limit(0);
+    assert_eq!(db.query("SELECT * FROM items WHERE qty = 0;").unwrap().len(), 7);
+}
+
+#[test]
+fn test_open_tables() {
+    let path = "test_open_tables.json";
+    let _ = fs::remove_file(path);
+    let mut db = LocalDB::create(path).unwrap();
+    db.exec(db.add_lines([
+        "CREATE TABLE users (id INT PRIMARY KEY, name TEXT); INSERT INTO users (id, name) VALUES (1, 'kk');",
+        "INSERT INTO logs (id, msg) VALUES (1, 'a'); INSERT INTO logs (id, msg) VALUES (2, 'b');"
+    ])).unwrap();
+    let before = fs::read_to_string(path).unwrap();
+
+    let mut db = LocalDB::open_tables(path, &["users"]).unwrap();
+    assert_eq!(db.query("SELECT * FROM users;").unwrap().len(), 1);
+
+    // writing users keeps the unread logs table as it was
+    db.exec("INSERT INTO users (id, name) VALUES (2, 'jo');".to_string()).unwrap();
+    assert!(db.exec("INSERT INTO users (id, name) VALUES (2, 'jo');".to_string()).is_err());
+    let reopened = LocalDB::open(path).unwrap();
+    assert_eq!(reopened.count("logs").unwrap(), 2);
+    assert_eq!(reopened.count("users").unwrap(), 2);
+    assert_ne!(before, fs::read_to_string(path).unwrap());
+
+    // other tables load on first use, for reads and writes alike
+    assert_eq!(db.query("SELECT msg FROM logs WHERE id = 2;").unwrap().len(), 1);
+    assert_eq!(db.count("logs").unwrap(), 2);
+    db.exec("DELETE FROM logs WHERE id = 1;".to_string()).unwrap();
+    assert_eq!(LocalDB::open(path).unwrap().count("logs").unwrap(), 1);
+    assert_eq!(db.dump_sql(), LocalDB::open(path).unwrap().dump_sql());
+
+    let _ = fs::remove_file(path);
+}
+
+#[test]
+fn test_result_ordering() {
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    db.exec(db.add_lines([
+        "CREATE TABLE items (sku TEXT, price INT, kind TEXT); CREATE INDEX idx_items_price ON items (price);",
+        "INSERT INTO items (sku, price, kind) VALUES ('c', 3, 'x'); INSERT INTO items (sku, price, kind) VALUES ('a', 1, 'y'); INSERT INTO items (sku, price, kind) VALUES ('b', 2, 'x'); INSERT INTO items (sku, price, kind, note) VALUES ('d', 1, 'y', 'n');"
+    ])).unwrap();
+
+    let skus = |db: &LocalDB, sql: &str| -> Vec<LocalDBValue> {
+        db.query(sql).unwrap().iter().map(|r| r["sku"].clone()).collect()
+    };
+    let expect = |list: &[&str]| -> Vec<LocalDBValue> { list.iter().map(|s| LocalDBValue::from(*s)).collect() };
+
+    assert_eq!(skus(&db, "SELECT * FROM items;"), expect(&["c", "a", "b", "d"]));
+    // the index returns candidates in price order; results keep insertion order
+    assert_eq!(skus(&db, "SELECT * FROM items WHERE price >= 1;"), expect(&["c", "a", "b", "d"]));
+    db.exec("DELETE FROM items WHERE sku = 'a';".to_string()).unwrap();
+    db.exec("INSERT INTO items (sku, price, kind) VALUES ('e', 0, 'x');".to_string()).unwrap();
+    assert_eq!(skus(&db, "SELECT * FROM items WHERE price < 5;"), expect(&["c", "b", "d", "e"]));
+
+    let kinds: Vec<LocalDBValue> = db.query("SELECT kind, COUNT(*) AS n FROM items GROUP BY kind;").unwrap().iter().map(|r| r["kind"].clone()).collect();
+    assert_eq!(kinds, expect(&["x", "y"]));
+
+    // QueryResult columns: declared ones in declaration order, then the others sorted
+    let result = db.query_result("SELECT * FROM items;").unwrap();
+    assert_eq!(result.columns, vec!["sku", "price", "kind", "note"]);
+    assert_eq!(db.query_result("SELECT * FROM items;").unwrap().columns, result.columns);
+}
+
+#[test]
+fn test_rowid() {
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    db.exec("CREATE TABLE logs (msg TEXT, level INT);".to_string()).unwrap();
+    for (i, msg) in ["a", "b", "c", "d", "e"].iter().enumerate() {
+        db.exec(format!("INSERT INTO logs (msg, level) VALUES ('{}', {});", msg, i % 2)).unwrap();
+    }
+
+    let rows = db.query("SELECT ROWID, * FROM logs;").unwrap();
+    assert_eq!(rows.len(), 5);
+    assert_eq!(rows[2]["ROWID"], LocalDBValue::INT(3));
+    assert_eq!(rows[2]["msg"], LocalDBValue::from("c"));
+    let result = db.query_result("SELECT ROWID, * FROM logs;").unwrap();
+    assert_eq!(result.columns, vec!["ROWID", "msg", "level"]);
+
+    // ROWID can filter and sort without showing up in *
+    let rows = db.query("SELECT * FROM logs WHERE ROWID >= 4;").unwrap();
+    assert_eq!(rows.len(), 2);
+    assert!(!rows[0].contains_key("ROWID"));
+    let rows = db.query("SELECT msg FROM logs WHERE level = 0 ORDER BY ROWID DESC LIMIT 1;").unwrap();
+    assert_eq!(rows[0]["msg"], LocalDBValue::from("e"));
+    let rows = db.query("SELECT COUNT(*) FROM logs WHERE ROWID < 3;").unwrap();
+    assert_eq!(rows[0]["COUNT(*)"], LocalDBValue::INT(2));
+
+    // rows behind a deleted one are renumbered
+    db.exec("DELETE FROM logs WHERE ROWID < 3;".to_string()).unwrap();
+    let rows = db.query("SELECT ROWID, msg FROM logs;").unwrap();
+    assert_eq!(rows.len(), 3);
+    assert_eq!(rows[0]["ROWID"], LocalDBValue::INT(1));
+    assert_eq!(rows[0]["msg"], LocalDBValue::from("c"));
+
+    // a stored ROWID column takes the place of the virtual one
+    db.exec("INSERT INTO tagged (ROWID, tag) VALUES (10, 'x');".to_string()).unwrap();
+    let rows = db.query("SELECT * FROM tagged WHERE ROWID = 10;").unwrap();
+    assert_eq!(rows[0]["ROWID"], LocalDBValue::INT(10));
+}
+
+#[test]
+fn test_table_size() {
+    use localdb::SaveOptions;
+
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    db.set_save_options(SaveOptions::canonical());
+    db.exec(db.add_lines([
+        "INSERT INTO small (id) VALUES (1);",
+        "INSERT INTO big (id, body) VALUES (1, 'xxxxxxxxxxxxxxxxxxxx'); INSERT INTO big (id, body) VALUES (2, 'yyyyyyyyyyyyyyyyyyyy');"
+    ])).unwrap();
+
+    assert_eq!(db.table_size("small").unwrap(), r#"[{"id":{"INT":1}}]"#.len());
+    assert!(db.table_size("big").unwrap() > 2 * db.table_size("small").unwrap());
+    assert!(db.table_size("missing").is_err());
+
+    let mut file = Vec::new();
+    db.write_to(&mut file).unwrap();
+    assert!(file.len() > db.table_size("big").unwrap() + db.table_size("small").unwrap());
+}
+
+#[test]
+fn test_positional_insert() {
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    db.exec(db.add_lines([
+        "CREATE TABLE items (sku TEXT, qty INT, owner UUID, price REAL);",
+        "INSERT INTO items VALUES ('a', 3, '11111111-1111-1111-1111-111111111111', 2.5);"
+    ])).unwrap();
+
+    let rows = db.query("SELECT * FROM items;").unwrap();
+    assert_eq!(rows[0]["sku"], LocalDBValue::from("a"));
+    assert_eq!(rows[0]["qty"], LocalDBValue::INT(3));
+    assert!(matches!(rows[0]["owner"], LocalDBValue::UUID(_)));
+    assert_eq!(rows[0]["price"], LocalDBValue::REAL(2.5));
+
+    let err = db.exec("INSERT INTO items VALUES ('b', 1);".to_string()).unwrap_err();
+    assert!(err.to_string().contains("expects 4 values for items (sku, qty, owner, price), got 2"));
+    assert_eq!(db.count("items").unwrap(), 1);
+}
+
+#[test]
+fn test_like_prefix_index() {
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    for name in ["apple", "apricot", "banana", "app", "Apple", "ap"] {
+        db.exec(format!("INSERT INTO words (name) VALUES ('{}');", name)).unwrap();
+    }
+    db.exec("INSERT INTO words (name) VALUES (5);".to_string()).unwrap();
+    db.exec("CREATE INDEX idx_words_name ON words (name);".to_string()).unwrap();
+
+    let names = |db: &LocalDB, sql: &str| -> Vec<LocalDBValue> {
+        db.query(sql).unwrap().iter().map(|r| r["name"].clone()).collect()
+    };
+    let expect = |list: &[&str]| -> Vec<LocalDBValue> { list.iter().map(|s| LocalDBValue::from(*s)).collect() };
+
+    assert_eq!(names(&db, "SELECT * FROM words WHERE name LIKE 'app%';"), expect(&["apple", "app"]));
+    assert_eq!(names(&db, "SELECT * FROM words WHERE name LIKE 'ap_i%';"), expect(&["apricot"]));
+    assert!(db.explain("SELECT * FROM words WHERE name LIKE 'app%';").unwrap().contains("index idx_words_name"));
+    let analyzed = db.explain_analyze("SELECT * FROM words WHERE name LIKE 'app%';").unwrap();
+    assert!(analyzed.contains("rows scanned: 2"));
+
+    // unanchored and case-insensitive patterns still scan
+    assert_eq!(names(&db, "SELECT * FROM words WHERE name LIKE '%an%';"), expect(&["banana"]));
+    assert!(db.explain("SELECT * FROM words WHERE name ILIKE 'app%';").unwrap().contains("full scan"));
+    assert_eq!(names(&db, "SELECT * FROM words WHERE name ILIKE 'app%';"), expect(&["apple", "app", "Apple"]));
+}
+
+#[test]
+fn test_with_transaction() {
+    let path = "test_with_transaction.db";
+    let _ = fs::remove_file(path);
+    let mut db = LocalDB::create(path).unwrap();
+    db.exec("INSERT INTO t (a) VALUES (1);".to_string()).unwrap();
+
+    let count = db
+        .with_transaction(|tx| {
+            tx.exec("INSERT INTO t (a) VALUES (2); INSERT INTO t (a) VALUES (3);".to_string())?;
+            Ok(tx.query("SELECT * FROM t;")?.len())
+        })
+        .unwrap();
+    assert_eq!(count, 3);
+    assert_eq!(LocalDB::open(path).unwrap().count("t").unwrap(), 3);
+
+    let failed = db.with_transaction(|tx| {
+        tx.exec("DELETE FROM t WHERE a = 1;".to_string())?;
+        tx.exec("SELEKT nothing;".to_string())
+    });
+    assert!(failed.is_err());
+    assert_eq!(db.count("t").unwrap(), 3);
+
+    let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        let _ = db.with_transaction(|tx| -> localdb::Result<()> {
+            tx.exec("DELETE FROM t;".to_string())?;
+            panic!("boom");
+        });
+    }));
+    assert!(panicked.is_err());
+    assert_eq!(db.count("t").unwrap(), 3);
+    assert_eq!(LocalDB::open(path).unwrap().count("t").unwrap(), 3);
+
+    let _ = fs::remove_file(path);
+}
+
+#[test]
+fn test_distinct_on() {
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    db.exec(db.add_lines([
+        "INSERT INTO events (user_id, kind, at) VALUES (1, 'login', 10); INSERT INTO events (user_id, kind, at) VALUES (2, 'login', 11);",
+        "INSERT INTO events (user_id, kind, at) VALUES (1, 'buy', 12); INSERT INTO events (user_id, kind, at) VALUES (2, 'logout', 9); INSERT INTO events (user_id, kind, at) VALUES (3, 'login', 5);"
+    ])).unwrap();
+
+    let latest = db.query("SELECT DISTINCT ON (user_id) * FROM events ORDER BY at DESC;").unwrap();
+    let pairs: Vec<(LocalDBValue, LocalDBValue)> = latest.iter().map(|r| (r["user_id"].clone(), r["kind"].clone())).collect();
+    assert_eq!(pairs, [(1, "buy"), (2, "login"), (3, "login")].map(|(id, kind)| (LocalDBValue::from(id), LocalDBValue::from(kind))));
+
+    // without ORDER BY the first row in insertion order wins; LIMIT counts kept rows
+    let first = db.query("SELECT DISTINCT ON (user_id) kind FROM events LIMIT 2;").unwrap();
+    let kinds: Vec<LocalDBValue> = first.iter().map(|r| r["kind"].clone()).collect();
+    assert_eq!(kinds, [LocalDBValue::from("login"), LocalDBValue::from("login")]);
+    assert_eq!(db.query("SELECT DISTINCT ON (kind, user_id) * FROM events;").unwrap().len(), 5);
+
+    assert!(db.explain("SELECT DISTINCT ON (user_id) * FROM events;").unwrap().contains("distinct on: user_id"));
+    assert!(db.query("SELECT DISTINCT ON (user_id) COUNT(*) FROM events;").is_err());
+    assert!(db.query("SELECT DISTINCT user_id FROM events;").is_err());
+}
+
+#[test]
+fn test_register_function() {
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    db.exec("INSERT INTO items (name, price) VALUES ('pen', 8); INSERT INTO items (name, price) VALUES ('book', 12);".to_string()).unwrap();
+    db.register_function("with_tax", |args: &[LocalDBValue]| match args {
+        [LocalDBValue::INT(n)] => Ok(LocalDBValue::INT(n + n / 4)),
+        [_] => Ok(LocalDBValue::NULL),
+        _ => Err(localdb::LocalDBError::SqlError(format!("with_tax takes 1 argument, got {}", args.len()))),
+    }).unwrap();
+
+    let rows = db.query("SELECT name, WITH_TAX(price) AS total FROM items WHERE with_tax(price) > 10;").unwrap();
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0]["total"], LocalDBValue::INT(15));
+
+    db.exec("UPDATE items SET price = with_tax(price) WHERE name = 'pen';".to_string()).unwrap();
+    assert_eq!(db.query("SELECT price FROM items WHERE name = 'pen';").unwrap()[0]["price"], LocalDBValue::INT(10));
+
+    // the function's own error fails the statement, and no row changes
+    let err = db.query("SELECT with_tax(price, 2) FROM items;").unwrap_err();
+    assert_eq!(err.to_string(), "SQL error: with_tax takes 1 argument, got 2");
+    assert!(db.exec("UPDATE items SET price = with_tax() WHERE name = 'book';".to_string()).is_err());
+    assert_eq!(db.query("SELECT price FROM items WHERE name = 'book';").unwrap()[0]["price"], LocalDBValue::INT(12));
+
+    assert!(db.query("SELECT nope(price) FROM items;").unwrap_err().to_string().contains("Unknown function nope"));
+    assert!(db.register_function("upper", |_| Ok(LocalDBValue::NULL)).is_err());
+    assert!(db.exec("DELETE FROM items RETURNING with_tax(price);".to_string()).is_err());
+}
+
+#[test]
+fn test_from_value() {
+    let value = serde_json::json!({
+        "users": [{"name": "kk", "age": 3, "tags": ["a", "b"]}, {"name": "jo", "age": null}],
+        "empty": []
+    });
+    let mut db = LocalDB::from_value(value).unwrap();
+    assert_eq!(db.count("users").unwrap(), 2);
+    assert_eq!(db.count("empty").unwrap(), 0);
+    let kk = db.query("SELECT * FROM users WHERE name = 'kk';").unwrap();
+    assert_eq!(kk[0]["age"], LocalDBValue::INT(3));
+    assert!(matches!(kk[0]["tags"], LocalDBValue::ARRAY(_)));
+
+    assert_eq!(db.load_value(serde_json::json!({"users": [{"name": "al"}]})).unwrap(), 1);
+    assert_eq!(db.count("users").unwrap(), 3);
+
+    // a bad table anywhere leaves every table as it was
+    for bad in [
+        serde_json::json!([1, 2]),
+        serde_json::json!({"users": {"name": "x"}}),
+        serde_json::json!({"users": [{"name": "x"}], "zzz": ["not a row"]}),
+        serde_json::json!({"users": [{"name": {"nested": true}}]}),
+    ] {
+        assert!(matches!(db.load_value(bad), Err(localdb::LocalDBError::CorruptData(_))));
+    }
+    assert_eq!(db.count("users").unwrap(), 3);
+    assert!(db.count("zzz").is_err());
+}
+
+#[test]
+fn test_dir_layout() {
+    let dir = "test_dir_layout.db";
+    let _ = fs::remove_dir_all(dir);
+    let mut db = LocalDB::create_dir(dir).unwrap();
+    db.exec("CREATE TABLE users (id INT PRIMARY KEY, name TEXT); INSERT INTO users (id, name) VALUES (1, 'kk');".to_string()).unwrap();
+    db.exec("INSERT INTO logs (msg) VALUES ('a'); INSERT INTO scratch (n) VALUES (1);".to_string()).unwrap();
+    assert!(std::path::Path::new(dir).join("users.json").exists());
+    assert!(LocalDB::create_dir(dir).is_err());
+
+    // only changed tables are rewritten: a hand edit of logs.json survives a write to users
+    fs::write(format!("{}/logs.json", dir), "[]").unwrap();
+    db.exec("INSERT INTO users (id, name) VALUES (2, 'jo');".to_string()).unwrap();
+
+    let reopened = LocalDB::open_dir(dir).unwrap();
+    assert_eq!(reopened.count("users").unwrap(), 2);
+    assert_eq!(reopened.count("logs").unwrap(), 0);
+    assert_eq!(reopened.count("scratch").unwrap(), 1);
+    assert!(reopened.schema("users").is_some());
+    assert!(LocalDB::open_dir("test_dir_layout_missing").is_err());
+
+    // removed tables lose their files
+    db.clear().unwrap();
+    assert!(!std::path::Path::new(dir).join("scratch.json").exists());
+    assert!(LocalDB::open_dir(dir).unwrap().count("users").is_err());
+
+    let _ = fs::remove_dir_all(dir);
+}
+
+#[test]
+fn test_element_predicates() {
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    db.exec(db.add_lines([
+        "INSERT INTO posts (id, tags, meta) VALUES ('p1', ['urgent', 'bug'], JSON '{\"addr\": {\"city\": \"NYC\"}, \"scores\": [3, 5]}');",
+        "INSERT INTO posts (id, tags, meta) VALUES ('p2', ['bug'], JSON '{\"addr\": {\"city\": \"LA\"}, \"scores\": [4]}');"
+    ])).unwrap();
+
+    let ids = |sql: &str| -> Vec<LocalDBValue> { db.query(sql).unwrap().iter().map(|r| r["id"].clone()).collect() };
+    assert_eq!(ids("SELECT id FROM posts WHERE tags[0] = 'urgent';"), [LocalDBValue::from("p1")]);
+    assert_eq!(ids("SELECT id FROM posts WHERE meta->'addr'->>'city' = 'NYC';"), [LocalDBValue::from("p1")]);
+    assert_eq!(ids("SELECT id FROM posts WHERE meta->'scores'[1] > 4;"), [LocalDBValue::from("p1")]);
+    // out of bounds finds nothing, negated or not
+    assert!(ids("SELECT id FROM posts WHERE tags[5] = 'bug';").is_empty());
+    assert!(ids("SELECT id FROM posts WHERE tags[5] != 'bug';").is_empty());
+
+    let rows = db.query("SELECT tags[1] FROM posts WHERE id = 'p1';").unwrap();
+    assert_eq!(rows[0]["tags[1]"], LocalDBValue::from("bug"));
+    assert!(db.query("SELECT id FROM posts WHERE tags[-1] = 'bug';").is_err());
+}
+
+#[test]
+fn test_reload_if_changed() {
+    let path = "test_reload_if_changed.db";
+    let _ = fs::remove_file(path);
+    let mut writer = LocalDB::create(path).unwrap();
+    writer.exec("CREATE TABLE t (id INT PRIMARY KEY, v TEXT); INSERT INTO t (id, v) VALUES (1, 'a');".to_string()).unwrap();
+
+    let mut reader = LocalDB::open_readonly(path).unwrap();
+    assert!(!reader.reload_if_changed().unwrap());
+
+    writer.exec("INSERT INTO t (id, v) VALUES (2, 'b');".to_string()).unwrap();
+    assert!(reader.reload_if_changed().unwrap());
+    assert_eq!(reader.count("t").unwrap(), 2);
+    assert_eq!(reader.query("SELECT * FROM t WHERE id = 2;").unwrap().len(), 1);
+    assert!(!reader.reload_if_changed().unwrap());
+
+    // a handle's own saves are not changes from elsewhere
+    assert!(!writer.reload_if_changed().unwrap());
+    assert!(!LocalDB::from_reader("{}".as_bytes()).unwrap().reload_if_changed().unwrap());
+
+    let _ = fs::remove_file(path);
+}
+
+#[test]
+fn test_trim_functions() {
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    db.exec("INSERT INTO imports (name, n) VALUES ('  kk \t', 5);".to_string()).unwrap();
+
+    let row = &db.query("SELECT TRIM(name) AS t, LTRIM(name) AS l, rtrim(name) AS r, TRIM(n) AS num FROM imports;").unwrap()[0];
+    assert_eq!(row["t"], LocalDBValue::from("kk"));
+    assert_eq!(row["l"], LocalDBValue::from("kk \t"));
+    assert_eq!(row["r"], LocalDBValue::from("  kk"));
+    assert_eq!(row["num"], LocalDBValue::NULL);
+    assert_eq!(db.query("SELECT * FROM imports WHERE TRIM(name) = 'kk';").unwrap().len(), 1);
+}
+
+#[test]
+fn test_substr() {
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    db.exec("INSERT INTO codes (code, n) VALUES ('AB-1234', 7); INSERT INTO codes (n) VALUES (8);".to_string()).unwrap();
+
+    let sub = |expr: &str| -> LocalDBValue {
+        let rows = db.query(&format!("SELECT {} AS s FROM codes WHERE n = 7;", expr)).unwrap();
+        rows[0]["s"].clone()
+    };
+    assert_eq!(sub("SUBSTR(code, 1, 2)"), LocalDBValue::from("AB"));
+    assert_eq!(sub("SUBSTRING(code, 4)"), LocalDBValue::from("1234"));
+    assert_eq!(sub("SUBSTR(code, 0, 2)"), LocalDBValue::from("A"));
+    assert_eq!(sub("SUBSTR(code, 6, 100)"), LocalDBValue::from("34"));
+    assert_eq!(sub("SUBSTR(code, 50, 2)"), LocalDBValue::from(""));
+    assert_eq!(sub("SUBSTR(code, 1, -1)"), LocalDBValue::NULL);
+    assert_eq!(sub("SUBSTR(n, 1, 1)"), LocalDBValue::NULL);
+
+    assert_eq!(db.query("SELECT * FROM codes WHERE SUBSTR(code, 1, 2) = 'AB';").unwrap().len(), 1);
+    let missing = db.query("SELECT SUBSTR(code, 1) AS s FROM codes WHERE n = 8;").unwrap();
+    assert_eq!(missing[0]["s"], LocalDBValue::NULL);
+}
+
+#[test]
+fn test_duplicate_columns() {
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    let err = db.exec("CREATE TABLE t (a INT, b TEXT, a TEXT);".to_string()).unwrap_err();
+    assert!(matches!(err, localdb::LocalDBError::SqlError(_)));
+    assert_eq!(err.to_string(), "SQL error: Table t declares column a more than once");
+    assert!(db.schema("t").is_none());
+    assert!(db.count("t").is_err());
+
+    db.exec("CREATE TABLE t (a INT, b TEXT);".to_string()).unwrap();
+    assert!(db.schema("t").is_some());
+}
+
+#[test]
+fn test_replace_table() {
+    let path = "test_replace_table.db";
+    let _ = fs::remove_file(path);
+    let mut db = LocalDB::create(path).unwrap();
+    db.exec("CREATE TABLE rates (code TEXT PRIMARY KEY, rate REAL); INSERT INTO rates (code, rate) VALUES ('EUR', 1.1);".to_string()).unwrap();
+
+    let row = |code: &str, rate: f64| {
+        std::collections::HashMap::from([("code".to_string(), LocalDBValue::from(code)), ("rate".to_string(), LocalDBValue::from(rate))])
+    };
+    assert_eq!(db.replace_table("rates", vec![row("USD", 1.0), row("GBP", 1.3)]).unwrap(), 2);
+    let reopened = LocalDB::open(path).unwrap();
+    assert_eq!(reopened.count("rates").unwrap(), 2);
+    assert!(reopened.schema("rates").is_some());
+    assert_eq!(reopened.query("SELECT * FROM rates WHERE code = 'EUR';").unwrap().len(), 0);
+
+    // a rejected row keeps the old contents
+    assert!(db.replace_table("rates", vec![row("JPY", 0.1), row("JPY", 0.2)]).is_err());
+    assert_eq!(db.query("SELECT * FROM rates WHERE code = 'USD';").unwrap().len(), 1);
+    assert_eq!(db.count("rates").unwrap(), 2);
+    assert!(db.replace_table("missing", Vec::new()).is_err());
+
+    let _ = fs::remove_file(path);
+}
+
+#[test]
+fn test_where_precedence() {
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    db.exec("CREATE TABLE t (id INT PRIMARY KEY, a INT, b INT, c INT);".to_string()).unwrap();
+    for (id, a, b, c) in [(1, 1, 0, 0), (2, 0, 2, 3), (3, 0, 2, 0), (4, 0, 0, 3), (5, 1, 2, 3)] {
+        db.exec(format!("INSERT INTO t (id, a, b, c) VALUES ({}, {}, {}, {});", id, a, b, c)).unwrap();
+    }
+    let ids = |db: &LocalDB, filter: &str| -> Vec<LocalDBValue> {
+        let sql = format!("SELECT id FROM t WHERE {} ORDER BY id;", filter);
+        db.query(&sql).unwrap().iter().map(|row| row["id"].clone()).collect()
+    };
+    let expect = |list: &[i64]| -> Vec<LocalDBValue> { list.iter().map(|id| LocalDBValue::from(*id)).collect() };
+
+    // AND binds tighter than OR
+    assert_eq!(ids(&db, "a = 1 OR b = 2 AND c = 3"), expect(&[1, 2, 5]));
+    assert_eq!(ids(&db, "b = 2 AND c = 3 OR a = 1"), expect(&[1, 2, 5]));
+    assert_eq!(ids(&db, "(a = 1 OR b = 2) AND c = 3"), expect(&[2, 5]));
+    assert_eq!(ids(&db, "((a = 1) OR (b = 2 AND (c = 3 OR c = 0))) AND id != 5"), expect(&[1, 2, 3]));
+    assert_eq!(ids(&db, "NOT (a = 1 OR c = 3)"), expect(&[3]));
+    assert_eq!(ids(&db, "id BETWEEN 2 AND 4 AND b = 2 OR id IN (5)"), expect(&[2, 3, 5]));
+
+    // the primary key index answers one side of an AND
+    assert_eq!(ids(&db, "id = 2 AND (c = 3 OR a = 1)"), expect(&[2]));
+    assert!(db.explain("SELECT * FROM t WHERE id = 2 AND c = 3;").unwrap().contains("index"));
+
+    // NULL stays unknown under NOT
+    db.exec("INSERT INTO t (id, a, b, c) VALUES (6, NULL, 0, 0);".to_string()).unwrap();
+    assert_eq!(ids(&db, "NOT (a = 1) AND id > 3"), expect(&[4]));
+    assert_eq!(ids(&db, "a = 1 OR id = 6"), expect(&[1, 5, 6]));
+
+    db.exec("UPDATE t SET c = 9 WHERE a = 1 AND (b = 0 OR c = 0);".to_string()).unwrap();
+    assert_eq!(ids(&db, "c = 9"), expect(&[1]));
+    db.exec("DELETE FROM t WHERE b = 2 OR NOT (c = 0 OR c = 9);".to_string()).unwrap();
+    assert_eq!(ids(&db, "id > 0"), expect(&[1, 6]));
+    assert!(db.query("SELECT * FROM t WHERE (a = 1;").is_err());
+}
+
+#[test]
+fn test_diff() {
+    let mut old = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    old.exec("CREATE TABLE users (id INT PRIMARY KEY, name TEXT); CREATE TABLE tags (name TEXT); CREATE TABLE gone (x INT);".to_string()).unwrap();
+    old.exec("INSERT INTO users (id, name) VALUES (1, 'kk'); INSERT INTO users (id, name) VALUES (2, 'lo');".to_string()).unwrap();
+    old.exec("INSERT INTO tags (name) VALUES ('a'); INSERT INTO tags (name) VALUES ('a'); INSERT INTO tags (name) VALUES ('b');".to_string()).unwrap();
+
+    let mut new = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    new.exec("CREATE TABLE users (id INT PRIMARY KEY, name TEXT); CREATE TABLE tags (name TEXT); CREATE TABLE fresh (x INT);".to_string()).unwrap();
+    new.exec("INSERT INTO users (id, name) VALUES (1, 'kay'); INSERT INTO users (id, name) VALUES (3, 'mo');".to_string()).unwrap();
+    new.exec("INSERT INTO tags (name) VALUES ('b'); INSERT INTO tags (name) VALUES ('a'); INSERT INTO tags (name) VALUES ('c');".to_string()).unwrap();
+
+    let diff = LocalDB::diff(&old, &new).unwrap();
+    assert!(!diff.is_empty());
+    assert_eq!(diff.tables_added, vec!["fresh".to_string()]);
+    assert_eq!(diff.tables_removed, vec!["gone".to_string()]);
+
+    let users = &diff.tables["users"];
+    assert_eq!(users.changed.len(), 1);
+    assert_eq!(users.changed[0].key, LocalDBValue::from(1));
+    assert_eq!(users.changed[0].before["name"], LocalDBValue::from("kk"));
+    assert_eq!(users.changed[0].after["name"], LocalDBValue::from("kay"));
+    assert_eq!(users.added.iter().map(|row| &row["name"]).collect::<Vec<_>>(), [&LocalDBValue::from("mo")]);
+    assert_eq!(users.removed.iter().map(|row| &row["name"]).collect::<Vec<_>>(), [&LocalDBValue::from("lo")]);
+
+    // keyless rows match as whole rows, duplicates counted
+    let tags = &diff.tables["tags"];
+    assert!(tags.changed.is_empty());
+    assert_eq!(tags.added.iter().map(|row| &row["name"]).collect::<Vec<_>>(), [&LocalDBValue::from("c")]);
+    assert_eq!(tags.removed.iter().map(|row| &row["name"]).collect::<Vec<_>>(), [&LocalDBValue::from("a")]);
+
+    assert!(LocalDB::diff(&new, &new).unwrap().is_empty());
+}
+
+#[test]
+fn test_query_with_defaults() {
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    db.exec("INSERT INTO notes (id, title) VALUES (1, 'a'); INSERT INTO notes (id, title, pinned) VALUES (2, 'b', TRUE);".to_string()).unwrap();
+    db.exec("INSERT INTO notes (id, title, pinned) VALUES (3, 'c', NULL);".to_string()).unwrap();
+
+    let defaults = std::collections::HashMap::from([("pinned".to_string(), LocalDBValue::from(false))]);
+    let rows = db.query_with_defaults("SELECT * FROM notes ORDER BY id;", &defaults).unwrap();
+    assert_eq!(rows.len(), 3);
+    assert_eq!(rows[0]["pinned"], LocalDBValue::BOOL(false));
+    assert_eq!(rows[1]["pinned"], LocalDBValue::BOOL(true));
+    assert_eq!(rows[2]["pinned"], LocalDBValue::NULL);
+
+    // plain query still omits the key
+    assert!(!db.query("SELECT * FROM notes WHERE id = 1;").unwrap()[0].contains_key("pinned"));
+}
+
+#[test]
+fn test_update_many() {
+    let path = "test_update_many.db";
+    let _ = fs::remove_file(path);
+    let mut db = LocalDB::create(path).unwrap();
+    db.exec("CREATE TABLE users (id INT PRIMARY KEY, name TEXT UNIQUE, age INT);".to_string()).unwrap();
+    for (id, name) in [(1, "kk"), (2, "lo"), (3, "mo")] {
+        db.exec(format!("INSERT INTO users (id, name, age) VALUES ({}, '{}', 20);", id, name)).unwrap();
+    }
+
+    let fields = |pairs: &[(&str, LocalDBValue)]| -> std::collections::HashMap<String, LocalDBValue> {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.clone())).collect()
+    };
+    let updates = std::collections::HashMap::from([
+        ("1".to_string(), fields(&[("age", LocalDBValue::from(31))])),
+        ("3".to_string(), fields(&[("name", LocalDBValue::from("moe"))])),
+        ("9".to_string(), fields(&[("age", LocalDBValue::from(1))])),
+    ]);
+    assert_eq!(db.update_many("users", updates).unwrap(), 2);
+
+    let reopened = LocalDB::open(path).unwrap();
+    assert_eq!(reopened.query("SELECT age FROM users WHERE id = 1;").unwrap()[0]["age"], LocalDBValue::INT(31));
+    assert_eq!(reopened.query("SELECT * FROM users WHERE name = 'moe';").unwrap().len(), 1);
+    assert_eq!(reopened.query("SELECT age FROM users WHERE id = 2;").unwrap()[0]["age"], LocalDBValue::INT(20));
+
+    // a rejected row leaves every row as it was
+    let updates = std::collections::HashMap::from([
+        ("1".to_string(), fields(&[("age", LocalDBValue::from(40))])),
+        ("2".to_string(), fields(&[("name", LocalDBValue::from("kk"))])),
+    ]);
+    assert!(db.update_many("users", updates).is_err());
+    assert_eq!(db.query("SELECT age FROM users WHERE id = 1;").unwrap()[0]["age"], LocalDBValue::INT(31));
+
+    // schemaless tables try the key as text, then as a number
+    db.exec("INSERT INTO kv (id, v) VALUES ('a', 1); INSERT INTO kv (id, v) VALUES (7, 2);".to_string()).unwrap();
+    let updates = std::collections::HashMap::from([
+        ("a".to_string(), fields(&[("v", LocalDBValue::from(10))])),
+        ("7".to_string(), fields(&[("v", LocalDBValue::from(20))])),
+    ]);
+    assert_eq!(db.update_many("kv", updates).unwrap(), 2);
+    assert_eq!(db.query("SELECT * FROM kv WHERE v >= 10;").unwrap().len(), 2);
+    assert!(db.update_many("missing", std::collections::HashMap::new()).is_err());
+
+    let _ = fs::remove_file(path);
+}
+
+#[test]
+fn test_is_distinct_from() {
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    db.exec("INSERT INTO t (id, a, b) VALUES (1, 1, 1); INSERT INTO t (id, a, b) VALUES (2, 1, 2);".to_string()).unwrap();
+    db.exec("INSERT INTO t (id, a, b) VALUES (3, NULL, NULL); INSERT INTO t (id, a, b) VALUES (4, NULL, 2); INSERT INTO t (id) VALUES (5);".to_string()).unwrap();
+    let ids = |db: &LocalDB, filter: &str| -> Vec<LocalDBValue> {
+        let sql = format!("SELECT id FROM t WHERE {} ORDER BY id;", filter);
+        db.query(&sql).unwrap().iter().map(|row| row["id"].clone()).collect()
+    };
+    let expect = |list: &[i64]| -> Vec<LocalDBValue> { list.iter().map(|id| LocalDBValue::from(*id)).collect() };
+
+    assert_eq!(ids(&db, "a IS DISTINCT FROM b"), expect(&[2, 4]));
+    assert_eq!(ids(&db, "a IS NOT DISTINCT FROM b"), expect(&[1, 3, 5]));
+    assert_eq!(ids(&db, "a IS DISTINCT FROM NULL"), expect(&[1, 2]));
+    assert_eq!(ids(&db, "a IS NOT DISTINCT FROM NULL"), expect(&[3, 4, 5]));
+    assert_eq!(ids(&db, "b IS DISTINCT FROM 2"), expect(&[1, 3, 5]));
+    // unlike !=, NULL never leaves the result unknown
+    assert_eq!(ids(&db, "b != 2"), expect(&[1]));
+    assert_eq!(ids(&db, "NOT (a IS NOT DISTINCT FROM 1)"), expect(&[3, 4, 5]));
+    assert!(db.query("SELECT * FROM t WHERE a IS DISTINCT b;").is_err());
+}
+
+#[test]
+fn test_query_rev() {
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    for n in 1..=5 {
+        db.exec(format!("INSERT INTO events (n, even) VALUES ({}, {});", n, n % 2 == 0)).unwrap();
+    }
+    let ns = |rows: Vec<localdb::Row>| -> Vec<LocalDBValue> { rows.iter().map(|row| row["n"].clone()).collect() };
+    let expect = |list: &[i64]| -> Vec<LocalDBValue> { list.iter().map(|n| LocalDBValue::from(*n)).collect() };
+
+    assert_eq!(ns(db.query_rev("SELECT * FROM events;").unwrap()), expect(&[5, 4, 3, 2, 1]));
+    assert_eq!(ns(db.query_rev("SELECT n FROM events LIMIT 2;").unwrap()), expect(&[5, 4]));
+    assert_eq!(ns(db.query_rev("SELECT n FROM events WHERE NOT even LIMIT 2 OFFSET 1;").unwrap()), expect(&[3, 1]));
+    assert_eq!(
+        ns(db.query_rev("SELECT n FROM events;").unwrap()),
+        ns(db.query("SELECT n FROM events ORDER BY ROWID DESC;").unwrap())
+    );
+    assert!(db.query_rev("SELECT * FROM events ORDER BY n;").is_err());
+}
+
+#[test]
+fn test_decimal() {
+    let path = "test_decimal.db";
+    let _ = fs::remove_file(path);
+    let mut db = LocalDB::create(path).unwrap();
+    db.exec("CREATE TABLE items (id INT PRIMARY KEY, price DECIMAL);".to_string()).unwrap();
+    db.exec("INSERT INTO items (id, price) VALUES (1, DECIMAL '0.10'); INSERT INTO items (id, price) VALUES (2, DECIMAL '0.20');".to_string()).unwrap();
+    db.exec("INSERT INTO items (id, price) VALUES (3, DECIMAL '-3');".to_string()).unwrap();
+
+    // 0.1 + 0.2 is exactly 0.3
+    let rows = db.query("SELECT SUM(price) AS total FROM items WHERE id < 3;").unwrap();
+    assert!(matches!(&rows[0]["total"], LocalDBValue::DECIMAL(s) if s == "0.30"));
+    let rows = db.query("SELECT price + DECIMAL '0.2' AS p FROM items WHERE id = 1;").unwrap();
+    assert!(matches!(&rows[0]["p"], LocalDBValue::DECIMAL(s) if s == "0.30"));
+    assert_eq!(db.query("SELECT * FROM items WHERE price = DECIMAL '0.1';").unwrap().len(), 1);
+    assert_eq!(db.query("SELECT * FROM items WHERE price BETWEEN DECIMAL '0.1' AND DECIMAL '0.2';").unwrap().len(), 2);
+    assert_eq!(db.query("SELECT * FROM items WHERE price < 0;").unwrap().len(), 1);
+    let rows = db.query("SELECT price * 3 AS triple FROM items WHERE id = 1;").unwrap();
+    assert!(matches!(&rows[0]["triple"], LocalDBValue::DECIMAL(s) if s == "0.30"));
+
+    // stored exactly, and ordered numerically by an index
+    let reopened = LocalDB::open(path).unwrap();
+    assert!(matches!(&reopened.query("SELECT price FROM items WHERE id = 2;").unwrap()[0]["price"], LocalDBValue::DECIMAL(s) if s == "0.20"));
+    db.exec("CREATE INDEX idx_price ON items (price);".to_string()).unwrap();
+    let rows = db.query("SELECT id FROM items WHERE price >= DECIMAL '-3.0' ORDER BY price;").unwrap();
+    let ids: Vec<LocalDBValue> = rows.iter().map(|row| row["id"].clone()).collect();
+    assert_eq!(ids, [LocalDBValue::INT(3), LocalDBValue::INT(1), LocalDBValue::INT(2)]);
+
+    assert!(matches!(LocalDBValue::decimal("+012.50").unwrap(), LocalDBValue::DECIMAL(s) if s == "12.50"));
+    assert!(LocalDBValue::decimal("1.2.3").is_err());
+    assert!(db.query("SELECT * FROM items WHERE price = DECIMAL 'abc';").is_err());
+
+    let _ = fs::remove_file(path);
+}
+
+#[test]
+fn test_query_html() {
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    db.exec("CREATE TABLE notes (id INT, body TEXT, extra TEXT);".to_string()).unwrap();
+    db.exec("INSERT INTO notes (id, body) VALUES (1, '<b>\"Tom\" & ''Jerry''</b>'); INSERT INTO notes (id, body, extra) VALUES (2, NULL, 'x');".to_string()).unwrap();
+
+    let html = db.query_html("SELECT * FROM notes;").unwrap();
+    assert_eq!(
+        html,
+        "<table>\n  <tr><th>id</th><th>body</th><th>extra</th></tr>\n\
+         \x20 <tr><td>1</td><td>&lt;b&gt;&quot;Tom&quot; &amp; &#39;Jerry&#39;&lt;/b&gt;</td><td></td></tr>\n\
+         \x20 <tr><td>2</td><td>NULL</td><td>x</td></tr>\n</table>\n"
+    );
+    assert_eq!(db.query_result("SELECT id FROM notes WHERE id = 9;").unwrap().to_html(), "<table>\n  <tr><th>id</th></tr>\n</table>\n");
+}
+
+#[test]
+fn test_limit_stops_scan() {
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    for n in 0..100 {
+        db.exec(format!("INSERT INTO logs (n, level) VALUES ({}, '{}');", n, if n % 10 == 3 { "error" } else { "info" })).unwrap();
+    }
+
+    // the scan stops at the first match
+    let report = db.explain_analyze("SELECT * FROM logs WHERE level = 'error' LIMIT 1;").unwrap();
+    assert!(report.contains("rows scanned: 4\nrows returned: 1\n"), "{}", report);
+    let report = db.explain_analyze("SELECT n FROM logs WHERE level = 'error' LIMIT 2 OFFSET 1;").unwrap();
+    assert!(report.contains("rows scanned: 24\nrows returned: 2\n"), "{}", report);
+    let rows = db.query("SELECT n FROM logs WHERE level = 'error' LIMIT 2 OFFSET 1;").unwrap();
+    assert_eq!(rows.iter().map(|r| &r["n"]).collect::<Vec<_>>(), [&LocalDBValue::INT(13), &LocalDBValue::INT(23)]);
+    let report = db.explain_analyze("SELECT ROWID, n FROM logs LIMIT 3;").unwrap();
+    assert!(report.contains("rows scanned: 3\n"), "{}", report);
+
+    // sorting and aggregates still need every match
+    let report = db.explain_analyze("SELECT * FROM logs WHERE level = 'error' ORDER BY n DESC LIMIT 1;").unwrap();
+    assert!(report.contains("rows scanned: 100\n"), "{}", report);
+    let report = db.explain_analyze("SELECT COUNT(*) AS c FROM logs WHERE level = 'info' LIMIT 1;").unwrap();
+    assert!(report.contains("rows scanned: 100\n"), "{}", report);
+
+    // also when an index supplies the candidates
+    db.exec("CREATE INDEX idx_level ON logs (level);".to_string()).unwrap();
+    let report = db.explain_analyze("SELECT * FROM logs WHERE level = 'info' LIMIT 5;").unwrap();
+    assert!(report.contains("rows scanned: 5\nrows returned: 5\nindexes used: idx_level\n"), "{}", report);
+}
+
+#[test]
+fn test_validate_import() {
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    db.exec("CREATE TABLE users (id INT PRIMARY KEY, email TEXT UNIQUE NOT NULL, name TEXT NOT NULL, role TEXT NOT NULL DEFAULT 'user');".to_string()).unwrap();
+    db.exec("INSERT INTO users (id, email, name) VALUES (1, 'a@x', 'kk');".to_string()).unwrap();
+
+    let row = |pairs: &[(&str, LocalDBValue)]| -> localdb::Row { pairs.iter().map(|(k, v)| (k.to_string(), v.clone())).collect() };
+    let rows = vec![
+        row(&[("id", 2.into()), ("email", "b@x".into()), ("name", "lo".into())]),
+        row(&[("id", 1.into()), ("email", "a@x".into())]),
+        row(&[("id", 3.into()), ("email", "b@x".into()), ("name", "mo".into())]),
+        row(&[("id", 4.into()), ("email", "d@x".into()), ("name", "no".into()), ("tags", LocalDBValue::ARRAY(vec![1.into(), "x".into()]))]),
+    ];
+    let problems = db.validate_import("users", &rows);
+    let found: Vec<(usize, Vec<String>)> = problems.iter().map(|p| (p.row, p.columns.clone())).collect();
+    assert_eq!(
+        found,
+        vec![
+            (1, vec!["id".to_string()]),
+            (1, vec!["name".to_string()]),
+            (1, vec!["email".to_string()]),
+            (2, vec!["email".to_string()]),
+            (3, Vec::new()),
+        ]
+    );
+    assert!(matches!(problems[0].error, localdb::LocalDBError::ConstraintViolation(_)));
+    assert!(matches!(problems[4].error, localdb::LocalDBError::TypeMismatch(_)));
+    assert_eq!(problems[1].to_string(), "row 1: NOT NULL constraint failed: users.name");
+
+    // nothing was inserted, and clean rows validate clean
+    assert_eq!(db.count("users").unwrap(), 1);
+    assert!(db.validate_import("users", &rows[..1]).is_empty());
+    assert!(db.insert_many("users", rows[..1].to_vec()).is_ok());
+}
+
+#[test]
+fn test_exec_dry_run() {
+    let path = "test_exec_dry_run.db";
+    let _ = fs::remove_file(path);
+
+    let mut db = LocalDB::create(path).unwrap();
+    db.exec("CREATE TABLE users (id INT PRIMARY KEY, name TEXT NOT NULL); INSERT INTO users (id, name) VALUES (1, 'kk');".to_string()).unwrap();
+    let saved = fs::read_to_string(path).unwrap();
+
+    let events = std::sync::Arc::new(std::sync::Mutex::new(0));
+    let seen = events.clone();
+    db.on_change(move |_| *seen.lock().unwrap() += 1);
+
+    assert!(db.exec_dry_run("INSERT INTO users (id, name) VALUES (2, 'lo'); UPDATE users SET name = 'mo' WHERE id = 1;").is_ok());
+    // later statements see the earlier ones' changes
+    assert!(matches!(
+        db.exec_dry_run("INSERT INTO users (id, name) VALUES (2, 'lo'); INSERT INTO users (id, name) VALUES (2, 'no');"),
+        Err(localdb::LocalDBError::ConstraintViolation(_))
+    ));
+    assert!(db.exec_dry_run("INSERT INTO users (id, name) VALUES (1, 'lo');").is_err());
+    assert!(db.exec_dry_run("INSERT INTO users (id) VALUES (3);").is_err());
+    assert!(db.exec_dry_run("SELEC * FROM users;").is_err());
+
+    assert_eq!(db.count("users").unwrap(), 1);
+    assert_eq!(db.query("SELECT name FROM users;").unwrap()[0]["name"], LocalDBValue::from("kk"));
+    assert_eq!(fs::read_to_string(path).unwrap(), saved);
+    assert_eq!(*events.lock().unwrap(), 0);
+
+    let _ = fs::remove_file(path);
+}
+
+#[test]
+fn test_natural_join() {
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    db.exec(db.add_lines([
+        "CREATE TABLE users (user_id INT PRIMARY KEY, name TEXT); CREATE TABLE orders (order_id INT, user_id INT, item TEXT);",
+        "CREATE TABLE items (item TEXT, price INT);"
+    ])).unwrap();
+    db.exec("INSERT INTO users (user_id, name) VALUES (1, 'kk'); INSERT INTO users (user_id, name) VALUES (2, 'lo');".to_string()).unwrap();
+    db.exec("INSERT INTO orders (order_id, user_id, item) VALUES (10, 1, 'pen'); INSERT INTO orders (order_id, user_id, item) VALUES (11, 1, 'ink');".to_string()).unwrap();
+    db.exec("INSERT INTO items (item, price) VALUES ('pen', 3); INSERT INTO items (item, price) VALUES ('ink', 5);".to_string()).unwrap();
+
+    // the shared column shows once, unqualified, ahead of the others
+    let result = db.query_result("SELECT * FROM users NATURAL JOIN orders ORDER BY order_id;").unwrap();
+    assert_eq!(result.columns, ["user_id", "users.name", "orders.order_id", "orders.item"]);
+    assert_eq!(result.rows.len(), 2);
+    assert_eq!(result.rows[1]["user_id"], LocalDBValue::INT(1));
+
+    let rows = db.query("SELECT name, price FROM users NATURAL JOIN orders NATURAL JOIN items WHERE user_id = 1 ORDER BY price;").unwrap();
+    assert_eq!((&rows[0]["name"], &rows[0]["price"], &rows[1]["price"]), (&LocalDBValue::from("kk"), &LocalDBValue::INT(3), &LocalDBValue::INT(5)));
+
+    // LEFT keeps the merged value from the left side
+    let rows = db.query("SELECT user_id, item FROM users NATURAL LEFT JOIN orders WHERE user_id = 2;").unwrap();
+    assert_eq!((&rows[0]["user_id"], &rows[0]["item"]), (&LocalDBValue::INT(2), &LocalDBValue::NULL));
+
+    // no shared columns: every pair of rows
+    assert_eq!(db.query("SELECT * FROM users NATURAL JOIN items;").unwrap().len(), 4);
+
+    // user_id is in both tables before the NATURAL JOIN
+    db.exec("CREATE TABLE visits (user_id INT);".to_string()).unwrap();
+    assert!(matches!(
+        db.query("SELECT * FROM users JOIN orders ON users.user_id = orders.user_id NATURAL JOIN visits;"),
+        Err(localdb::LocalDBError::SqlError(_))
+    ));
+    assert!(db.query("SELECT * FROM users NATURAL JOIN orders ON users.user_id = orders.user_id;").is_err());
+    assert!(db.explain("SELECT * FROM users NATURAL JOIN orders;").unwrap().contains("join: NATURAL JOIN orders (nested loop)"));
+}
+
+#[test]
+fn test_query_csv_to() {
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    db.exec("CREATE TABLE notes (id INT PRIMARY KEY, body TEXT, score REAL);".to_string()).unwrap();
+    db.exec("INSERT INTO notes (id, body, score) VALUES (1, 'plain', 1.5);".to_string()).unwrap();
+    db.exec("INSERT INTO notes (id, body) VALUES (2, 'say \"hi\", then\nleave');".to_string()).unwrap();
+
+    let mut out = Vec::new();
+    db.query_csv_to("SELECT * FROM notes ORDER BY id;", &mut out).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), "id,body,score\n1,plain,1.5\n2,\"say \"\"hi\"\", then\nleave\",\n");
+
+    let mut out = Vec::new();
+    db.query_csv_to("SELECT id AS \"a,b\" FROM notes WHERE id > 5;", &mut out).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), "\"a,b\"\n");
+    assert!(db.query_csv_to("DELETE FROM notes;", Vec::new()).is_err());
+}
+
+#[test]
+fn test_numeric_functions() {
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    db.exec("CREATE TABLE items (name TEXT, price REAL, qty INT, cost DECIMAL);".to_string()).unwrap();
+    db.exec("INSERT INTO items (name, price, qty, cost) VALUES ('pen', 2.345, -3, DECIMAL '-12.345');".to_string()).unwrap();
+    db.exec("INSERT INTO items (name, qty) VALUES ('ink', 1250);".to_string()).unwrap();
+
+    let rows = db.query(
+        "SELECT ROUND(price, 2) AS p, round(price) AS p0, ABS(qty) AS q, CEIL(price) AS c, FLOOR(price) AS f, \
+         ROUND(cost, 2) AS d, FLOOR(cost) AS df, ABS(cost) AS da FROM items WHERE name = 'pen';",
+    ).unwrap();
+    let got = |c: &str| rows[0][c].clone();
+    assert_eq!(got("p"), LocalDBValue::REAL(2.35));
+    assert_eq!(got("p0"), LocalDBValue::REAL(2.0));
+    assert_eq!(got("q"), LocalDBValue::INT(3));
+    assert_eq!(got("c"), LocalDBValue::REAL(3.0));
+    assert_eq!(got("f"), LocalDBValue::REAL(2.0));
+    // DECIMALs compare equal by value, so check the digits kept as well
+    assert_eq!(format!("{:?}", got("d")), "DECIMAL(\"-12.35\")");
+    assert_eq!(format!("{:?}", got("df")), "DECIMAL(\"-13\")");
+    assert_eq!(format!("{:?}", got("da")), "DECIMAL(\"12.345\")");
+
+    // NULL in, NULL out; negative digits round to tens, hundreds and so on
+    let rows = db.query("SELECT ROUND(price, 1) AS p, ROUND(qty, -2) AS q FROM items WHERE name = 'ink';").unwrap();
+    assert_eq!((&rows[0]["p"], &rows[0]["q"]), (&LocalDBValue::NULL, &LocalDBValue::INT(1300)));
+
+    // in WHERE too
+    assert_eq!(db.query("SELECT name FROM items WHERE ABS(qty) = 3;").unwrap().len(), 1);
+
+    assert!(matches!(db.query("SELECT ABS(name) FROM items;"), Err(localdb::LocalDBError::TypeMismatch(_))));
+    assert!(matches!(db.query("SELECT ROUND(price, 'x') FROM items;"), Err(localdb::LocalDBError::TypeMismatch(_))));
+    assert!(matches!(db.query("SELECT ROUND(price, 1, 2) FROM items;"), Err(localdb::LocalDBError::SqlError(_))));
+    assert!(db.register_function("abs", |_| Ok(LocalDBValue::NULL)).is_err());
+}
+
+#[test]
+fn test_repair() {
+    let path = "test_repair.db";
+    let _ = fs::remove_file(path);
+
+    let mut db = LocalDB::create(path).unwrap();
+    db.exec("CREATE TABLE users (id INT PRIMARY KEY, name TEXT NOT NULL, score REAL);".to_string()).unwrap();
+    db.exec("INSERT INTO users (id, name, score) VALUES (1, 'kk', 2);".to_string()).unwrap();
+    assert!(db.repair().unwrap().is_empty());
+
+    // as edited by hand: a TEXT score, a missing name and a repeated key
+    let schema = r#""__schema__":{"users":{"name":"users","columns":[{"name":"id","col_type":"Int","primary_key":true},{"name":"name","col_type":"Text","not_null":true},{"name":"score","col_type":"Real"}]}}"#;
+    let rows = [
+        r#"{"id":{"INT":1},"name":{"TEXT":"kk"},"score":{"INT":2}}"#,
+        r#"{"id":{"INT":2},"name":{"TEXT":"lo"},"score":{"TEXT":"high"}}"#,
+        r#"{"id":{"INT":3}}"#,
+        r#"{"id":{"INT":1},"name":{"TEXT":"mo"}}"#,
+        r#"{"id":{"INT":4},"name":{"TEXT":"no"}}"#,
+    ];
+    fs::write(path, format!(r#"{{"__meta__":{{"format":1,"localdb":"0.1.0"}},{},"users":[{}]}}"#, schema, rows.join(","))).unwrap();
+
+    let mut db = LocalDB::open(path).unwrap();
+    assert_eq!(db.count("users").unwrap(), 5);
+    let report = db.repair().unwrap();
+    assert_eq!(report.count(), 3);
+    let removed: Vec<usize> = report.removed["users"].iter().map(|r| r.position).collect();
+    assert_eq!(removed, [1, 2, 3]);
+    assert!(matches!(report.removed["users"][0].error, localdb::LocalDBError::TypeMismatch(_)));
+    assert_eq!(report.removed["users"][1].error.to_string(), "NOT NULL constraint failed: users.name");
+    assert_eq!(report.removed["users"][2].error.to_string(), "PRIMARY KEY constraint failed: users.id");
+    assert_eq!(report.removed["users"][2].row["name"], LocalDBValue::from("mo"));
+
+    let reopened = LocalDB::open(path).unwrap();
+    let ids: Vec<LocalDBValue> = reopened.query("SELECT id FROM users ORDER BY id;").unwrap().iter().map(|r| r["id"].clone()).collect();
+    assert_eq!(ids, [LocalDBValue::INT(1), LocalDBValue::INT(4)]);
+
+    let _ = fs::remove_file(path);
+}
+
+#[test]
+fn test_insert_many_with() {
+    use localdb::{InsertCounts, OnConflict};
+
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    db.exec("CREATE TABLE users (id INT PRIMARY KEY, email TEXT UNIQUE, name TEXT NOT NULL);".to_string()).unwrap();
+    db.exec("INSERT INTO users (id, email, name) VALUES (1, 'a@x', 'kk'); INSERT INTO users (id, email, name) VALUES (2, 'b@x', 'lo');".to_string()).unwrap();
+
+    let row = |id: i64, email: &str, name: &str| -> localdb::Row {
+        [("id", LocalDBValue::INT(id)), ("email", email.into()), ("name", name.into())].into_iter().map(|(k, v)| (k.to_string(), v)).collect()
+    };
+    let batch = vec![row(1, "z@x", "new"), row(3, "c@x", "mo"), row(3, "c@x", "again")];
+
+    // the default fails on the first conflict and inserts nothing
+    assert!(matches!(db.insert_many_with("users", batch.clone(), OnConflict::Error), Err(localdb::LocalDBError::ConstraintViolation(_))));
+    assert_eq!(db.count("users").unwrap(), 2);
+
+    let counts = db.insert_many_with("users", batch.clone(), OnConflict::Skip).unwrap();
+    assert_eq!(counts, InsertCounts { inserted: 1, skipped: 2, overwritten: 0 });
+    assert_eq!(db.query("SELECT name FROM users WHERE id = 3;").unwrap()[0]["name"], LocalDBValue::from("mo"));
+
+    let counts = db.insert_many_with("users", vec![row(1, "a@x", "new"), row(4, "d@x", "no")], OnConflict::Overwrite).unwrap();
+    assert_eq!(counts, InsertCounts { inserted: 1, skipped: 0, overwritten: 1 });
+    let names: Vec<LocalDBValue> = db.query("SELECT name FROM users;").unwrap().iter().map(|r| r["name"].clone()).collect();
+    assert_eq!(names, [LocalDBValue::from("new"), LocalDBValue::from("lo"), LocalDBValue::from("mo"), LocalDBValue::from("no")]);
+
+    // conflicting with two rows, or breaking NOT NULL, still fails the whole batch
+    assert!(db.insert_many_with("users", vec![row(5, "e@x", "pp"), row(1, "b@x", "x")], OnConflict::Overwrite).is_err());
+    let mut unnamed = row(2, "b@x", "");
+    unnamed.remove("name");
+    assert!(db.insert_many_with("users", vec![unnamed], OnConflict::Overwrite).is_err());
+    assert_eq!(db.count("users").unwrap(), 4);
+}
+
+#[test]
+fn test_cast_column() {
+    use localdb::ColType;
+
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    db.exec("CREATE TABLE items (id INT PRIMARY KEY, qty TEXT UNIQUE, note TEXT);".to_string()).unwrap();
+    db.exec("INSERT INTO items (id, qty) VALUES (1, '42'); INSERT INTO items (id, qty) VALUES (2, ' 7 ');".to_string()).unwrap();
+    db.exec("INSERT INTO items (id, qty) VALUES (3, NULL); INSERT INTO items (id, qty, note) VALUES (4, '1.5', 'x');".to_string()).unwrap();
+
+    // 1.5 isn't a whole number: nothing changes
+    assert!(matches!(db.cast_column("items", "qty", ColType::Int), Err(localdb::LocalDBError::TypeMismatch(_))));
+    assert_eq!(db.query("SELECT qty FROM items WHERE id = 1;").unwrap()[0]["qty"], LocalDBValue::from("42"));
+
+    assert_eq!(db.cast_column("items", "qty", ColType::Real).unwrap(), 3);
+    let qty: Vec<LocalDBValue> = db.query("SELECT qty FROM items;").unwrap().iter().map(|r| r["qty"].clone()).collect();
+    assert_eq!(qty, [LocalDBValue::REAL(42.0), LocalDBValue::REAL(7.0), LocalDBValue::NULL, LocalDBValue::REAL(1.5)]);
+    assert_eq!(db.schema("items").unwrap().column("qty").unwrap().col_type, ColType::Real);
+    assert_eq!(db.query("SELECT id FROM items WHERE qty > 10;").unwrap().len(), 1);
+
+    // '7' and '7.0' differ as text but not as numbers
+    db.exec("CREATE TABLE codes (code TEXT UNIQUE); INSERT INTO codes (code) VALUES ('7'); INSERT INTO codes (code) VALUES ('7.0');".to_string()).unwrap();
+    assert!(matches!(db.cast_column("codes", "code", ColType::Real), Err(localdb::LocalDBError::ConstraintViolation(_))));
+    assert_eq!(db.schema("codes").unwrap().column("code").unwrap().col_type, ColType::Text);
+
+    assert!(matches!(db.cast_column("items", "missing", ColType::Int), Err(localdb::LocalDBError::ColumnNotFound(_))));
+    assert!(matches!(db.cast_column("nope", "qty", ColType::Int), Err(localdb::LocalDBError::TableNotFound(_))));
+}
+
+#[test]
+fn test_fulltext_index() {
+    let _ = fs::remove_file("test_fulltext.db");
+    let mut db = LocalDB::create("test_fulltext.db").unwrap();
+    db.exec("CREATE TABLE docs (id INT PRIMARY KEY, body TEXT);".to_string()).unwrap();
+    db.exec("INSERT INTO docs (id, body) VALUES (1, 'Rust notes: ownership and borrowing');".to_string()).unwrap();
+    db.exec("CREATE FULLTEXT INDEX ON docs (body);".to_string()).unwrap();
+    db.exec("INSERT INTO docs (id, body) VALUES (2, 'Shopping list, rust remover');".to_string()).unwrap();
+    db.exec("INSERT INTO docs (id, body) VALUES (3, 'Meeting NOTES');".to_string()).unwrap();
+
+    let ids = |db: &LocalDB, sql: &str| -> Vec<LocalDBValue> {
+        db.query(sql).unwrap().iter().map(|r| r["id"].clone()).collect()
+    };
+    assert_eq!(ids(&db, "SELECT id FROM docs WHERE body MATCH 'RUST';"), [LocalDBValue::INT(1), LocalDBValue::INT(2)]);
+    assert_eq!(ids(&db, "SELECT id FROM docs WHERE body MATCH 'notes rust';"), [LocalDBValue::INT(1)]);
+    assert!(ids(&db, "SELECT id FROM docs WHERE body MATCH '';").is_empty());
+    assert!(db.explain("SELECT id FROM docs WHERE body MATCH 'notes';").unwrap().contains("docs_body_fulltext"));
+
+    db.exec("DELETE FROM docs WHERE id = 1;".to_string()).unwrap();
+    db.exec("UPDATE docs SET body = 'rust notes' WHERE id = 2;".to_string()).unwrap();
+    assert_eq!(ids(&db, "SELECT id FROM docs WHERE body MATCH 'notes';"), [LocalDBValue::INT(2), LocalDBValue::INT(3)]);
+    assert!(ids(&db, "SELECT id FROM docs WHERE body MATCH 'shopping';").is_empty());
+
+    // the definition is saved, and the index rebuilt on open
+    let db = LocalDB::open("test_fulltext.db").unwrap();
+    assert!(db.explain("SELECT id FROM docs WHERE body MATCH 'notes';").unwrap().contains("docs_body_fulltext"));
+    assert_eq!(ids(&db, "SELECT id FROM docs WHERE body MATCH 'rust';"), [LocalDBValue::INT(2)]);
+    let _ = fs::remove_file("test_fulltext.db");
+}
+
+#[test]
+fn test_query_json() {
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    db.exec("CREATE TABLE items (id INT PRIMARY KEY, price DECIMAL, weight REAL, ok BOOL, tags ARRAY, meta JSON, note TEXT);".to_string()).unwrap();
+    db.exec(r#"INSERT INTO items (id, price, weight, ok, tags, meta, note) VALUES (1, DECIMAL '12.30', 0.5, TRUE, ['a', 'b'], JSON '{"x": [1, null]}', NULL);"#.to_string()).unwrap();
+
+    let rows = db.query_json("SELECT * FROM items;").unwrap();
+    assert_eq!(
+        rows,
+        [serde_json::json!({
+            "id": 1, "price": "12.30", "weight": 0.5, "ok": true, "tags": ["a", "b"], "meta": {"x": [1, null]}, "note": null
+        })]
+    );
+    assert_eq!(serde_json::to_string(&db.query_json("SELECT id FROM items;").unwrap()).unwrap(), r#"[{"id":1}]"#);
+}
+
+#[test]
+fn test_query_columns() {
+    let path = "test_query_columns.db";
+    let _ = fs::remove_file(path);
+    let mut db = LocalDB::create(path).unwrap();
+    db.exec("CREATE TABLE docs (id INT PRIMARY KEY, title TEXT, body TEXT);".to_string()).unwrap();
+    db.exec("INSERT INTO docs (id, title, body) VALUES (1, 'a', 'long text'); INSERT INTO docs (id, body) VALUES (2, 'more');".to_string()).unwrap();
+
+    let shown = |rows: Vec<localdb::Row>| -> Vec<(LocalDBValue, Option<LocalDBValue>, bool)> {
+        rows.iter().map(|r| (r["id"].clone(), r.get("title").cloned(), r.contains_key("body"))).collect()
+    };
+    let expected = [(LocalDBValue::INT(1), Some(LocalDBValue::from("a")), false), (LocalDBValue::INT(2), None, false)];
+    assert_eq!(shown(db.query_columns("docs", &["id", "title"]).unwrap()), expected);
+
+    // parsed straight from the unread table's JSON
+    let mut db = LocalDB::open_tables(path, &[]).unwrap();
+    assert_eq!(shown(db.query_columns("docs", &["id", "title"]).unwrap()), expected);
+    db.exec("INSERT INTO docs (id, title) VALUES (3, 'c');".to_string()).unwrap();
+    assert_eq!(db.query_columns("docs", &["title"]).unwrap().len(), 3);
+
+    assert!(matches!(db.query_columns("docs", &["nope"]), Err(localdb::LocalDBError::ColumnNotFound(_))));
+    assert!(matches!(db.query_columns("missing", &["id"]), Err(localdb::LocalDBError::TableNotFound(_))));
+    let _ = fs::remove_file(path);
+}
+
+#[test]
+fn test_exec_one() {
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    assert_eq!(db.exec_one("CREATE TABLE users (id INT PRIMARY KEY, name TEXT);").unwrap(), 0);
+    assert_eq!(db.exec_one("  INSERT INTO users (id, name) VALUES (1, 'a; b');  ").unwrap(), 1);
+    assert_eq!(db.exec_one("INSERT INTO users (id, name) VALUES (2, 'c');;").unwrap(), 1);
+
+    let injected = "UPDATE users SET name = 'x' WHERE id = 1; DELETE FROM users";
+    assert!(matches!(db.exec_one(injected), Err(localdb::LocalDBError::SqlError(_))));
+    assert!(matches!(db.exec_one(" ; "), Err(localdb::LocalDBError::SqlError(_))));
+    assert_eq!(db.query("SELECT * FROM users WHERE name = 'x';").unwrap().len(), 0);
+    assert_eq!(db.count("users").unwrap(), 2);
+
+    assert_eq!(db.exec_one("UPDATE users SET name = 'z';").unwrap(), 2);
+}
+
+#[test]
+fn test_collation() {
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    db.exec("CREATE TABLE people (name TEXT);".to_string()).unwrap();
+    for name in ["emma", "Bob", "Émile", "alice", "Eli"] {
+        db.exec(format!("INSERT INTO people (name) VALUES ('{}');", name)).unwrap();
+    }
+    db.exec("CREATE INDEX idx_name ON people (name);".to_string()).unwrap();
+
+    let names = |db: &LocalDB, sql: &str| -> Vec<String> {
+        db.query(sql).unwrap().iter().map(|r| match &r["name"] { LocalDBValue::TEXT(s) => s.clone(), _ => panic!() }).collect()
+    };
+    assert_eq!(names(&db, "SELECT name FROM people ORDER BY name;"), ["Bob", "Eli", "alice", "emma", "Émile"]);
+    assert_eq!(names(&db, "SELECT name FROM people ORDER BY name COLLATE NOCASE;"), ["alice", "Bob", "Eli", "emma", "Émile"]);
+    assert_eq!(names(&db, "SELECT name FROM people ORDER BY name COLLATE fold DESC LIMIT 3;"), ["emma", "Émile", "Eli"]);
+    assert!(db.query("SELECT name FROM people ORDER BY name COLLATE GERMAN;").is_err());
+    assert!(names(&db, "SELECT name FROM people WHERE name = 'BOB';").is_empty());
+
+    db.set_collation(localdb::Collation::NoCase);
+    assert_eq!(names(&db, "SELECT name FROM people ORDER BY name LIMIT 2;"), ["alice", "Bob"]);
+    assert_eq!(names(&db, "SELECT name FROM people ORDER BY name COLLATE BINARY LIMIT 2;"), ["Bob", "Eli"]);
+    assert_eq!(names(&db, "SELECT name FROM people WHERE name = 'BOB';"), ["Bob"]);
+    assert_eq!(names(&db, "SELECT name FROM people WHERE name IN ('ALICE', 'emile');"), ["alice"]);
+
+    db.set_collation(localdb::Collation::Fold);
+    assert_eq!(names(&db, "SELECT name FROM people WHERE name = 'emile';"), ["Émile"]);
+}
+
+#[test]
+fn test_query_shaped() {
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    db.insert_many("people", [
+        [("name".to_string(), LocalDBValue::from("kk")), ("age".to_string(), LocalDBValue::INT(3))].into_iter().collect(),
+        [("nick".to_string(), LocalDBValue::from("z")), ("extra".to_string(), LocalDBValue::BOOL(true))].into_iter().collect(),
+    ]).unwrap();
+
+    let result = db.query_shaped("SELECT *, nick AS alias FROM people;", &["alias", "name", "age"]).unwrap();
+    assert_eq!(result.columns, ["alias", "name", "age"]);
+    let values: Vec<Vec<Option<&LocalDBValue>>> = result.values().collect();
+    assert_eq!(values, [
+        [Some(&LocalDBValue::NULL), Some(&LocalDBValue::from("kk")), Some(&LocalDBValue::INT(3))],
+        [Some(&LocalDBValue::from("z")), Some(&LocalDBValue::NULL), Some(&LocalDBValue::NULL)],
+    ]);
+    assert!(result.rows.iter().all(|row| row.len() == 3));
+
+    assert!(matches!(db.query_shaped("SELECT * FROM people;", &["a", "a"]), Err(localdb::LocalDBError::SqlError(_))));
+}
+
+#[test]
+fn test_bulk_load_unchecked() {
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    db.exec("CREATE TABLE users (id INT PRIMARY KEY, name TEXT NOT NULL);".to_string()).unwrap();
+    db.exec("INSERT INTO users (id, name) VALUES (1, 'a');".to_string()).unwrap();
+
+    let row = |id: i64, name: Option<&str>| -> localdb::Row {
+        let mut row = localdb::Row::new();
+        row.insert("id".to_string(), LocalDBValue::INT(id));
+        if let Some(name) = name {
+            row.insert("name".to_string(), LocalDBValue::from(name));
+        }
+        row
+    };
+    let rows = (2..1000).map(|id| row(id, Some("x"))).chain([row(1, Some("dup")), row(1000, None)]);
+    assert_eq!(db.bulk_load_unchecked("users", rows).unwrap(), 1000);
+    assert_eq!(db.count("users").unwrap(), 1001);
+    // the PRIMARY KEY index was rebuilt with the new rows
+    assert_eq!(db.query("SELECT * FROM users WHERE id = 500;").unwrap().len(), 1);
+
+    let report = db.verify();
+    assert_eq!(report.count(), 2);
+    let positions: Vec<usize> = report.removed["users"].iter().map(|r| r.position).collect();
+    assert_eq!(positions, [999, 1000]);
+    assert_eq!(db.count("users").unwrap(), 1001);
+
+    assert_eq!(db.repair().unwrap().count(), 2);
+    assert!(db.verify().is_empty());
+}
+
+#[test]
+fn test_greatest_least() {
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    db.exec("CREATE TABLE scores (name TEXT, a INT, b REAL, c INT);".to_string()).unwrap();
+    db.exec("INSERT INTO scores (name, a, b, c) VALUES ('x', 3, 2.5, NULL);".to_string()).unwrap();
+    db.exec("INSERT INTO scores (name, a, b) VALUES ('y', 1, 7.5);".to_string()).unwrap();
+
+    let rows = db.query("SELECT GREATEST(a, b, c) AS hi, LEAST(a, b, c) AS lo, greatest(name, 'm') AS n FROM scores;").unwrap();
+    let got: Vec<[&LocalDBValue; 3]> = rows.iter().map(|r| [&r["hi"], &r["lo"], &r["n"]]).collect();
+    assert_eq!(got, [
+        [&LocalDBValue::INT(3), &LocalDBValue::REAL(2.5), &LocalDBValue::from("x")],
+        [&LocalDBValue::REAL(7.5), &LocalDBValue::INT(1), &LocalDBValue::from("y")],
+    ]);
+
+    let rows = db.query("SELECT LEAST(c, NULL) AS v FROM scores WHERE name = 'x';").unwrap();
+    assert_eq!(rows[0]["v"], LocalDBValue::NULL);
+    assert_eq!(db.query("SELECT name FROM scores WHERE GREATEST(a, b) > 5;").unwrap().len(), 1);
+
+    assert!(matches!(db.query("SELECT GREATEST(a, name) FROM scores;"), Err(localdb::LocalDBError::TypeMismatch(_))));
+    match db.query("SELECT LEAST() FROM scores;") {
+        Err(localdb::LocalDBError::SqlError(msg)) => assert_eq!(msg, "LEAST takes at least 1 argument, got 0"),
+        other => panic!("unexpected {:?}", other),
+    }
+}
+
+#[test]
+fn test_flush_debounce() {
+    let path = "test_flush_debounce.db";
+    let _ = fs::remove_file(path);
+    let on_disk = || LocalDB::open(path).unwrap().count("logs").unwrap_or(0);
+
+    let mut db = LocalDB::create(path).unwrap();
+    db.set_flush_debounce(std::time::Duration::from_secs(60));
+    for n in 0..5 {
+        db.exec(format!("INSERT INTO logs (n) VALUES ({});", n)).unwrap();
+    }
+    // the first write went out, the rest wait
+    assert_eq!(db.count("logs").unwrap(), 5);
+    assert_eq!(on_disk(), 1);
+
+    db.save().unwrap();
+    assert_eq!(on_disk(), 5);
+
+    db.exec("INSERT INTO logs (n) VALUES (5);".to_string()).unwrap();
+    assert_eq!(on_disk(), 5);
+    drop(db);
+    assert_eq!(on_disk(), 6);
+
+    let mut db = LocalDB::open(path).unwrap();
+    db.set_flush_debounce(std::time::Duration::ZERO);
+    db.exec("INSERT INTO logs (n) VALUES (6);".to_string()).unwrap();
+    assert_eq!(on_disk(), 7);
+    let _ = fs::remove_file(path);
+}
+
+#[test]
+fn test_where_now() {
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    db.enable_query_cache(4);
+    db.exec("CREATE TABLE sessions (id INT PRIMARY KEY, expires_at TIMESTAMP);".to_string()).unwrap();
+    let now = match LocalDBValue::now() { LocalDBValue::TIMESTAMP(ms) => ms, _ => unreachable!() };
+    db.exec(format!(
+        "INSERT INTO sessions (id, expires_at) VALUES (1, TIMESTAMP '{}'); INSERT INTO sessions (id, expires_at) VALUES (2, TIMESTAMP '{}');",
+        now - 60_000,
+        now + 200
+    ))
+    .unwrap();
+
+    let expired = db.query("SELECT id FROM sessions WHERE expires_at < now();").unwrap();
+    assert_eq!(expired.len(), 1);
+    assert_eq!(expired[0]["id"], LocalDBValue::INT(1));
+    assert_eq!(db.query("SELECT * FROM sessions WHERE expires_at BETWEEN TIMESTAMP '0' AND now();").unwrap().len(), 1);
+
+    // not cached: the same query later sees the later time
+    std::thread::sleep(std::time::Duration::from_millis(300));
+    assert_eq!(db.query("SELECT id FROM sessions WHERE expires_at < now();").unwrap().len(), 2);
+
+    db.exec("DELETE FROM sessions WHERE expires_at < now();".to_string()).unwrap();
+    assert_eq!(db.count("sessions").unwrap(), 0);
+
+    assert!(matches!(db.query("SELECT * FROM sessions WHERE expires_at < now(1);"), Err(localdb::LocalDBError::Parse(_))));
+    assert!(db.exec("CREATE INDEX live ON sessions (id) WHERE expires_at > now();".to_string()).is_err());
+    assert!(db.register_function("now", |_| Ok(LocalDBValue::NULL)).is_err());
+}
+
+#[test]
+fn test_changes_since() {
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    db.exec("INSERT INTO logs (msg) VALUES ('a'); INSERT INTO logs (msg) VALUES ('b'); INSERT INTO logs (msg) VALUES ('c');".to_string()).unwrap();
+
+    assert_eq!(db.changes_since("logs", 0).unwrap().len(), 3);
+    let new = db.changes_since("logs", 2).unwrap();
+    assert_eq!(new.len(), 1);
+    assert_eq!(new[0]["msg"], LocalDBValue::from("c"));
+    assert_eq!(new[0]["ROWID"], LocalDBValue::INT(3));
+    assert!(db.changes_since("logs", 3).unwrap().is_empty());
+    assert!(db.changes_since("logs", 10).unwrap().is_empty());
+    assert_eq!(db.changes_since("logs", -5).unwrap().len(), 3);
+
+    // updates keep their ROWID
+    db.exec("UPDATE logs SET msg = 'z' WHERE msg = 'a';".to_string()).unwrap();
+    assert!(db.changes_since("logs", 3).unwrap().is_empty());
+
+    db.exec("INSERT INTO feed (ROWID, msg) VALUES (TIMESTAMP '1000', 'x'); INSERT INTO feed (ROWID, msg) VALUES (TIMESTAMP '2000', 'y');".to_string()).unwrap();
+    let new = db.changes_since("feed", 1500).unwrap();
+    assert_eq!(new.len(), 1);
+    assert_eq!(new[0]["msg"], LocalDBValue::from("y"));
+
+    assert!(matches!(db.changes_since("missing", 0), Err(localdb::LocalDBError::TableNotFound(_))));
+}
+
+#[test]
+fn test_quote_ident_literal() {
+    assert_eq!(LocalDB::quote_ident("my \"odd\" table"), "\"my \"\"odd\"\" table\"");
+    assert_eq!(LocalDB::quote_literal(&LocalDBValue::TEXT("it's".to_string())), "'it''s'");
+    assert_eq!(LocalDB::quote_literal(&LocalDBValue::TIMESTAMP(1000)), "TIMESTAMP '1000'");
+
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    let table = LocalDB::quote_ident("user \"notes\"");
+    let column = LocalDB::quote_ident("select");
+    let text = LocalDBValue::TEXT("Robert'); DELETE FROM x; --".to_string());
+    db.exec(format!("CREATE TABLE {} ({} TEXT, n INT);", table, column)).unwrap();
+    db.exec(format!("INSERT INTO {} ({}, n) VALUES ({}, {});", table, column, LocalDB::quote_literal(&text), LocalDB::quote_literal(&LocalDBValue::INT(1))))
+        .unwrap();
+
+    let rows = db.query(&format!("SELECT {} FROM {} WHERE {} = {};", column, table, column, LocalDB::quote_literal(&text))).unwrap();
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0]["select"], text);
+    assert_eq!(db.count("user \"notes\"").unwrap(), 1);
+
+}
+
+#[test]
+fn test_export_json_to() {
+    let path = "test_export_json_to.db";
+    let _ = fs::remove_file(path);
+    let mut db = LocalDB::create(path).unwrap();
+    db.exec("CREATE TABLE zeta (id INT PRIMARY KEY, name TEXT NOT NULL);".to_string()).unwrap();
+    db.exec("INSERT INTO zeta (id, name) VALUES (1, 'a'); INSERT INTO Alpha (doc, n) VALUES (JSON '{\"b\": [1, {\"d\": 2, \"c\": 3}], \"a\": null}', 2.5);".to_string())
+        .unwrap();
+    db.exec("INSERT INTO _under (x) VALUES (TIMESTAMP '5'); CREATE FULLTEXT INDEX ON zeta (name);".to_string()).unwrap();
+
+    for options in [localdb::SaveOptions::canonical(), localdb::SaveOptions::pretty(4), localdb::SaveOptions::pretty(2)] {
+        db.set_save_options(options);
+        db.save().unwrap();
+        let mut out = Vec::new();
+        db.export_json_to(&mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), fs::read_to_string(path).unwrap());
+    }
+
+    // tables open_tables left unread come out the same
+    let mut partial = LocalDB::open_tables(path, &["zeta"]).unwrap();
+    partial.set_save_options(localdb::SaveOptions::pretty(2));
+    let mut out = Vec::new();
+    partial.export_json_to(&mut out).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), fs::read_to_string(path).unwrap());
+
+    let mut empty = Vec::new();
+    LocalDB::from_reader("{}".as_bytes()).unwrap().export_json_to(&mut empty).unwrap();
+    let copy = LocalDB::from_reader(empty.as_slice()).unwrap();
+    assert_eq!(copy.iter_tables().count(), 0);
+    let _ = fs::remove_file(path);
+}
+
+#[test]
+fn test_value_eq_ord() {
+    use LocalDBValue::*;
+
+    assert_eq!(INT(1), INT(1));
+    assert_eq!(NULL, NULL);
+    assert_ne!(INT(1), REAL(1.0));
+    assert_ne!(TEXT("a".into()), UUID("a".into()));
+    assert_ne!(TIMESTAMP(5), INT(5));
+    assert_eq!(LocalDBValue::decimal("1.10").unwrap(), LocalDBValue::decimal("1.1").unwrap());
+    assert_ne!(REAL(f64::NAN), REAL(f64::NAN));
+    assert_eq!(LocalDBValue::array(vec![INT(1), INT(2)]).unwrap(), LocalDBValue::array(vec![INT(1), INT(2)]).unwrap());
+
+    assert!(INT(1) < INT(2));
+    assert!(TEXT("B".into()) < TEXT("a".into()));
+    assert!(LocalDBValue::decimal("2.5").unwrap() < LocalDBValue::decimal("10").unwrap());
+    assert!(LocalDBValue::array(vec![INT(1)]).unwrap() < LocalDBValue::array(vec![INT(1), INT(0)]).unwrap());
+    assert_eq!(INT(1).partial_cmp(&REAL(2.0)), None);
+    assert_eq!(JSON(serde_json::json!({"a": 1})).partial_cmp(&JSON(serde_json::json!({"a": 2}))), None);
+
+    // rows compare directly
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    db.exec("INSERT INTO t (id, name) VALUES (1, 'kk');".to_string()).unwrap();
+    let rows = db.query("SELECT * FROM t;").unwrap();
+    let expected: localdb::Row = [("id".to_string(), INT(1)), ("name".to_string(), TEXT("kk".into()))].into_iter().collect();
+    assert_eq!(rows, vec![expected]);
+    assert_eq!(rows[0]["name"], TEXT("kk".into()));
+}
+
+#[test]
+fn test_unreadable_file_is_an_error() {
+    let path = "test_unreadable_file.db";
+    let newer = r#"{"__meta__":{"format":2,"localdb":"9.0.0"},"t":[{"b":{"BLOB":"AAAA"}}]}"#;
+    fs::write(path, newer).unwrap();
+    assert!(matches!(LocalDB::open(path), Err(localdb::LocalDBError::UnsupportedFormat { found: 2, supported: 1 })));
+    assert!(matches!(LocalDB::open_tables(path, &[]), Err(localdb::LocalDBError::UnsupportedFormat { found: 2, .. })));
+    assert!(matches!(LocalDB::from_reader(newer.as_bytes()), Err(localdb::LocalDBError::UnsupportedFormat { .. })));
+
+    for corrupt in [r#"{"t":[{"b":{"BLOB":"AAAA"}}]}"#, "{\"t\": [", "", r#"{"__schema__": 5, "t": []}"#] {
+        fs::write(path, corrupt).unwrap();
+        assert!(matches!(LocalDB::open(path), Err(localdb::LocalDBError::CorruptData(_))), "{}", corrupt);
+        assert!(matches!(LocalDB::from_reader(corrupt.as_bytes()), Err(localdb::LocalDBError::CorruptData(_))));
+        assert_eq!(fs::read_to_string(path).unwrap(), corrupt);
+    }
+    // open_tables leaves the rows of unlisted tables unparsed
+    fs::write(path, "{\"t\": [").unwrap();
+    assert!(matches!(LocalDB::open_tables(path, &[]), Err(localdb::LocalDBError::CorruptData(_))));
+    fs::write(path, r#"{"__schema__": 5, "t": []}"#).unwrap();
+    assert!(matches!(LocalDB::open_tables(path, &[]), Err(localdb::LocalDBError::CorruptData(_))));
+    let _ = fs::remove_file(path);
+}
+
+#[test]
+fn test_declared_types_enforced() {
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    db.exec("CREATE TABLE t (id INT PRIMARY KEY, age INT, score REAL, tag UUID, at TIMESTAMP);".to_string()).unwrap();
+    db.exec("INSERT INTO t (id, age, score, tag, at) VALUES (1, 30, 2, 'u-1', 5);".to_string()).unwrap();
+
+    for sql in ["INSERT INTO t (id, age) VALUES (2, 'notanint');", "INSERT INTO t (id, score) VALUES (2, TRUE);", "UPDATE t SET age = 1.5 WHERE id = 1;"] {
+        assert!(matches!(db.exec(sql.to_string()), Err(localdb::LocalDBError::TypeMismatch(_))), "{}", sql);
+    }
+
+    let mut row = localdb::Row::new();
+    row.insert("id".to_string(), LocalDBValue::INT(3));
+    row.insert("at".to_string(), LocalDBValue::TEXT("soon".into()));
+    assert!(matches!(db.insert_many("t", vec![row]), Err(localdb::LocalDBError::TypeMismatch(_))));
+    let fields: std::collections::HashMap<String, LocalDBValue> = [("age".to_string(), LocalDBValue::BOOL(true))].into_iter().collect();
+    assert!(matches!(db.update_fields("t", 1, fields), Err(localdb::LocalDBError::TypeMismatch(_))));
+
+    assert_eq!(db.count("t").unwrap(), 1);
+    // repair only removes rows written without checks
+    assert!(db.verify().is_empty());
+    assert_eq!(db.query("SELECT * FROM t WHERE age = 30;").unwrap().len(), 1);
+}
+
+#[test]
+fn test_update_where_checks_constraints() {
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    db.exec("CREATE TABLE u (id INT PRIMARY KEY, name TEXT NOT NULL);".to_string()).unwrap();
+    db.exec("INSERT INTO u (id, name) VALUES (1, 'a'); INSERT INTO u (id, name) VALUES (2, 'b');".to_string()).unwrap();
+    let before = db.dump_sql();
+
+    let same_id = db.update_where("u", |_| true, |row| {
+        row.insert("id".into(), LocalDBValue::INT(1));
+    });
+    assert!(matches!(same_id, Err(localdb::LocalDBError::ConstraintViolation(_))));
+    let no_name = db.update_where("u", |_| true, |row| {
+        row.insert("name".into(), LocalDBValue::NULL);
+    });
+    assert!(matches!(no_name, Err(localdb::LocalDBError::ConstraintViolation(_))));
+    assert_eq!(db.dump_sql(), before);
+    assert_eq!(db.query("SELECT * FROM u WHERE id = 2;").unwrap().len(), 1);
+
+    // keys may be traded, since rows are checked once all have changed
+    let swapped = db.update_where("u", |_| true, |row| {
+        let id = if matches!(row["id"], LocalDBValue::INT(1)) { 2 } else { 1 };
+        row.insert("id".into(), LocalDBValue::INT(id));
+    });
+    assert_eq!(swapped.unwrap(), 2);
+    assert_eq!(db.query("SELECT name FROM u WHERE id = 1;").unwrap()[0]["name"], LocalDBValue::TEXT("b".into()));
+}
+
+#[test]
+fn test_update_where_unique() {
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    db.exec("CREATE TABLE users (id INT PRIMARY KEY, email TEXT, UNIQUE (email));".to_string()).unwrap();
+    db.exec("INSERT INTO users (id, email) VALUES (1, 'a@x'); INSERT INTO users (id, email) VALUES (2, 'b@x');".to_string()).unwrap();
+
+    let duplicate = db.update_where("users", |row| matches!(row["id"], LocalDBValue::INT(2)), |row| {
+        row.insert("email".into(), LocalDBValue::TEXT("a@x".into()));
+    });
+    assert!(matches!(duplicate, Err(localdb::LocalDBError::ConstraintViolation(_))));
+    assert_eq!(db.query("SELECT * FROM users WHERE email = 'b@x';").unwrap().len(), 1);
+
+    // a NULL never conflicts
+    let cleared = db.update_where("users", |_| true, |row| {
+        row.insert("email".into(), LocalDBValue::NULL);
+    });
+    assert_eq!(cleared.unwrap(), 2);
+}
+
+#[test]
+fn test_validate_import_types() {
+    let mut db = LocalDB::from_reader("{}".as_bytes()).unwrap();
+    db.exec("CREATE TABLE users (id INT PRIMARY KEY, age INT, score REAL NOT NULL);".to_string()).unwrap();
+
+    let row = |pairs: &[(&str, LocalDBValue)]| -> localdb::Row { pairs.iter().map(|(k, v)| (k.to_string(), v.clone())).collect() };
+    let rows = vec![
+        row(&[("id", 1.into()), ("age", "old".into()), ("score", 2.into())]),
+        row(&[("id", 2.into()), ("age", 3.into()), ("score", true.into())]),
+        row(&[("id", 3.into()), ("age", 3.into()), ("score", 2.5.into())]),
+    ];
+    let problems = db.validate_import("users", &rows);
+    let found: Vec<(usize, Vec<String>)> = problems.iter().map(|p| (p.row, p.columns.clone())).collect();
+    assert_eq!(found, vec![(0, vec!["age".to_string()]), (1, vec!["score".to_string()])]);
+    assert!(problems.iter().all(|p| matches!(p.error, localdb::LocalDBError::TypeMismatch(_))));
+    assert_eq!(problems[0].to_string(), "row 0: Type mismatch: Value of users.age is not of type INT: 'old'");
+
+    // the same rows insert_many rejects
+    assert!(db.insert_many("users", rows[..1].to_vec()).is_err());
+    assert!(db.insert_many("users", rows[2..].to_vec()).is_ok());
+}